@@ -24,6 +24,10 @@
 //   Software.
 
 mod fft;
+mod period;
+
+use period::Acf;
+pub use period::{autocorrelation, dominant_period};
 
 // Smooth out the data to promote human readability, resolution is an upper bound on the number of points returned
 pub fn asap_smooth(data: &[f64], resolution: u32) -> Vec<f64> {
@@ -36,8 +40,14 @@ pub fn asap_smooth(data: &[f64], resolution: u32) -> Vec<f64> {
         Cow::Borrowed(data)
     };
 
-    let mut acf = Acf::new(&data, (data.len() as f64 / 10.0).round() as u32);
-    let peaks = acf.find_peaks();
+    let mut acf = Acf::new(&data, (data.len() as f64 / 10.0).round() as usize);
+    let mut peaks = acf.find_peaks();
+
+    /* If there is no autocorrelation peak within the MAX_WINDOW boundary,
+    # try windows from the largest to the smallest */
+    if peaks.len() <= 1 {
+        peaks.extend(2..acf.correlations.len() as u32);
+    }
     let mut metrics = Metrics::new(&data);
     let original_kurt = metrics.kurtosis();
     let mut min_obj = metrics.roughness();
@@ -153,85 +163,6 @@ fn std(values: &[f64]) -> f64 {
     (std / values.len() as f64).sqrt()
 }
 
-impl<'a> Acf<'a> {
-    fn new(values: &'a [f64], max_lag: u32) -> Acf<'a> {
-        let mut acf = Acf {
-            mean: mean(values),
-            values,
-            correlations: Vec::with_capacity(max_lag as usize),
-            max_acf: 0.0,
-        };
-        acf.calculate();
-        acf
-    }
-
-    fn calculate(&mut self) {
-        /* Padding to the closest power of 2 */
-        let len = (2_u32).pow((self.values.len() as f64).log2() as u32 + 1);
-        let mut fftreal = vec![0.0; len as usize];
-        let mut fftimg = vec![0.0; len as usize];
-
-        for (i, real) in fftreal.iter_mut().enumerate().take(self.values.len()) {
-            *real = self.values[i] - self.mean;
-        }
-
-        /* F_R(f) = FFT(X) */
-        fft::transform(&mut fftreal, &mut fftimg);
-
-        /* S(f) = F_R(f)F_R*(f) */
-        for i in 0..fftreal.len() {
-            fftreal[i] = fftreal[i].powi(2) + fftimg[i].powi(2);
-            fftimg[i] = 0.0;
-        }
-
-        /*  R(t) = IFFT(S(f)) */
-        fft::inverse_transform(&mut fftreal, &mut fftimg);
-        for i in 1..self.correlations.len() {
-            self.correlations[i] = fftreal[i] / fftreal[0];
-        }
-    }
-
-    fn find_peaks(&mut self) -> Vec<u32> {
-        const CORR_THRESH: f64 = 0.2;
-
-        let mut peak_indicies = Vec::new();
-
-        if self.correlations.len() > 1 {
-            let mut positive = self.correlations[1] > self.correlations[0];
-            let mut max = 1;
-            for i in 2..self.correlations.len() {
-                if !positive && self.correlations[i] > self.correlations[i - 1] {
-                    max = i;
-                    positive = !positive;
-                } else if positive && self.correlations[i] > self.correlations[max] {
-                    max = i;
-                } else if positive
-                    && self.correlations[i] < self.correlations[i - 1]
-                    && max > 1
-                    && self.correlations[max] > CORR_THRESH
-                {
-                    peak_indicies.push(max as u32);
-                    if self.correlations[max] > self.max_acf {
-                        self.max_acf = self.correlations[max];
-                    }
-                    positive = !positive;
-                }
-            }
-        }
-
-        /* If there is no autocorrelation peak within the MAX_WINDOW boundary,
-        # try windows from the largest to the smallest */
-
-        if peak_indicies.len() <= 1 {
-            for i in 2..self.correlations.len() {
-                peak_indicies.push(i as u32);
-            }
-        }
-
-        peak_indicies
-    }
-}
-
 struct Metrics<'a> {
     len: u32,
     values: &'a [f64],
@@ -272,13 +203,6 @@ impl<'a> Metrics<'a> {
     }
 }
 
-struct Acf<'a> {
-    mean: f64,
-    values: &'a [f64],
-    correlations: Vec<f64>,
-    max_acf: f64,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,140 @@
+use crate::{fft, mean};
+
+// The autocorrelation of a series for lags 0..max_lag, computed via FFT
+// (Wiener-Khinchin: R = IFFT(|FFT(X)|^2)).
+pub(crate) struct Acf {
+    pub(crate) correlations: Vec<f64>,
+    pub(crate) max_acf: f64,
+}
+
+impl Acf {
+    pub(crate) fn new(values: &[f64], max_lag: usize) -> Acf {
+        let mut acf = Acf {
+            correlations: vec![0.0; max_lag.min(values.len())],
+            max_acf: 0.0,
+        };
+        if acf.correlations.is_empty() {
+            return acf;
+        }
+
+        /* Padding to at least twice the length so the correlation doesn't wrap */
+        let len = (2 * values.len()).next_power_of_two();
+        let mut fftreal = vec![0.0; len];
+        let mut fftimg = vec![0.0; len];
+
+        let mean = mean(values);
+        for (real, value) in fftreal.iter_mut().zip(values) {
+            *real = value - mean;
+        }
+
+        /* F_R(f) = FFT(X) */
+        fft::transform(&mut fftreal, &mut fftimg);
+
+        /* S(f) = F_R(f)F_R*(f) */
+        for i in 0..len {
+            fftreal[i] = fftreal[i].powi(2) + fftimg[i].powi(2);
+            fftimg[i] = 0.0;
+        }
+
+        /*  R(t) = IFFT(S(f)) */
+        fft::inverse_transform(&mut fftreal, &mut fftimg);
+
+        if fftreal[0] == 0.0 {
+            // constant input, there is no correlation structure to speak of
+            return acf;
+        }
+        for (i, correlation) in acf.correlations.iter_mut().enumerate() {
+            *correlation = fftreal[i] / fftreal[0];
+        }
+        acf
+    }
+
+    // The lags past the first whose correlation is a local maximum above the
+    // threshold, `max_acf` is set to the highest of them.
+    pub(crate) fn find_peaks(&mut self) -> Vec<u32> {
+        const CORR_THRESH: f64 = 0.2;
+
+        let mut peak_indicies = Vec::new();
+
+        if self.correlations.len() > 1 {
+            let mut positive = self.correlations[1] > self.correlations[0];
+            let mut max = 1;
+            for i in 2..self.correlations.len() {
+                if !positive && self.correlations[i] > self.correlations[i - 1] {
+                    max = i;
+                    positive = !positive;
+                } else if positive && self.correlations[i] > self.correlations[max] {
+                    max = i;
+                } else if positive
+                    && self.correlations[i] < self.correlations[i - 1]
+                    && max > 1
+                    && self.correlations[max] > CORR_THRESH
+                {
+                    peak_indicies.push(max as u32);
+                    if self.correlations[max] > self.max_acf {
+                        self.max_acf = self.correlations[max];
+                    }
+                    positive = !positive;
+                }
+            }
+        }
+
+        peak_indicies
+    }
+}
+
+// Autocorrelation of `values` for lags 0..=max_lag.
+pub fn autocorrelation(values: &[f64], max_lag: usize) -> Vec<f64> {
+    Acf::new(values, max_lag.saturating_add(1)).correlations
+}
+
+// Find the most likely seasonal period of `values`: the lag with the highest
+// autocorrelation peak, considering lags up to half the series length.
+// Returns None if no peak rises above the correlation threshold.
+pub fn dominant_period(values: &[f64]) -> Option<usize> {
+    let mut acf = Acf::new(values, values.len() / 2 + 1);
+    let peaks = acf.find_peaks();
+    peaks
+        .into_iter()
+        .find(|&lag| acf.correlations[lag as usize] == acf.max_acf)
+        .map(|lag| lag as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autocorrelation_of_alternating_series() {
+        let data: Vec<f64> = (0..64)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let acf = autocorrelation(&data, 4);
+        assert_eq!(acf.len(), 5);
+        assert_eq!(acf[0], 1.0);
+        assert!(acf[1] < -0.9);
+        assert!(acf[2] > 0.9);
+    }
+
+    #[test]
+    fn constant_series_has_no_period() {
+        let data = vec![3.0; 50];
+        assert_eq!(autocorrelation(&data, 3), vec![0.0; 4]);
+        assert_eq!(dominant_period(&data), None);
+    }
+
+    #[test]
+    fn finds_period_of_sine() {
+        let data: Vec<f64> = (0..120)
+            .map(|i| (2.0 * std::f64::consts::PI * i as f64 / 12.0).sin())
+            .collect();
+        assert_eq!(dominant_period(&data), Some(12));
+    }
+
+    #[test]
+    fn finds_period_of_repeating_pattern() {
+        let pattern = [1.0, 5.0, 2.0, 8.0, 3.0, 0.0, 4.0];
+        let data: Vec<f64> = pattern.iter().cycle().take(70).cloned().collect();
+        assert_eq!(dominant_period(&data), Some(7));
+    }
+}
@@ -323,3 +323,268 @@ pub mod prefix_varint {
         }
     }
 }
+
+pub mod xor {
+    //! Float compression from Facebook's
+    //! [Gorilla](https://www.vldb.org/pvldb/vol8/p1816-teller.pdf): each value
+    //! is XORed with the previous one, and only the bits that differ are
+    //! stored. Values are written as a bitstream (`x` is a bit of the XOR)
+    //! ```python,ignore,no_run
+    //! 0                          XOR is 0, value is unchanged
+    //! 10 x...                    XOR fits in the previous meaningful bits
+    //! 11 lllll nnnnnn x...       `l` leading zeros (at most 31) followed by
+    //!                            `n + 1` meaningful bits
+    //! ```
+    //! with bits packed most significant first. Since the last byte may be
+    //! padded with zeros the number of values must be stored separately.
+
+    pub fn f64_encoder() -> impl FnMut(f64) -> u64 {
+        let mut prev = 0u64;
+        move |value: f64| {
+            let bits = value.to_bits();
+            let xor = bits ^ prev;
+            prev = bits;
+            xor
+        }
+    }
+
+    pub fn f64_decoder() -> impl FnMut(u64) -> f64 {
+        let mut prev = 0u64;
+        move |xor| {
+            prev ^= xor;
+            f64::from_bits(prev)
+        }
+    }
+
+    pub fn compress_f64s_to_vec<I: Iterator<Item = f64>>(bytes: &mut Vec<u8>, values: I) {
+        compress_xors_to_vec(bytes, values.map(f64_encoder()))
+    }
+
+    pub fn compress_xors_to_vec<I: Iterator<Item = u64>>(bytes: &mut Vec<u8>, xors: I) {
+        let mut writer = BitWriter { bytes, used: 8 };
+        // no window to start with, 64 leading zeros never fits
+        let (mut leading, mut trailing) = (64, 0);
+        for xor in xors {
+            if xor == 0 {
+                writer.write(0, 1);
+                continue;
+            }
+
+            let new_leading = xor.leading_zeros().min(31);
+            let new_trailing = xor.trailing_zeros();
+            if new_leading >= leading && new_trailing >= trailing {
+                writer.write(0b10, 2);
+                writer.write(xor >> trailing, 64 - leading - trailing);
+            } else {
+                leading = new_leading;
+                trailing = new_trailing;
+                let len = 64 - leading - trailing;
+                writer.write(0b11, 2);
+                writer.write(leading as u64, 5);
+                writer.write((len - 1) as u64, 6);
+                writer.write(xor >> trailing, len);
+            }
+        }
+    }
+
+    pub fn f64_decompressor(bytes: &[u8], num_values: usize) -> F64Decompressor<'_> {
+        F64Decompressor {
+            xors: XorDecompressor::new(bytes, num_values),
+            decoder: 0,
+        }
+    }
+
+    pub fn xor_decompressor(bytes: &[u8], num_values: usize) -> XorDecompressor<'_> {
+        XorDecompressor::new(bytes, num_values)
+    }
+
+    pub struct F64Decompressor<'a> {
+        xors: XorDecompressor<'a>,
+        // same as `f64_decoder()`, stored inline so the type can be named
+        decoder: u64,
+    }
+
+    impl<'a> F64Decompressor<'a> {
+        pub fn len(&self) -> usize {
+            self.xors.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.xors.is_empty()
+        }
+    }
+
+    impl<'a> Iterator for F64Decompressor<'a> {
+        type Item = f64;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let xor = self.xors.next()?;
+            self.decoder ^= xor;
+            Some(f64::from_bits(self.decoder))
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.xors.size_hint()
+        }
+    }
+
+    pub struct XorDecompressor<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+        remaining: usize,
+        leading: u32,
+        trailing: u32,
+    }
+
+    impl<'a> XorDecompressor<'a> {
+        fn new(bytes: &'a [u8], num_values: usize) -> Self {
+            Self {
+                bytes,
+                pos: 0,
+                remaining: num_values,
+                leading: 0,
+                trailing: 0,
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.remaining
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.remaining == 0
+        }
+
+        fn read(&mut self, mut bits: u32) -> u64 {
+            let mut value = 0;
+            while bits > 0 {
+                let offset = (self.pos % 8) as u32;
+                let available = 8 - offset;
+                let n = available.min(bits);
+                let byte = self.bytes[self.pos / 8] as u64;
+                let chunk = (byte >> (available - n)) & ((1 << n) - 1);
+                value = (value << n) | chunk;
+                self.pos += n as usize;
+                bits -= n;
+            }
+            value
+        }
+    }
+
+    impl<'a> Iterator for XorDecompressor<'a> {
+        type Item = u64;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.remaining == 0 {
+                return None;
+            }
+            self.remaining -= 1;
+
+            if self.read(1) == 0 {
+                return Some(0);
+            }
+            if self.read(1) == 1 {
+                self.leading = self.read(5) as u32;
+                let len = self.read(6) as u32 + 1;
+                self.trailing = 64 - self.leading - len;
+            }
+            let len = 64 - self.leading - self.trailing;
+            Some(self.read(len) << self.trailing)
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.remaining, Some(self.remaining))
+        }
+    }
+
+    struct BitWriter<'a> {
+        bytes: &'a mut Vec<u8>,
+        // number of bits used in the last byte, starts full so the first
+        // write begins a new byte
+        used: u32,
+    }
+
+    impl<'a> BitWriter<'a> {
+        // writes the low `bits` bits of `value`
+        fn write(&mut self, value: u64, mut bits: u32) {
+            while bits > 0 {
+                if self.used == 8 {
+                    self.bytes.push(0);
+                    self.used = 0;
+                }
+                let free = 8 - self.used;
+                let n = free.min(bits);
+                let chunk = (value >> (bits - n)) & ((1 << n) - 1);
+                *self.bytes.last_mut().unwrap() |= (chunk as u8) << (free - n);
+                self.used += n;
+                bits -= n;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use quickcheck_macros::quickcheck;
+
+        use super::*;
+
+        #[quickcheck]
+        fn quick_test_roundtrip_f64(values: Vec<f64>) -> bool {
+            let mut bytes = vec![];
+            compress_f64s_to_vec(&mut bytes, values.iter().cloned());
+
+            let output: Vec<f64> = f64_decompressor(&bytes, values.len()).collect();
+            // compare bits since NaN != NaN
+            let values: Vec<u64> = values.iter().map(|v| v.to_bits()).collect();
+            let output: Vec<u64> = output.iter().map(|v| v.to_bits()).collect();
+            assert_eq!(values, output);
+            true
+        }
+
+        #[quickcheck]
+        fn quick_test_roundtrip_xor(values: Vec<u64>) -> bool {
+            let mut bytes = vec![];
+            compress_xors_to_vec(&mut bytes, values.iter().cloned());
+
+            let output: Vec<u64> = xor_decompressor(&bytes, values.len()).collect();
+            assert_eq!(values, output);
+            true
+        }
+
+        #[quickcheck]
+        fn quick_test_roundtrip_encoder(values: Vec<f64>) -> bool {
+            let output: Vec<f64> = values
+                .iter()
+                .cloned()
+                .map(f64_encoder())
+                .map(f64_decoder())
+                .collect();
+            let values: Vec<u64> = values.iter().map(|v| v.to_bits()).collect();
+            let output: Vec<u64> = output.iter().map(|v| v.to_bits()).collect();
+            assert_eq!(values, output);
+            true
+        }
+
+        #[test]
+        fn test_repeated_values() {
+            let mut bytes = vec![];
+            compress_f64s_to_vec(&mut bytes, vec![1.5; 64].into_iter());
+            // 2 control bits (`11`) + 5 + 6 + the meaningful bits for the first value,
+            // then a single bit for each repeat
+            assert!(bytes.len() <= 12);
+
+            let output: Vec<f64> = f64_decompressor(&bytes, 64).collect();
+            assert_eq!(output, vec![1.5; 64]);
+        }
+
+        #[test]
+        fn test_append_to_existing() {
+            let mut bytes = vec![0xff];
+            compress_f64s_to_vec(&mut bytes, [1.0, 2.0, -3.25].iter().cloned());
+            assert_eq!(bytes[0], 0xff);
+
+            let output: Vec<f64> = f64_decompressor(&bytes[1..], 3).collect();
+            assert_eq!(output, vec![1.0, 2.0, -3.25]);
+        }
+    }
+}
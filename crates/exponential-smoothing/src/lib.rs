@@ -0,0 +1,459 @@
+//! Exponential smoothing models: simple exponential smoothing, Holt's linear
+//! trend method, and Holt-Winters with additive or multiplicative seasonality.
+//!
+//! Smoothing parameters that aren't supplied are estimated by minimizing the
+//! sum of squared one-step-ahead errors over the data.
+
+use std::cmp::Ordering;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seasonality {
+    Additive,
+    Multiplicative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Simple,
+    Linear,
+    HoltWinters {
+        season_length: usize,
+        seasonality: Seasonality,
+    },
+}
+
+// Smoothing parameters, those that are `None` will be estimated. Parameters a
+// model doesn't use are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Params {
+    pub alpha: Option<f64>,
+    pub beta: Option<f64>,
+    pub gamma: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fit {
+    pub model: Model,
+    pub alpha: f64,
+    pub beta: Option<f64>,
+    pub gamma: Option<f64>,
+    // the state after the last observation
+    pub level: f64,
+    pub trend: Option<f64>,
+    // the seasonal components, in the order they will next be used
+    pub seasonal: Option<Vec<f64>>,
+    // one-step-ahead predictions for every observation
+    pub fitted: Vec<f64>,
+    pub sse: f64,
+}
+
+impl Model {
+    pub fn min_points(&self) -> usize {
+        match self {
+            Model::Simple => 1,
+            Model::Linear => 2,
+            Model::HoltWinters { season_length, .. } => 2 * season_length,
+        }
+    }
+
+    fn num_params(&self) -> usize {
+        match self {
+            Model::Simple => 1,
+            Model::Linear => 2,
+            Model::HoltWinters { .. } => 3,
+        }
+    }
+}
+
+pub fn fit(model: Model, data: &[f64], params: Params) -> Fit {
+    if let Model::HoltWinters { season_length, .. } = model {
+        assert!(season_length > 0, "season length must be positive");
+    }
+    assert!(
+        data.len() >= model.min_points(),
+        "not enough values to fit the model, need at least {}",
+        model.min_points()
+    );
+    if let Model::HoltWinters {
+        seasonality: Seasonality::Multiplicative,
+        ..
+    } = model
+    {
+        assert!(
+            data.iter().all(|&y| y > 0.0),
+            "multiplicative seasonality requires positive values"
+        );
+    }
+
+    let given = [params.alpha, params.beta, params.gamma];
+    let given = &given[..model.num_params()];
+    for param in given.iter().flatten() {
+        assert!(
+            (0.0..=1.0).contains(param),
+            "smoothing parameters must be between 0 and 1"
+        );
+    }
+
+    // the free parameters are estimated together, starting from commonly used
+    // defaults
+    const DEFAULTS: [f64; 3] = [0.5, 0.1, 0.1];
+    let free: Vec<usize> = (0..given.len()).filter(|&i| given[i].is_none()).collect();
+    let with_free = |x: &[f64]| {
+        let mut all = [0.0; 3];
+        for (i, param) in given.iter().enumerate() {
+            all[i] = param.unwrap_or(DEFAULTS[i]);
+        }
+        for (&i, &val) in free.iter().zip(x) {
+            all[i] = val.clamp(0.0, 1.0);
+        }
+        all
+    };
+    let start: Vec<f64> = free.iter().map(|&i| DEFAULTS[i]).collect();
+    let best = minimize(
+        |x| {
+            let sse = run(model, data, with_free(x)).sse;
+            if sse.is_nan() {
+                f64::INFINITY
+            } else {
+                sse
+            }
+        },
+        &start,
+    );
+    let [alpha, beta, gamma] = with_free(&best);
+    let run = run(model, data, [alpha, beta, gamma]);
+
+    let m = run.seasonal.len();
+    let (beta, gamma, trend, seasonal) = match model {
+        Model::Simple => (None, None, None, None),
+        Model::Linear => (Some(beta), None, Some(run.trend), None),
+        Model::HoltWinters { .. } => {
+            let mut seasonal = run.seasonal;
+            seasonal.rotate_left(data.len() % m);
+            (Some(beta), Some(gamma), Some(run.trend), Some(seasonal))
+        }
+    };
+    Fit {
+        model,
+        alpha,
+        beta,
+        gamma,
+        level: run.level,
+        trend,
+        seasonal,
+        fitted: run.fitted,
+        sse: run.sse,
+    }
+}
+
+impl Fit {
+    // predictions for the `horizon` steps after the last observation
+    pub fn forecast(&self, horizon: usize) -> Vec<f64> {
+        let trend = self.trend.unwrap_or(0.0);
+        (1..=horizon)
+            .map(|h| {
+                let base = self.level + h as f64 * trend;
+                match (self.model, &self.seasonal) {
+                    (
+                        Model::HoltWinters {
+                            seasonality: Seasonality::Additive,
+                            ..
+                        },
+                        Some(seasonal),
+                    ) => base + seasonal[(h - 1) % seasonal.len()],
+                    (
+                        Model::HoltWinters {
+                            seasonality: Seasonality::Multiplicative,
+                            ..
+                        },
+                        Some(seasonal),
+                    ) => base * seasonal[(h - 1) % seasonal.len()],
+                    _ => base,
+                }
+            })
+            .collect()
+    }
+}
+
+struct Run {
+    level: f64,
+    trend: f64,
+    // indexed by time modulo the season length
+    seasonal: Vec<f64>,
+    fitted: Vec<f64>,
+    sse: f64,
+}
+
+fn run(model: Model, data: &[f64], [alpha, beta, gamma]: [f64; 3]) -> Run {
+    let (mut level, mut trend, mut seasonal) = initial_state(model, data);
+    let mut fitted = Vec::with_capacity(data.len());
+    let mut sse = 0.0;
+    for (t, &y) in data.iter().enumerate() {
+        let prediction = match model {
+            Model::Simple => level,
+            Model::Linear => level + trend,
+            Model::HoltWinters { seasonality, .. } => {
+                let season = seasonal[t % seasonal.len()];
+                match seasonality {
+                    Seasonality::Additive => level + trend + season,
+                    Seasonality::Multiplicative => (level + trend) * season,
+                }
+            }
+        };
+        fitted.push(prediction);
+        sse += (y - prediction) * (y - prediction);
+
+        match model {
+            Model::Simple => level = alpha * y + (1.0 - alpha) * level,
+            Model::Linear => {
+                let new_level = alpha * y + (1.0 - alpha) * (level + trend);
+                trend = beta * (new_level - level) + (1.0 - beta) * trend;
+                level = new_level;
+            }
+            Model::HoltWinters { seasonality, .. } => {
+                let idx = t % seasonal.len();
+                let season = seasonal[idx];
+                let new_level = match seasonality {
+                    Seasonality::Additive => alpha * (y - season),
+                    Seasonality::Multiplicative => alpha * (y / season),
+                } + (1.0 - alpha) * (level + trend);
+                trend = beta * (new_level - level) + (1.0 - beta) * trend;
+                seasonal[idx] = match seasonality {
+                    Seasonality::Additive => gamma * (y - new_level),
+                    Seasonality::Multiplicative => gamma * (y / new_level),
+                } + (1.0 - gamma) * season;
+                level = new_level;
+            }
+        }
+    }
+    Run {
+        level,
+        trend,
+        seasonal,
+        fitted,
+        sse,
+    }
+}
+
+// The state before the first observation. It is chosen so that the first
+// prediction continues the trend of the first values, and for seasonal models
+// from the averages of the first two seasons.
+fn initial_state(model: Model, data: &[f64]) -> (f64, f64, Vec<f64>) {
+    match model {
+        Model::Simple => (data[0], 0.0, vec![]),
+        Model::Linear => {
+            let trend = data[1] - data[0];
+            (data[0] - trend, trend, vec![])
+        }
+        Model::HoltWinters {
+            season_length: m,
+            seasonality,
+        } => {
+            let mean = |season: &[f64]| season.iter().sum::<f64>() / m as f64;
+            let first = mean(&data[..m]);
+            let second = mean(&data[m..2 * m]);
+            let trend = (second - first) / m as f64;
+            // the first mean is the level in the middle of the first season
+            let center = (m as f64 - 1.0) / 2.0;
+            let seasonal = data[..m]
+                .iter()
+                .enumerate()
+                .map(|(i, &y)| {
+                    let level = first + (i as f64 - center) * trend;
+                    match seasonality {
+                        Seasonality::Additive => y - level,
+                        Seasonality::Multiplicative => y / level,
+                    }
+                })
+                .collect();
+            (first - (center + 1.0) * trend, trend, seasonal)
+        }
+    }
+}
+
+// Nelder-Mead minimization of `f` starting from `start`
+fn minimize(f: impl Fn(&[f64]) -> f64, start: &[f64]) -> Vec<f64> {
+    const MAX_ITERATIONS: usize = 1000;
+    const TOLERANCE: f64 = 1e-10;
+
+    let n = start.len();
+    if n == 0 {
+        return vec![];
+    }
+
+    let mut simplex: Vec<(Vec<f64>, f64)> = (0..=n)
+        .map(|i| {
+            let mut x = start.to_vec();
+            if i > 0 {
+                x[i - 1] += if x[i - 1] > 0.5 { -0.1 } else { 0.1 };
+            }
+            let fx = f(&x);
+            (x, fx)
+        })
+        .collect();
+
+    for _ in 0..MAX_ITERATIONS {
+        simplex.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        let best = simplex[0].1;
+        let worst = simplex[n].1;
+        if (worst - best).abs() <= TOLERANCE * (1.0 + best.abs()) {
+            break;
+        }
+
+        let centroid: Vec<f64> = (0..n)
+            .map(|j| simplex[..n].iter().map(|(x, _)| x[j]).sum::<f64>() / n as f64)
+            .collect();
+        let towards = |scale: f64| -> Vec<f64> {
+            centroid
+                .iter()
+                .zip(&simplex[n].0)
+                .map(|(c, w)| c + scale * (w - c))
+                .collect()
+        };
+
+        let reflected = towards(-1.0);
+        let f_reflected = f(&reflected);
+        if f_reflected < best {
+            let expanded = towards(-2.0);
+            let f_expanded = f(&expanded);
+            simplex[n] = if f_expanded < f_reflected {
+                (expanded, f_expanded)
+            } else {
+                (reflected, f_reflected)
+            };
+        } else if f_reflected < simplex[n - 1].1 {
+            simplex[n] = (reflected, f_reflected);
+        } else {
+            let contracted = towards(0.5);
+            let f_contracted = f(&contracted);
+            if f_contracted < worst {
+                simplex[n] = (contracted, f_contracted);
+            } else {
+                let best = simplex[0].0.clone();
+                for (x, fx) in &mut simplex[1..] {
+                    for (xj, bj) in x.iter_mut().zip(&best) {
+                        *xj = bj + 0.5 * (*xj - bj);
+                    }
+                    *fx = f(x);
+                }
+            }
+        }
+    }
+
+    simplex
+        .into_iter()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .unwrap()
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len(), "{:?} != {:?}", a, b);
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn simple_with_alpha() {
+        let fit = fit(
+            Model::Simple,
+            &[1.0, 2.0, 3.0],
+            Params {
+                alpha: Some(0.5),
+                ..Default::default()
+            },
+        );
+        assert_close(&fit.fitted, &[1.0, 1.0, 1.5]);
+        assert_eq!(fit.level, 2.25);
+        assert_eq!(fit.sse, 0.0 + 1.0 + 2.25);
+        assert_close(&fit.forecast(2), &[2.25, 2.25]);
+        assert_eq!(fit.beta, None);
+        assert_eq!(fit.trend, None);
+    }
+
+    #[test]
+    fn simple_estimates_alpha() {
+        // a steadily rising series is best followed by ignoring history
+        let data: Vec<f64> = (0..20).map(|i| i as f64).collect();
+        let fit = fit(Model::Simple, &data, Params::default());
+        assert!(fit.alpha > 0.99, "{}", fit.alpha);
+
+        // noise around a constant is best smoothed heavily
+        let data: Vec<f64> = (0..40)
+            .map(|i| if i % 2 == 0 { 9.0 } else { 11.0 })
+            .collect();
+        let fit = super::fit(Model::Simple, &data, Params::default());
+        assert!(fit.alpha < 0.2, "{}", fit.alpha);
+    }
+
+    #[test]
+    fn linear_follows_line() {
+        let data: Vec<f64> = (0..10).map(|i| 2.0 * i as f64 + 1.0).collect();
+        let fit = fit(Model::Linear, &data, Params::default());
+        assert_close(&fit.fitted, &data);
+        assert_close(&fit.forecast(3), &[21.0, 23.0, 25.0]);
+        assert!((fit.trend.unwrap() - 2.0).abs() < 1e-9);
+        assert!(fit.sse < 1e-18);
+    }
+
+    #[test]
+    fn additive_repeats_season() {
+        let season = [1.0, 3.0, 2.0, 4.0];
+        let data: Vec<f64> = season.iter().cycle().take(14).cloned().collect();
+        let model = Model::HoltWinters {
+            season_length: 4,
+            seasonality: Seasonality::Additive,
+        };
+        let fit = fit(model, &data, Params::default());
+        assert_close(&fit.fitted, &data);
+        assert_close(&fit.forecast(4), &[2.0, 4.0, 1.0, 3.0]);
+        assert_close(fit.seasonal.as_ref().unwrap(), &[-0.5, 1.5, -1.5, 0.5]);
+    }
+
+    #[test]
+    fn additive_with_trend() {
+        let season = [1.0, 3.0, 2.0, 4.0];
+        let data: Vec<f64> = (0..12).map(|i| season[i % 4] + 0.5 * i as f64).collect();
+        let model = Model::HoltWinters {
+            season_length: 4,
+            seasonality: Seasonality::Additive,
+        };
+        let fit = fit(model, &data, Params::default());
+        assert_close(&fit.fitted, &data);
+        assert_close(&fit.forecast(2), &[1.0 + 6.0, 3.0 + 6.5]);
+    }
+
+    #[test]
+    fn multiplicative_scales_season() {
+        let season = [0.5, 1.5, 1.0, 1.0];
+        let data: Vec<f64> = (0..12).map(|i| season[i % 4] * 10.0).collect();
+        let model = Model::HoltWinters {
+            season_length: 4,
+            seasonality: Seasonality::Multiplicative,
+        };
+        let fit = fit(model, &data, Params::default());
+        assert_close(&fit.fitted, &data);
+        assert_close(&fit.forecast(4), &[5.0, 15.0, 10.0, 10.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough values")]
+    fn seasonal_needs_two_seasons() {
+        let model = Model::HoltWinters {
+            season_length: 4,
+            seasonality: Seasonality::Additive,
+        };
+        fit(model, &[1.0; 7], Params::default());
+    }
+
+    #[test]
+    fn minimize_quadratic() {
+        let min = minimize(|x| (x[0] - 0.3).powi(2) + (x[1] - 0.7).powi(2), &[0.5, 0.5]);
+        assert!((min[0] - 0.3).abs() < 1e-4 && (min[1] - 0.7).abs() < 1e-4);
+    }
+}
@@ -114,8 +114,8 @@ impl SketchHashMap {
     }
 
     // Increment the count at a key, creating the entry if needed.
-    fn increment(&mut self, key: SketchHashKey) {
-        self.entry(key).count += 1;
+    fn increment(&mut self, key: SketchHashKey, count: u64) {
+        self.entry(key).count += count;
     }
 
     fn iter(&self) -> SketchHashIterator {
@@ -275,14 +275,24 @@ impl UDDSketch {
 
 impl UDDSketch {
     pub fn add_value(&mut self, value: f64) {
-        self.buckets.increment(self.key(value));
+        self.add_weighted_value(value, 1)
+    }
+
+    // Add a value as if it had been added `weight` times, e.g. for the
+    // buckets of a pre-aggregated histogram.
+    pub fn add_weighted_value(&mut self, value: f64, weight: u64) {
+        if weight == 0 {
+            return;
+        }
+
+        self.buckets.increment(self.key(value), weight);
 
         while self.buckets.len() > self.max_buckets as usize {
             self.compact_buckets();
         }
 
-        self.num_values += 1;
-        self.values_sum += value;
+        self.num_values += weight;
+        self.values_sum += value * weight as f64;
     }
 
     pub fn merge_sketch(&mut self, other: &UDDSketch) {
@@ -380,6 +390,26 @@ impl UDDSketch {
     pub fn estimate_quantile_at_value(&self, value: f64) -> f64 {
         estimate_quantile_at_value(value, self.gamma, self.num_values, self.buckets.iter())
     }
+
+    // The value the values in a bucket are estimated to have.
+    pub fn bucket_value(&self, bucket: SketchHashKey) -> f64 {
+        bucket_to_value(self.alpha, self.gamma, bucket)
+    }
+
+    // The bounds of the values in a bucket, the bound closer to zero is
+    // exclusive.
+    pub fn bucket_bounds(&self, bucket: SketchHashKey) -> (f64, f64) {
+        match bucket {
+            SketchHashKey::Zero => (0.0, 0.0),
+            SketchHashKey::Positive(i) => {
+                (self.gamma.powf(i as f64 - 1.0), self.gamma.powf(i as f64))
+            }
+            SketchHashKey::Negative(i) => {
+                (-self.gamma.powf(i as f64), -self.gamma.powf(i as f64 - 1.0))
+            }
+            SketchHashKey::Invalid => panic!("Unable to convert invalid bucket id to value"),
+        }
+    }
 }
 
 pub fn estimate_quantile(
@@ -471,6 +501,212 @@ pub fn gamma(alpha: f64) -> f64 {
     (1.0 + alpha) / (1.0 - alpha)
 }
 
+// OpenTelemetry exponential histograms have buckets (base^i, base^(i+1)] with
+// base = 2^(2^-scale), which are the buckets of a sketch with gamma = base:
+// bucket i has the key i + 1. Sketches built from them all start at the
+// largest scale and are compacted once per scale below it, so that histograms
+// that were downscaled differently can still be merged.
+pub const MAX_EXPONENTIAL_SCALE: i32 = 20;
+// below this the relative error of a bucket rounds to 1
+pub const MIN_EXPONENTIAL_SCALE: i32 = -5;
+// the smallest scale in the OpenTelemetry data model
+const MIN_EXPORTED_SCALE: i32 = -10;
+
+/// The buckets of an OpenTelemetry exponential histogram. The counts of the
+/// positive and negative buckets start at the bucket of their offset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExponentialHistogram {
+    pub scale: i32,
+    pub zero_count: u64,
+    pub positive_offset: i32,
+    pub positive_counts: Vec<u64>,
+    pub negative_offset: i32,
+    pub negative_counts: Vec<u64>,
+}
+
+pub fn exponential_base(scale: i32) -> f64 {
+    2.0_f64.powf(2.0_f64.powi(-scale))
+}
+
+impl UDDSketch {
+    /// Builds a sketch from an exponential histogram, compacting it if it has
+    /// more than `max_buckets` buckets. When `sum` isn't known it's estimated
+    /// from the buckets.
+    pub fn from_exponential_histogram(
+        max_buckets: u64,
+        histogram: &ExponentialHistogram,
+        sum: Option<f64>,
+    ) -> Self {
+        let scale = histogram.scale;
+        assert!((MIN_EXPONENTIAL_SCALE..=MAX_EXPONENTIAL_SCALE).contains(&scale));
+        let gamma = exponential_base(scale);
+        let alpha = (gamma - 1.0) / (gamma + 1.0);
+        let mut sketch = UDDSketch {
+            buckets: SketchHashMap::new(),
+            alpha,
+            gamma,
+            compactions: (MAX_EXPONENTIAL_SCALE - scale) as u32,
+            max_buckets,
+            num_values: 0,
+            values_sum: 0.0,
+        };
+
+        let buckets = |offset: i32, counts: &[u64], key: fn(i64) -> SketchHashKey| {
+            counts
+                .iter()
+                .enumerate()
+                .map(move |(i, &count)| (key(offset as i64 + i as i64 + 1), count))
+                .collect::<Vec<_>>()
+        };
+        let negative = buckets(
+            histogram.negative_offset,
+            &histogram.negative_counts,
+            SketchHashKey::Negative,
+        );
+        let positive = buckets(
+            histogram.positive_offset,
+            &histogram.positive_counts,
+            SketchHashKey::Positive,
+        );
+        let zero = Some((SketchHashKey::Zero, histogram.zero_count));
+        for (key, count) in negative.into_iter().chain(zero).chain(positive) {
+            // the value stays in the same bucket as the sketch gets compacted
+            sketch.add_weighted_value(bucket_to_value(alpha, gamma, key), count);
+        }
+
+        if let Some(sum) = sum {
+            sketch.values_sum = sum;
+        }
+        sketch
+    }
+
+    /// The buckets of the sketch as an exponential histogram with the largest
+    /// scale whose buckets each hold whole buckets of the sketch. That is the
+    /// sketch's own scale if it was built from an exponential histogram. The
+    /// scale is reduced further until there are at most as many positive, and
+    /// as many negative, buckets as the sketch can have.
+    pub fn to_exponential_histogram(&self) -> ExponentialHistogram {
+        let scale = (-self.gamma.log2().log2() + 1e-9).floor() as i32;
+        let mut scale = scale.min(MAX_EXPONENTIAL_SCALE);
+
+        let mut zero_count = 0;
+        let mut positive = vec![];
+        let mut negative = vec![];
+        for (key, count) in self.bucket_iter() {
+            let value = bucket_to_value(self.alpha, self.gamma, key);
+            let index = || (value.abs().log2() * 2.0_f64.powi(scale)).ceil() as i64 - 1;
+            match key {
+                SketchHashKey::Zero => zero_count += count,
+                SketchHashKey::Positive(_) => positive.push((index(), count)),
+                SketchHashKey::Negative(_) => negative.push((index(), count)),
+                SketchHashKey::Invalid => unreachable!(),
+            }
+        }
+
+        let span = |indexes: &[(i64, u64)]| {
+            let min = indexes.iter().map(|&(i, _)| i).min();
+            let max = indexes.iter().map(|&(i, _)| i).max();
+            match (min, max) {
+                (Some(min), Some(max)) => (max - min + 1) as u64,
+                _ => 0,
+            }
+        };
+        let max_span = self.max_buckets.max(1);
+        while scale > MIN_EXPORTED_SCALE
+            && (span(&positive) > max_span || span(&negative) > max_span)
+        {
+            scale -= 1;
+            for (index, _) in positive.iter_mut().chain(negative.iter_mut()) {
+                *index >>= 1;
+            }
+        }
+
+        let dense = |indexes: &[(i64, u64)]| {
+            let offset = indexes.iter().map(|&(i, _)| i).min().unwrap_or(0);
+            let mut counts = vec![0; span(indexes) as usize];
+            for &(index, count) in indexes {
+                counts[(index - offset) as usize] += count;
+            }
+            (offset as i32, counts)
+        };
+        let (positive_offset, positive_counts) = dense(&positive);
+        let (negative_offset, negative_counts) = dense(&negative);
+        ExponentialHistogram {
+            scale,
+            zero_count,
+            positive_offset,
+            positive_counts,
+            negative_offset,
+            negative_counts,
+        }
+    }
+
+    /// Builds a sketch from a Prometheus-style histogram: the upper bounds of
+    /// its buckets, increasing and possibly ending with infinity, and the
+    /// number of values at most each of them. As in `histogram_quantile()`
+    /// the values are assumed to be spread evenly within each bucket, the
+    /// first bucket to start at 0 if its bound is positive, and the values
+    /// above the largest finite bound to be at that bound. When `sum` isn't
+    /// known it's estimated from the buckets.
+    pub fn from_cumulative_buckets(
+        max_buckets: u64,
+        max_error: f64,
+        upper_bounds: &[f64],
+        cumulative_counts: &[u64],
+        sum: Option<f64>,
+    ) -> Self {
+        assert_eq!(upper_bounds.len(), cumulative_counts.len());
+        let mut sketch = UDDSketch::new(max_buckets, max_error);
+
+        let mut lower = None;
+        let mut below = 0;
+        for (i, (&upper, &cumulative)) in upper_bounds.iter().zip(cumulative_counts).enumerate() {
+            assert!(upper > f64::NEG_INFINITY);
+            assert!(upper.is_finite() || i == upper_bounds.len() - 1);
+            assert!(lower.is_none_or(|lower| lower < upper));
+            assert!(cumulative >= below);
+            let count = cumulative - below;
+            below = cumulative;
+            match lower {
+                _ if count == 0 => (),
+                Some(lower) if upper == f64::INFINITY => sketch.add_weighted_value(lower, count),
+                Some(lower) => sketch.add_evenly(lower, upper, count),
+                None if upper == f64::INFINITY => panic!("no finite bucket bound"),
+                None if upper <= 0.0 => sketch.add_weighted_value(upper, count),
+                None => sketch.add_evenly(0.0, upper, count),
+            }
+            lower = Some(upper);
+        }
+
+        if let Some(sum) = sum {
+            sketch.values_sum = sum;
+        }
+        sketch
+    }
+
+    // Add `count` values evenly spaced in (lower, upper]. Rather than adding
+    // them one by one, each bucket gets all of the values that fall in it.
+    fn add_evenly(&mut self, lower: f64, upper: f64, count: u64) {
+        let width = upper - lower;
+        // the values are at the middles of `count` equal parts of the range
+        let value = |i: u64| lower + width * (i as f64 - 0.5) / count as f64;
+        let position = |value: f64| ((value - lower) / width * count as f64 + 0.5).floor();
+        let mut i = 1;
+        while i <= count {
+            let key = self.key(value(i));
+            let bucket_upper = match key {
+                SketchHashKey::Positive(k) => self.gamma.powf(k as f64),
+                SketchHashKey::Zero => 0.0,
+                SketchHashKey::Negative(k) => -self.gamma.powf(k as f64 - 1.0),
+                SketchHashKey::Invalid => unreachable!(),
+            };
+            let last = position(bucket_upper).min(count as f64).max(i as f64) as u64;
+            self.add_weighted_value(bucket_to_value(self.alpha, self.gamma, key), last - i + 1);
+            i = last + 1;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rand::{Rng, SeedableRng};
@@ -489,6 +725,195 @@ mod tests {
         assert_eq!(sketch.max_error(), 0.1);
     }
 
+    #[test]
+    fn add_weighted_values() {
+        let mut weighted = UDDSketch::new(20, 0.1);
+        let mut repeated = UDDSketch::new(20, 0.1);
+        for (i, value) in [0.5, -2.0, 0.0, 3.0, 1000.0].iter().enumerate() {
+            let weight = i as u64 * 7 + 1;
+            weighted.add_weighted_value(*value, weight);
+            for _ in 0..weight {
+                repeated.add_value(*value);
+            }
+        }
+        weighted.add_weighted_value(12.0, 0);
+
+        assert_eq!(weighted.count(), 75);
+        assert_eq!(weighted.sum(), repeated.sum());
+        assert_eq!(weighted.mean(), repeated.mean());
+        assert_eq!(
+            weighted.bucket_iter().collect::<Vec<_>>(),
+            repeated.bucket_iter().collect::<Vec<_>>()
+        );
+        for quantile in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_eq!(
+                weighted.estimate_quantile(quantile),
+                repeated.estimate_quantile(quantile)
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_values_compact() {
+        let mut sketch = UDDSketch::new(20, 0.1);
+        for i in 0..30 {
+            sketch.add_weighted_value(1000.0 * 1.23_f64.powi(i), 1_000_000);
+        }
+
+        assert_eq!(sketch.count(), 30_000_000);
+        assert_eq!(sketch.max_error(), 0.2 / 1.01);
+        assert_eq!(
+            sketch.bucket_iter().map(|(_, count)| count).sum::<u64>(),
+            30_000_000
+        );
+    }
+
+    #[test]
+    fn exponential_histogram_round_trip() {
+        let histogram = ExponentialHistogram {
+            scale: 3,
+            zero_count: 4,
+            positive_offset: -2,
+            positive_counts: vec![1, 0, 5, 2],
+            negative_offset: 7,
+            negative_counts: vec![3],
+        };
+        let sketch = UDDSketch::from_exponential_histogram(20, &histogram, Some(-12.5));
+        assert_eq!(sketch.count(), 15);
+        assert_eq!(sketch.sum(), -12.5);
+        assert_eq!(sketch.times_compacted(), 17);
+        assert_eq!(sketch.to_exponential_histogram(), histogram);
+
+        // all the values of a bucket are within it
+        let base = exponential_base(3);
+        let median = sketch.estimate_quantile(0.5);
+        assert!(median > base.powi(-2) && median <= base.powi(-1));
+        let min = sketch.estimate_quantile(0.0);
+        assert!(min < -base.powi(7) && min >= -base.powi(8));
+
+        // the sketch survives being rebuilt from its data, as the extension does
+        let rebuilt = UDDSketch::new_from_data(
+            sketch.max_allowed_buckets(),
+            sketch.max_error(),
+            sketch.times_compacted() as u64,
+            sketch.count(),
+            sketch.sum(),
+            sketch.bucket_iter().map(|(key, _)| key),
+            sketch.bucket_iter().map(|(_, count)| count),
+        );
+        assert_eq!(rebuilt.to_exponential_histogram(), histogram);
+    }
+
+    #[test]
+    fn exponential_histograms_compact_and_merge() {
+        let histogram = ExponentialHistogram {
+            scale: 4,
+            positive_offset: 10,
+            positive_counts: vec![1; 10],
+            ..Default::default()
+        };
+        let mut sketch = UDDSketch::from_exponential_histogram(4, &histogram, None);
+        let exported = sketch.to_exponential_histogram();
+        assert_eq!(exported.scale, 2);
+        assert_eq!(exported.positive_offset, 2);
+        assert_eq!(exported.positive_counts, vec![2, 4, 4]);
+
+        // histograms at different scales can be merged
+        let other = ExponentialHistogram {
+            scale: 1,
+            positive_offset: 0,
+            positive_counts: vec![6],
+            ..Default::default()
+        };
+        sketch.merge_sketch(&UDDSketch::from_exponential_histogram(4, &other, None));
+        let merged = sketch.to_exponential_histogram();
+        assert_eq!(merged.scale, 1);
+        assert_eq!(merged.positive_offset, 0);
+        assert_eq!(merged.positive_counts, vec![6, 6, 4]);
+    }
+
+    #[test]
+    fn sketch_as_exponential_histogram() {
+        // each bucket of the sketch lands in one bucket of the histogram
+        let mut sketch = UDDSketch::new(200, 0.01);
+        for i in 1..=1000 {
+            sketch.add_value(i as f64 / 10.0);
+            sketch.add_value(-(i as f64));
+        }
+        let histogram = sketch.to_exponential_histogram();
+        assert_eq!(histogram.scale, 3);
+        assert!(exponential_base(3) >= 1.0 + 2.0 * sketch.max_error());
+        let base = exponential_base(histogram.scale);
+        let total: u64 = histogram.positive_counts.iter().sum::<u64>()
+            + histogram.negative_counts.iter().sum::<u64>();
+        assert_eq!(total, 2000);
+        let below_one: u64 = histogram
+            .positive_counts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| base.powi(histogram.positive_offset + *i as i32 + 1) <= 1.0)
+            .map(|(_, count)| count)
+            .sum();
+        assert_eq!(below_one, 10);
+    }
+
+    #[test]
+    fn cumulative_buckets() {
+        let bounds = [0.1, 0.5, 1.0, f64::INFINITY];
+        let sketch =
+            UDDSketch::from_cumulative_buckets(100, 0.01, &bounds, &[10, 30, 40, 45], None);
+        assert_eq!(sketch.count(), 45);
+        // the values of a bucket are spread over it
+        let median = sketch.estimate_quantile(0.5);
+        assert!((median - 0.35).abs() <= 0.35 * 0.01);
+        let max = sketch.estimate_quantile(1.0);
+        assert!((max - 1.0).abs() <= 0.01);
+        let min = sketch.estimate_quantile(0.0);
+        assert!((min - 0.005).abs() <= 0.005 * 0.01);
+        assert!((sketch.sum() - (0.5 + 6.0 + 7.5 + 5.0)).abs() < 0.2);
+
+        let sketch = UDDSketch::from_cumulative_buckets(
+            100,
+            0.01,
+            &[-5.0, 5.0],
+            &[1, 1_000_000_000_001],
+            Some(3.0),
+        );
+        assert_eq!(sketch.count(), 1_000_000_000_001);
+        assert_eq!(sketch.sum(), 3.0);
+        let min = sketch.estimate_quantile(0.0);
+        assert!((min + 5.0).abs() <= 5.0 * sketch.max_error());
+        let median = sketch.estimate_quantile(0.5);
+        assert!(median.abs() < 0.1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn cumulative_buckets_must_be_increasing() {
+        UDDSketch::from_cumulative_buckets(100, 0.01, &[1.0, 0.5], &[1, 2], None);
+    }
+
+    #[test]
+    fn bucket_bounds_hold_bucket_values() {
+        let mut sketch = UDDSketch::new(20, 0.1);
+        for value in [-30.0, -0.2, 0.0, 0.5, 1.0, 7.0] {
+            sketch.add_value(value);
+        }
+        for (key, _) in sketch.bucket_iter() {
+            let (lower, upper) = sketch.bucket_bounds(key);
+            let value = sketch.bucket_value(key);
+            assert!(lower <= value && value <= upper);
+            if key == SketchHashKey::Zero {
+                continue;
+            }
+            // the value is within the error of either bound
+            let (near, far) = (lower.abs().min(upper.abs()), lower.abs().max(upper.abs()));
+            assert!(((value.abs() - near) / near - sketch.max_error()).abs() < 1e-9);
+            assert!(((far - value.abs()) / far - sketch.max_error()).abs() < 1e-9);
+        }
+        assert_eq!(sketch.bucket_bounds(SketchHashKey::Zero), (0.0, 0.0));
+    }
+
     #[test]
     fn exceed_buckets() {
         let mut sketch = UDDSketch::new(20, 0.1);
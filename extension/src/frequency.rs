@@ -658,6 +658,19 @@ pub fn freq_agg_text_trans(
     freq_agg_trans(state, freq, value, fcinfo)
 }
 
+// Builds the `freq_agg` of a sequence of `DOUBLE PRECISION` values, as used by
+// the timevector pipelines.
+pub(crate) fn freq_agg_from_floats(
+    freq: f64,
+    values: impl IntoIterator<Item = f64>,
+) -> toolkit_experimental::SpaceSavingAggregate<'static> {
+    let mut trans = SpaceSavingTransState::freq_agg_from_type_id(freq, pg_sys::FLOAT8OID, None);
+    for value in values {
+        trans.add((value.into_datum().unwrap(), pg_sys::FLOAT8OID).into());
+    }
+    SpaceSavingAggregate::from(&trans)
+}
+
 pub fn space_saving_trans<F>(
     state: Option<Inner<SpaceSavingTransState>>,
     value: Option<AnyElement>,
@@ -39,7 +39,7 @@ pub struct FlatSummary {
 }
 
 #[pg_schema]
-mod toolkit_experimental {
+pub mod toolkit_experimental {
     use super::*;
 
     pg_type! {
@@ -36,6 +36,7 @@ mod palloc;
 mod pg_any_element;
 mod raw;
 mod serialization;
+mod sketch_utils;
 mod stabilization_info;
 mod stabilization_tests;
 mod type_builder;
@@ -24,6 +24,9 @@ mod min_by_float;
 mod min_by_int;
 mod min_by_time;
 
+pub(crate) use max_float::{max_n_float_from_values, toolkit_experimental::MaxFloats};
+pub(crate) use min_float::{min_n_float_from_values, toolkit_experimental::MinFloats};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NMostTransState<T: Ord> {
     capacity: usize,
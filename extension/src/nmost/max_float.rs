@@ -110,6 +110,22 @@ pub fn max_n_float_final(state: Internal) -> toolkit_experimental::MaxFloats<'st
     unsafe { &mut *state.to_inner::<MaxFloatTransType>().unwrap() }.into()
 }
 
+// Builds the `max_n` of a sequence of values, as used by the timevector
+// pipelines. Returns `None` if there are no values.
+pub(crate) fn max_n_float_from_values(
+    capacity: usize,
+    values: impl IntoIterator<Item = f64>,
+) -> Option<toolkit_experimental::MaxFloats<'static>> {
+    let mut values = values
+        .into_iter()
+        .map(|value| Reverse(NotNan::new(value).unwrap()));
+    let mut state = MaxFloatTransType::new(capacity, values.next()?);
+    for value in values {
+        state.new_entry(value);
+    }
+    Some((&mut state).into())
+}
+
 #[pg_extern(
     schema = "toolkit_experimental",
     name = "into_array",
@@ -109,6 +109,20 @@ pub fn min_n_float_final(state: Internal) -> toolkit_experimental::MinFloats<'st
     unsafe { &mut *state.to_inner::<MinFloatTransType>().unwrap() }.into()
 }
 
+// Builds the `min_n` of a sequence of values, as used by the timevector
+// pipelines. Returns `None` if there are no values.
+pub(crate) fn min_n_float_from_values(
+    capacity: usize,
+    values: impl IntoIterator<Item = f64>,
+) -> Option<toolkit_experimental::MinFloats<'static>> {
+    let mut values = values.into_iter().map(|value| NotNan::new(value).unwrap());
+    let mut state = MinFloatTransType::new(capacity, values.next()?);
+    for value in values {
+        state.new_entry(value);
+    }
+    Some((&mut state).into())
+}
+
 #[pg_extern(
     schema = "toolkit_experimental",
     name = "into_array",
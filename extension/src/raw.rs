@@ -16,6 +16,7 @@ extension_sql!(
         Type(bytea),
         Type(text),
         Type(TimestampTz),
+        Type(Timestamp),
         Type(AnyElement),
         Type(tstzrange),
         Type(Interval),
@@ -110,6 +111,22 @@ impl From<pg_sys::TimestampTz> for TimestampTz {
     }
 }
 
+pub struct Timestamp(pub pg_sys::Datum);
+
+raw_type!(Timestamp, pg_sys::TIMESTAMPOID, pg_sys::TIMESTAMPARRAYOID);
+
+impl From<Timestamp> for pg_sys::Timestamp {
+    fn from(ts: Timestamp) -> Self {
+        ts.0.value() as _
+    }
+}
+
+impl From<pg_sys::Timestamp> for Timestamp {
+    fn from(ts: pg_sys::Timestamp) -> Self {
+        Self(pg_sys::Datum::from(ts))
+    }
+}
+
 pub struct AnyElement(pub pg_sys::Datum);
 
 raw_type!(AnyElement, pg_sys::ANYELEMENTOID, pg_sys::ANYARRAYOID);
@@ -26,44 +26,56 @@ extern "C" {
     pub fn format_procedure_qualified(procedure_oid: pg_sys::Oid) -> *const c_char;
 }
 
-impl Serialize for PgProcId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
+impl PgProcId {
+    /// Look up a function by its signature, e.g. `date_trunc(text, interval)`.
+    /// Errors if no function matches the signature exactly.
+    pub fn from_signature(signature: &str) -> Self {
+        // FIXME pgx wraps all functions in rust wrappers, which makes them
+        //       uncallable with DirectFunctionCall(). Is there a way to
+        //       export both?
+        extern "C" {
+            fn regprocedurein(fcinfo: pg_sys::FunctionCallInfo) -> Datum;
+        }
+        let signature = CString::new(signature).unwrap();
+        let oid = unsafe {
+            pg_sys::DirectFunctionCall1Coll(
+                Some(regprocedurein),
+                pg_sys::InvalidOid,
+                pg_sys::Datum::from(signature.as_ptr()),
+            )
+        };
+
+        Self(oid.value() as _)
+    }
+
+    /// The function's signature as `namespace.name(args)`
+    pub fn qualified_name(&self) -> String {
         unsafe {
             let qualified_name = format_procedure_qualified(self.0);
             let len = CStr::from_ptr(qualified_name).to_bytes().len();
             let qualified_name =
                 pg_sys::pg_server_to_any(qualified_name, len as _, pg_sys::pg_enc_PG_UTF8 as _);
             let qualified_name = CStr::from_ptr(qualified_name);
-            let qualified_name = qualified_name.to_str().unwrap();
-            qualified_name.serialize(serializer)
+            qualified_name.to_str().unwrap().to_string()
         }
     }
 }
 
+impl Serialize for PgProcId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.qualified_name().serialize(serializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for PgProcId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        // FIXME pgx wraps all functions in rust wrappers, which makes them
-        //       uncallable with DirectFunctionCall(). Is there a way to
-        //       export both?
-        extern "C" {
-            fn regprocedurein(fcinfo: pg_sys::FunctionCallInfo) -> Datum;
-        }
         let qualified_name = <&str>::deserialize(deserializer)?;
-        let qualified_name = CString::new(qualified_name).unwrap();
-        let oid = unsafe {
-            pg_sys::DirectFunctionCall1Coll(
-                Some(regprocedurein),
-                pg_sys::InvalidOid,
-                pg_sys::Datum::from(qualified_name.as_ptr()),
-            )
-        };
-
-        Ok(Self(oid.value() as _))
+        Ok(Self::from_signature(qualified_name))
     }
 }
@@ -0,0 +1,120 @@
+// Helpers shared by the percentile sketches (uddsketch and tdigest) for
+// extracting distributions from them.
+
+// Count weighted values into the buckets delimited by `boundaries`. There is
+// one more bucket than there are boundaries: the first bucket holds everything
+// up to and including the first boundary, the last everything above the last
+// one. Each bucket is returned as `(lower, upper, count)`.
+pub fn histogram(
+    boundaries: &[f64],
+    values: impl Iterator<Item = (f64, u64)>,
+) -> Vec<(f64, f64, i64)> {
+    if boundaries.iter().any(|b| b.is_nan()) {
+        panic!("histogram boundaries cannot be NaN")
+    }
+    for window in boundaries.windows(2) {
+        if window[0] >= window[1] {
+            panic!(
+                "histogram boundaries must be strictly increasing, got {:?}",
+                boundaries
+            )
+        }
+    }
+
+    let mut counts = vec![0i64; boundaries.len() + 1];
+    for (value, count) in values {
+        let bucket = boundaries.partition_point(|b| *b < value);
+        counts[bucket] += count as i64;
+    }
+
+    let lowers = std::iter::once(f64::NEG_INFINITY).chain(boundaries.iter().copied());
+    let uppers = boundaries
+        .iter()
+        .copied()
+        .chain(std::iter::once(f64::INFINITY));
+    lowers
+        .zip(uppers)
+        .zip(counts)
+        .map(|((lower, upper), count)| (lower, upper, count))
+        .collect()
+}
+
+// The fractions at which to sample a cumulative distribution function with
+// `n_points` evenly spaced points, including both 0 and 1.
+pub fn cdf_fractions(n_points: i32) -> impl Iterator<Item = f64> {
+    if n_points < 2 {
+        panic!("cdf needs at least 2 points, got {}", n_points)
+    }
+    let last = (n_points - 1) as f64;
+    (0..n_points).map(move |i| i as f64 / last)
+}
+
+// The sum of the values ranked between the `low_q` and `high_q` quantiles,
+// given the values of a sketch in increasing order with their counts. Values
+// straddling either quantile contribute the part of their count that lies
+// between them.
+fn sum_between(
+    values: impl Iterator<Item = (f64, u64)>,
+    total: f64,
+    low_q: f64,
+    high_q: f64,
+) -> f64 {
+    let (low_rank, high_rank) = (low_q * total, high_q * total);
+    let mut seen = 0.0;
+    let mut sum = 0.0;
+    for (value, count) in values {
+        let (start, end) = (seen, seen + count as f64);
+        seen = end;
+        let overlap = end.min(high_rank) - start.max(low_rank);
+        if overlap > 0.0 {
+            sum += overlap * value;
+        }
+    }
+    sum
+}
+
+fn check_quantiles(low_q: f64, high_q: f64) {
+    // written so that NaNs fail the check
+    if !(0.0 <= low_q && low_q < high_q && high_q <= 1.0) {
+        panic!(
+            "quantiles must satisfy 0 <= low_q < high_q <= 1, got {} and {}",
+            low_q, high_q
+        )
+    }
+}
+
+// The mean of the values between the `low_q` and `high_q` quantiles.
+pub fn trimmed_mean(
+    values: impl Iterator<Item = (f64, u64)>,
+    total: u64,
+    low_q: f64,
+    high_q: f64,
+) -> Option<f64> {
+    check_quantiles(low_q, high_q);
+    if total == 0 {
+        return None;
+    }
+    let total = total as f64;
+    let sum = sum_between(values, total, low_q, high_q);
+    Some(sum / ((high_q - low_q) * total))
+}
+
+// The mean of the values after replacing those below the `low_q` quantile and
+// those above the `high_q` quantile by the value `quantile` estimates at them.
+pub fn winsorized_mean(
+    values: impl Iterator<Item = (f64, u64)>,
+    total: u64,
+    low_q: f64,
+    high_q: f64,
+    quantile: impl Fn(f64) -> f64,
+) -> Option<f64> {
+    check_quantiles(low_q, high_q);
+    if total == 0 {
+        return None;
+    }
+    let total = total as f64;
+    let sum = sum_between(values, total, low_q, high_q)
+        + low_q * total * quantile(low_q)
+        + (1.0 - high_q) * total * quantile(high_q);
+    Some(sum / total)
+}
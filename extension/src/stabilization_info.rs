@@ -11,6 +11,128 @@
 
 crate::functions_stabilized_at! {
     STABLE_FUNCTIONS
+    "1.13.0" => {
+        accessorapproxpercentile_recv(internal),
+        accessorapproxpercentile_send(accessorapproxpercentile),
+        accessorapproxpercentilerank_recv(internal),
+        accessorapproxpercentilerank_send(accessorapproxpercentilerank),
+        accessoraverage_recv(internal),
+        accessoraverage_send(accessoraverage),
+        accessoraveragex_recv(internal),
+        accessoraveragex_send(accessoraveragex),
+        accessoraveragey_recv(internal),
+        accessoraveragey_send(accessoraveragey),
+        accessorcorr_recv(internal),
+        accessorcorr_send(accessorcorr),
+        accessorcounterzerotime_recv(internal),
+        accessorcounterzerotime_send(accessorcounterzerotime),
+        accessorcovar_recv(internal),
+        accessorcovar_send(accessorcovar),
+        accessordelta_recv(internal),
+        accessordelta_send(accessordelta),
+        accessordeterminationcoeff_recv(internal),
+        accessordeterminationcoeff_send(accessordeterminationcoeff),
+        accessordistinctcount_recv(internal),
+        accessordistinctcount_send(accessordistinctcount),
+        accessorerror_recv(internal),
+        accessorerror_send(accessorerror),
+        accessorextrapolateddelta_recv(internal),
+        accessorextrapolateddelta_send(accessorextrapolateddelta),
+        accessorextrapolatedrate_recv(internal),
+        accessorextrapolatedrate_send(accessorextrapolatedrate),
+        accessorfirsttime_recv(internal),
+        accessorfirsttime_send(accessorfirsttime),
+        accessorfirstval_recv(internal),
+        accessorfirstval_send(accessorfirstval),
+        accessorideltaleft_recv(internal),
+        accessorideltaleft_send(accessorideltaleft),
+        accessorideltaright_recv(internal),
+        accessorideltaright_send(accessorideltaright),
+        accessorintercept_recv(internal),
+        accessorintercept_send(accessorintercept),
+        accessorirateleft_recv(internal),
+        accessorirateleft_send(accessorirateleft),
+        accessorirateright_recv(internal),
+        accessorirateright_send(accessorirateright),
+        accessorkurtosis_recv(internal),
+        accessorkurtosis_send(accessorkurtosis),
+        accessorkurtosisx_recv(internal),
+        accessorkurtosisx_send(accessorkurtosisx),
+        accessorkurtosisy_recv(internal),
+        accessorkurtosisy_send(accessorkurtosisy),
+        accessorlasttime_recv(internal),
+        accessorlasttime_send(accessorlasttime),
+        accessorlastval_recv(internal),
+        accessorlastval_send(accessorlastval),
+        accessormaxval_recv(internal),
+        accessormaxval_send(accessormaxval),
+        accessormean_recv(internal),
+        accessormean_send(accessormean),
+        accessorminval_recv(internal),
+        accessorminval_send(accessorminval),
+        accessornumchanges_recv(internal),
+        accessornumchanges_send(accessornumchanges),
+        accessornumelements_recv(internal),
+        accessornumelements_send(accessornumelements),
+        accessornumresets_recv(internal),
+        accessornumresets_send(accessornumresets),
+        accessornumvals_recv(internal),
+        accessornumvals_send(accessornumvals),
+        accessorrate_recv(internal),
+        accessorrate_send(accessorrate),
+        accessorskewness_recv(internal),
+        accessorskewness_send(accessorskewness),
+        accessorskewnessx_recv(internal),
+        accessorskewnessx_send(accessorskewnessx),
+        accessorskewnessy_recv(internal),
+        accessorskewnessy_send(accessorskewnessy),
+        accessorslope_recv(internal),
+        accessorslope_send(accessorslope),
+        accessorstddev_recv(internal),
+        accessorstddev_send(accessorstddev),
+        accessorstddevx_recv(internal),
+        accessorstddevx_send(accessorstddevx),
+        accessorstddevy_recv(internal),
+        accessorstddevy_send(accessorstddevy),
+        accessorstderror_recv(internal),
+        accessorstderror_send(accessorstderror),
+        accessorsum_recv(internal),
+        accessorsum_send(accessorsum),
+        accessorsumx_recv(internal),
+        accessorsumx_send(accessorsumx),
+        accessorsumy_recv(internal),
+        accessorsumy_send(accessorsumy),
+        accessortimedelta_recv(internal),
+        accessortimedelta_send(accessortimedelta),
+        accessorunnest_recv(internal),
+        accessorunnest_send(accessorunnest),
+        accessorvariance_recv(internal),
+        accessorvariance_send(accessorvariance),
+        accessorvariancex_recv(internal),
+        accessorvariancex_send(accessorvariancex),
+        accessorvariancey_recv(internal),
+        accessorvariancey_send(accessorvariancey),
+        accessorwithbounds_recv(internal),
+        accessorwithbounds_send(accessorwithbounds),
+        accessorxintercept_recv(internal),
+        accessorxintercept_send(accessorxintercept),
+        countersummary_recv(internal),
+        countersummary_send(countersummary),
+        hyperloglog_recv(internal),
+        hyperloglog_send(hyperloglog),
+        statssummary1d_recv(internal),
+        statssummary1d_send(statssummary1d),
+        statssummary2d_recv(internal),
+        statssummary2d_send(statssummary2d),
+        tdigest_recv(internal),
+        tdigest_send(tdigest),
+        timevector_tstz_f64_recv(internal),
+        timevector_tstz_f64_send(timevector_tstz_f64),
+        timeweightsummary_recv(internal),
+        timeweightsummary_send(timeweightsummary),
+        uddsketch_recv(internal),
+        uddsketch_send(uddsketch),
+    }
     "1.12.0" => {
         stats1d_tf_inv_trans(internal,double precision),
         stats1d_tf_final(internal),
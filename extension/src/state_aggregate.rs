@@ -248,25 +248,37 @@ impl toolkit_experimental::state_agg {
     }
 
     fn finally(state: Option<&mut State>) -> Option<StateAgg<'static>> {
-        state.map(|s| {
-            let mut states = String::new();
-            let mut durations: Vec<DurationInState> = vec![];
-            let (map, first, last) = s.drain_to_duration_map_and_bounds();
-            for (state, duration) in map {
-                let state_beg = states.len() as u32;
-                let state_end = state_beg + state.len() as u32;
-                states.push_str(&state);
-                durations.push(DurationInState {
-                    duration,
-                    state_beg,
-                    state_end,
-                });
-            }
-            StateAgg::new(states, durations, first, last)
-        })
+        state.map(|s| s.to_state_agg())
     }
 }
 
+/// Builds the aggregate from a text timevector, for when the states have
+/// already been collected, or filtered with a pipeline. NULL values are
+/// skipped, as they are by the aggregate.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "state_agg",
+    schema = "toolkit_experimental"
+)]
+pub fn state_agg_from_timevector<'a>(
+    series: crate::time_vector::Timevector_TSTZ_Text<'a>,
+) -> StateAgg<'static> {
+    state_agg_from_series(series.to_series())
+}
+
+pub(crate) fn state_agg_from_series(
+    series: crate::time_vector::TypedSeries<String>,
+) -> StateAgg<'static> {
+    let mut state = StateAggTransState::new();
+    for (time, value) in series.points {
+        if let Some(value) = value {
+            state.record(value, time);
+        }
+    }
+    state.to_state_agg()
+}
+
 // Intermediate state kept in postgres.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct StateAggTransState {
@@ -286,6 +298,23 @@ impl StateAggTransState {
         self.records.append(&mut other.records)
     }
 
+    fn to_state_agg(&mut self) -> StateAgg<'static> {
+        let mut states = String::new();
+        let mut durations: Vec<DurationInState> = vec![];
+        let (map, first, last) = self.drain_to_duration_map_and_bounds();
+        for (state, duration) in map {
+            let state_beg = states.len() as u32;
+            let state_end = state_beg + state.len() as u32;
+            states.push_str(&state);
+            durations.push(DurationInState {
+                duration,
+                state_beg,
+                state_end,
+            });
+        }
+        StateAgg::new(states, durations, first, last)
+    }
+
     /// Drain accumulated state, sort, and return tuple of map of states to durations along with first and last record.
     fn drain_to_duration_map_and_bounds(
         &mut self,
@@ -2,7 +2,7 @@ use std::{convert::TryInto, mem::take, ops::Deref};
 
 use serde::{Deserialize, Serialize};
 
-use pgx::*;
+use pgx::{iter::TableIterator, *};
 
 use crate::{
     accessors::{
@@ -198,6 +198,22 @@ impl<'input> TDigest<'input> {
             })
         }
     }
+
+    pub(crate) fn from_values(
+        size: usize,
+        values: impl IntoIterator<Item = f64>,
+    ) -> TDigest<'static> {
+        let mut state = TDigestTransState {
+            buffer: vec![],
+            digested: InternalTDigest::new_with_size(size),
+        };
+        // NaNs are nonsensical in the context of a percentile, so exclude them
+        for value in values.into_iter().filter(|value| !value.is_nan()) {
+            state.push(value);
+        }
+        state.digest();
+        TDigest::from_internal_tdigest(&state.digested)
+    }
 }
 
 // PG function to generate a user-facing TDigest object from an internal TDigestTransState.
@@ -438,6 +454,70 @@ pub fn tdigest_mean<'a>(digest: TDigest<'a>) -> f64 {
     }
 }
 
+// The number of values in the digest falling into each of the buckets
+// delimited by `boundaries`. All the values of a centroid are counted at its
+// mean.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "histogram"
+)]
+pub fn tdigest_histogram<'a>(
+    digest: TDigest<'a>,
+    boundaries: Vec<f64>,
+) -> TableIterator<'a, (name!(lower, f64), name!(upper, f64), name!(count, i64))> {
+    let values = digest
+        .centroids
+        .iter()
+        .map(|centroid| (centroid.mean(), centroid.weight()));
+    TableIterator::new(crate::sketch_utils::histogram(&boundaries, values).into_iter())
+}
+
+// The approximate cumulative distribution function of the values in the
+// digest, sampled at `n_points` evenly spaced fractions from 0 to 1.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "cdf"
+)]
+pub fn tdigest_cdf<'a>(
+    digest: TDigest<'a>,
+    n_points: i32,
+) -> TableIterator<'a, (name!(value, f64), name!(fraction, f64))> {
+    let internal = digest.to_internal_tdigest();
+    TableIterator::new(
+        crate::sketch_utils::cdf_fractions(n_points)
+            .map(move |fraction| (internal.estimate_quantile(fraction), fraction)),
+    )
+}
+
+// Approximate mean of the values between the `low_q` and `high_q` quantiles,
+// e.g. the mean excluding the top 1% with `low_q = 0` and `high_q = 0.99`.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn approx_trimmed_mean<'a>(digest: TDigest<'a>, low_q: f64, high_q: f64) -> Option<f64> {
+    let values = digest
+        .centroids
+        .iter()
+        .map(|centroid| (centroid.mean(), centroid.weight()));
+    crate::sketch_utils::trimmed_mean(values, digest.count, low_q, high_q)
+}
+
+// Approximate mean of the values after clamping them to the values at the
+// `low_q` and `high_q` quantiles.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn approx_winsorized_mean<'a>(digest: TDigest<'a>, low_q: f64, high_q: f64) -> Option<f64> {
+    let internal = digest.to_internal_tdigest();
+    let values = digest
+        .centroids
+        .iter()
+        .map(|centroid| (centroid.mean(), centroid.weight()));
+    crate::sketch_utils::winsorized_mean(values, digest.count, low_q, high_q, |q| {
+        internal.estimate_quantile(q)
+    })
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -600,6 +680,77 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_tdigest_histogram() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE VIEW digest AS \
+                SELECT tdigest(100, data) FROM generate_series(1, 100) data",
+                None,
+                None,
+            );
+
+            let counts = client
+                .select(
+                    "SELECT array_agg(count ORDER BY lower)::TEXT \
+                    FROM digest, toolkit_experimental.histogram(tdigest, '{25.5, 50.5, 75.5}')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(counts.unwrap(), "{25,25,25,25}");
+
+            let (fractions, median) = client
+                .select(
+                    "SELECT array_agg(fraction)::TEXT, max(value) FILTER (WHERE fraction = 0.5) \
+                    FROM digest, toolkit_experimental.cdf(tdigest, 5)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, f64>();
+            assert_eq!(fractions.unwrap(), "{0,0.25,0.5,0.75,1}");
+            apx_eql(median.unwrap(), 50.5, 0.1);
+        });
+    }
+
+    #[pg_test(error = "cdf needs at least 2 points, got 1")]
+    fn test_tdigest_cdf_points() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.cdf(\
+                    (SELECT tdigest(100, data) FROM generate_series(1, 10) data), 1)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_trimmed_means() {
+        Spi::execute(|client| {
+            let (trimmed, untrimmed, winsorized) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_trimmed_mean(digest, 0, 0.99), \
+                        toolkit_experimental.approx_trimmed_mean(digest, 0, 1), \
+                        toolkit_experimental.approx_winsorized_mean(digest, 0.1, 0.9) \
+                    FROM (SELECT tdigest(100, data) AS digest \
+                        FROM generate_series(1, 100) data) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<f64, f64, f64>();
+            // the mean of 1 to 99
+            apx_eql(trimmed.unwrap(), 50.0, 0.01);
+            apx_eql(untrimmed.unwrap(), 50.5, 0.01);
+            // 1 to 10 become 11, 91 to 100 become 90
+            apx_eql(winsorized.unwrap(), 50.5, 0.1);
+        });
+    }
+
     #[pg_test]
     fn test_tdigest_io() {
         Spi::execute(|client| {
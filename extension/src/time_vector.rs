@@ -6,21 +6,34 @@ use crate::{
     aggregate_utils::in_aggregate_context,
     build,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    pg_type,
 };
 
+use serde::{Deserialize, Serialize};
 use tspoint::TSPoint;
 
 pub use iter::Iter;
+pub use typed::toolkit_experimental::{
+    Timevector_TSTZ_Bool, Timevector_TSTZ_I64, Timevector_TSTZ_Text,
+};
 
 use flat_serialize::*;
 
+mod compressed;
+mod export;
 mod iter;
+mod multi;
 mod pipeline;
+mod slice;
+mod time_axis;
+mod typed;
+
+pub(crate) use time_axis::{TextTime, TimeAxis, TimeUnit, TIME_AXIS_MASK};
+pub(crate) use typed::TypedSeries;
 
 use crate::raw::bytea;
 
-// Bit flags stored in Timevector flags
+// Bit flags stored in Timevector flags, bits 2-4 hold the `TimeAxis`
 pub const FLAG_IS_SORTED: u8 = 0x01;
 pub const FLAG_HAS_NULLS: u8 = 0x01 << 1;
 
@@ -36,7 +49,102 @@ pg_type! {
     }
 }
 
-ron_inout_funcs!(Timevector_TSTZ_F64);
+// The text format is the serialization of the stored data, except that the
+// times are written the way the time axis reads them: as timestamps, or as
+// plain integers for integer times.
+#[derive(Serialize, Deserialize)]
+struct ReadableTimevector {
+    version: u8,
+    num_points: u32,
+    flags: u8,
+    internal_padding: [u8; 3],
+    points: Vec<ReadablePoint>,
+    null_val: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReadablePoint {
+    ts: TextTime,
+    val: f64,
+}
+
+impl From<&Timevector_TSTZ_F64<'_>> for ReadableTimevector {
+    fn from(series: &Timevector_TSTZ_F64<'_>) -> Self {
+        let time_axis = series.time_axis();
+        Self {
+            version: series.version,
+            num_points: series.num_points,
+            flags: series.flags,
+            internal_padding: series.internal_padding,
+            points: series
+                .iter()
+                .map(|point| ReadablePoint {
+                    ts: time_axis.time_to_text(point.ts),
+                    val: point.val,
+                })
+                .collect(),
+            null_val: series.null_val.as_slice().to_vec(),
+        }
+    }
+}
+
+impl From<ReadableTimevector> for Timevector_TSTZ_F64<'static> {
+    fn from(readable: ReadableTimevector) -> Self {
+        let num_points = readable.points.len();
+        if readable.num_points as usize != num_points
+            || readable.null_val.len() != (num_points + 7) / 8
+        {
+            panic!(
+                "invalid timevector, num_points is {} but it has {} points and {} null bytes",
+                readable.num_points,
+                num_points,
+                readable.null_val.len()
+            )
+        }
+        let time_axis = TimeAxis::from_flags(readable.flags);
+        let points: Vec<_> = readable
+            .points
+            .iter()
+            .map(|point| TSPoint {
+                ts: time_axis.time_from_text(&point.ts),
+                val: point.val,
+            })
+            .collect();
+        build! {
+            Timevector_TSTZ_F64 {
+                num_points: readable.num_points,
+                flags: readable.flags,
+                internal_padding: readable.internal_padding,
+                points: points.into(),
+                null_val: readable.null_val.into(),
+            }
+        }
+    }
+}
+
+impl<'input> InOutFuncs for Timevector_TSTZ_F64<'input> {
+    fn output(&self, buffer: &mut StringInfo) {
+        use crate::serialization::{str_to_db_encoding, EncodedStr::*};
+
+        let stringified = ron::to_string(&ReadableTimevector::from(self)).unwrap();
+        match str_to_db_encoding(&stringified) {
+            Utf8(s) => buffer.push_str(s),
+            Other(s) => buffer.push_bytes(s.to_bytes()),
+        }
+    }
+
+    fn input(input: &pgx::cstr_core::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        use crate::serialization::str_from_db_encoding;
+
+        let input = str_from_db_encoding(input);
+        let readable: ReadableTimevector = ron::from_str(input).unwrap();
+        let series = Timevector_TSTZ_F64::from(readable);
+        unsafe { series.0.flatten() }
+    }
+}
 
 impl<'input> Timevector_TSTZ_F64<'input> {
     pub fn num_points(&self) -> usize {
@@ -63,6 +171,11 @@ impl<'input> Timevector_TSTZ_F64<'input> {
         self.flags & FLAG_HAS_NULLS != 0
     }
 
+    #[inline]
+    pub fn time_axis(&self) -> TimeAxis {
+        TimeAxis::from_flags(self.flags)
+    }
+
     pub fn is_null_val(&self, index: usize) -> bool {
         assert!(index < self.num_points()); // should we handle this better
 
@@ -108,6 +221,9 @@ pub static TIMEVECTOR_OID: once_cell::sync::Lazy<pg_sys::Oid> =
 pub fn unnest<'a>(
     series: Timevector_TSTZ_F64<'a>,
 ) -> TableIterator<'a, (name!(time, crate::raw::TimestampTz), name!(value, f64))> {
+    series
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "unnest");
     TableIterator::new(
         series
             .into_iter()
@@ -124,6 +240,39 @@ pub fn arrow_timevector_unnest<'a>(
     unnest(series)
 }
 
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest_timestamp",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_timestamp<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+) -> TableIterator<'a, (name!(time, crate::raw::Timestamp), name!(value, f64))> {
+    series
+        .time_axis()
+        .assert_is(TimeAxis::Timestamp, "unnest_timestamp");
+    TableIterator::new(series.into_iter().map(|point| (point.ts.into(), point.val)))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest_bigint",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_bigint<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+) -> TableIterator<'a, (name!(time, i64), name!(value, f64))> {
+    if !matches!(series.time_axis(), TimeAxis::Integer(_)) {
+        panic!(
+            "unnest_bigint requires a timevector with bigint times, this one has {} times",
+            series.time_axis().name()
+        )
+    }
+    TableIterator::new(series.into_iter().map(|point| (point.ts, point.val)))
+}
+
 #[pg_extern(immutable, parallel_safe, strict)]
 pub fn timevector_serialize(state: Internal) -> bytea {
     // FIXME: This might duplicate the version and padding bits
@@ -144,26 +293,32 @@ pub fn timevector_tstz_f64_trans(
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    unsafe { timevector_trans_inner(state.to_inner(), time, value, fcinfo).internal() }
+    let time = time.map(pg_sys::TimestampTz::from);
+    unsafe {
+        timevector_trans_inner(state.to_inner(), time, value, TimeAxis::TimestampTz, fcinfo)
+            .internal()
+    }
 }
 
+// `time_axis` is only used for the first point, it's recorded in the flags
 pub fn timevector_trans_inner(
     state: Option<Inner<Timevector_TSTZ_F64<'_>>>,
-    time: Option<crate::raw::TimestampTz>,
+    time: Option<i64>,
     value: Option<f64>,
+    time_axis: TimeAxis,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<Timevector_TSTZ_F64<'_>>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let time: pg_sys::TimestampTz = match time {
+            let time = match time {
                 None => return state,
-                Some(time) => time.into(),
+                Some(time) => time,
             };
             let mut state = match state {
                 None => Inner::from(build! {
                     Timevector_TSTZ_F64 {
                         num_points: 0,
-                        flags: FLAG_IS_SORTED,
+                        flags: FLAG_IS_SORTED | time_axis.flag_bits(),
                         internal_padding: [0; 3],
                         points: vec![].into(),
                         null_val: vec![].into(),
@@ -259,13 +414,23 @@ pub fn combine(
         return first.clone_owned();
     }
 
+    if first.time_axis() != second.time_axis() {
+        panic!(
+            "cannot combine a timevector with {} times and one with {} times",
+            first.time_axis().name(),
+            second.time_axis().name()
+        )
+    }
+
     let is_sorted = first.is_sorted()
         && second.is_sorted()
         && first.points.as_slice().last().unwrap().ts
             <= second.points.as_slice().first().unwrap().ts;
     let points: Vec<_> = first.iter().chain(second.iter()).collect();
 
-    let mut flags = (first.flags & FLAG_HAS_NULLS) | (second.flags & FLAG_HAS_NULLS);
+    let mut flags = (first.flags & FLAG_HAS_NULLS)
+        | (second.flags & FLAG_HAS_NULLS)
+        | (first.flags & TIME_AXIS_MASK);
     if is_sorted {
         flags |= FLAG_IS_SORTED;
     }
@@ -368,6 +533,100 @@ CREATE AGGREGATE rollup(\n\
     ],
 );
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_ts_f64_trans(
+    state: Internal,
+    time: Option<crate::raw::Timestamp>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let time = time.map(pg_sys::Timestamp::from);
+    unsafe {
+        timevector_trans_inner(state.to_inner(), time, value, TimeAxis::Timestamp, fcinfo)
+            .internal()
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_i64_f64_trans(
+    state: Internal,
+    time: Option<i64>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe {
+        timevector_trans_inner(
+            state.to_inner(),
+            time,
+            value,
+            TimeAxis::Integer(None),
+            fcinfo,
+        )
+        .internal()
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_i64_f64_unit_trans(
+    state: Internal,
+    time: Option<i64>,
+    value: Option<f64>,
+    unit: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    // the unit is recorded when the first point is added
+    let time_axis = match (&state, unit) {
+        (None, Some(unit)) if time.is_some() => TimeAxis::Integer(Some(TimeUnit::from_name(&unit))),
+        _ => TimeAxis::Integer(None),
+    };
+    timevector_trans_inner(state, time, value, time_axis, fcinfo).internal()
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts TIMESTAMP, value DOUBLE PRECISION) (\n\
+        sfunc = toolkit_experimental.timevector_ts_f64_trans,\n\
+        stype = internal,\n\
+        finalfunc = timevector_final,\n\
+        combinefunc = timevector_combine,\n\
+        serialfunc = timevector_serialize,\n\
+        deserialfunc = timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts BIGINT, value DOUBLE PRECISION) (\n\
+        sfunc = toolkit_experimental.timevector_i64_f64_trans,\n\
+        stype = internal,\n\
+        finalfunc = timevector_final,\n\
+        combinefunc = timevector_combine,\n\
+        serialfunc = timevector_serialize,\n\
+        deserialfunc = timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts BIGINT, value DOUBLE PRECISION, unit TEXT) (\n\
+        sfunc = toolkit_experimental.timevector_i64_f64_unit_trans,\n\
+        stype = internal,\n\
+        finalfunc = timevector_final,\n\
+        combinefunc = timevector_combine,\n\
+        serialfunc = timevector_serialize,\n\
+        deserialfunc = timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_other_time_axes_agg",
+    requires = [
+        timevector_ts_f64_trans,
+        timevector_i64_f64_trans,
+        timevector_i64_f64_unit_trans,
+        timevector_final,
+        timevector_combine,
+        timevector_serialize,
+        timevector_deserialize
+    ],
+);
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -644,4 +903,151 @@ mod tests {
             assert_eq!(tvec, expected);
         })
     }
+
+    #[pg_test]
+    pub fn test_timestamp_and_integer_time_axes() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE data(ts TIMESTAMP, ns BIGINT, value DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO data VALUES
+                    ('2020-01-01 00:00:00', 1577836800000000000, 10.0),
+                    ('2020-01-01 00:00:03', 1577836803000000000, 40.0)"#,
+                None,
+                None,
+            );
+
+            // the time axis is recorded in the flags
+            let tvec = client
+                .select("SELECT timevector(ts, value)::TEXT FROM data", None, None)
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                tvec,
+                "(version:1,num_points:2,flags:5,internal_padding:(0,0,0),points:[\
+                    (ts:\"2020-01-01 00:00:00\",val:10),\
+                    (ts:\"2020-01-01 00:00:03\",val:40)\
+                ],null_val:[0])"
+            );
+            let tvec = client
+                .select(
+                    "SELECT timevector(ns, value, 'ns')::TEXT FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                tvec,
+                "(version:1,num_points:2,flags:13,internal_padding:(0,0,0),points:[\
+                    (ts:1577836800000000000,val:10),\
+                    (ts:1577836803000000000,val:40)\
+                ],null_val:[0])"
+            );
+
+            // and the text form reads back into the same time axis
+            let round_trip = |query: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT {0}::TEXT::timevector_tstz_f64::TEXT = {0}::TEXT FROM data",
+                            query
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<bool>()
+                    .unwrap()
+            };
+            assert!(round_trip("timevector(ts, value)"));
+            assert!(round_trip("timevector(ns, value, 'ns')"));
+            assert!(round_trip("timevector(ns, value)"));
+
+            let unnest = |query: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(time || ' ' || value, ', ') FROM {}",
+                            query
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            assert_eq!(
+                unnest(
+                    "unnest_timestamp((SELECT timevector(ts, value) \
+                        -> fill_to('1 second', 'interpolate') FROM data))"
+                ),
+                "2020-01-01 00:00:00 10, 2020-01-01 00:00:01 20, \
+                2020-01-01 00:00:02 30, 2020-01-01 00:00:03 40"
+            );
+
+            // intervals are converted into the unit of the times
+            assert_eq!(
+                unnest(
+                    "unnest_bigint((SELECT timevector(ns, value, 'ns') \
+                        -> fill_to('1 second', 'locf') FROM data))"
+                ),
+                "1577836800000000000 10, 1577836801000000000 10, \
+                1577836802000000000 10, 1577836803000000000 40"
+            );
+            assert_eq!(
+                unnest(
+                    "unnest_bigint((SELECT timevector(ns, value, 'ns') \
+                        -> map($$ ($time + '1 minute'i, $value) $$) FROM data))"
+                ),
+                "1577836860000000000 10, 1577836863000000000 40"
+            );
+            // times a lambda doesn't change keep their nanoseconds
+            assert_eq!(
+                unnest(
+                    "unnest_bigint((SELECT timevector(ns + 123, value, 'ns') \
+                        -> map($$ ($time, $value * 2) $$) FROM data))"
+                ),
+                "1577836800000000123 20, 1577836803000000123 80"
+            );
+
+            // lambdas that don't use $time work on times without a unit
+            assert_eq!(
+                unnest(
+                    "unnest_bigint((SELECT timevector(ns, value) \
+                        -> filter($$ $value > 20 $$) FROM data))"
+                ),
+                "1577836803000000000 40"
+            );
+
+            // TIMESTAMP times don't depend on the session timezone
+            client.select("SET LOCAL timezone TO 'America/New_York'", None, None);
+            assert_eq!(
+                unnest(
+                    "unnest_timestamp((SELECT timevector(ts, value) \
+                        -> map($$ ($time + '1 minute'i, $value) $$) FROM data))"
+                ),
+                "2020-01-01 00:01:00 10, 2020-01-01 00:01:03 40"
+            );
+        })
+    }
 }
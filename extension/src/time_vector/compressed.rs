@@ -0,0 +1,366 @@
+//! Compressed timevectors. Timestamps are stored as delta-of-deltas in
+//! prefix-varint form, which takes a single byte per point for regularly
+//! spaced series, and values are XORed with their predecessor and stored
+//! with the leading and trailing zeros removed, as in Facebook's Gorilla.
+//! Either form converts to the other with `compress()` and `decompress()`,
+//! and the compressed form casts implicitly to a timevector so it can be
+//! used everywhere a timevector can.
+
+use pgx::{iter::TableIterator, *};
+
+use encodings::{delta, prefix_varint, xor, zigzag};
+use flat_serialize::*;
+
+use tspoint::TSPoint;
+
+use crate::{build, pg_type};
+
+use super::{Iter, ReadableTimevector, TimeAxis, Timevector_TSTZ_F64};
+
+use toolkit_experimental::{CompressedTimevector_TSTZ_F64, CompressedTimevector_TSTZ_F64Data};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        #[allow(non_camel_case_types)]
+        struct CompressedTimevector_TSTZ_F64<'input> {
+            num_points: u32,
+            flags: u8,         // same as the flags of a Timevector_TSTZ_F64
+            internal_padding: [u8; 3],
+            times_len: u32,
+            values_len: u32,
+            times: [u8; self.times_len],
+            values: [u8; self.values_len],
+            null_val: [u8; (self.num_points + 7) / 8], // bit vector, same layout as Timevector_TSTZ_F64
+        }
+    }
+}
+
+// The text format is the same as an uncompressed timevector's, so values can
+// be moved between the two as text.
+impl<'input> InOutFuncs for CompressedTimevector_TSTZ_F64<'input> {
+    fn output(&self, buffer: &mut StringInfo) {
+        use crate::serialization::{str_to_db_encoding, EncodedStr::*};
+
+        let stringified = ron::to_string(&ReadableTimevector::from(&self.decompress())).unwrap();
+        match str_to_db_encoding(&stringified) {
+            Utf8(s) => buffer.push_str(s),
+            Other(s) => buffer.push_bytes(s.to_bytes()),
+        }
+    }
+
+    fn input(input: &pgx::cstr_core::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        use crate::serialization::str_from_db_encoding;
+
+        let input = str_from_db_encoding(input);
+        let readable: ReadableTimevector = ron::from_str(input).unwrap();
+        let compressed = compress(&readable.into());
+        unsafe { compressed.0.flatten() }
+    }
+}
+
+impl<'input> CompressedTimevector_TSTZ_F64<'input> {
+    pub fn num_points(&self) -> usize {
+        self.num_points as usize
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::Compressed {
+            iter: PointIter::new(
+                self.times.as_slice(),
+                self.values.as_slice(),
+                self.num_points(),
+            ),
+        }
+    }
+
+    pub fn decompress(&self) -> Timevector_TSTZ_F64<'static> {
+        let points: Vec<_> = self.iter().collect();
+        build! {
+            Timevector_TSTZ_F64 {
+                num_points: self.num_points,
+                flags: self.flags,
+                internal_padding: [0; 3],
+                points: points.into(),
+                null_val: self.null_val.as_slice().to_vec().into(),
+            }
+        }
+    }
+}
+
+pub fn compress(series: &Timevector_TSTZ_F64<'_>) -> CompressedTimevector_TSTZ_F64<'static> {
+    let mut times = vec![];
+    prefix_varint::compress_i64s_to_vec(
+        &mut times,
+        series
+            .iter()
+            .map(|p| p.ts)
+            .map(delta::i64_encoder())
+            .map(delta::i64_encoder()),
+    );
+    let mut values = vec![];
+    xor::compress_f64s_to_vec(&mut values, series.iter().map(|p| p.val));
+
+    build! {
+        CompressedTimevector_TSTZ_F64 {
+            num_points: series.num_points,
+            flags: series.flags,
+            internal_padding: [0; 3],
+            times_len: times.len() as _,
+            values_len: values.len() as _,
+            times: times.into(),
+            values: values.into(),
+            null_val: series.null_val.as_slice().to_vec().into(),
+        }
+    }
+}
+
+// Decodes the points of a compressed timevector as they are read
+pub struct PointIter<'a> {
+    times: &'a [u8],
+    values: xor::F64Decompressor<'a>,
+    prev_time: i64,
+    prev_delta: i64,
+}
+
+impl<'a> PointIter<'a> {
+    fn new(times: &'a [u8], values: &'a [u8], num_points: usize) -> Self {
+        Self {
+            times,
+            values: xor::f64_decompressor(values, num_points),
+            prev_time: 0,
+            prev_delta: 0,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+impl<'a> Iterator for PointIter<'a> {
+    type Item = TSPoint;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let val = self.values.next()?;
+
+        let (delta_of_delta, len) = prefix_varint::read_from_slice(self.times);
+        self.times = &self.times[len..];
+        self.prev_delta = self.prev_delta.wrapping_add(zigzag::decode(delta_of_delta));
+        self.prev_time = self.prev_time.wrapping_add(self.prev_delta);
+
+        Some(TSPoint {
+            ts: self.prev_time,
+            val,
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn compress_timevector<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+) -> CompressedTimevector_TSTZ_F64<'static> {
+    compress(&series)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn decompress_timevector<'a>(
+    series: CompressedTimevector_TSTZ_F64<'a>,
+) -> Timevector_TSTZ_F64<'static> {
+    series.decompress()
+}
+
+extension_sql!(
+    r#"
+    CREATE CAST (toolkit_experimental.CompressedTimevector_TSTZ_F64 AS Timevector_TSTZ_F64)
+        WITH FUNCTION toolkit_experimental.decompress_timevector
+        AS IMPLICIT;
+    CREATE CAST (Timevector_TSTZ_F64 AS toolkit_experimental.CompressedTimevector_TSTZ_F64)
+        WITH FUNCTION toolkit_experimental.compress_timevector
+        AS ASSIGNMENT;
+"#,
+    name = "compressed_timevector_casts",
+    requires = [
+        CompressedTimevector_TSTZ_F64,
+        Timevector_TSTZ_F64,
+        compress_timevector,
+        decompress_timevector
+    ],
+);
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_compressed<'a>(
+    series: CompressedTimevector_TSTZ_F64<'a>,
+) -> TableIterator<'a, (name!(time, crate::raw::TimestampTz), name!(value, f64))> {
+    TimeAxis::from_flags(series.flags).assert_is(TimeAxis::TimestampTz, "unnest");
+    let points: Vec<_> = series.iter().collect();
+    TableIterator::new(
+        points
+            .into_iter()
+            .map(|points| (points.ts.into(), points.val)),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_compressed_timevector() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE data(time TIMESTAMPTZ, value DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO data VALUES
+                    ('2020-1-1', 30.0),
+                    ('2020-1-2', 45.0),
+                    ('2020-1-3', NULL),
+                    ('2020-1-4', 55.5),
+                    ('2020-1-5', 10.0),
+                    ('2020-1-7', 10.0),
+                    ('2020-1-6', -0.25)"#,
+                None,
+                None,
+            );
+
+            let expected = "(version:1,num_points:7,flags:2,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:30),\
+                (ts:\"2020-01-02 00:00:00+00\",val:45),\
+                (ts:\"2020-01-03 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-04 00:00:00+00\",val:55.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10),\
+                (ts:\"2020-01-07 00:00:00+00\",val:10),\
+                (ts:\"2020-01-06 00:00:00+00\",val:-0.25)\
+            ],null_val:[4])";
+
+            let val = client
+                .select(
+                    "SELECT compress_timevector(timevector(time, value))::TEXT FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            // text input and output
+            let val = client
+                .select(
+                    &format!("SELECT '{}'::CompressedTimevector_TSTZ_F64::TEXT", expected),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            // round trip, and implicit casting
+            let val = client
+                .select(
+                    "SELECT timevector(time, value)::TEXT = \
+                        decompress_timevector(compress_timevector(timevector(time, value)))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
+
+            let val = client
+                .select(
+                    "SELECT (compress_timevector(timevector(time, value)) -> sort())::TEXT FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:7,flags:3,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:30),\
+                (ts:\"2020-01-02 00:00:00+00\",val:45),\
+                (ts:\"2020-01-03 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-04 00:00:00+00\",val:55.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10),\
+                (ts:\"2020-01-06 00:00:00+00\",val:-0.25),\
+                (ts:\"2020-01-07 00:00:00+00\",val:10)\
+            ],null_val:[4])"
+            );
+
+            let mut unnest = client.select(
+                "SELECT unnest(compress_timevector(timevector(time, value)))::TEXT FROM data",
+                None,
+                None,
+            );
+            assert_eq!(
+                unnest.next().unwrap()[1].value(),
+                Some("(\"2020-01-01 00:00:00+00\",30)")
+            );
+            assert_eq!(
+                unnest.next().unwrap()[1].value(),
+                Some("(\"2020-01-02 00:00:00+00\",45)")
+            );
+            assert_eq!(
+                unnest.next().unwrap()[1].value(),
+                Some("(\"2020-01-03 00:00:00+00\",NaN)")
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_compressed_timevector_size() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            let (compressed, uncompressed, same) = client
+                .select(
+                    "SELECT pg_column_size(toolkit_experimental.compress_timevector(tv)), \
+                        pg_column_size(tv), \
+                        toolkit_experimental.compress_timevector(tv)::TEXT = tv::TEXT \
+                    FROM (\
+                        SELECT timevector(time, round(sin(i / 10.0)::numeric, 1)::float) AS tv \
+                        FROM generate_series(0, 9999) i, \
+                            LATERAL (SELECT '2020-01-01'::timestamptz + i * '1 minute'::interval AS time) t\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<i32, i32, bool>();
+            assert_eq!(same, Some(true));
+            // 16 bytes per point uncompressed
+            assert!(uncompressed.unwrap() > 160_000);
+            assert!(compressed.unwrap() < uncompressed.unwrap() / 2);
+        });
+    }
+}
@@ -0,0 +1,425 @@
+//! Exporting timevectors to other tools: as an Arrow IPC stream, which is
+//! columnar and can be read without parsing text, and as CSV or JSON.
+
+use pgx::*;
+
+use tspoint::TSPoint;
+
+use crate::build;
+
+use super::{
+    time_axis::{POSTGRES_EPOCH_SECS, USECS_PER_SEC},
+    TimeAxis, TimeUnit, Timevector_TSTZ_F64, FLAG_HAS_NULLS, FLAG_IS_SORTED,
+};
+
+mod arrow_ipc;
+
+const POSTGRES_EPOCH_USECS: i64 = POSTGRES_EPOCH_SECS * USECS_PER_SEC;
+
+/// Returns the timevector as an Arrow IPC stream with a `time` and a
+/// nullable `value` column. TIMESTAMPTZ and TIMESTAMP times become Arrow
+/// microsecond timestamps, with and without a timezone, integer times are
+/// written as they are, with their unit in the column's metadata.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn to_arrow_ipc<'a>(series: Timevector_TSTZ_F64<'a>) -> Vec<u8> {
+    let time_axis = series.time_axis();
+    let (time_type, offset) = match time_axis {
+        TimeAxis::TimestampTz | TimeAxis::Timestamp => (
+            arrow_ipc::TimeType::Timestamp {
+                unit: arrow_ipc::TimeUnit::Microsecond,
+                with_timezone: time_axis == TimeAxis::TimestampTz,
+            },
+            POSTGRES_EPOCH_USECS,
+        ),
+        TimeAxis::Integer(unit) => (
+            arrow_ipc::TimeType::Int64 {
+                unit: unit.map(arrow_unit),
+            },
+            0,
+        ),
+    };
+    // infinite times are kept as the largest and smallest times
+    let to_arrow = move |ts: i64| match ts {
+        i64::MAX | i64::MIN => ts,
+        _ => ts
+            .checked_add(offset)
+            .unwrap_or_else(|| panic!("timestamp out of range for Arrow")),
+    };
+    arrow_ipc::write(
+        time_type,
+        points(&series).map(|(ts, val)| (to_arrow(ts), val)),
+    )
+}
+
+/// Builds a timevector from an Arrow IPC stream whose first column holds the
+/// times and whose second holds FLOAT8 values. Timestamps with a timezone
+/// become TIMESTAMPTZ times, timestamps without one TIMESTAMP times, both
+/// truncated to microseconds, and 64-bit integers become integer times.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn from_arrow_ipc(bytes: &[u8]) -> Timevector_TSTZ_F64<'static> {
+    let (time_type, arrow_points) = match arrow_ipc::read(bytes) {
+        Ok(read) => read,
+        Err(error) => panic!("invalid Arrow IPC stream: {}", error),
+    };
+
+    let (time_axis, to_usecs): (_, fn(i64) -> Option<i64>) = match time_type {
+        arrow_ipc::TimeType::Timestamp {
+            unit,
+            with_timezone,
+        } => {
+            let axis = if with_timezone {
+                TimeAxis::TimestampTz
+            } else {
+                TimeAxis::Timestamp
+            };
+            let to_usecs: fn(i64) -> Option<i64> = match unit {
+                arrow_ipc::TimeUnit::Second => |t| t.checked_mul(USECS_PER_SEC),
+                arrow_ipc::TimeUnit::Millisecond => |t| t.checked_mul(1000),
+                arrow_ipc::TimeUnit::Microsecond => Some,
+                arrow_ipc::TimeUnit::Nanosecond => |t| Some(t.div_euclid(1000)),
+            };
+            (axis, to_usecs)
+        }
+        arrow_ipc::TimeType::Int64 { unit } => (TimeAxis::Integer(unit.map(time_unit)), Some),
+    };
+    let to_ticks = |time: i64| match time_axis {
+        TimeAxis::Integer(_) => Some(time),
+        _ if time == i64::MAX || time == i64::MIN => Some(time),
+        _ => to_usecs(time).and_then(|usecs| usecs.checked_sub(POSTGRES_EPOCH_USECS)),
+    };
+
+    let mut points = Vec::with_capacity(arrow_points.len());
+    let mut null_val = vec![];
+    let mut flags = time_axis.flag_bits() | FLAG_IS_SORTED;
+    for (i, (time, value)) in arrow_points.into_iter().enumerate() {
+        let ts = to_ticks(time).unwrap_or_else(|| panic!("time {} is out of range", time));
+        if i % 8 == 0 {
+            null_val.push(0);
+        }
+        if value.is_none() {
+            null_val[i / 8] |= 1 << (i % 8);
+            flags |= FLAG_HAS_NULLS;
+        }
+        if points.last().map_or(false, |last: &TSPoint| last.ts > ts) {
+            flags &= !FLAG_IS_SORTED;
+        }
+        points.push(TSPoint {
+            ts,
+            val: value.unwrap_or(0.0),
+        });
+    }
+
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: null_val.into(),
+        }
+    }
+}
+
+/// Returns the timevector as CSV with a header line. Times are written in
+/// ISO 8601, TIMESTAMPTZ ones in UTC, and NULL values as empty fields.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn to_csv<'a>(series: Timevector_TSTZ_F64<'a>) -> String {
+    let time_axis = series.time_axis();
+    let mut csv = String::from("time,value\n");
+    for (ts, val) in points(&series) {
+        csv.push_str(&format_time(time_axis, ts));
+        csv.push(',');
+        if let Some(val) = val {
+            csv.push_str(&format_value(val));
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Returns the timevector as a JSON object holding a `time` and a `value`
+/// array. Times are ISO 8601 strings, or numbers for integer times, and
+/// non-finite values are strings, as `to_json()` writes them.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn to_json_arrays<'a>(series: Timevector_TSTZ_F64<'a>) -> JsonString {
+    let time_axis = series.time_axis();
+    let mut times = vec![];
+    let mut values = vec![];
+    for (ts, val) in points(&series) {
+        times.push(match time_axis {
+            TimeAxis::Integer(_) => ts.to_string(),
+            _ => format!("\"{}\"", format_time(time_axis, ts)),
+        });
+        values.push(match val {
+            None => "null".to_string(),
+            Some(val) if val.is_finite() => format_value(val),
+            Some(val) => format!("\"{}\"", format_value(val)),
+        });
+    }
+    JsonString(format!(
+        "{{\"time\":[{}],\"value\":[{}]}}",
+        times.join(","),
+        values.join(",")
+    ))
+}
+
+// the points of a timevector, with `None` for the NULL values
+fn points<'s>(
+    series: &'s Timevector_TSTZ_F64<'_>,
+) -> impl Iterator<Item = (i64, Option<f64>)> + 's {
+    let has_nulls = series.has_nulls();
+    series.iter().enumerate().map(move |(i, point)| {
+        let null = has_nulls && series.is_null_val(i);
+        (point.ts, if null { None } else { Some(point.val) })
+    })
+}
+
+fn arrow_unit(unit: TimeUnit) -> arrow_ipc::TimeUnit {
+    match unit {
+        TimeUnit::Seconds => arrow_ipc::TimeUnit::Second,
+        TimeUnit::Milliseconds => arrow_ipc::TimeUnit::Millisecond,
+        TimeUnit::Microseconds => arrow_ipc::TimeUnit::Microsecond,
+        TimeUnit::Nanoseconds => arrow_ipc::TimeUnit::Nanosecond,
+    }
+}
+
+fn time_unit(unit: arrow_ipc::TimeUnit) -> TimeUnit {
+    match unit {
+        arrow_ipc::TimeUnit::Second => TimeUnit::Seconds,
+        arrow_ipc::TimeUnit::Millisecond => TimeUnit::Milliseconds,
+        arrow_ipc::TimeUnit::Microsecond => TimeUnit::Microseconds,
+        arrow_ipc::TimeUnit::Nanosecond => TimeUnit::Nanoseconds,
+    }
+}
+
+fn format_time(time_axis: TimeAxis, ts: i64) -> String {
+    match time_axis {
+        TimeAxis::Integer(_) => ts.to_string(),
+        _ if ts == i64::MAX => "infinity".to_string(),
+        _ if ts == i64::MIN => "-infinity".to_string(),
+        TimeAxis::TimestampTz => format!("{}Z", format_timestamp(ts)),
+        TimeAxis::Timestamp => format_timestamp(ts),
+    }
+}
+
+// ISO 8601 for microseconds since 2000-01-01
+fn format_timestamp(ts: i64) -> String {
+    let secs = ts.div_euclid(USECS_PER_SEC) + POSTGRES_EPOCH_SECS;
+    let usecs = ts.rem_euclid(USECS_PER_SEC);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs = secs.rem_euclid(86_400);
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    if usecs != 0 {
+        formatted.push_str(&format!(".{:06}", usecs));
+    }
+    formatted
+}
+
+// the proleptic Gregorian date of a number of days since 1970-01-01, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// FLOAT8s as Postgres writes them
+fn format_value(val: f64) -> String {
+    if val.is_nan() {
+        "NaN".to_string()
+    } else if val.is_infinite() {
+        if val > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_arrow_ipc_round_trip() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-02 12:00:00.5 UTC'::TIMESTAMPTZ, 15.0)",
+                None,
+                None,
+            );
+
+            let (original, round_tripped) = client
+                .select(
+                    "SELECT tv::TEXT, from_arrow_ipc(to_arrow_ipc(tv))::TEXT \
+                    FROM (SELECT timevector(time, value) AS tv FROM series) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(round_tripped, original);
+
+            // a stream is made of messages starting with 0xFFFFFFFF and ends
+            // with an empty one
+            let (start, end) = client
+                .select(
+                    "SELECT encode(substr(ipc, 1, 4), 'hex'), encode(substr(ipc, length(ipc) - 7), 'hex') \
+                    FROM (SELECT to_arrow_ipc(timevector(time, value)) AS ipc FROM series) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(start.unwrap(), "ffffffff");
+            assert_eq!(end.unwrap(), "ffffffff00000000");
+
+            // the time axis is kept
+            let (original, round_tripped) = client
+                .select(
+                    "SELECT tv::TEXT, from_arrow_ipc(to_arrow_ipc(tv))::TEXT \
+                    FROM (SELECT timevector(time::timestamp, value) AS tv FROM series) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(round_tripped, original);
+
+            let val = client
+                .select(
+                    "SELECT unnest_bigint(from_arrow_ipc(to_arrow_ipc(timevector(t, v, 'ms'))))::TEXT \
+                    FROM (VALUES (1000::bigint, 1.0::float8)) v(t, v)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(1000,1)");
+        });
+    }
+
+    #[pg_test(error = "invalid Arrow IPC stream: truncated Arrow data")]
+    fn test_from_arrow_ipc_truncated() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.from_arrow_ipc(\
+                    substr(toolkit_experimental.to_arrow_ipc(toolkit_experimental.timevector(now(), 1.0)), 1, 20))",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_csv_and_json_export() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.5), \
+                    ('2020-01-02 00:00:00.25 UTC'::TIMESTAMPTZ, NULL), \
+                    ('1999-12-31 23:00:00 UTC'::TIMESTAMPTZ, 'NaN')) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!("SELECT to_csv(series) FROM ({}) s", create_series),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "time,value\n\
+                2020-01-01T00:00:00Z,10.5\n\
+                2020-01-02T00:00:00.250000Z,\n\
+                1999-12-31T23:00:00Z,NaN\n"
+            );
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT to_json_arrays(series)::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "{\"time\":[\"2020-01-01T00:00:00Z\",\"2020-01-02T00:00:00.250000Z\",\"1999-12-31T23:00:00Z\"],\
+                \"value\":[10.5,null,\"NaN\"]}"
+            );
+
+            let val = client
+                .select(
+                    "SELECT to_json_arrays(timevector(t, v))::TEXT \
+                    FROM (VALUES (-5::bigint, 1.0::float8)) v(t, v)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{\"time\":[-5],\"value\":[1]}");
+        });
+    }
+}
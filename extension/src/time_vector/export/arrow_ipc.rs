@@ -0,0 +1,668 @@
+//! The Arrow IPC streaming format, restricted to what a timevector needs: a
+//! schema with a non-nullable time column and a nullable FLOAT8 value column,
+//! followed by record batches holding the points.
+//!
+//! The message metadata is stored in flatbuffers, which are written and read
+//! by hand here, see `Format.fbs`, `Schema.fbs` and `Message.fbs` in the
+//! Arrow repository for the tables and the field ids used below.
+
+use std::convert::TryInto;
+
+// 0xFFFFFFFF, written before each message's length
+const CONTINUATION: [u8; 4] = [0xff; 4];
+
+const METADATA_V5: i16 = 4;
+
+// MessageHeader union
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_DICTIONARY_BATCH: u8 = 2;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+// Type union
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_TIMESTAMP: u8 = 10;
+
+const PRECISION_DOUBLE: i16 = 2;
+const ENDIANNESS_BIG: i16 = 1;
+
+// custom metadata key recording the unit of integer times
+const UNIT_KEY: &str = "unit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Second,
+    Millisecond,
+    Microsecond,
+    Nanosecond,
+}
+
+impl TimeUnit {
+    fn from_i16(unit: i16) -> Result<Self, String> {
+        match unit {
+            0 => Ok(TimeUnit::Second),
+            1 => Ok(TimeUnit::Millisecond),
+            2 => Ok(TimeUnit::Microsecond),
+            3 => Ok(TimeUnit::Nanosecond),
+            _ => Err(format!("invalid Arrow time unit {}", unit)),
+        }
+    }
+
+    fn as_i16(self) -> i16 {
+        match self {
+            TimeUnit::Second => 0,
+            TimeUnit::Millisecond => 1,
+            TimeUnit::Microsecond => 2,
+            TimeUnit::Nanosecond => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TimeUnit::Second => "s",
+            TimeUnit::Millisecond => "ms",
+            TimeUnit::Microsecond => "us",
+            TimeUnit::Nanosecond => "ns",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            TimeUnit::Second,
+            TimeUnit::Millisecond,
+            TimeUnit::Microsecond,
+            TimeUnit::Nanosecond,
+        ]
+        .iter()
+        .copied()
+        .find(|unit| unit.name() == name)
+    }
+}
+
+/// The Arrow type of the time column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeType {
+    /// Times counted from the unix epoch. Those with a timezone are instants,
+    /// the timezone only says how to display them, and are always written
+    /// with "UTC".
+    Timestamp { unit: TimeUnit, with_timezone: bool },
+    /// 64-bit integers, with the unit they are in, if any, recorded in the
+    /// field's metadata.
+    Int64 { unit: Option<TimeUnit> },
+}
+
+/// The points of a timevector, as times and values.
+pub type Points = Vec<(i64, Option<f64>)>;
+
+/// Writes `points` as an Arrow IPC stream with a single record batch.
+pub fn write(time_type: TimeType, points: impl Iterator<Item = (i64, Option<f64>)>) -> Vec<u8> {
+    let mut times = vec![];
+    let mut values = vec![];
+    let mut validity = vec![];
+    let mut null_count = 0;
+    for (i, (time, value)) in points.enumerate() {
+        if i % 8 == 0 {
+            validity.push(0_u8);
+        }
+        match value {
+            Some(value) => {
+                validity[i / 8] |= 1 << (i % 8);
+                values.push(value);
+            }
+            None => {
+                null_count += 1;
+                values.push(0.0);
+            }
+        }
+        times.push(time);
+    }
+    let length = times.len() as i64;
+
+    let mut out = vec![];
+    write_message(&mut out, HEADER_SCHEMA, schema(time_type), &[]);
+
+    // the body holds the buffers of both columns in order, each padded to 8
+    // bytes: the time validity (empty, it has no nulls), the times, the value
+    // validity (empty if there are no nulls) and the values
+    let mut body = vec![];
+    let mut buffers = vec![];
+    let mut add_buffer = |bytes: &[u8]| {
+        buffers.push((body.len() as i64, bytes.len() as i64));
+        body.extend_from_slice(bytes);
+        pad_to_8(&mut body);
+    };
+    add_buffer(&[]);
+    add_buffer(
+        &times
+            .iter()
+            .flat_map(|t| t.to_le_bytes())
+            .collect::<Vec<_>>(),
+    );
+    add_buffer(if null_count > 0 { &validity[..] } else { &[] });
+    add_buffer(
+        &values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>(),
+    );
+
+    let nodes = [(length, 0), (length, null_count)];
+    let batch = Node::Table(vec![
+        (0, Value::I64(length)),
+        (1, Value::Offset(Node::structs(&nodes))),
+        (2, Value::Offset(Node::structs(&buffers))),
+    ]);
+    write_message(&mut out, HEADER_RECORD_BATCH, batch, &body);
+
+    // end-of-stream marker
+    out.extend_from_slice(&CONTINUATION);
+    out.extend_from_slice(&0_i32.to_le_bytes());
+    out
+}
+
+/// Reads an Arrow IPC stream written by `write()`, or by any other Arrow
+/// implementation as long as it holds the same two columns.
+pub fn read(bytes: &[u8]) -> Result<(TimeType, Points), String> {
+    let mut time_type = None;
+    let mut points = vec![];
+    let mut pos = 0;
+    while pos + 4 <= bytes.len() {
+        let mut len = read_i32(bytes, pos)?;
+        pos += 4;
+        // streams written before Arrow 0.15 have no continuation marker
+        if len == -1 {
+            len = read_i32(bytes, pos)?;
+            pos += 4;
+        }
+        if len == 0 {
+            break;
+        }
+        let metadata = slice(bytes, pos, len as usize)?;
+        pos += len as usize;
+
+        let message = Table::root(metadata)?;
+        let body_length = message.i64(3, 0)?;
+        let body = slice(bytes, pos, body_length as usize)?;
+        pos += body_length as usize;
+
+        let header = message
+            .table(2)?
+            .ok_or_else(|| "Arrow message has no header".to_string())?;
+        match message.u8(1, 0)? {
+            HEADER_SCHEMA => time_type = Some(read_schema(&header)?),
+            HEADER_RECORD_BATCH => {
+                if time_type.is_none() {
+                    return Err("Arrow record batch before the schema".into());
+                }
+                read_batch(&header, body, &mut points)?
+            }
+            HEADER_DICTIONARY_BATCH => return Err("Arrow dictionaries are not supported".into()),
+            other => return Err(format!("unsupported Arrow message type {}", other)),
+        }
+    }
+    let time_type = time_type.ok_or_else(|| "Arrow stream has no schema".to_string())?;
+    Ok((time_type, points))
+}
+
+fn schema(time_type: TimeType) -> Node<'static> {
+    let (type_type, type_table, metadata) = match time_type {
+        TimeType::Timestamp {
+            unit,
+            with_timezone,
+        } => {
+            let mut fields = vec![(0, Value::I16(unit.as_i16()))];
+            if with_timezone {
+                fields.push((1, Value::Offset(Node::Str("UTC"))));
+            }
+            (TYPE_TIMESTAMP, Node::Table(fields), None)
+        }
+        TimeType::Int64 { unit } => {
+            let int = Node::Table(vec![(0, Value::I32(64)), (1, Value::U8(1))]);
+            (TYPE_INT, int, unit)
+        }
+    };
+    let mut time = vec![
+        (0, Value::Offset(Node::Str("time"))),
+        (1, Value::U8(0)),
+        (2, Value::U8(type_type)),
+        (3, Value::Offset(type_table)),
+        (5, Value::Offset(Node::Tables(vec![]))),
+    ];
+    if let Some(unit) = metadata {
+        let entry = Node::Table(vec![
+            (0, Value::Offset(Node::Str(UNIT_KEY))),
+            (1, Value::Offset(Node::Str(unit.name()))),
+        ]);
+        time.push((6, Value::Offset(Node::Tables(vec![entry]))));
+    }
+    let value = vec![
+        (0, Value::Offset(Node::Str("value"))),
+        (1, Value::U8(1)),
+        (2, Value::U8(TYPE_FLOATING_POINT)),
+        (
+            3,
+            Value::Offset(Node::Table(vec![(0, Value::I16(PRECISION_DOUBLE))])),
+        ),
+        (5, Value::Offset(Node::Tables(vec![]))),
+    ];
+    Node::Table(vec![(
+        1,
+        Value::Offset(Node::Tables(vec![Node::Table(time), Node::Table(value)])),
+    )])
+}
+
+fn read_schema(schema: &Table) -> Result<TimeType, String> {
+    if schema.i16(0, 0)? == ENDIANNESS_BIG {
+        return Err("big-endian Arrow data is not supported".into());
+    }
+    let fields = schema.tables(1)?;
+    if fields.len() != 2 {
+        return Err(format!(
+            "expected an Arrow schema with a time and a value column, found {} columns",
+            fields.len()
+        ));
+    }
+    let (time, value) = (&fields[0], &fields[1]);
+    let time_type = match time.u8(2, 0)? {
+        TYPE_TIMESTAMP => {
+            let timestamp = field_type(time)?;
+            TimeType::Timestamp {
+                unit: TimeUnit::from_i16(timestamp.i16(0, 0)?)?,
+                with_timezone: timestamp.str(1)?.is_some(),
+            }
+        }
+        TYPE_INT => {
+            let int = field_type(time)?;
+            if int.i32(0, 0)? != 64 || int.u8(1, 0)? == 0 {
+                return Err("the time column must hold signed 64-bit integers".into());
+            }
+            let mut unit = None;
+            for entry in time.tables(6)? {
+                if entry.str(0)? == Some(UNIT_KEY) {
+                    unit = entry.str(1)?.and_then(TimeUnit::from_name);
+                }
+            }
+            TimeType::Int64 { unit }
+        }
+        _ => return Err("the time column must hold timestamps or 64-bit integers".into()),
+    };
+
+    if value.u8(2, 0)? != TYPE_FLOATING_POINT || field_type(value)?.i16(0, 0)? != PRECISION_DOUBLE {
+        return Err("the value column must hold 64-bit floats".into());
+    }
+    Ok(time_type)
+}
+
+fn field_type<'a>(field: &Table<'a>) -> Result<Table<'a>, String> {
+    field
+        .table(3)?
+        .ok_or_else(|| "Arrow field has no type".to_string())
+}
+
+fn read_batch(batch: &Table, body: &[u8], points: &mut Points) -> Result<(), String> {
+    if batch.table(3)?.is_some() {
+        return Err("compressed Arrow data is not supported".into());
+    }
+    let length = usize::try_from(batch.i64(0, 0)?)
+        .map_err(|_| "Arrow record batch has a negative length".to_string())?;
+    let nodes = batch.structs(1)?;
+    let buffers = batch.structs(2)?;
+    if nodes.len() != 2 || buffers.len() != 4 {
+        return Err("expected an Arrow record batch with two primitive columns".into());
+    }
+    if nodes[0].1 != 0 {
+        return Err("the time column cannot contain nulls".into());
+    }
+    let buffer = |i: usize| {
+        let (offset, len) = buffers[i];
+        match (usize::try_from(offset), usize::try_from(len)) {
+            (Ok(offset), Ok(len)) => slice(body, offset, len),
+            _ => Err("Arrow buffer has a negative offset or length".to_string()),
+        }
+    };
+    let times = buffer(1)?;
+    let validity = buffer(2)?;
+    let values = buffer(3)?;
+    let column_bytes = length
+        .checked_mul(8)
+        .ok_or_else(|| "Arrow record batch is too long".to_string())?;
+    if times.len() < column_bytes || values.len() < column_bytes {
+        return Err("Arrow buffer is shorter than its column".into());
+    }
+    let has_nulls = nodes[1].1 != 0 && !validity.is_empty();
+    if has_nulls && validity.len() < (length + 7) / 8 {
+        return Err("Arrow buffer is shorter than its column".into());
+    }
+
+    points.reserve(length);
+    for i in 0..length {
+        let time = i64::from_le_bytes(times[i * 8..i * 8 + 8].try_into().unwrap());
+        let valid = !has_nulls || validity[i / 8] & (1 << (i % 8)) != 0;
+        let value = f64::from_le_bytes(values[i * 8..i * 8 + 8].try_into().unwrap());
+        points.push((time, if valid { Some(value) } else { None }));
+    }
+    Ok(())
+}
+
+// Each message is the continuation marker, the length of the metadata, the
+// metadata flatbuffer padded to 8 bytes, and the body.
+fn write_message(out: &mut Vec<u8>, header_type: u8, header: Node, body: &[u8]) {
+    let message = Node::Table(vec![
+        (0, Value::I16(METADATA_V5)),
+        (1, Value::U8(header_type)),
+        (2, Value::Offset(header)),
+        (3, Value::I64(body.len() as i64)),
+    ]);
+    let metadata = Builder::finish(message);
+    out.extend_from_slice(&CONTINUATION);
+    out.extend_from_slice(&(metadata.len() as i32).to_le_bytes());
+    out.extend_from_slice(&metadata);
+    out.extend_from_slice(body);
+}
+
+fn pad_to_8(bytes: &mut Vec<u8>) {
+    while bytes.len() % 8 != 0 {
+        bytes.push(0);
+    }
+}
+
+fn slice(bytes: &[u8], pos: usize, len: usize) -> Result<&[u8], String> {
+    pos.checked_add(len)
+        .and_then(|end| bytes.get(pos..end))
+        .ok_or_else(|| "truncated Arrow data".to_string())
+}
+
+fn read_i32(bytes: &[u8], pos: usize) -> Result<i32, String> {
+    Ok(i32::from_le_bytes(
+        slice(bytes, pos, 4)?.try_into().unwrap(),
+    ))
+}
+
+//
+// flatbuffers
+//
+
+enum Node<'a> {
+    Table(Vec<(u16, Value<'a>)>),
+    Str(&'a str),
+    Tables(Vec<Node<'a>>),
+    // a vector of structs made of two longs, which is all Arrow needs
+    Structs(Vec<u8>),
+}
+
+enum Value<'a> {
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Offset(Node<'a>),
+}
+
+impl Node<'_> {
+    fn structs(pairs: &[(i64, i64)]) -> Self {
+        Node::Structs(
+            pairs
+                .iter()
+                .flat_map(|(a, b)| a.to_le_bytes().into_iter().chain(b.to_le_bytes()))
+                .collect(),
+        )
+    }
+}
+
+impl Value<'_> {
+    fn size(&self) -> usize {
+        match self {
+            Value::U8(_) => 1,
+            Value::I16(_) => 2,
+            Value::I32(_) | Value::Offset(_) => 4,
+            Value::I64(_) => 8,
+        }
+    }
+}
+
+// Writes the buffer front to back: every offset points forward, to an object
+// written after the one holding it.
+struct Builder {
+    buf: Vec<u8>,
+}
+
+impl Builder {
+    fn finish(root: Node) -> Vec<u8> {
+        let mut builder = Builder { buf: vec![0; 4] };
+        let root = builder.node(root);
+        builder.patch_offset(0, root);
+        pad_to_8(&mut builder.buf);
+        builder.buf
+    }
+
+    fn align(&mut self, alignment: usize, shift: usize) {
+        while (self.buf.len() + shift) % alignment != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn patch_offset(&mut self, at: usize, target: usize) {
+        let offset = (target - at) as u32;
+        self.buf[at..at + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    // returns the position offsets to the node must point to
+    fn node(&mut self, node: Node) -> usize {
+        match node {
+            Node::Str(s) => {
+                self.align(4, 0);
+                let pos = self.buf.len();
+                self.buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+                pos
+            }
+            Node::Structs(bytes) => {
+                // the structs hold longs, so they start 8-aligned
+                self.align(8, 4);
+                let pos = self.buf.len();
+                self.buf
+                    .extend_from_slice(&((bytes.len() / 16) as u32).to_le_bytes());
+                self.buf.extend_from_slice(&bytes);
+                pos
+            }
+            Node::Tables(tables) => {
+                self.align(4, 0);
+                let pos = self.buf.len();
+                self.buf
+                    .extend_from_slice(&(tables.len() as u32).to_le_bytes());
+                let slots = self.buf.len();
+                self.buf.resize(slots + 4 * tables.len(), 0);
+                for (i, table) in tables.into_iter().enumerate() {
+                    let target = self.node(table);
+                    self.patch_offset(slots + 4 * i, target);
+                }
+                pos
+            }
+            Node::Table(fields) => self.table(fields),
+        }
+    }
+
+    fn table(&mut self, mut fields: Vec<(u16, Value)>) -> usize {
+        // the fields are laid out largest first so they stay aligned
+        fields.sort_by_key(|(_, value)| std::cmp::Reverse(value.size()));
+        let num_slots = fields
+            .iter()
+            .map(|(id, _)| *id as usize + 1)
+            .max()
+            .unwrap_or(0);
+
+        let mut layout = vec![0_u16; num_slots];
+        let mut size = 4; // the offset to the vtable
+        let mut table_alignment = 4;
+        for (id, value) in &fields {
+            let field_size = value.size();
+            table_alignment = table_alignment.max(field_size);
+            while size % field_size != 0 {
+                size += 1;
+            }
+            layout[*id as usize] = size as u16;
+            size += field_size;
+        }
+
+        // the vtable goes right before the table
+        self.align(2, 0);
+        let vtable = self.buf.len();
+        self.buf
+            .extend_from_slice(&((4 + 2 * num_slots) as u16).to_le_bytes());
+        self.buf.extend_from_slice(&(size as u16).to_le_bytes());
+        for offset in &layout {
+            self.buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        self.align(table_alignment, 0);
+        let table = self.buf.len();
+        self.buf.resize(table + size, 0);
+        let to_vtable = (table - vtable) as i32;
+        self.buf[table..table + 4].copy_from_slice(&to_vtable.to_le_bytes());
+
+        let mut children = vec![];
+        for (id, value) in fields {
+            let at = table + layout[id as usize] as usize;
+            let bytes = match value {
+                Value::U8(v) => v.to_le_bytes().to_vec(),
+                Value::I16(v) => v.to_le_bytes().to_vec(),
+                Value::I32(v) => v.to_le_bytes().to_vec(),
+                Value::I64(v) => v.to_le_bytes().to_vec(),
+                Value::Offset(node) => {
+                    children.push((at, node));
+                    continue;
+                }
+            };
+            self.buf[at..at + bytes.len()].copy_from_slice(&bytes);
+        }
+        for (at, node) in children {
+            let target = self.node(node);
+            self.patch_offset(at, target);
+        }
+        table
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Table<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Table<'a> {
+    fn root(buf: &'a [u8]) -> Result<Self, String> {
+        Self::at_offset(buf, 0)
+    }
+
+    // the table an offset stored at `at` points to
+    fn at_offset(buf: &'a [u8], at: usize) -> Result<Self, String> {
+        let pos = at + read_u32(buf, at)? as usize;
+        Ok(Table { buf, pos })
+    }
+
+    // where field `id` is stored, if it is present
+    fn field(&self, id: usize) -> Result<Option<usize>, String> {
+        let vtable = self.pos as i64 - read_i32(self.buf, self.pos)? as i64;
+        if vtable < 0 {
+            return Err("invalid Arrow metadata".into());
+        }
+        let vtable = vtable as usize;
+        let vtable_len = read_u16(self.buf, vtable)? as usize;
+        if 4 + 2 * id >= vtable_len {
+            return Ok(None);
+        }
+        match read_u16(self.buf, vtable + 4 + 2 * id)? {
+            0 => Ok(None),
+            offset => Ok(Some(self.pos + offset as usize)),
+        }
+    }
+
+    fn scalar<const N: usize>(&self, id: usize) -> Result<Option<[u8; N]>, String> {
+        match self.field(id)? {
+            None => Ok(None),
+            Some(pos) => Ok(Some(slice(self.buf, pos, N)?.try_into().unwrap())),
+        }
+    }
+
+    fn u8(&self, id: usize, default: u8) -> Result<u8, String> {
+        Ok(self.scalar(id)?.map_or(default, u8::from_le_bytes))
+    }
+
+    fn i16(&self, id: usize, default: i16) -> Result<i16, String> {
+        Ok(self.scalar(id)?.map_or(default, i16::from_le_bytes))
+    }
+
+    fn i32(&self, id: usize, default: i32) -> Result<i32, String> {
+        Ok(self.scalar(id)?.map_or(default, i32::from_le_bytes))
+    }
+
+    fn i64(&self, id: usize, default: i64) -> Result<i64, String> {
+        Ok(self.scalar(id)?.map_or(default, i64::from_le_bytes))
+    }
+
+    fn table(&self, id: usize) -> Result<Option<Table<'a>>, String> {
+        match self.field(id)? {
+            None => Ok(None),
+            Some(pos) => Ok(Some(Self::at_offset(self.buf, pos)?)),
+        }
+    }
+
+    // the position of the elements of vector field `id` and their number
+    fn vector(&self, id: usize) -> Result<Option<(usize, usize)>, String> {
+        match self.field(id)? {
+            None => Ok(None),
+            Some(pos) => {
+                let start = pos + read_u32(self.buf, pos)? as usize;
+                let len = read_u32(self.buf, start)? as usize;
+                Ok(Some((start + 4, len)))
+            }
+        }
+    }
+
+    fn str(&self, id: usize) -> Result<Option<&'a str>, String> {
+        match self.vector(id)? {
+            None => Ok(None),
+            Some((start, len)) => std::str::from_utf8(slice(self.buf, start, len)?)
+                .map(Some)
+                .map_err(|_| "invalid UTF-8 in Arrow metadata".to_string()),
+        }
+    }
+
+    fn tables(&self, id: usize) -> Result<Vec<Table<'a>>, String> {
+        match self.vector(id)? {
+            None => Ok(vec![]),
+            Some((start, len)) => (0..len)
+                .map(|i| Self::at_offset(self.buf, start + 4 * i))
+                .collect(),
+        }
+    }
+
+    fn structs(&self, id: usize) -> Result<Vec<(i64, i64)>, String> {
+        let (start, len) = match self.vector(id)? {
+            None => return Ok(vec![]),
+            Some(vector) => vector,
+        };
+        let bytes = slice(self.buf, start, len * 16)?;
+        Ok(bytes
+            .chunks_exact(16)
+            .map(|pair| {
+                (
+                    i64::from_le_bytes(pair[..8].try_into().unwrap()),
+                    i64::from_le_bytes(pair[8..].try_into().unwrap()),
+                )
+            })
+            .collect())
+    }
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Result<u16, String> {
+    Ok(u16::from_le_bytes(
+        slice(bytes, pos, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(
+        slice(bytes, pos, 4)?.try_into().unwrap(),
+    ))
+}
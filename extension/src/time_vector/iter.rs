@@ -1,11 +1,16 @@
 use tspoint::TSPoint;
 
+use super::compressed::PointIter;
+
 use Iter::*;
 
 pub enum Iter<'a> {
     Slice {
         iter: flat_serialize::Iter<'a, 'a, TSPoint>,
     },
+    Compressed {
+        iter: PointIter<'a>,
+    },
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -14,6 +19,7 @@ impl<'a> Iterator for Iter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         match self {
             Slice { iter } => iter.next(),
+            Compressed { iter } => iter.next(),
         }
     }
 
@@ -24,6 +30,7 @@ impl<'a> Iterator for Iter<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) {
         match self {
             Slice { iter } => (iter.len(), Some(iter.len())),
+            Compressed { iter } => (iter.len(), Some(iter.len())),
         }
     }
 
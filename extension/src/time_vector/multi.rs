@@ -0,0 +1,877 @@
+//! Timevectors containing multiple series, each identified by a set of labels,
+//! similar to a Prometheus range vector. For example
+//! ```SQL
+//! SELECT multi_timevector(format('{host="%s"}', host), time, cpu_used)
+//!     -> sort() -> delta()
+//! FROM metrics;
+//! ```
+//! builds one series per host and applies the pipeline to each of them.
+
+use std::collections::BTreeMap;
+
+use pgx::{iter::TableIterator, *};
+use serde::{Deserialize, Serialize};
+
+use aggregate_builder::aggregate;
+use flat_serialize::*;
+use flat_serialize_macro::FlatSerializable;
+
+use tspoint::TSPoint;
+
+use crate::{
+    build,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::{bytea, TimestampTz},
+    ron_inout_funcs,
+};
+
+use super::{
+    pipeline::{run_pipeline_elements, UnstableTimevectorPipeline},
+    Timevector_TSTZ_F64, Timevector_TSTZ_F64Data, FLAG_HAS_NULLS, FLAG_IS_SORTED,
+};
+
+use toolkit_experimental::{MultiTimevector_TSTZ_F64, MultiTimevector_TSTZ_F64Data};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        #[allow(non_camel_case_types)]
+        struct MultiTimevector_TSTZ_F64<'input> {
+            num_series: u32,
+            num_labels: u32,
+            num_points: u32,
+            label_bytes_len: u32,
+            points: [TSPoint; self.num_points],
+            series: [SeriesEntry; self.num_series],
+            labels: [LabelEntry; self.num_labels],
+            null_val: [u8; (self.num_points + 7) / 8], // bit vector, same layout as Timevector_TSTZ_F64
+            label_bytes: [u8; self.label_bytes_len],
+        }
+    }
+
+    ron_inout_funcs!(MultiTimevector_TSTZ_F64);
+}
+
+// A series is a range of `points` and a range of `labels`, the flags are the
+// same as those of a Timevector_TSTZ_F64 with the same points
+#[derive(Clone, Copy, Debug, Deserialize, Eq, FlatSerializable, PartialEq, Serialize)]
+#[repr(C)]
+pub struct SeriesEntry {
+    points_beg: u32,
+    points_end: u32,
+    labels_beg: u32,
+    labels_end: u32,
+    flags: u8,
+    internal_padding: [u8; 3],
+}
+
+// A label is a name and value stored as ranges into `label_bytes`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, FlatSerializable, PartialEq, Serialize)]
+#[repr(C)]
+pub struct LabelEntry {
+    name_beg: u32,
+    name_end: u32,
+    value_beg: u32,
+    value_end: u32,
+}
+
+pub type Labels = Vec<(String, String)>;
+
+impl<'input> MultiTimevector_TSTZ_F64<'input> {
+    pub fn num_series(&self) -> usize {
+        self.num_series as usize
+    }
+
+    pub fn series_iter(&self) -> impl Iterator<Item = (Labels, Timevector_TSTZ_F64<'static>)> + '_ {
+        self.series
+            .iter()
+            .map(move |entry| (self.labels_of(&entry), self.timevector_of(&entry)))
+    }
+
+    // run on values read from their text or binary format, see
+    // `CheckInvariants`
+    pub fn check_invariants(&self) {
+        for entry in self.series.iter() {
+            if entry.points_beg > entry.points_end
+                || entry.points_end > self.num_points
+                || entry.labels_beg > entry.labels_end
+                || entry.labels_end > self.num_labels
+            {
+                panic!("invalid series {:?} in a multi-timevector", entry)
+            }
+        }
+        for label in self.labels.iter() {
+            self.label_str(label.name_beg, label.name_end);
+            self.label_str(label.value_beg, label.value_end);
+        }
+    }
+
+    fn label_str(&self, beg: u32, end: u32) -> &str {
+        let label_bytes = self.label_bytes.as_slice();
+        if beg > end || end as usize > label_bytes.len() {
+            panic!(
+                "invalid label range {}..{} in a multi-timevector with {} label bytes",
+                beg,
+                end,
+                label_bytes.len()
+            )
+        }
+        std::str::from_utf8(&label_bytes[beg as usize..end as usize])
+            .unwrap_or_else(|e| panic!("invalid label in a multi-timevector: {}", e))
+    }
+
+    fn labels_of(&self, entry: &SeriesEntry) -> Labels {
+        self.labels.as_slice()[entry.labels_beg as usize..entry.labels_end as usize]
+            .iter()
+            .map(|label| {
+                (
+                    self.label_str(label.name_beg, label.name_end).to_string(),
+                    self.label_str(label.value_beg, label.value_end).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn is_null_val(&self, index: usize) -> bool {
+        self.null_val.as_slice()[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn timevector_of(&self, entry: &SeriesEntry) -> Timevector_TSTZ_F64<'static> {
+        let beg = entry.points_beg as usize;
+        let end = entry.points_end as usize;
+        let points = self.points.as_slice()[beg..end].to_vec();
+        let mut null_val = std::vec::from_elem(0_u8, (points.len() + 7) / 8);
+        if entry.flags & FLAG_HAS_NULLS != 0 {
+            for i in 0..points.len() {
+                if self.is_null_val(beg + i) {
+                    null_val[i / 8] |= 1 << (i % 8);
+                }
+            }
+        }
+        build! {
+            Timevector_TSTZ_F64 {
+                num_points: points.len() as _,
+                flags: entry.flags,
+                internal_padding: [0; 3],
+                points: points.into(),
+                null_val: null_val.into(),
+            }
+        }
+    }
+}
+
+// Accumulates series into the flat layout used by MultiTimevector_TSTZ_F64
+#[derive(Default)]
+pub struct MultiTimevectorBuilder {
+    points: Vec<TSPoint>,
+    null_val: Vec<u8>,
+    series: Vec<SeriesEntry>,
+    labels: Vec<LabelEntry>,
+    label_bytes: String,
+}
+
+impl MultiTimevectorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // points are given as `(point, is_null)`
+    pub fn push_series(
+        &mut self,
+        labels: &[(String, String)],
+        points: impl IntoIterator<Item = (TSPoint, bool)>,
+    ) {
+        let labels_beg = self.labels.len() as u32;
+        for (name, value) in labels {
+            let name_beg = self.label_bytes.len() as u32;
+            self.label_bytes.push_str(name);
+            let value_beg = self.label_bytes.len() as u32;
+            self.label_bytes.push_str(value);
+            self.labels.push(LabelEntry {
+                name_beg,
+                name_end: value_beg,
+                value_beg,
+                value_end: self.label_bytes.len() as u32,
+            });
+        }
+
+        let points_beg = self.points.len() as u32;
+        let mut flags = FLAG_IS_SORTED;
+        let mut last_ts = None;
+        for (point, is_null) in points {
+            if matches!(last_ts, Some(ts) if ts > point.ts) {
+                flags &= !FLAG_IS_SORTED;
+            }
+            last_ts = Some(point.ts);
+            let idx = self.points.len();
+            if idx % 8 == 0 {
+                self.null_val.push(0);
+            }
+            if is_null {
+                flags |= FLAG_HAS_NULLS;
+                *self.null_val.last_mut().unwrap() |= 1 << (idx % 8);
+            }
+            self.points.push(point);
+        }
+
+        self.series.push(SeriesEntry {
+            points_beg,
+            points_end: self.points.len() as u32,
+            labels_beg,
+            labels_end: self.labels.len() as u32,
+            flags,
+            internal_padding: [0; 3],
+        });
+    }
+
+    pub fn push_timevector(&mut self, labels: &[(String, String)], series: &Timevector_TSTZ_F64) {
+        let has_nulls = series.has_nulls();
+        self.push_series(
+            labels,
+            series
+                .iter()
+                .enumerate()
+                .map(|(i, point)| (point, has_nulls && series.is_null_val(i))),
+        )
+    }
+
+    pub fn build(self) -> MultiTimevector_TSTZ_F64<'static> {
+        build! {
+            MultiTimevector_TSTZ_F64 {
+                num_series: self.series.len() as _,
+                num_labels: self.labels.len() as _,
+                num_points: self.points.len() as _,
+                label_bytes_len: self.label_bytes.len() as _,
+                points: self.points.into(),
+                series: self.series.into(),
+                labels: self.labels.into(),
+                null_val: self.null_val.into(),
+                label_bytes: self.label_bytes.into_bytes().into(),
+            }
+        }
+    }
+}
+
+//
+// label sets
+//
+
+// Parses a label set written either in the Prometheus style
+// `{job="api", instance="a:9090"}` or as a bare `job=api, instance=a:9090`.
+// The result is sorted by label name so that it can be used as a series key.
+pub fn parse_labels(input: &str) -> Labels {
+    let input = input.trim();
+    let input = match input.strip_prefix('{') {
+        Some(rest) => rest
+            .strip_suffix('}')
+            .unwrap_or_else(|| panic!("invalid label set `{}`: missing `}}`", input)),
+        None => input,
+    };
+
+    let mut labels = Labels::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while matches!(chars.peek(), Some(c) if c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                None | Some(',') => panic!(
+                    "invalid label set `{}`: expected `=` after label name",
+                    input
+                ),
+                Some('=') => break,
+                Some(c) => name.push(c),
+            }
+        }
+        let name = name.trim();
+        if name.is_empty() {
+            panic!("invalid label set `{}`: empty label name", input)
+        }
+
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    None => panic!("invalid label set `{}`: unterminated string", input),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some(c) => value.push(c),
+                        None => panic!("invalid label set `{}`: unterminated string", input),
+                    },
+                    Some(c) => value.push(c),
+                }
+            }
+            value
+        } else {
+            let value: String = chars.by_ref().take_while(|c| *c != ',').collect();
+            value.trim().to_string()
+        };
+
+        if labels.iter().any(|(n, _)| n == name) {
+            panic!("invalid label set `{}`: duplicate label `{}`", input, name)
+        }
+        labels.push((name.to_string(), value));
+    }
+
+    labels.sort();
+    labels
+}
+
+pub fn format_labels(labels: &[(String, String)]) -> String {
+    let mut output = String::from("{");
+    for (i, (name, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push_str(name);
+        output.push_str("=\"");
+        for c in value.chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                c => output.push(c),
+            }
+        }
+        output.push('"');
+    }
+    output.push('}');
+    output
+}
+
+//
+// aggregate
+//
+
+#[aggregate]
+impl toolkit_experimental::multi_timevector {
+    type State = MultiTimevectorTransState;
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("text")] labels: Option<String>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+    ) -> Option<State> {
+        let ts = match ts {
+            None => return state,
+            Some(ts) => ts.into(),
+        };
+        let labels = labels.map(|l| parse_labels(&l)).unwrap_or_default();
+        let mut state = state.unwrap_or_default();
+        state.series.entry(labels).or_default().push((ts, value));
+        Some(state)
+    }
+
+    fn combine(a: Option<&State>, b: Option<&State>) -> Option<State> {
+        match (a, b) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                for (labels, points) in &b.series {
+                    a.series
+                        .entry(labels.clone())
+                        .or_default()
+                        .extend_from_slice(points);
+                }
+                Some(a)
+            }
+        }
+    }
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, MultiTimevectorTransState)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<MultiTimevector_TSTZ_F64<'static>> {
+        state.map(|state| {
+            let mut builder = MultiTimevectorBuilder::new();
+            for (labels, points) in &state.series {
+                builder.push_series(
+                    labels,
+                    points.iter().map(|(ts, val)| {
+                        (
+                            TSPoint {
+                                ts: *ts,
+                                val: val.unwrap_or(f64::NAN),
+                            },
+                            val.is_none(),
+                        )
+                    }),
+                );
+            }
+            builder.build()
+        })
+    }
+}
+
+// Intermediate state kept in postgres, series are kept in label order.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MultiTimevectorTransState {
+    series: BTreeMap<Labels, Vec<(i64, Option<f64>)>>,
+}
+
+//
+// accessors
+//
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_multi<'a>(
+    multi: MultiTimevector_TSTZ_F64<'a>,
+) -> TableIterator<
+    'a,
+    (
+        name!(labels, String),
+        name!(time, TimestampTz),
+        name!(value, f64),
+    ),
+> {
+    let rows: Vec<_> = multi
+        .series_iter()
+        .flat_map(|(labels, series)| {
+            let labels = format_labels(&labels);
+            series
+                .into_iter()
+                .map(move |point| (labels.clone(), point.ts.into(), point.val))
+        })
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn into_series<'a>(
+    multi: MultiTimevector_TSTZ_F64<'a>,
+) -> TableIterator<
+    'a,
+    (
+        name!(labels, String),
+        name!(series, Timevector_TSTZ_F64<'static>),
+    ),
+> {
+    let rows: Vec<_> = multi
+        .series_iter()
+        .map(|(labels, series)| (format_labels(&labels), series))
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_series<'a>(multi: MultiTimevector_TSTZ_F64<'a>) -> i64 {
+    multi.num_series() as _
+}
+
+//
+// pipelines
+//
+
+// TODO add a support function so that `multi -> a() -> b()` folds into a
+//      single pipeline the way it does for single-series timevectors
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_per_series<'a>(
+    multi: MultiTimevector_TSTZ_F64<'a>,
+    pipeline: UnstableTimevectorPipeline<'a>,
+) -> MultiTimevector_TSTZ_F64<'static> {
+    let mut builder = MultiTimevectorBuilder::new();
+    for (labels, series) in multi.series_iter() {
+        let series = run_pipeline_elements(series, pipeline.elements.iter());
+        builder.push_timevector(&labels, &series);
+    }
+    builder.build()
+}
+
+//
+// vector matching
+//
+
+#[derive(Clone, Copy, Debug)]
+pub enum VectorOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+impl VectorOp {
+    fn from_name(name: &str) -> Self {
+        use VectorOp::*;
+        match name.trim().to_lowercase().as_str() {
+            "+" | "add" => Add,
+            "-" | "sub" => Sub,
+            "*" | "mul" => Mul,
+            "/" | "div" => Div,
+            "%" | "mod" => Mod,
+            "^" | "pow" | "power" => Pow,
+            _ => panic!("invalid binary operator `{}`", name),
+        }
+    }
+
+    fn apply(self, lhs: f64, rhs: f64) -> f64 {
+        use VectorOp::*;
+        match self {
+            Add => lhs + rhs,
+            Sub => lhs - rhs,
+            Mul => lhs * rhs,
+            Div => lhs / rhs,
+            Mod => lhs % rhs,
+            Pow => lhs.powf(rhs),
+        }
+    }
+}
+
+// The metric name is never used for matching and is dropped from the result,
+// as in PromQL arithmetic.
+const METRIC_NAME_LABEL: &str = "__name__";
+
+// The labels that a series is matched on, and that the result series will
+// have. With `on` only the listed labels are used, otherwise all of them.
+fn matching_labels(labels: &[(String, String)], on: Option<&[String]>) -> Labels {
+    labels
+        .iter()
+        .filter(|(name, _)| name != METRIC_NAME_LABEL)
+        .filter(|(name, _)| on.map_or(true, |on| on.contains(name)))
+        .cloned()
+        .collect()
+}
+
+// One-to-one vector matching: every series on the left is paired with the
+// series on the right that has the same matching labels, series without a
+// partner are dropped. Within a pair, points are joined on equal timestamps.
+pub fn match_series(
+    lhs: &MultiTimevector_TSTZ_F64<'_>,
+    rhs: &MultiTimevector_TSTZ_F64<'_>,
+    op: VectorOp,
+    on: Option<&[String]>,
+) -> MultiTimevector_TSTZ_F64<'static> {
+    let mut rhs_by_labels = BTreeMap::new();
+    for (labels, series) in rhs.series_iter() {
+        let key = matching_labels(&labels, on);
+        if rhs_by_labels.insert(key.clone(), series).is_some() {
+            panic!(
+                "found duplicate series for the match group {} on the right-hand side of the operation",
+                format_labels(&key)
+            )
+        }
+    }
+
+    let mut seen = BTreeMap::new();
+    let mut builder = MultiTimevectorBuilder::new();
+    for (labels, lhs_series) in lhs.series_iter() {
+        let key = matching_labels(&labels, on);
+        let rhs_series = match rhs_by_labels.get(&key) {
+            None => continue,
+            Some(series) => series,
+        };
+        if seen.insert(key.clone(), ()).is_some() {
+            panic!(
+                "found duplicate series for the match group {} on the left-hand side of the operation",
+                format_labels(&key)
+            )
+        }
+
+        let rhs_points: BTreeMap<i64, Option<f64>> = rhs_series
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let is_null = rhs_series.has_nulls() && rhs_series.is_null_val(i);
+                (p.ts, if is_null { None } else { Some(p.val) })
+            })
+            .collect();
+        let joined: Vec<_> = lhs_series
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let rhs_val = rhs_points.get(&p.ts)?;
+                let is_null = lhs_series.has_nulls() && lhs_series.is_null_val(i);
+                let val = match (is_null, rhs_val) {
+                    (false, Some(rhs_val)) => Some(op.apply(p.val, *rhs_val)),
+                    _ => None,
+                };
+                Some((
+                    TSPoint {
+                        ts: p.ts,
+                        val: val.unwrap_or(f64::NAN),
+                    },
+                    val.is_none(),
+                ))
+            })
+            .collect();
+        builder.push_series(&key, joined);
+    }
+    builder.build()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn vector_match_on<'a>(
+    lhs: MultiTimevector_TSTZ_F64<'a>,
+    op: String,
+    rhs: MultiTimevector_TSTZ_F64<'a>,
+    on_labels: String,
+) -> MultiTimevector_TSTZ_F64<'static> {
+    let on: Vec<String> = on_labels
+        .split(',')
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    match_series(&lhs, &rhs, VectorOp::from_name(&op), Some(&on))
+}
+
+// Matches on all labels other than the metric name.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn vector_match<'a>(
+    lhs: MultiTimevector_TSTZ_F64<'a>,
+    op: String,
+    rhs: MultiTimevector_TSTZ_F64<'a>,
+) -> MultiTimevector_TSTZ_F64<'static> {
+    match_series(&lhs, &rhs, VectorOp::from_name(&op), None)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    fn setup(client: &SpiClient) {
+        client.select("SET timezone TO 'UTC'", None, None);
+        // using the search path trick for this test b/c the operator is
+        // difficult to spot otherwise.
+        let sp = client
+            .select(
+                "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                None,
+                None,
+            )
+            .first()
+            .get_one::<String>()
+            .unwrap();
+        client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+        client.select(
+            "CREATE TABLE metrics(time TIMESTAMPTZ, labels TEXT, value DOUBLE PRECISION)",
+            None,
+            None,
+        );
+        client.select(
+            r#"INSERT INTO metrics VALUES
+                ('2020-1-2', '{job="api", instance="a"}', 25.0),
+                ('2020-1-1', '{job="api", instance="a"}', 10.0),
+                ('2020-1-1', 'instance=b, job=api', 40.0),
+                ('2020-1-2', '{instance="b",job="api"}', NULL),
+                ('2020-1-3', '{job="api", instance="b"}', 45.0),
+                ('2020-1-1', '{job="db"}', 1.0)"#,
+            None,
+            None,
+        );
+    }
+
+    #[pg_test]
+    fn test_multi_timevector_unnest() {
+        Spi::execute(|client| {
+            setup(&client);
+
+            let num_series = client
+                .select(
+                    "SELECT num_series(multi_timevector(labels, time, value)) FROM metrics",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(num_series, 3);
+
+            let mut unnest = client.select(
+                "SELECT format('%s %s %s', labels, time, value) \
+                FROM unnest((SELECT multi_timevector(labels, time, value) FROM metrics))",
+                None,
+                None,
+            );
+            // series are in label order, points in insertion order
+            for expected in [
+                r#"{instance="a",job="api"} 2020-01-02 00:00:00+00 25"#,
+                r#"{instance="a",job="api"} 2020-01-01 00:00:00+00 10"#,
+                r#"{instance="b",job="api"} 2020-01-01 00:00:00+00 40"#,
+                r#"{instance="b",job="api"} 2020-01-02 00:00:00+00 NaN"#,
+                r#"{instance="b",job="api"} 2020-01-03 00:00:00+00 45"#,
+                r#"{job="db"} 2020-01-01 00:00:00+00 1"#,
+            ] {
+                assert_eq!(
+                    unnest.next().unwrap()[1].value::<String>().unwrap(),
+                    expected
+                );
+            }
+            assert!(unnest.next().is_none());
+
+            let mut series = client.select(
+                "SELECT labels, series::TEXT \
+                FROM into_series((SELECT multi_timevector(labels, time, value) FROM metrics)) \
+                WHERE labels = '{job=\"db\"}'",
+                None,
+                None,
+            );
+            let row = series.next().unwrap();
+            assert_eq!(row[1].value::<String>().unwrap(), r#"{job="db"}"#);
+            assert_eq!(
+                row[2].value::<String>().unwrap(),
+                "(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[\
+                    (ts:\"2020-01-01 00:00:00+00\",val:1)\
+                ],null_val:[0])"
+            );
+            assert!(series.next().is_none());
+        });
+    }
+
+    #[pg_test(error = "invalid label range 1..3 in a multi-timevector with 2 label bytes")]
+    fn test_multi_timevector_label_out_of_range() {
+        Spi::execute(|client| {
+            setup(&client);
+            client.select(
+                "SELECT replace(multi_timevector('{a=\"b\"}', time, value)::TEXT, 'value_end:2', 'value_end:3')\
+                    ::toolkit_experimental.MultiTimevector_TSTZ_F64 \
+                FROM metrics",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "invalid label in a multi-timevector: invalid utf-8 sequence of 1 bytes from index 1"
+    )]
+    fn test_multi_timevector_label_not_utf8() {
+        Spi::execute(|client| {
+            setup(&client);
+            client.select(
+                "SELECT replace(multi_timevector('{a=\"b\"}', time, value)::TEXT, '[97,98]', '[97,255]')\
+                    ::toolkit_experimental.MultiTimevector_TSTZ_F64 \
+                FROM metrics",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_multi_timevector_pipeline() {
+        Spi::execute(|client| {
+            setup(&client);
+
+            let mut unnest = client.select(
+                "SELECT format('%s %s %s', labels, time, value) \
+                FROM unnest((SELECT multi_timevector(labels, time, value) -> sort() -> mul(2.0) FROM metrics))",
+                None,
+                None,
+            );
+            for expected in [
+                r#"{instance="a",job="api"} 2020-01-01 00:00:00+00 20"#,
+                r#"{instance="a",job="api"} 2020-01-02 00:00:00+00 50"#,
+                r#"{instance="b",job="api"} 2020-01-01 00:00:00+00 80"#,
+                r#"{instance="b",job="api"} 2020-01-02 00:00:00+00 NaN"#,
+                r#"{instance="b",job="api"} 2020-01-03 00:00:00+00 90"#,
+                r#"{job="db"} 2020-01-01 00:00:00+00 2"#,
+            ] {
+                assert_eq!(
+                    unnest.next().unwrap()[1].value::<String>().unwrap(),
+                    expected
+                );
+            }
+            assert!(unnest.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn test_multi_timevector_vector_matching() {
+        Spi::execute(|client| {
+            setup(&client);
+            client.select(
+                "CREATE TABLE totals AS SELECT time, \
+                    replace(labels, 'api', 'total') AS labels, 100.0::float8 AS value \
+                FROM metrics WHERE labels LIKE '%api%'",
+                None,
+                None,
+            );
+
+            // every series matches the one with the identical label set
+            let mut unnest = client.select(
+                "SELECT format('%s %s %s', labels, time, value) \
+                FROM unnest(vector_match( \
+                    (SELECT multi_timevector(labels, time, value) FROM metrics), \
+                    '+', \
+                    (SELECT multi_timevector(labels, time, value) FROM metrics) \
+                ))",
+                None,
+                None,
+            );
+            for expected in [
+                r#"{instance="a",job="api"} 2020-01-02 00:00:00+00 50"#,
+                r#"{instance="a",job="api"} 2020-01-01 00:00:00+00 20"#,
+                r#"{instance="b",job="api"} 2020-01-01 00:00:00+00 80"#,
+                r#"{instance="b",job="api"} 2020-01-02 00:00:00+00 NaN"#,
+                r#"{instance="b",job="api"} 2020-01-03 00:00:00+00 90"#,
+                r#"{job="db"} 2020-01-01 00:00:00+00 2"#,
+            ] {
+                assert_eq!(
+                    unnest.next().unwrap()[1].value::<String>().unwrap(),
+                    expected
+                );
+            }
+            assert!(unnest.next().is_none());
+
+            // matching only on `instance` pairs up series with different jobs
+            let mut unnest = client.select(
+                "SELECT format('%s %s %s', labels, time, value) \
+                FROM unnest(vector_match_on( \
+                    (SELECT multi_timevector(labels, time, value) FROM metrics WHERE labels LIKE '%api%'), \
+                    '/', \
+                    (SELECT multi_timevector(labels, time, value) FROM totals), \
+                    'instance' \
+                ))",
+                None,
+                None,
+            );
+            for expected in [
+                r#"{instance="a"} 2020-01-02 00:00:00+00 0.25"#,
+                r#"{instance="a"} 2020-01-01 00:00:00+00 0.1"#,
+                r#"{instance="b"} 2020-01-01 00:00:00+00 0.4"#,
+                r#"{instance="b"} 2020-01-02 00:00:00+00 NaN"#,
+                r#"{instance="b"} 2020-01-03 00:00:00+00 0.45"#,
+            ] {
+                assert_eq!(
+                    unnest.next().unwrap()[1].value::<String>().unwrap(),
+                    expected
+                );
+            }
+            assert!(unnest.next().is_none());
+        });
+    }
+}
@@ -1,12 +1,18 @@
 mod aggregation;
+mod anomaly;
 mod arithmetic;
 mod delta;
 mod expansion;
+mod explain;
 mod fill_to;
 mod filter;
 mod lambda;
 mod map;
+mod resample;
+mod rolling;
+mod smoothing;
 mod sort;
+mod stream;
 
 use std::convert::TryInto;
 
@@ -18,8 +24,15 @@ use crate::{flatten, pg_type, ron_inout_funcs};
 
 use fill_to::{fill_to, FillToMethod};
 
+use anomaly::detect_anomalies;
 use delta::timevector_delta;
+use resample::resample;
+use rolling::rolling;
+use smoothing::smooth;
 use sort::sort_timevector;
+use stream::PointStream;
+
+use super::typed::{TimevectorValue, TypedSeries};
 
 pub use self::toolkit_experimental::*;
 use crate::serialization::PgProcId;
@@ -76,6 +89,42 @@ pub mod toolkit_experimental {
                 interval: i64,
                 fill_method: FillToMethod,
             },
+            // replaces resample_to_rate, the old tag is not reused so that
+            // stored pipelines containing it cannot be misread
+            Resample: 12 {
+                months: i64,
+                usecs: i64,
+                origin: i64,
+                aggregate: resample::ResampleAggregate,
+                timezone_len: u32,
+                timezone: [u8; self.timezone_len],
+            },
+            Rolling: 13 {
+                function: rolling::RollingFunction,
+                alignment: rolling::RollingAlignment,
+                // exactly one of these is non-zero
+                num_points: u64,
+                usecs: i64,
+            },
+            Smoothing: 14 {
+                model: smoothing::SmoothingModel,
+                season_length: u64,
+                // NaN for parameters that should be estimated
+                alpha: f64,
+                beta: f64,
+                gamma: f64,
+                forecast_points: u64,
+            },
+            Anomaly: 15 {
+                method: anomaly::AnomalyMethod,
+                output: anomaly::AnomalyOutput,
+                // zscore window or seasonal period, 0 if not given
+                window: u64,
+                threshold: f64,
+            },
+            FlatMapLambda: 16 {
+                lambda: LambdaData<'input>,
+            },
         }
     }
 
@@ -120,29 +169,102 @@ pub fn arrow_run_pipeline<'a>(
 }
 
 pub fn run_pipeline_elements<'s, 'j, 'i>(
-    mut timevector: Timevector_TSTZ_F64<'s>,
+    timevector: Timevector_TSTZ_F64<'s>,
     pipeline: impl Iterator<Item = Element<'j>> + 'i,
 ) -> Timevector_TSTZ_F64<'s> {
-    for element in pipeline {
-        timevector = execute_pipeline_element(timevector, &element);
+    stream_pipeline_elements(timevector, pipeline).materialize()
+}
+
+// Sets up the pipeline as a chain of iterators over the points of
+// `timevector`. Only the elements that need the whole timevector at once are
+// run before the points are asked for, on the points up to them.
+pub fn stream_pipeline_elements<'s, 'j, 'i>(
+    timevector: Timevector_TSTZ_F64<'s>,
+    pipeline: impl Iterator<Item = Element<'j>> + 'i,
+) -> PointStream<'s> {
+    let elements: Vec<_> = pipeline.collect();
+    let mut stream = PointStream::new(timevector);
+    for step in explain::plan(&elements) {
+        let element = &step.elements[0];
+        stream = match step.fusion {
+            Some(explain::Fusion::Arithmetic) => arithmetic::apply(stream, step.elements),
+            // redundant elements are skipped
+            None | Some(explain::Fusion::Redundant) => {
+                if explain::forces_materialization(element) {
+                    PointStream::new(execute_pipeline_element(stream.materialize(), element))
+                } else {
+                    stream_pipeline_element(stream, element)
+                }
+            }
+        };
     }
-    timevector
+    stream
 }
 
-pub fn execute_pipeline_element<'s>(
+fn stream_pipeline_element<'s>(stream: PointStream<'s>, element: &Element) -> PointStream<'s> {
+    match element {
+        Element::Delta { .. } => timevector_delta(stream),
+        Element::MapData { function } => map::apply_to(stream, function.0),
+        Element::MapLambda { lambda } => map::apply_lambda_to(stream, lambda),
+        Element::FilterLambda { lambda } => filter::apply_lambda_to(stream, lambda),
+        Element::FlatMapLambda { lambda } => map::apply_flat_map_lambda_to(stream, lambda),
+        Element::Arithmetic { .. } => arithmetic::apply(stream, std::slice::from_ref(element)),
+        Element::FillTo { .. } => fill_to(stream, element),
+        _ => unreachable!(),
+    }
+}
+
+fn execute_pipeline_element<'s>(
     timevector: Timevector_TSTZ_F64<'s>,
     element: &Element,
 ) -> Timevector_TSTZ_F64<'s> {
-    match element {
+    let time_axis = timevector.time_axis();
+    let mut result = match element {
         Element::LTTB { resolution } => crate::lttb::lttb_ts(timevector, *resolution as _),
         Element::Sort { .. } => sort_timevector(timevector),
-        Element::Delta { .. } => timevector_delta(&timevector),
-        Element::MapData { function } => map::apply_to(timevector, function.0),
         Element::MapSeries { function } => map::apply_to_series(timevector, function.0),
-        Element::MapLambda { lambda } => map::apply_lambda_to(timevector, lambda),
-        Element::FilterLambda { lambda } => filter::apply_lambda_to(timevector, lambda),
-        Element::Arithmetic { function, rhs } => arithmetic::apply(timevector, *function, *rhs),
-        Element::FillTo { .. } => fill_to(timevector, element),
+        Element::Resample { .. } => resample(&timevector, element),
+        Element::Rolling { .. } => rolling(&timevector, element),
+        Element::Smoothing { .. } => smooth(&timevector, element),
+        Element::Anomaly { .. } => detect_anomalies(&timevector, element),
+        _ => unreachable!(),
+    };
+    // elements that build a new timevector start from TIMESTAMPTZ flags
+    if result.time_axis() != time_axis {
+        result.flags = time_axis.set_in(result.flags);
+    }
+    result
+}
+
+// Runs a pipeline over one of the non-float timevectors. Only the elements
+// that don't look at the values as numbers can be used.
+pub fn run_typed_pipeline_elements<'j, T: TimevectorValue + filter::LambdaValue>(
+    mut series: TypedSeries<T>,
+    pipeline: impl Iterator<Item = Element<'j>>,
+) -> TypedSeries<T> {
+    for element in pipeline {
+        series = match &element {
+            Element::Sort { .. } => sort::sort_typed(series),
+            Element::FilterLambda { lambda } => filter::apply_lambda_to_typed(series, lambda),
+            Element::FillTo { .. } => fill_to::fill_to_typed(series, &element),
+            _ => panic!(
+                "only sort, filter and fill_to can be applied to a {}",
+                T::TIMEVECTOR_TYPE
+            ),
+        };
+    }
+    series
+}
+
+// Lambdas work with TIMESTAMPTZ times, this is the axis to convert a
+// timevector's times from before passing them in. Lambdas that never look at
+// `$time` get the times as they are, so they also work for integer times
+// without a unit.
+fn lambda_time_axis(expression: &lambda::Expression, time_axis: TimeAxis) -> TimeAxis {
+    if expression.uses_time() {
+        time_axis
+    } else {
+        TimeAxis::TimestampTz
     }
 }
 
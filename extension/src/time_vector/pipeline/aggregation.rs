@@ -2,7 +2,8 @@ use std::mem::take;
 
 use pgx::*;
 
-use counter_agg::CounterSummaryBuilder;
+use counter_agg::{CounterSummaryBuilder, GaugeSummaryBuilder};
+use time_weighted_average::TimeWeightMethod;
 
 use super::*;
 
@@ -10,18 +11,29 @@ use crate::{
     accessors::{AccessorAverage, AccessorNumVals, AccessorSum},
     build,
     counter_agg::CounterSummary,
+    frequency::{freq_agg_from_floats, toolkit_experimental::SpaceSavingAggregate},
+    gauge_agg::toolkit_experimental::GaugeSummary,
     hyperloglog::HyperLogLog,
+    nmost::{max_n_float_from_values, min_n_float_from_values, MaxFloats, MinFloats},
+    ohlc::toolkit_experimental::Candlestick,
     pg_type, ron_inout_funcs,
+    state_aggregate::{state_agg_from_series, toolkit_experimental::StateAgg},
     stats_agg::{self, InternalStatsSummary1D, StatsSummary1D},
+    tdigest::TDigest,
+    time_weighted_average::{parse_method, TimeWeightSummary},
     uddsketch::UddSketch,
 };
 
 use self::toolkit_experimental::{
-    PipelineThenAverage, PipelineThenAverageData, PipelineThenCounterAgg,
-    PipelineThenCounterAggData, PipelineThenHyperLogLog, PipelineThenHyperLogLogData,
-    PipelineThenNumVals, PipelineThenNumValsData, PipelineThenPercentileAgg,
-    PipelineThenPercentileAggData, PipelineThenStatsAgg, PipelineThenStatsAggData, PipelineThenSum,
-    PipelineThenSumData,
+    PipelineThenAverage, PipelineThenAverageData, PipelineThenCandlestick,
+    PipelineThenCandlestickData, PipelineThenCounterAgg, PipelineThenCounterAggData,
+    PipelineThenFreqAgg, PipelineThenFreqAggData, PipelineThenGaugeAgg, PipelineThenGaugeAggData,
+    PipelineThenHyperLogLog, PipelineThenHyperLogLogData, PipelineThenMaxN, PipelineThenMaxNData,
+    PipelineThenMinN, PipelineThenMinNData, PipelineThenNumVals, PipelineThenNumValsData,
+    PipelineThenPercentileAgg, PipelineThenPercentileAggData, PipelineThenStateAgg,
+    PipelineThenStateAggData, PipelineThenStatsAgg, PipelineThenStatsAggData, PipelineThenSum,
+    PipelineThenSumData, PipelineThenTDigest, PipelineThenTDigestData, PipelineThenTimeWeight,
+    PipelineThenTimeWeightData, PipelineThenUddSketch, PipelineThenUddSketchData,
 };
 
 #[pg_schema]
@@ -99,20 +111,126 @@ pub mod toolkit_experimental {
     }
 
     ron_inout_funcs!(PipelineThenPercentileAgg);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenGaugeAgg<'input> {
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenGaugeAgg);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenTimeWeight<'input> {
+            method: TimeWeightMethod,
+            internal_padding: [u8; 7],
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenTimeWeight);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenCandlestick<'input> {
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenCandlestick);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenUddSketch<'input> {
+            size: u64,
+            max_error: f64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenUddSketch);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenTDigest<'input> {
+            size: u64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenTDigest);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenFreqAgg<'input> {
+            min_freq: f64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenFreqAgg);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenMinN<'input> {
+            capacity: u64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenMinN);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenMaxN<'input> {
+            capacity: u64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenMaxN);
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenStateAgg<'input> {
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenStateAgg);
+}
+
+// The aggregates skip NULL values, as they do when run over a table.
+fn non_null_points<'s>(stream: PointStream<'s>) -> impl Iterator<Item = TSPoint> + 's {
+    stream
+        .into_points()
+        .filter(|(_, null)| !null)
+        .map(|(point, _)| point)
 }
 
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_stats_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenStatsAgg<'a>,
 ) -> StatsSummary1D<'static> {
     if timevector.has_nulls() {
         panic!("Unable to compute stats aggregate over timevector containing nulls");
     }
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter()).into_points();
     let mut stats = InternalStatsSummary1D::new();
-    for TSPoint { val, .. } in timevector.iter() {
+    for (TSPoint { val, .. }, _) in points {
         stats.accum(val).expect("error while running stats_agg");
     }
     StatsSummary1D::from_internal(stats)
@@ -402,7 +520,9 @@ pub fn arrow_pipeline_then_num_vals<'a>(
     timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenNumVals<'a>,
 ) -> i64 {
-    run_pipeline_elements(timevector, pipeline.elements.iter()).num_vals() as _
+    stream_pipeline_elements(timevector, pipeline.elements.iter())
+        .into_points()
+        .count() as _
 }
 
 #[pg_operator(immutable, parallel_safe)]
@@ -452,20 +572,19 @@ ALTER FUNCTION "arrow_pipeline_then_num_vals" SUPPORT toolkit_experimental.pipel
     requires = [pipeline_num_vals_support],
 );
 
-// TODO support gauge
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_counter_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenCounterAgg<'a>,
 ) -> Option<CounterSummary<'static>> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
-    if timevector.num_points() == 0 {
-        return None;
-    }
-    let mut it = timevector.iter();
-    let mut summary = CounterSummaryBuilder::new(&it.next().unwrap(), None);
-    for point in it {
+    let stream = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    stream
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "counter_agg");
+    let mut points = stream.into_points().map(|(point, _)| point);
+    let mut summary = CounterSummaryBuilder::new(&points.next()?, None);
+    for point in points {
         summary
             .add_point(&point)
             .expect("error while running counter_agg");
@@ -542,17 +661,15 @@ ALTER FUNCTION "arrow_run_pipeline_then_counter_agg" SUPPORT toolkit_experimenta
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_hyperloglog<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenHyperLogLog<'a>,
 ) -> HyperLogLog<'static> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter()).into_points();
     HyperLogLog::build_from(
         pipeline.hll_size as i32,
         PgBuiltInOids::FLOAT8OID as u32,
         None,
-        timevector
-            .iter()
-            .map(|point| point.val.into_datum().unwrap()),
+        points.map(|(point, _)| point.val.into_datum().unwrap()),
     )
 }
 
@@ -626,11 +743,11 @@ ALTER FUNCTION "arrow_run_pipeline_then_hyperloglog" SUPPORT toolkit_experimenta
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_percentile_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenPercentileAgg<'a>,
 ) -> UddSketch<'static> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
-    UddSketch::from_iter(timevector.into_iter().map(|p| p.val))
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter()).into_points();
+    UddSketch::from_iter(points.map(|(point, _)| point.val))
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -697,143 +814,1045 @@ ALTER FUNCTION "arrow_run_pipeline_then_percentile_agg" SUPPORT toolkit_experime
     requires = [pipeline_percentile_agg_support],
 );
 
-#[cfg(any(test, feature = "pg_test"))]
-#[pg_schema]
-mod tests {
-    use pgx::*;
-    use pgx_macros::pg_test;
-
-    #[pg_test]
-    fn test_stats_agg_finalizer() {
-        Spi::execute(|client| {
-            client.select("SET timezone TO 'UTC'", None, None);
-            // using the search path trick for this test b/c the operator is
-            // difficult to spot otherwise.
-            let sp = client
-                .select(
-                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<String>()
-                .unwrap();
-            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
-
-            // we use a subselect to guarantee order
-            let create_series = "SELECT timevector(time, value) as series FROM \
-                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
-                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
-                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
-                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
-                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_gauge_agg<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenGaugeAgg<'a>,
+) -> Option<GaugeSummary<'static>> {
+    let stream = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    stream
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "gauge_agg");
+    let mut points = non_null_points(stream);
+    let mut summary = GaugeSummaryBuilder::new(&points.next()?, None);
+    for point in points {
+        summary
+            .add_point(&point)
+            .expect("error while running gauge_agg");
+    }
+    Some(summary.build().into())
+}
 
-            let val = client
-                .select(
-                    &format!(
-                        "SELECT (series -> stats_agg())::TEXT FROM ({}) s",
-                        create_series
-                    ),
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<String>();
-            assert_eq!(
-                val.unwrap(),
-                "(version:1,n:5,sx:100,sx2:250,sx3:0,sx4:21250)"
-            );
-        });
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_gauge_agg<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_gauge_agg: toolkit_experimental::PipelineThenGaugeAgg<'e>,
+) -> toolkit_experimental::PipelineThenGaugeAgg<'e> {
+    if then_gauge_agg.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenGaugeAgg {
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
     }
 
-    #[pg_test]
-    fn test_stats_agg_pipeline_folding() {
-        Spi::execute(|client| {
-            client.select("SET timezone TO 'UTC'", None, None);
-            // using the search path trick for this test b/c the operator is
-            // difficult to spot otherwise.
-            let sp = client
-                .select(
-                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<String>()
-                .unwrap();
-            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_gauge_agg.elements.iter());
+    build! {
+        PipelineThenGaugeAgg {
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
 
-            let output = client
-                .select(
-                    "EXPLAIN (verbose) SELECT \
-                timevector('1930-04-05'::timestamptz, 123.0) \
-                -> ceil() -> abs() -> floor() \
-                -> stats_agg() -> average();",
-                    None,
-                    None,
-                )
-                .nth(1)
-                .unwrap()
-                .by_ordinal(1)
-                .unwrap()
-                .value::<String>()
-                .unwrap();
-            assert_eq!(output.trim(), "Output: (\
-                arrow_run_pipeline_then_stats_agg(\
-                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,num_elements:3,elements:[\
-                        Arithmetic(function:Ceil,rhs:0),\
-                        Arithmetic(function:Abs,rhs:0),\
-                        Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethenstatsagg\
-                ) -> '(version:1)'::accessoraverage)");
-        });
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "gauge_agg",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_gauge_agg() -> toolkit_experimental::PipelineThenGaugeAgg<'static> {
+    build! {
+        PipelineThenGaugeAgg {
+            num_elements: 0,
+            elements: vec![].into(),
+        }
     }
+}
 
-    #[pg_test]
-    fn test_sum_finalizer() {
-        Spi::execute(|client| {
-            client.select("SET timezone TO 'UTC'", None, None);
-            // using the search path trick for this test b/c the operator is
-            // difficult to spot otherwise.
-            let sp = client
-                .select(
-                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<String>()
-                .unwrap();
-            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_gauge_agg_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenGaugeAgg::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_gauge_agg(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
 
-            // we use a subselect to guarantee order
-            let create_series = "SELECT timevector(time, value) as series FROM \
-                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
-                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
-                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
-                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
-                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_gauge_agg" SUPPORT toolkit_experimental.pipeline_gauge_agg_support;
+"#,
+    name = "pipe_then_gauge_agg",
+    requires = [pipeline_gauge_agg_support],
+);
 
-            let val = client
-                .select(
-                    &format!("SELECT (series -> sum())::TEXT FROM ({}) s", create_series),
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<String>();
-            assert_eq!(val.unwrap(), "100");
-        });
-    }
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_time_weight<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenTimeWeight<'a>,
+) -> Option<TimeWeightSummary<'static>> {
+    let stream = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    stream
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "time_weight");
+    TimeWeightSummary::from_sorted_points(pipeline.method, non_null_points(stream))
+}
 
-    #[pg_test]
-    fn test_sum_pipeline_folding() {
-        Spi::execute(|client| {
-            client.select("SET timezone TO 'UTC'", None, None);
-            // using the search path trick for this test b/c the operator is
-            // difficult to spot otherwise.
-            let sp = client
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_time_weight<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_time_weight: toolkit_experimental::PipelineThenTimeWeight<'e>,
+) -> toolkit_experimental::PipelineThenTimeWeight<'e> {
+    if then_time_weight.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenTimeWeight {
+                    method: then_time_weight.method,
+                    internal_padding: then_time_weight.internal_padding,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_time_weight.elements.iter());
+    build! {
+        PipelineThenTimeWeight {
+            method: then_time_weight.method,
+            internal_padding: then_time_weight.internal_padding,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "time_weight",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_time_weight(
+    method: String,
+) -> toolkit_experimental::PipelineThenTimeWeight<'static> {
+    build! {
+        PipelineThenTimeWeight {
+            method: parse_method(&method),
+            internal_padding: [0; 7],
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_time_weight_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenTimeWeight::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_time_weight(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_time_weight" SUPPORT toolkit_experimental.pipeline_time_weight_support;
+"#,
+    name = "pipe_then_time_weight",
+    requires = [pipeline_time_weight_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_candlestick_agg<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenCandlestick<'a>,
+) -> Option<Candlestick<'static>> {
+    let stream = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    stream
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "candlestick_agg");
+    let mut points = non_null_points(stream);
+    let first = points.next()?;
+    let mut candlestick = Candlestick::from_tick(first.ts, first.val, None);
+    for point in points {
+        candlestick.add_tick_data(point.ts, point.val, None);
+    }
+    Some(candlestick)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_candlestick_agg<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_candlestick_agg: toolkit_experimental::PipelineThenCandlestick<'e>,
+) -> toolkit_experimental::PipelineThenCandlestick<'e> {
+    if then_candlestick_agg.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenCandlestick {
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_candlestick_agg.elements.iter());
+    build! {
+        PipelineThenCandlestick {
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "candlestick_agg",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_candlestick_agg() -> toolkit_experimental::PipelineThenCandlestick<'static> {
+    build! {
+        PipelineThenCandlestick {
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_candlestick_agg_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenCandlestick::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_candlestick_agg(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_candlestick_agg" SUPPORT toolkit_experimental.pipeline_candlestick_agg_support;
+"#,
+    name = "pipe_then_candlestick_agg",
+    requires = [pipeline_candlestick_agg_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_uddsketch<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenUddSketch<'a>,
+) -> UddSketch<'static> {
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    UddSketch::from_values(
+        pipeline.size,
+        pipeline.max_error,
+        non_null_points(points).map(|point| point.val),
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_uddsketch<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_uddsketch: toolkit_experimental::PipelineThenUddSketch<'e>,
+) -> toolkit_experimental::PipelineThenUddSketch<'e> {
+    if then_uddsketch.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenUddSketch {
+                    size: then_uddsketch.size,
+                    max_error: then_uddsketch.max_error,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_uddsketch.elements.iter());
+    build! {
+        PipelineThenUddSketch {
+            size: then_uddsketch.size,
+            max_error: then_uddsketch.max_error,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "uddsketch",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_uddsketch(
+    size: i32,
+    max_error: f64,
+) -> toolkit_experimental::PipelineThenUddSketch<'static> {
+    build! {
+        PipelineThenUddSketch {
+            size: size as u64,
+            max_error,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_uddsketch_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenUddSketch::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_uddsketch(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_uddsketch" SUPPORT toolkit_experimental.pipeline_uddsketch_support;
+"#,
+    name = "pipe_then_uddsketch",
+    requires = [pipeline_uddsketch_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_tdigest<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenTDigest<'a>,
+) -> TDigest<'static> {
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    TDigest::from_values(
+        pipeline.size as usize,
+        non_null_points(points).map(|point| point.val),
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_tdigest<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_tdigest: toolkit_experimental::PipelineThenTDigest<'e>,
+) -> toolkit_experimental::PipelineThenTDigest<'e> {
+    if then_tdigest.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenTDigest {
+                    size: then_tdigest.size,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_tdigest.elements.iter());
+    build! {
+        PipelineThenTDigest {
+            size: then_tdigest.size,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "tdigest",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_tdigest(size: i32) -> toolkit_experimental::PipelineThenTDigest<'static> {
+    build! {
+        PipelineThenTDigest {
+            size: size as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_tdigest_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenTDigest::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_tdigest(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_tdigest" SUPPORT toolkit_experimental.pipeline_tdigest_support;
+"#,
+    name = "pipe_then_tdigest",
+    requires = [pipeline_tdigest_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_freq_agg<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenFreqAgg<'a>,
+) -> SpaceSavingAggregate<'static> {
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    freq_agg_from_floats(
+        pipeline.min_freq,
+        non_null_points(points).map(|point| point.val),
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_freq_agg<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_freq_agg: toolkit_experimental::PipelineThenFreqAgg<'e>,
+) -> toolkit_experimental::PipelineThenFreqAgg<'e> {
+    if then_freq_agg.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenFreqAgg {
+                    min_freq: then_freq_agg.min_freq,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_freq_agg.elements.iter());
+    build! {
+        PipelineThenFreqAgg {
+            min_freq: then_freq_agg.min_freq,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "freq_agg",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_freq_agg(min_freq: f64) -> toolkit_experimental::PipelineThenFreqAgg<'static> {
+    if min_freq <= 0. || min_freq >= 1.0 {
+        pgx::error!("frequency aggregate requires a frequency in the range (0.0, 1.0)")
+    }
+    build! {
+        PipelineThenFreqAgg {
+            min_freq,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_freq_agg_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element =
+            PipelineThenFreqAgg::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_freq_agg(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_freq_agg" SUPPORT toolkit_experimental.pipeline_freq_agg_support;
+"#,
+    name = "pipe_then_freq_agg",
+    requires = [pipeline_freq_agg_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_min_n<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenMinN<'a>,
+) -> Option<MinFloats<'static>> {
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    min_n_float_from_values(
+        pipeline.capacity as usize,
+        non_null_points(points).map(|point| point.val),
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_min_n<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_min_n: toolkit_experimental::PipelineThenMinN<'e>,
+) -> toolkit_experimental::PipelineThenMinN<'e> {
+    if then_min_n.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenMinN {
+                    capacity: then_min_n.capacity,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_min_n.elements.iter());
+    build! {
+        PipelineThenMinN {
+            capacity: then_min_n.capacity,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "min_n",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_min_n(capacity: i64) -> toolkit_experimental::PipelineThenMinN<'static> {
+    build! {
+        PipelineThenMinN {
+            capacity: capacity as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_min_n_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element = PipelineThenMinN::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_min_n(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_min_n" SUPPORT toolkit_experimental.pipeline_min_n_support;
+"#,
+    name = "pipe_then_min_n",
+    requires = [pipeline_min_n_support],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_max_n<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenMaxN<'a>,
+) -> Option<MaxFloats<'static>> {
+    let points = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    max_n_float_from_values(
+        pipeline.capacity as usize,
+        non_null_points(points).map(|point| point.val),
+    )
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_max_n<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_max_n: toolkit_experimental::PipelineThenMaxN<'e>,
+) -> toolkit_experimental::PipelineThenMaxN<'e> {
+    if then_max_n.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenMaxN {
+                    capacity: then_max_n.capacity,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_max_n.elements.iter());
+    build! {
+        PipelineThenMaxN {
+            capacity: then_max_n.capacity,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "max_n",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_max_n(capacity: i64) -> toolkit_experimental::PipelineThenMaxN<'static> {
+    build! {
+        PipelineThenMaxN {
+            capacity: capacity as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_max_n_support(input: Internal) -> Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element = PipelineThenMaxN::from_polymorphic_datum(new_element, false, 0).unwrap();
+        finalize_with_max_n(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_max_n" SUPPORT toolkit_experimental.pipeline_max_n_support;
+"#,
+    name = "pipe_then_max_n",
+    requires = [pipeline_max_n_support],
+);
+
+// state_agg works on text timevectors, which don't have a support function to
+// fold their pipelines, so it runs the pipeline itself.
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_state_agg<'a>(
+    timevector: Timevector_TSTZ_Text<'a>,
+    pipeline: toolkit_experimental::PipelineThenStateAgg<'a>,
+) -> StateAgg<'static> {
+    let series = run_typed_pipeline_elements(timevector.to_series(), pipeline.elements.iter());
+    state_agg_from_series(series)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn finalize_with_state_agg<'e>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'e>,
+    then_state_agg: toolkit_experimental::PipelineThenStateAgg<'e>,
+) -> toolkit_experimental::PipelineThenStateAgg<'e> {
+    if then_state_agg.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenStateAgg {
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_state_agg.elements.iter());
+    build! {
+        PipelineThenStateAgg {
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "state_agg",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_state_agg() -> toolkit_experimental::PipelineThenStateAgg<'static> {
+    build! {
+        PipelineThenStateAgg {
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_stats_agg_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> stats_agg())::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,n:5,sx:100,sx2:250,sx3:0,sx4:21250)"
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_agg_pipeline_folding() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> stats_agg() -> average();",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: (\
+                arrow_run_pipeline_then_stats_agg(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethenstatsagg\
+                ) -> '(version:1)'::accessoraverage)");
+        });
+    }
+
+    #[pg_test]
+    fn test_sum_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!("SELECT (series -> sum())::TEXT FROM ({}) s", create_series),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "100");
+        });
+    }
+
+    #[pg_test]
+    fn test_sum_pipeline_folding() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> sum();",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: \
+                arrow_pipeline_then_sum(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethensum\
+                )");
+        });
+    }
+
+    #[pg_test]
+    fn test_average_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> average())::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "20");
+        });
+    }
+
+    #[pg_test]
+    fn test_average_pipeline_folding() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> average();",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: \
+                arrow_pipeline_then_average(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethenaverage\
+                )");
+        });
+    }
+
+    #[pg_test]
+    fn test_num_vals_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> num_vals())::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "5");
+        });
+    }
+
+    #[pg_test]
+    fn test_num_vals_pipeline_folding() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> num_vals();",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: \
+                arrow_pipeline_then_num_vals(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethennumvals\
+                )");
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_agg_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
                 .select(
                     "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
                     None,
@@ -844,12 +1863,243 @@ mod tests {
                 .unwrap();
             client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
 
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+            (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0), \
+                ('2020-01-01 UTC'::TIMESTAMPTZ, 15.0), \
+                ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
+                ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> sort() -> counter_agg())::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(version:1,stats:(n:5,sx:3156624000,sx2:74649600000,sx3:0,sx4:1894671345254400000000,sy:215,sy2:2280,sy3:6720.000000000007,sy4:1788960,sxy:12960000),first:(ts:\"2020-01-01 00:00:00+00\",val:15),second:(ts:\"2020-01-02 00:00:00+00\",val:25),penultimate:(ts:\"2020-01-04 00:00:00+00\",val:10),last:(ts:\"2020-01-05 00:00:00+00\",val:30),reset_sum:45,num_resets:2,num_changes:4,bounds:(is_present:0,has_left:0,has_right:0,padding:(0,0,0,0,0),left:None,right:None))");
+
+            let val = client.select(
+                &format!("SELECT series -> sort() -> counter_agg() -> with_bounds('[2020-01-01 UTC, 2020-02-01 UTC)') -> extrapolated_delta('prometheus') FROM ({}) s", create_series),
+                None,
+                None
+            )
+                .first()
+                .get_one::<f64>().unwrap();
+            assert!((val - 67.5).abs() < f64::EPSILON);
+
             let output = client
                 .select(
                     "EXPLAIN (verbose) SELECT \
                 timevector('1930-04-05'::timestamptz, 123.0) \
                 -> ceil() -> abs() -> floor() \
-                -> sum();",
+                -> counter_agg();",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: \
+                arrow_run_pipeline_then_counter_agg(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethencounteragg\
+                )");
+        })
+    }
+
+    #[pg_test(
+        error = "counter_agg requires a timevector with timestamptz times, this one has bigint times"
+    )]
+    fn test_counter_agg_finalizer_bigint_times() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.timevector(time, value, 'ns') \
+                    OPERATOR(toolkit_experimental.->) \
+                    toolkit_experimental.counter_agg() \
+                FROM (VALUES (1::BIGINT, 10.0), (2::BIGINT, 20.0)) as v(time, value)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_hyperloglog_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+            (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0), \
+                ('2020-01-01 UTC'::TIMESTAMPTZ, 15.0), \
+                ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
+                ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0), \
+                ('2020-01-06 UTC'::TIMESTAMPTZ, 25.0), \
+                ('2020-01-07 UTC'::TIMESTAMPTZ, 15.0), \
+                ('2020-01-08 UTC'::TIMESTAMPTZ, 35.0), \
+                ('2020-01-09 UTC'::TIMESTAMPTZ, 10.0), \
+                ('2020-01-10 UTC'::TIMESTAMPTZ, 5.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> hyperloglog(100))::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(version:1,log:Sparse(num_compressed:7,element_type:FLOAT8,collation:None,compressed_bytes:28,precision:7,compressed:[136,188,20,7,8,30,244,43,72,69,89,2,72,255,97,27,72,83,248,27,200,110,35,5,8,37,85,12]))");
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT series -> hyperloglog(100) -> distinct_count() FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            assert_eq!(val, 7);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> hyperloglog(100);",
+                    None,
+                    None,
+                )
+                .nth(1)
+                .unwrap()
+                .by_ordinal(1)
+                .unwrap()
+                .value::<String>()
+                .unwrap();
+            assert_eq!(output.trim(), "Output: \
+                arrow_run_pipeline_then_hyperloglog(\
+                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
+                    '(version:1,hll_size:100,num_elements:3,elements:[\
+                        Arithmetic(function:Ceil,rhs:0),\
+                        Arithmetic(function:Abs,rhs:0),\
+                        Arithmetic(function:Floor,rhs:0)\
+                    ])'::pipelinethenhyperloglog\
+                )");
+        })
+    }
+
+    #[pg_test]
+    fn test_percentile_agg_finalizer() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> percentile_agg())::TEXT FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,\
+                    alpha:0.001,\
+                    max_buckets:200,\
+                    num_buckets:5,\
+                    compactions:0,\
+                    count:5,\
+                    sum:100,\
+                    buckets:[\
+                        (Positive(1152),1),\
+                        (Positive(1355),1),\
+                        (Positive(1498),1),\
+                        (Positive(1610),1),\
+                        (Positive(1701),1)\
+                    ]\
+                )",
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_percentile_agg_pipeline_folding() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let output = client
+                .select(
+                    "EXPLAIN (verbose) SELECT \
+                timevector('1930-04-05'::timestamptz, 123.0) \
+                -> ceil() -> abs() -> floor() \
+                -> percentile_agg();",
                     None,
                     None,
                 )
@@ -860,19 +2110,19 @@ mod tests {
                 .value::<String>()
                 .unwrap();
             assert_eq!(output.trim(), "Output: \
-                arrow_pipeline_then_sum(\
+                arrow_run_pipeline_then_percentile_agg(\
                     timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
                     '(version:1,num_elements:3,elements:[\
                         Arithmetic(function:Ceil,rhs:0),\
                         Arithmetic(function:Abs,rhs:0),\
                         Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethensum\
+                    ])'::pipelinethenpercentileagg\
                 )");
         });
     }
 
     #[pg_test]
-    fn test_average_finalizer() {
+    fn test_gauge_agg_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -899,20 +2149,34 @@ mod tests {
             let val = client
                 .select(
                     &format!(
-                        "SELECT (series -> average())::TEXT FROM ({}) s",
+                        "SELECT delta(series -> sort() -> gauge_agg()) FROM ({}) s",
                         create_series
                     ),
                     None,
                     None,
                 )
                 .first()
-                .get_one::<String>();
-            assert_eq!(val.unwrap(), "20");
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 20.0);
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (series -> filter($$ $value > 100 $$) -> gauge_agg()) IS NULL \
+                        FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
         });
     }
 
     #[pg_test]
-    fn test_average_pipeline_folding() {
+    fn test_time_weight_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -928,35 +2192,44 @@ mod tests {
                 .unwrap();
             client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
 
-            let output = client
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
                 .select(
-                    "EXPLAIN (verbose) SELECT \
-                timevector('1930-04-05'::timestamptz, 123.0) \
-                -> ceil() -> abs() -> floor() \
-                -> average();",
+                    &format!(
+                        "SELECT average(series -> sort() -> time_weight('linear')) FROM ({}) s",
+                        create_series
+                    ),
                     None,
                     None,
                 )
-                .nth(1)
-                .unwrap()
-                .by_ordinal(1)
-                .unwrap()
-                .value::<String>()
-                .unwrap();
-            assert_eq!(output.trim(), "Output: \
-                arrow_pipeline_then_average(\
-                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,num_elements:3,elements:[\
-                        Arithmetic(function:Ceil,rhs:0),\
-                        Arithmetic(function:Abs,rhs:0),\
-                        Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethenaverage\
-                )");
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 20.0);
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT average(series -> sort() -> time_weight('LOCF')) FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(val.unwrap(), 17.5);
         });
     }
 
     #[pg_test]
-    fn test_num_vals_finalizer() {
+    fn test_candlestick_agg_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -983,7 +2256,8 @@ mod tests {
             let val = client
                 .select(
                     &format!(
-                        "SELECT (series -> num_vals())::TEXT FROM ({}) s",
+                        "SELECT (open(c), high(c), low(c), close(c))::TEXT \
+                        FROM (SELECT series -> candlestick_agg() AS c FROM ({}) s) c",
                         create_series
                     ),
                     None,
@@ -991,12 +2265,12 @@ mod tests {
                 )
                 .first()
                 .get_one::<String>();
-            assert_eq!(val.unwrap(), "5");
+            assert_eq!(val.unwrap(), "(10,30,10,30)");
         });
     }
 
     #[pg_test]
-    fn test_num_vals_pipeline_folding() {
+    fn test_uddsketch_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -1012,12 +2286,34 @@ mod tests {
                 .unwrap();
             client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
 
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (num_vals(sketch), approx_percentile(0.5, sketch))::TEXT \
+                        FROM (SELECT series -> uddsketch(100, 0.01) AS sketch FROM ({}) s) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(5,19.886670240866184)");
+
             let output = client
                 .select(
                     "EXPLAIN (verbose) SELECT \
                 timevector('1930-04-05'::timestamptz, 123.0) \
                 -> ceil() -> abs() -> floor() \
-                -> num_vals();",
+                -> uddsketch(100, 0.01);",
                     None,
                     None,
                 )
@@ -1028,19 +2324,19 @@ mod tests {
                 .value::<String>()
                 .unwrap();
             assert_eq!(output.trim(), "Output: \
-                arrow_pipeline_then_num_vals(\
+                arrow_run_pipeline_then_uddsketch(\
                     timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,num_elements:3,elements:[\
+                    '(version:1,size:100,max_error:0.01,num_elements:3,elements:[\
                         Arithmetic(function:Ceil,rhs:0),\
                         Arithmetic(function:Abs,rhs:0),\
                         Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethennumvals\
+                    ])'::pipelinethenuddsketch\
                 )");
         });
     }
 
     #[pg_test]
-    fn test_counter_agg_finalizer() {
+    fn test_tdigest_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -1058,16 +2354,17 @@ mod tests {
 
             // we use a subselect to guarantee order
             let create_series = "SELECT timevector(time, value) as series FROM \
-            (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0), \
-                ('2020-01-01 UTC'::TIMESTAMPTZ, 15.0), \
-                ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
-                ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
-                ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
 
             let val = client
                 .select(
                     &format!(
-                        "SELECT (series -> sort() -> counter_agg())::TEXT FROM ({}) s",
+                        "SELECT (num_vals(digest), min_val(digest), max_val(digest))::TEXT \
+                        FROM (SELECT series -> tdigest(100) AS digest FROM ({}) s) s",
                         create_series
                     ),
                     None,
@@ -1075,46 +2372,12 @@ mod tests {
                 )
                 .first()
                 .get_one::<String>();
-            assert_eq!(val.unwrap(), "(version:1,stats:(n:5,sx:3156624000,sx2:74649600000,sx3:0,sx4:1894671345254400000000,sy:215,sy2:2280,sy3:6720.000000000007,sy4:1788960,sxy:12960000),first:(ts:\"2020-01-01 00:00:00+00\",val:15),second:(ts:\"2020-01-02 00:00:00+00\",val:25),penultimate:(ts:\"2020-01-04 00:00:00+00\",val:10),last:(ts:\"2020-01-05 00:00:00+00\",val:30),reset_sum:45,num_resets:2,num_changes:4,bounds:(is_present:0,has_left:0,has_right:0,padding:(0,0,0,0,0),left:None,right:None))");
-
-            let val = client.select(
-                &format!("SELECT series -> sort() -> counter_agg() -> with_bounds('[2020-01-01 UTC, 2020-02-01 UTC)') -> extrapolated_delta('prometheus') FROM ({}) s", create_series),
-                None,
-                None
-            )
-                .first()
-                .get_one::<f64>().unwrap();
-            assert!((val - 67.5).abs() < f64::EPSILON);
-
-            let output = client
-                .select(
-                    "EXPLAIN (verbose) SELECT \
-                timevector('1930-04-05'::timestamptz, 123.0) \
-                -> ceil() -> abs() -> floor() \
-                -> counter_agg();",
-                    None,
-                    None,
-                )
-                .nth(1)
-                .unwrap()
-                .by_ordinal(1)
-                .unwrap()
-                .value::<String>()
-                .unwrap();
-            assert_eq!(output.trim(), "Output: \
-                arrow_run_pipeline_then_counter_agg(\
-                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,num_elements:3,elements:[\
-                        Arithmetic(function:Ceil,rhs:0),\
-                        Arithmetic(function:Abs,rhs:0),\
-                        Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethencounteragg\
-                )");
-        })
+            assert_eq!(val.unwrap(), "(5,10,30)");
+        });
     }
 
     #[pg_test]
-    fn test_hyperloglog_finalizer() {
+    fn test_freq_agg_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -1132,21 +2395,18 @@ mod tests {
 
             // we use a subselect to guarantee order
             let create_series = "SELECT timevector(time, value) as series FROM \
-            (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 10.0), \
-                ('2020-01-01 UTC'::TIMESTAMPTZ, 15.0), \
-                ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
-                ('2020-01-02 UTC'::TIMESTAMPTZ, 25.0), \
-                ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0), \
-                ('2020-01-06 UTC'::TIMESTAMPTZ, 25.0), \
-                ('2020-01-07 UTC'::TIMESTAMPTZ, 15.0), \
-                ('2020-01-08 UTC'::TIMESTAMPTZ, 35.0), \
-                ('2020-01-09 UTC'::TIMESTAMPTZ, 10.0), \
-                ('2020-01-10 UTC'::TIMESTAMPTZ, 5.0)) as v(time, value)";
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)) as v(time, value)";
 
             let val = client
                 .select(
                     &format!(
-                        "SELECT (series -> hyperloglog(100))::TEXT FROM ({}) s",
+                        "SELECT (min_frequency(agg, 2.0::float8), min_frequency(agg, 3.0::float8))::TEXT \
+                        FROM (SELECT series -> map($$ $value / 10 $$) -> floor() -> freq_agg(0.3) AS agg \
+                            FROM ({}) s) s",
                         create_series
                     ),
                     None,
@@ -1154,51 +2414,12 @@ mod tests {
                 )
                 .first()
                 .get_one::<String>();
-            assert_eq!(val.unwrap(), "(version:1,log:Sparse(num_compressed:7,element_type:FLOAT8,collation:None,compressed_bytes:28,precision:7,compressed:[136,188,20,7,8,30,244,43,72,69,89,2,72,255,97,27,72,83,248,27,200,110,35,5,8,37,85,12]))");
-
-            let val = client
-                .select(
-                    &format!(
-                        "SELECT series -> hyperloglog(100) -> distinct_count() FROM ({}) s",
-                        create_series
-                    ),
-                    None,
-                    None,
-                )
-                .first()
-                .get_one::<i32>()
-                .unwrap();
-            assert_eq!(val, 7);
-
-            let output = client
-                .select(
-                    "EXPLAIN (verbose) SELECT \
-                timevector('1930-04-05'::timestamptz, 123.0) \
-                -> ceil() -> abs() -> floor() \
-                -> hyperloglog(100);",
-                    None,
-                    None,
-                )
-                .nth(1)
-                .unwrap()
-                .by_ordinal(1)
-                .unwrap()
-                .value::<String>()
-                .unwrap();
-            assert_eq!(output.trim(), "Output: \
-                arrow_run_pipeline_then_hyperloglog(\
-                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,hll_size:100,num_elements:3,elements:[\
-                        Arithmetic(function:Ceil,rhs:0),\
-                        Arithmetic(function:Abs,rhs:0),\
-                        Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethenhyperloglog\
-                )");
-        })
+            assert_eq!(val.unwrap(), "(0.4,0.2)");
+        });
     }
 
     #[pg_test]
-    fn test_percentile_agg_finalizer() {
+    fn test_min_n_max_n_finalizers() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -1225,7 +2446,8 @@ mod tests {
             let val = client
                 .select(
                     &format!(
-                        "SELECT (series -> percentile_agg())::TEXT FROM ({}) s",
+                        "SELECT (into_array(series -> min_n(2)), into_array(series -> max_n(2)))::TEXT \
+                        FROM ({}) s",
                         create_series
                     ),
                     None,
@@ -1233,29 +2455,12 @@ mod tests {
                 )
                 .first()
                 .get_one::<String>();
-            assert_eq!(
-                val.unwrap(),
-                "(version:1,\
-                    alpha:0.001,\
-                    max_buckets:200,\
-                    num_buckets:5,\
-                    compactions:0,\
-                    count:5,\
-                    sum:100,\
-                    buckets:[\
-                        (Positive(1152),1),\
-                        (Positive(1355),1),\
-                        (Positive(1498),1),\
-                        (Positive(1610),1),\
-                        (Positive(1701),1)\
-                    ]\
-                )",
-            );
+            assert_eq!(val.unwrap(), "(\"{10,15}\",\"{30,25}\")");
         });
     }
 
     #[pg_test]
-    fn test_percentile_agg_pipeline_folding() {
+    fn test_state_agg_finalizer() {
         Spi::execute(|client| {
             client.select("SET timezone TO 'UTC'", None, None);
             // using the search path trick for this test b/c the operator is
@@ -1271,30 +2476,39 @@ mod tests {
                 .unwrap();
             client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
 
-            let output = client
+            let create_series = "SELECT timevector(time, state) as series FROM \
+                (VALUES ('2020-01-04 UTC'::TIMESTAMPTZ, 'running'), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 'running'), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 'stopped'), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 'stopped')) as v(time, state)";
+
+            let val = client
                 .select(
-                    "EXPLAIN (verbose) SELECT \
-                timevector('1930-04-05'::timestamptz, 123.0) \
-                -> ceil() -> abs() -> floor() \
-                -> percentile_agg();",
+                    &format!(
+                        "SELECT duration_in('running', series -> state_agg())::TEXT FROM ({}) s",
+                        create_series
+                    ),
                     None,
                     None,
                 )
-                .nth(1)
-                .unwrap()
-                .by_ordinal(1)
-                .unwrap()
-                .value::<String>()
-                .unwrap();
-            assert_eq!(output.trim(), "Output: \
-                arrow_run_pipeline_then_percentile_agg(\
-                    timevector('1930-04-05 00:00:00+00'::timestamp with time zone, '123'::double precision), \
-                    '(version:1,num_elements:3,elements:[\
-                        Arithmetic(function:Ceil,rhs:0),\
-                        Arithmetic(function:Abs,rhs:0),\
-                        Arithmetic(function:Floor,rhs:0)\
-                    ])'::pipelinethenpercentileagg\
-                )");
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "2 days");
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT duration_in('running', \
+                            series -> filter($$ $value != 'stopped' $$) -> state_agg())::TEXT \
+                        FROM ({}) s",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "3 days");
         });
     }
 }
@@ -0,0 +1,565 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use crate::stats_agg::InternalStatsSummary1D;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum AnomalyMethod {
+    Zscore,
+    Mad,
+    Iqr,
+    Seasonal,
+}
+
+// Anomaly detectors either drop every point that isn't anomalous, or replace
+// each value with 1 if it's anomalous and 0 if it isn't.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum AnomalyOutput {
+    Filter,
+    Flag,
+}
+
+impl AnomalyOutput {
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "filter" => AnomalyOutput::Filter,
+            "flag" => AnomalyOutput::Flag,
+            _ => panic!("Invalid anomaly output, expected 'filter' or 'flag'"),
+        }
+    }
+}
+
+fn anomaly_element<'e>(
+    method: AnomalyMethod,
+    window: Option<i64>,
+    threshold: f64,
+    output: &str,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    let window = match window {
+        None => 0,
+        Some(w) if w >= 2 => w as u64,
+        Some(_) => panic!("anomaly detection window must contain at least two points"),
+    };
+    if threshold.is_nan() || threshold <= 0.0 {
+        panic!("anomaly threshold must be positive")
+    }
+    Element::Anomaly {
+        method,
+        output: AnomalyOutput::from_name(output),
+        window,
+        threshold,
+    }
+    .flatten()
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "zscore",
+    schema = "toolkit_experimental"
+)]
+pub fn zscore_pipeline_element<'e>(
+    window: default!(Option<i64>, "NULL"),
+    threshold: default!(f64, 3.0),
+    output: default!(&str, "'filter'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    anomaly_element(AnomalyMethod::Zscore, window, threshold, output)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "mad_outliers",
+    schema = "toolkit_experimental"
+)]
+pub fn mad_outliers_pipeline_element<'e>(
+    threshold: default!(f64, 3.5),
+    output: default!(&str, "'filter'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    anomaly_element(AnomalyMethod::Mad, None, threshold, output)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "iqr_outliers",
+    schema = "toolkit_experimental"
+)]
+pub fn iqr_outliers_pipeline_element<'e>(
+    k: default!(f64, 1.5),
+    output: default!(&str, "'filter'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    anomaly_element(AnomalyMethod::Iqr, None, k, output)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "seasonal_outliers",
+    schema = "toolkit_experimental"
+)]
+pub fn seasonal_outliers_pipeline_element<'e>(
+    period: default!(Option<i64>, "NULL"),
+    threshold: default!(f64, 3.5),
+    output: default!(&str, "'filter'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    anomaly_element(AnomalyMethod::Seasonal, period, threshold, output)
+}
+
+pub fn detect_anomalies<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'static> {
+    let (method, output, window, threshold) = match element {
+        Element::Anomaly {
+            method,
+            output,
+            window,
+            threshold,
+        } => (*method, *output, *window as usize, *threshold),
+        _ => unreachable!(),
+    };
+
+    // NULLs are never anomalous, and don't contribute to the statistics
+    let is_null = |i: usize| series.has_nulls() && series.is_null_val(i);
+    let indices: Vec<usize> = (0..series.num_points()).filter(|&i| !is_null(i)).collect();
+    let points = series.points.as_slice();
+    let values: Vec<f64> = indices.iter().map(|&i| points[i].val).collect();
+
+    let anomalous = match method {
+        AnomalyMethod::Zscore => {
+            if window != 0 && !series.is_sorted() {
+                panic!("Timevector must be sorted prior to computing a rolling zscore")
+            }
+            zscore_anomalies(&values, window, threshold)
+        }
+        AnomalyMethod::Mad => mad_anomalies(&values, threshold),
+        AnomalyMethod::Iqr => iqr_anomalies(&values, threshold),
+        AnomalyMethod::Seasonal => {
+            if !series.is_sorted() {
+                panic!("Timevector must be sorted prior to seasonal anomaly detection")
+            }
+            seasonal_anomalies(&values, window, threshold)
+        }
+    };
+
+    match output {
+        AnomalyOutput::Filter => {
+            let points: Vec<TSPoint> = indices
+                .iter()
+                .zip(anomalous)
+                .filter(|(_, anomalous)| *anomalous)
+                .map(|(&i, _)| points[i])
+                .collect();
+            let null_val = std::vec::from_elem(0_u8, (points.len() + 7) / 8);
+            build! {
+                Timevector_TSTZ_F64 {
+                    num_points: points.len() as _,
+                    flags: series.flags & !FLAG_HAS_NULLS,
+                    internal_padding: [0; 3],
+                    points: points.into(),
+                    null_val: null_val.into(),
+                }
+            }
+        }
+        AnomalyOutput::Flag => {
+            let mut points = points.to_vec();
+            for point in points.iter_mut() {
+                point.val = f64::NAN;
+            }
+            for (&i, anomalous) in indices.iter().zip(anomalous) {
+                points[i].val = if anomalous { 1.0 } else { 0.0 };
+            }
+            build! {
+                Timevector_TSTZ_F64 {
+                    num_points: points.len() as _,
+                    flags: series.flags,
+                    internal_padding: [0; 3],
+                    points: points.into(),
+                    null_val: series.null_val.as_slice().to_vec().into(),
+                }
+            }
+        }
+    }
+}
+
+// A value is anomalous if it is more than `threshold` standard deviations
+// away from the mean. With a window the mean and standard deviation are those
+// of the `window` values preceding it, otherwise those of the whole series.
+fn zscore_anomalies(values: &[f64], window: usize, threshold: f64) -> Vec<bool> {
+    let is_outlier = |stats: &InternalStatsSummary1D<f64>, val: f64| {
+        if stats.n < 2 {
+            return false;
+        }
+        let mean = stats.avg().unwrap();
+        let stddev = stats.stddev_samp().unwrap();
+        if stddev == 0.0 {
+            return val != mean;
+        }
+        ((val - mean) / stddev).abs() > threshold
+    };
+    let accum = |stats: &mut InternalStatsSummary1D<f64>, val: f64| {
+        stats
+            .accum(val)
+            .expect("error while computing zscore statistics")
+    };
+
+    let mut stats = InternalStatsSummary1D::new();
+    if window == 0 {
+        values.iter().for_each(|&val| accum(&mut stats, val));
+        return values.iter().map(|&val| is_outlier(&stats, val)).collect();
+    }
+
+    let mut anomalous = Vec::with_capacity(values.len());
+    for (i, &val) in values.iter().enumerate() {
+        anomalous.push(is_outlier(&stats, val));
+        accum(&mut stats, val);
+        if i >= window {
+            let leaving = values[i - window];
+            stats = match stats.remove(leaving) {
+                Some(stats) => stats,
+                None => {
+                    let mut stats = InternalStatsSummary1D::new();
+                    for &val in &values[i + 1 - window..=i] {
+                        accum(&mut stats, val);
+                    }
+                    stats
+                }
+            };
+        }
+    }
+    anomalous
+}
+
+// Modified z-score of Iglewicz and Hoaglin: the distance from the median in
+// units of the (normal-consistent) median absolute deviation. When more than
+// half the values are identical the MAD is 0, in which case the mean absolute
+// deviation is used instead.
+fn mad_anomalies(values: &[f64], threshold: f64) -> Vec<bool> {
+    if values.is_empty() {
+        return vec![];
+    }
+    let center = median(values.to_vec());
+    let deviations: Vec<f64> = values.iter().map(|val| (val - center).abs()).collect();
+    let mad = median(deviations.clone());
+    let scale = if mad != 0.0 {
+        mad / 0.6745
+    } else {
+        let mean_deviation = deviations.iter().sum::<f64>() / deviations.len() as f64;
+        mean_deviation * 1.253314
+    };
+    if scale == 0.0 {
+        return vec![false; values.len()];
+    }
+    deviations
+        .iter()
+        .map(|dev| dev / scale > threshold)
+        .collect()
+}
+
+// Tukey's fences: anything more than `k` interquartile ranges below the first
+// quartile or above the third.
+fn iqr_anomalies(values: &[f64], k: f64) -> Vec<bool> {
+    if values.is_empty() {
+        return vec![];
+    }
+    let mut sorted = values.to_vec();
+    sort_values(&mut sorted);
+    let q1 = quantile(&sorted, 0.25);
+    let q3 = quantile(&sorted, 0.75);
+    let iqr = q3 - q1;
+    let (low, high) = (q1 - k * iqr, q3 + k * iqr);
+    values.iter().map(|&val| val < low || val > high).collect()
+}
+
+// Removes the trend and seasonal components from the series and looks for
+// outliers among what remains. Loosely follows STL: the seasonal component is
+// the median detrended value at each phase of the period, the trend a local
+// linear fit over the nearest two periods of points, first of the raw values
+// and then, refit over `2 * period + 1` points, of the deseasonalized ones,
+// alternating with the seasonal component a few times. The decomposition is
+// then repeated ignoring the outliers found, so that they don't drag the
+// components, and with them their neighbours' residuals, along. If no period
+// is given it's taken from the strongest autocorrelation peak of the
+// differenced series. Points are assumed to be evenly spaced.
+fn seasonal_anomalies(values: &[f64], period: usize, threshold: f64) -> Vec<bool> {
+    const ROBUSTNESS_ITERATIONS: usize = 2;
+
+    let period = match period {
+        0 => {
+            let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+            asap::dominant_period(&diffs)
+                .unwrap_or_else(|| panic!("unable to detect a seasonal period, please provide one"))
+        }
+        period => period,
+    };
+    if values.len() < 2 * period {
+        panic!("seasonal anomaly detection requires at least two full periods of data")
+    }
+
+    let mut anomalous = vec![false; values.len()];
+    for _ in 0..=ROBUSTNESS_ITERATIONS {
+        let fitted = seasonal_fit(values, period, &anomalous);
+        let residuals: Vec<f64> = values.iter().zip(&fitted).map(|(v, f)| v - f).collect();
+        anomalous = mad_anomalies(&residuals, threshold);
+    }
+    anomalous
+}
+
+// trend + seasonal component for each value, ignoring the `excluded` ones
+fn seasonal_fit(values: &[f64], period: usize, excluded: &[bool]) -> Vec<f64> {
+    const INNER_ITERATIONS: usize = 3;
+
+    let mut seasonal = vec![0.0; period];
+    let mut trend = local_linear_trend(values, 2 * period, excluded);
+    for _ in 0..INNER_ITERATIONS {
+        for (phase, component) in seasonal.iter_mut().enumerate() {
+            let detrended: Vec<f64> = (phase..values.len())
+                .step_by(period)
+                .filter(|&i| !excluded[i])
+                .map(|i| values[i] - trend[i])
+                .collect();
+            if !detrended.is_empty() {
+                *component = median(detrended);
+            }
+        }
+        let deseasonalized: Vec<f64> = values
+            .iter()
+            .enumerate()
+            .map(|(i, val)| val - seasonal[i % period])
+            .collect();
+        trend = local_linear_trend(&deseasonalized, 2 * period + 1, excluded);
+    }
+
+    trend
+        .iter()
+        .enumerate()
+        .map(|(i, t)| t + seasonal[i % period])
+        .collect()
+}
+
+// For each point, the value at that point of the least-squares line through
+// the `width` nearest points that aren't excluded. Near the ends of the series
+// the neighbourhood is shifted to stay inside it.
+fn local_linear_trend(values: &[f64], width: usize, excluded: &[bool]) -> Vec<f64> {
+    let included: Vec<usize> = (0..values.len()).filter(|&j| !excluded[j]).collect();
+    if included.is_empty() {
+        return values.to_vec();
+    }
+    let width = width.min(included.len());
+    (0..values.len())
+        .map(|i| {
+            let center = included.partition_point(|&j| j < i);
+            let lo = center.saturating_sub(width / 2).min(included.len() - width);
+            let neighbours = &included[lo..lo + width];
+            let n = width as f64;
+            let mean_x = neighbours.iter().map(|&j| j as f64).sum::<f64>() / n;
+            let mean_y = neighbours.iter().map(|&j| values[j]).sum::<f64>() / n;
+            let (mut sxy, mut sxx) = (0.0, 0.0);
+            for &j in neighbours {
+                let dx = j as f64 - mean_x;
+                sxy += dx * (values[j] - mean_y);
+                sxx += dx * dx;
+            }
+            let slope = if sxx == 0.0 { 0.0 } else { sxy / sxx };
+            mean_y + slope * (i as f64 - mean_x)
+        })
+        .collect()
+}
+
+fn sort_values(values: &mut [f64]) {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    sort_values(&mut values);
+    quantile(&values, 0.5)
+}
+
+// linear interpolation between the closest ranks, `sorted` must be non-empty
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let h = (sorted.len() - 1) as f64 * q;
+    let (lo, hi) = (h.floor() as usize, h.ceil() as usize);
+    sorted[lo] + (h - lo as f64) * (sorted[hi] - sorted[lo])
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_outliers() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 90.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 40.0), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-08 UTC'::TIMESTAMPTZ, 35.0)",
+                None,
+                None,
+            );
+
+            let only_spike = "(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:90)\
+            ],null_val:[0])";
+
+            for element in [
+                "zscore(threshold => 2)",
+                "zscore(3, threshold => 2)",
+                "mad_outliers()",
+                "iqr_outliers()",
+            ] {
+                let val = client
+                    .select(
+                        &format!(
+                            "SELECT (timevector(time, value) -> {})::TEXT FROM series",
+                            element
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>();
+                assert_eq!(val.unwrap(), only_spike, "{}", element);
+            }
+
+            // with a higher threshold nothing stands out
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> zscore())::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:0,flags:1,internal_padding:(0,0,0),points:[],null_val:[])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> iqr_outliers(output => 'flag'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:8,flags:3,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1),\
+                (ts:\"2020-01-04 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0),\
+                (ts:\"2020-01-06 00:00:00+00\",val:0),\
+                (ts:\"2020-01-07 00:00:00+00\",val:0),\
+                (ts:\"2020-01-08 00:00:00+00\",val:0)\
+            ],null_val:[8])"
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_pipeline_seasonal_outliers() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // a repeating pattern on top of a trend, plus some noise and a
+            // spike that's well inside the normal range of the values
+            client.select(
+                "CREATE TABLE series AS \
+                SELECT \
+                    '2020-01-01 UTC'::TIMESTAMPTZ + i * '1 hour'::INTERVAL AS time, \
+                    ((ARRAY[1, 5, 2, 8])[i % 4 + 1] \
+                        + i * 0.5 \
+                        + (i * 7) % 5 * 0.1 \
+                        + CASE WHEN i = 21 THEN 10 ELSE 0 END)::FLOAT8 AS value \
+                FROM generate_series(0, 39) i",
+                None,
+                None,
+            );
+
+            let expected = "(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 21:00:00+00\",val:25.7)\
+            ],null_val:[0])";
+
+            for element in ["seasonal_outliers(4)", "seasonal_outliers()"] {
+                let val = client
+                    .select(
+                        &format!(
+                            "SELECT (timevector(time, value) -> {})::TEXT FROM series",
+                            element
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>();
+                assert_eq!(val.unwrap(), expected, "{}", element);
+            }
+
+            // the spike doesn't stand out from the raw values
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> mad_outliers())::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:0,flags:1,internal_padding:(0,0,0),points:[],null_val:[])"
+            );
+        });
+    }
+}
@@ -2,6 +2,7 @@ use pgx::*;
 
 use super::*;
 
+use super::fill_to::interval_to_usecs;
 use super::Element::Arithmetic;
 use Function::*;
 
@@ -33,34 +34,48 @@ pub enum Function {
     Trunc,
 }
 
-pub fn apply(
-    mut series: Timevector_TSTZ_F64<'_>,
-    function: Function,
-    rhs: f64,
-) -> Timevector_TSTZ_F64<'_> {
-    let function: fn(f64, f64) -> f64 = match function {
-        Add => |a, b| a + b,
-        Sub => |a, b| a - b,
-        Mul => |a, b| a * b,
-        Div => |a, b| a / b,
-        // TODO is this the right mod?
-        Mod => |a, b| a % b,
-        Power => |a, b| a.powf(b),
-        LogN => |a, b| a.log(b),
-        // unary functions just ignore the second arg
-        Abs => |a, _| a.abs(),
-        Cbrt => |a, _| a.cbrt(),
-        Ceil => |a, _| a.ceil(),
-        Floor => |a, _| a.floor(),
-        Ln => |a, _| a.ln(),
-        Log10 => |a, _| a.log10(),
-        Round => |a, _| a.round(),
-        Sign => |a, _| a.signum(),
-        Sqrt => |a, _| a.sqrt(),
-        Trunc => |a, _| a.trunc(),
-    };
-    map::map_series(&mut series, |lhs| function(lhs, rhs));
-    series
+// Applies a run of adjacent arithmetic elements, often just the one, in a
+// single pass over the values, with the same result as applying them one by
+// one.
+pub fn apply<'s>(stream: PointStream<'s>, elements: &[Element]) -> PointStream<'s> {
+    let functions: Vec<_> = elements
+        .iter()
+        .map(|element| match element {
+            Arithmetic { function, rhs } => (function.as_fn(), *rhs),
+            _ => unreachable!(),
+        })
+        .collect();
+    stream.map_values(move |lhs| {
+        functions
+            .iter()
+            .fold(lhs, |val, (function, rhs)| function(val, *rhs))
+    })
+}
+
+impl Function {
+    fn as_fn(self) -> fn(f64, f64) -> f64 {
+        match self {
+            Add => |a, b| a + b,
+            Sub => |a, b| a - b,
+            Mul => |a, b| a * b,
+            Div => |a, b| a / b,
+            // TODO is this the right mod?
+            Mod => |a, b| a % b,
+            Power => |a, b| a.powf(b),
+            LogN => |a, b| a.log(b),
+            // unary functions just ignore the second arg
+            Abs => |a, _| a.abs(),
+            Cbrt => |a, _| a.cbrt(),
+            Ceil => |a, _| a.ceil(),
+            Floor => |a, _| a.floor(),
+            Ln => |a, _| a.ln(),
+            Log10 => |a, _| a.log10(),
+            Round => |a, _| a.round(),
+            Sign => |a, _| a.signum(),
+            Sqrt => |a, _| a.sqrt(),
+            Trunc => |a, _| a.trunc(),
+        }
+    }
 }
 
 //
@@ -285,6 +300,258 @@ pub fn pipeline_trunc<'e>() -> toolkit_experimental::UnstableTimevectorPipeline<
     .flatten()
 }
 
+//
+// operations between two timevectors
+//
+
+// How the points of the right-hand timevector are lined up with those of the
+// left-hand one. The result always has the timestamps of the left-hand side,
+// left-hand points with no matching right-hand value are dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JoinMethod {
+    // only points with the exact same timestamp are matched
+    Exact,
+    // the closest right-hand point, preferring the earlier one on ties
+    Nearest,
+    // the right-hand value at the left-hand timestamp as `fill_to` would
+    // compute it from the surrounding right-hand points
+    Fill(FillToMethod),
+}
+
+impl JoinMethod {
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "exact" => JoinMethod::Exact,
+            "nearest" => JoinMethod::Nearest,
+            "locf" => JoinMethod::Fill(FillToMethod::Locf),
+            "interpolate" | "linear" => JoinMethod::Fill(FillToMethod::Interpolate),
+            _ => panic!("Invalid join method"),
+        }
+    }
+}
+
+// Combines `lhs` and `rhs` point-by-point with `function`. When a `tolerance`
+// is given, right-hand points further than it from the left-hand timestamp are
+// never used. A NULL on either side results in a NULL.
+pub fn apply_timevectors(
+    lhs: &Timevector_TSTZ_F64<'_>,
+    rhs: &Timevector_TSTZ_F64<'_>,
+    function: Function,
+    join_method: &JoinMethod,
+    tolerance: Option<i64>,
+) -> Timevector_TSTZ_F64<'static> {
+    if !lhs.is_sorted() || !rhs.is_sorted() {
+        panic!("Timevectors must be sorted prior to being combined")
+    }
+    if lhs.time_axis() != rhs.time_axis() {
+        panic!(
+            "cannot combine a timevector with {} times and one with {} times",
+            lhs.time_axis().name(),
+            rhs.time_axis().name()
+        )
+    }
+
+    let function = function.as_fn();
+    let rhs_points = rhs.points.as_slice();
+    let within_tolerance = |ts: i64, point: &TSPoint| match tolerance {
+        None => true,
+        Some(tolerance) => (point.ts - ts).abs() <= tolerance,
+    };
+
+    let mut points = vec![];
+    let mut null_val = vec![];
+    let mut flags = FLAG_IS_SORTED | lhs.time_axis().flag_bits();
+    // index of the first right-hand point after the current left-hand one
+    let mut next = 0;
+    for (i, point) in lhs.iter().enumerate() {
+        while next < rhs_points.len() && rhs_points[next].ts <= point.ts {
+            next += 1;
+        }
+        let before = next.checked_sub(1);
+        let after = if next < rhs_points.len() {
+            Some(next)
+        } else {
+            None
+        };
+
+        // the right-hand value, and whether it is NULL
+        let rhs_val = match (join_method, before) {
+            (_, Some(b)) if rhs_points[b].ts == point.ts => {
+                Some((rhs_points[b].val, rhs.is_null_val(b)))
+            }
+            (JoinMethod::Exact, _) => None,
+            (JoinMethod::Nearest, _) => {
+                let nearest = match (before, after) {
+                    (Some(b), Some(a)) => {
+                        if rhs_points[a].ts - point.ts < point.ts - rhs_points[b].ts {
+                            Some(a)
+                        } else {
+                            Some(b)
+                        }
+                    }
+                    (b, a) => b.or(a),
+                };
+                nearest
+                    .filter(|&n| within_tolerance(point.ts, &rhs_points[n]))
+                    .map(|n| (rhs_points[n].val, rhs.is_null_val(n)))
+            }
+            (JoinMethod::Fill(FillToMethod::Locf), Some(b)) => {
+                if within_tolerance(point.ts, &rhs_points[b]) {
+                    Some((rhs_points[b].val, rhs.is_null_val(b)))
+                } else {
+                    None
+                }
+            }
+            (JoinMethod::Fill(method), Some(b)) => match after {
+                Some(a)
+                    if within_tolerance(point.ts, &rhs_points[b])
+                        && within_tolerance(point.ts, &rhs_points[a]) =>
+                {
+                    let filled = method.fill_point(&rhs_points[b], &rhs_points[a], point.ts);
+                    Some((filled.val, rhs.is_null_val(b) || rhs.is_null_val(a)))
+                }
+                _ => None,
+            },
+            (JoinMethod::Fill(_), None) => None,
+        };
+
+        let (rhs_val, rhs_null) = match rhs_val {
+            None => continue,
+            Some(val) => val,
+        };
+
+        let idx = points.len();
+        if idx % 8 == 0 {
+            null_val.push(0);
+        }
+        if rhs_null || lhs.is_null_val(i) {
+            flags |= FLAG_HAS_NULLS;
+            *null_val.last_mut().unwrap() |= 1 << (idx % 8);
+            points.push(TSPoint {
+                ts: point.ts,
+                val: f64::NAN,
+            });
+        } else {
+            points.push(TSPoint {
+                ts: point.ts,
+                val: function(point.val, rhs_val),
+            });
+        }
+    }
+
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: null_val.into(),
+        }
+    }
+}
+
+fn apply_timevectors_sql(
+    lhs: Timevector_TSTZ_F64<'_>,
+    rhs: Timevector_TSTZ_F64<'_>,
+    function: Function,
+    join_method: &str,
+    tolerance: Option<crate::raw::Interval>,
+) -> Timevector_TSTZ_F64<'static> {
+    let join_method = JoinMethod::from_name(join_method);
+    let tolerance = tolerance.map(|t| lhs.time_axis().usecs_to_ticks(interval_to_usecs(&t)));
+    apply_timevectors(&lhs, &rhs, function, &join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "add",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_add<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Add, join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "sub",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_sub<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Sub, join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "mul",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_mul<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Mul, join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "div",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_div<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Div, join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "mod",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_mod<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Mod, join_method, tolerance)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "power",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_power<'a>(
+    lhs: Timevector_TSTZ_F64<'a>,
+    rhs: Timevector_TSTZ_F64<'a>,
+    join_method: default!(&str, "'exact'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> Timevector_TSTZ_F64<'static> {
+    apply_timevectors_sql(lhs, rhs, Power, join_method, tolerance)
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -687,4 +954,94 @@ mod tests {
             );
         });
     }
+
+    #[pg_test]
+    fn test_timevector_timevector_arith() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE used AS SELECT timevector(time, value) AS series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 50.0)) as v(time, value)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE total AS SELECT timevector(time, value) AS series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 100.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 300.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 400.0)) as v(time, value)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT div(used.series, total.series)::TEXT FROM used, total",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0.1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.1)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT div(used.series, total.series, 'nearest', '1 day')::TEXT \
+                    FROM used, total",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0.1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0.2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:0.1),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0.125)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (div(used.series, total.series, 'interpolate') -> mul(100.0))::TEXT \
+                    FROM used, total",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10)\
+            ],null_val:[0])"
+            );
+        });
+    }
 }
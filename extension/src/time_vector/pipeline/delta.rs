@@ -27,34 +27,22 @@ extension_sql!(
     name = "accessor_delta_cast",
 );
 
-pub fn timevector_delta<'s>(series: &Timevector_TSTZ_F64<'s>) -> Timevector_TSTZ_F64<'s> {
-    if !series.is_sorted() {
-        panic!("can only compute deltas for sorted timevector");
-    }
-    if series.has_nulls() {
+pub fn timevector_delta(stream: PointStream<'_>) -> PointStream<'_> {
+    let stream = stream.require_sorted("can only compute deltas for sorted timevector");
+    if stream.has_nulls() {
         panic!("Unable to compute deltas over timevector containing nulls");
     }
 
-    let mut it = series.iter();
-    let mut prev = it.next().unwrap().val;
-    let mut delta_points = Vec::new();
-
-    for pt in it {
-        delta_points.push(TSPoint {
-            ts: pt.ts,
-            val: pt.val - prev,
-        });
-        prev = pt.val;
-    }
-
-    let nulls_len = (delta_points.len() + 7) / 8;
-
-    build!(Timevector_TSTZ_F64 {
-        num_points: delta_points.len() as u32,
-        flags: series.flags,
-        internal_padding: [0; 3],
-        points: delta_points.into(),
-        null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+    let mut prev = None;
+    stream.adapt(|points| {
+        points.filter_map(move |(point, _)| {
+            let delta = prev.map(|prev| TSPoint {
+                ts: point.ts,
+                val: point.val - prev,
+            });
+            prev = Some(point.val);
+            delta.map(|delta| (delta, false))
+        })
     })
 }
 
@@ -84,11 +84,16 @@ pub fn arrow_finalize_with_unnest<'p>(
 pub fn arrow_run_pipeline_then_unnest<'a>(
     timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenUnnest<'a>,
-) -> TableIterator<'static, (name!(time, crate::raw::TimestampTz), name!(value, f64))> {
-    let series = run_pipeline_elements(timevector, pipeline.elements.iter())
-        .0
-        .into_owned();
-    crate::time_vector::unnest(series.into())
+) -> TableIterator<'a, (name!(time, crate::raw::TimestampTz), name!(value, f64))> {
+    let stream = stream_pipeline_elements(timevector, pipeline.elements.iter());
+    stream
+        .time_axis()
+        .assert_is(TimeAxis::TimestampTz, "unnest");
+    TableIterator::new(
+        stream
+            .into_points()
+            .map(|(point, _)| (point.ts.into(), point.val)),
+    )
 }
 
 #[pg_extern(
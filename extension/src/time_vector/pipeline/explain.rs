@@ -0,0 +1,464 @@
+use std::fmt;
+
+use pgx::{iter::TableIterator, *};
+
+use super::*;
+
+use super::{
+    anomaly::{AnomalyMethod, AnomalyOutput},
+    arithmetic::Function,
+    smoothing::SmoothingModel,
+};
+
+// A pipeline is run as a sequence of steps, each of which is one element or a
+// run of adjacent elements that can be executed together.
+pub struct Step<'a, 'e> {
+    pub elements: &'a [Element<'e>],
+    pub fusion: Option<Fusion>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Fusion {
+    // adjacent arithmetic elements are applied in a single pass over the values
+    Arithmetic,
+    // the elements after the first one cannot change its output and are skipped
+    Redundant,
+}
+
+pub fn plan<'a, 'e>(elements: &'a [Element<'e>]) -> Vec<Step<'a, 'e>> {
+    let mut steps = vec![];
+    let mut start = 0;
+    while start < elements.len() {
+        let first = &elements[start];
+        let fusion = elements
+            .get(start + 1)
+            .and_then(|next| fuses_with(first, next));
+        let mut end = start + 1;
+        while end < elements.len()
+            && fusion.is_some()
+            && fuses_with(first, &elements[end]) == fusion
+        {
+            end += 1;
+        }
+        steps.push(Step {
+            elements: &elements[start..end],
+            fusion,
+        });
+        start = end;
+    }
+    steps
+}
+
+fn fuses_with(first: &Element, next: &Element) -> Option<Fusion> {
+    match (first, next) {
+        (Element::Arithmetic { .. }, Element::Arithmetic { .. }) => Some(Fusion::Arithmetic),
+        (Element::Sort { .. }, Element::Sort { .. }) => Some(Fusion::Redundant),
+        // lttb() returns its input when it has at most `resolution` points
+        (Element::LTTB { resolution: first }, Element::LTTB { resolution: next })
+            if *first != 0 && (*next == 0 || next >= first) =>
+        {
+            Some(Fusion::Redundant)
+        }
+        _ => None,
+    }
+}
+
+/// Whether an element needs the whole timevector at once, rather than being
+/// able to work on the points one at a time.
+pub fn forces_materialization(element: &Element) -> bool {
+    match element {
+        // reorders or picks points based on all of them, or is handed the
+        // whole timevector by design
+        Element::Sort { .. } | Element::LTTB { .. } | Element::MapSeries { .. } => true,
+        // the input doesn't have to be sorted, and with a timezone the same
+        // local bucket comes around again when the clocks go back, so no
+        // bucket is complete before the last point has been seen
+        Element::Resample { .. } => true,
+        // centered and time-based windows look ahead an unbounded number of
+        // points, and empty windows make NULLs the input may not have, which
+        // a stream's flags have to say before its first point is produced
+        Element::Rolling { .. } => true,
+        // parameters that aren't given are fitted with repeated passes over
+        // the values, and the forecast is spaced by the average distance
+        // between all the points
+        Element::Smoothing { .. } => true,
+        // a point is scored against statistics of the whole series: its mean
+        // and deviation, median, quartiles, or seasonal decomposition. zscore
+        // with a window only looks back, but is run like the other detectors
+        Element::Anomaly { .. } => true,
+        Element::Delta { .. }
+        | Element::MapData { .. }
+        | Element::Arithmetic { .. }
+        | Element::MapLambda { .. }
+        | Element::FilterLambda { .. }
+        | Element::FlatMapLambda { .. }
+        | Element::FillTo { .. } => false,
+    }
+}
+
+/// Shows how a pipeline will be run: one row per element with its
+/// parameters, the step it is executed in, whether it was fused with its
+/// neighbours, an estimate of the number of points after it, and whether it
+/// needs the whole timevector at once. The estimates start from
+/// `input_points` if it is given.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "explain_pipeline",
+    schema = "toolkit_experimental"
+)]
+pub fn explain_pipeline<'a>(
+    pipeline: toolkit_experimental::UnstableTimevectorPipeline<'a>,
+    input_points: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(position, i32),
+        name!(element, String),
+        name!(parameters, Option<String>),
+        name!(step, i32),
+        name!(fusion, Option<String>),
+        name!(output_points, String),
+        name!(materializes, bool),
+    ),
+> {
+    let elements: Vec<_> = pipeline.elements.iter().collect();
+    let mut size = match input_points {
+        None => Estimate::Unknown,
+        Some(n) => Estimate::Exactly(n.max(0)),
+    };
+    let mut rows = vec![];
+    for (step_number, step) in plan(&elements).iter().enumerate() {
+        for (i, element) in step.elements.iter().enumerate() {
+            let fusion = match step.fusion {
+                Some(Fusion::Arithmetic) => Some("arithmetic pass"),
+                Some(Fusion::Redundant) if i > 0 => Some("redundant"),
+                _ => None,
+            };
+            if fusion != Some("redundant") {
+                size = size.after(element);
+            }
+            rows.push((
+                rows.len() as i32 + 1,
+                element_name(element),
+                element_parameters(element),
+                step_number as i32 + 1,
+                fusion.map(String::from),
+                size.to_string(),
+                forces_materialization(element),
+            ));
+        }
+    }
+    TableIterator::new(rows.into_iter())
+}
+
+// The number of points a timevector will have at some point of a pipeline.
+#[derive(Clone, Copy)]
+enum Estimate {
+    Exactly(i64),
+    AtMost(i64),
+    AtLeast(i64),
+    Unknown,
+}
+
+impl Estimate {
+    fn after(self, element: &Element) -> Self {
+        use Estimate::*;
+        match element {
+            Element::Sort { .. }
+            | Element::MapData { .. }
+            | Element::Arithmetic { .. }
+            | Element::MapLambda { .. }
+            | Element::Rolling { .. } => self,
+            Element::LTTB { resolution: 0 } => self,
+            Element::LTTB { resolution } => {
+                let resolution = *resolution as i64;
+                match self {
+                    Exactly(n) => Exactly(n.min(resolution)),
+                    AtMost(n) => AtMost(n.min(resolution)),
+                    AtLeast(_) | Unknown => AtMost(resolution),
+                }
+            }
+            Element::Delta { .. } => self.map(|n| (n - 1).max(0)),
+            Element::FilterLambda { .. }
+            | Element::Resample { .. }
+            | Element::Anomaly {
+                output: AnomalyOutput::Filter,
+                ..
+            } => match self {
+                Exactly(n) | AtMost(n) => AtMost(n),
+                AtLeast(_) | Unknown => Unknown,
+            },
+            Element::Anomaly { .. } => self,
+            Element::FillTo { .. } => match self {
+                Exactly(n) | AtLeast(n) => AtLeast(n),
+                AtMost(_) | Unknown => Unknown,
+            },
+            Element::Smoothing {
+                forecast_points, ..
+            } => self.map(|n| n + *forecast_points as i64),
+            Element::MapSeries { .. } | Element::FlatMapLambda { .. } => Unknown,
+        }
+    }
+
+    fn map(self, f: impl Fn(i64) -> i64) -> Self {
+        use Estimate::*;
+        match self {
+            Exactly(n) => Exactly(f(n)),
+            AtMost(n) => AtMost(f(n)),
+            AtLeast(n) => AtLeast(f(n)),
+            Unknown => Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Estimate::Exactly(n) => write!(f, "{}", n),
+            Estimate::AtMost(n) => write!(f, "<= {}", n),
+            Estimate::AtLeast(n) => write!(f, ">= {}", n),
+            Estimate::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+// the name of the SQL function that creates the element
+fn element_name(element: &Element) -> String {
+    let name = match element {
+        Element::LTTB { .. } => "lttb",
+        Element::Sort { .. } => "sort",
+        Element::Delta { .. } => "delta",
+        Element::MapData { .. } => "map_data",
+        Element::MapSeries { .. } => "map_series",
+        Element::Arithmetic { function, .. } => return format!("{:?}", function).to_lowercase(),
+        Element::MapLambda { .. } => "map",
+        Element::FilterLambda { .. } => "filter",
+        Element::FlatMapLambda { .. } => "flat_map",
+        Element::FillTo { .. } => "fill_to",
+        Element::Resample { .. } => "resample",
+        Element::Rolling { function, .. } => {
+            return format!("rolling_{:?}", function).to_lowercase()
+        }
+        Element::Smoothing { model, .. } => match model {
+            SmoothingModel::Simple => "exponential_smoothing",
+            SmoothingModel::Linear => "holt_linear",
+            SmoothingModel::HoltWintersAdditive | SmoothingModel::HoltWintersMultiplicative => {
+                "holt_winters"
+            }
+        },
+        Element::Anomaly { method, .. } => match method {
+            AnomalyMethod::Zscore => "zscore",
+            AnomalyMethod::Mad => "mad_outliers",
+            AnomalyMethod::Iqr => "iqr_outliers",
+            AnomalyMethod::Seasonal => "seasonal_outliers",
+        },
+    };
+    name.to_string()
+}
+
+fn element_parameters(element: &Element) -> Option<String> {
+    let lower = |v: &dyn fmt::Debug| format!("{:?}", v).to_lowercase();
+    let params = match element {
+        Element::LTTB { resolution } => vec![format!("resolution => {}", resolution)],
+        Element::Sort { .. } | Element::Delta { .. } => vec![],
+        Element::MapData { function } | Element::MapSeries { function } => {
+            vec![format!("function => {}", function.qualified_name())]
+        }
+        Element::Arithmetic { function, rhs } => match function {
+            Function::Add
+            | Function::Sub
+            | Function::Mul
+            | Function::Div
+            | Function::Mod
+            | Function::Power
+            | Function::LogN => vec![format!("rhs => {}", rhs)],
+            _ => vec![],
+        },
+        Element::MapLambda { lambda }
+        | Element::FilterLambda { lambda }
+        | Element::FlatMapLambda { lambda } => vec![format!("lambda => {}", lambda.as_str())],
+        Element::FillTo {
+            interval,
+            fill_method,
+        } => vec![
+            format!("interval => {}", format_usecs(*interval)),
+            format!("fill_method => {}", lower(fill_method)),
+        ],
+        Element::Resample {
+            months,
+            usecs,
+            aggregate,
+            timezone,
+            ..
+        } => {
+            let width = match *months {
+                0 => format_usecs(*usecs),
+                1 => "1 mon".to_string(),
+                m => format!("{} mons", m),
+            };
+            let mut params = vec![
+                format!("bucket_width => {}", width),
+                format!("agg => {}", lower(aggregate)),
+            ];
+            if !timezone.is_empty() {
+                let timezone = String::from_utf8_lossy(timezone.as_slice());
+                params.push(format!("timezone => {}", timezone));
+            }
+            params
+        }
+        Element::Rolling {
+            alignment,
+            num_points,
+            usecs,
+            ..
+        } => {
+            let window = match *num_points {
+                0 => format_usecs(*usecs),
+                n => format!("{} points", n),
+            };
+            vec![
+                format!("window => {}", window),
+                format!("alignment => {}", lower(alignment)),
+            ]
+        }
+        Element::Smoothing {
+            model,
+            season_length,
+            alpha,
+            beta,
+            gamma,
+            forecast_points,
+        } => {
+            let mut params = vec![];
+            match model {
+                SmoothingModel::HoltWintersAdditive => {
+                    params.push(format!("season_length => {}", season_length));
+                    params.push("seasonality => additive".to_string());
+                }
+                SmoothingModel::HoltWintersMultiplicative => {
+                    params.push(format!("season_length => {}", season_length));
+                    params.push("seasonality => multiplicative".to_string());
+                }
+                SmoothingModel::Simple | SmoothingModel::Linear => {}
+            }
+            // parameters that weren't given are stored as NaN
+            let used = match model {
+                SmoothingModel::Simple => 1,
+                SmoothingModel::Linear => 2,
+                _ => 3,
+            };
+            let names = ["alpha", "beta", "gamma"];
+            for (name, value) in names.iter().zip([alpha, beta, gamma]).take(used) {
+                if value.is_nan() {
+                    params.push(format!("{} => estimated", name));
+                } else {
+                    params.push(format!("{} => {}", name, value));
+                }
+            }
+            if *forecast_points != 0 {
+                params.push(format!("forecast_points => {}", forecast_points));
+            }
+            params
+        }
+        Element::Anomaly {
+            method,
+            output,
+            window,
+            threshold,
+        } => {
+            let mut params = vec![];
+            if *window != 0 {
+                let name = match method {
+                    AnomalyMethod::Seasonal => "period",
+                    _ => "window",
+                };
+                params.push(format!("{} => {}", name, window));
+            }
+            params.push(format!("threshold => {}", threshold));
+            params.push(format!("output => {}", lower(output)));
+            params
+        }
+    };
+    (!params.is_empty()).then(|| params.join(", "))
+}
+
+// formats a duration the way postgres prints intervals, e.g. `1 day 02:00:00`
+fn format_usecs(usecs: i64) -> String {
+    const USECS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000;
+    let (days, time) = (usecs / USECS_PER_DAY, usecs % USECS_PER_DAY);
+    let mut parts = vec![];
+    match days {
+        0 => {}
+        1 | -1 => parts.push(format!("{} day", days)),
+        _ => parts.push(format!("{} days", days)),
+    }
+    if time != 0 || days == 0 {
+        let sign = if time < 0 { "-" } else { "" };
+        let time = time.abs();
+        let secs = time / 1_000_000;
+        let mut clock = format!(
+            "{}{:02}:{:02}:{:02}",
+            sign,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        if time % 1_000_000 != 0 {
+            let fraction = format!("{:06}", time % 1_000_000);
+            clock.push('.');
+            clock.push_str(fraction.trim_end_matches('0'));
+        }
+        parts.push(clock);
+    }
+    parts.join(" ")
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_explain_pipeline() {
+        Spi::execute(|client| {
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let mut rows = client.select(
+                "SELECT explain_pipeline::TEXT FROM explain_pipeline(\
+                    sort() -> sort() -> fill_to('1 hour', 'locf') \
+                    -> add(1) -> mul(2) -> abs() \
+                    -> filter($$ $value > 3 $$) \
+                    -> lttb(100) -> lttb(200), \
+                    input_points => 500)",
+                None,
+                None,
+            );
+            let expected = [
+                "(1,sort,,1,,500,t)",
+                "(2,sort,,1,redundant,500,t)",
+                r#"(3,fill_to,"interval => 01:00:00, fill_method => locf",2,,">= 500",f)"#,
+                r#"(4,add,"rhs => 1",3,"arithmetic pass",">= 500",f)"#,
+                r#"(5,mul,"rhs => 2",3,"arithmetic pass",">= 500",f)"#,
+                r#"(6,abs,,3,"arithmetic pass",">= 500",f)"#,
+                r#"(7,filter,"lambda =>  $value > 3 ",4,,unknown,f)"#,
+                r#"(8,lttb,"resolution => 100",5,,"<= 100",t)"#,
+                r#"(9,lttb,"resolution => 200",5,redundant,"<= 100",t)"#,
+            ];
+            for expected in expected {
+                assert_eq!(rows.next().unwrap()[1].value::<String>().unwrap(), expected);
+            }
+            assert!(rows.next().is_none());
+        });
+    }
+}
@@ -59,33 +59,93 @@ pub fn fillto_pipeline_element<'e>(
     interval: crate::raw::Interval,
     fill_method: String,
 ) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    let interval = interval_to_usecs(&interval);
+
+    let fill_method = match fill_method.to_lowercase().as_str() {
+        "locf" => FillToMethod::Locf,
+        "interpolate" => FillToMethod::Interpolate,
+        "linear" => FillToMethod::Interpolate,
+        "nearest" => FillToMethod::Nearest,
+        _ => panic!("Invalid fill method"),
+    };
+
+    Element::FillTo {
+        interval,
+        fill_method,
+    }
+    .flatten()
+}
+
+// TODO: store the postgres interval object and use postgres timestamp/interval functions
+pub fn interval_to_usecs(interval: &crate::raw::Interval) -> i64 {
     unsafe {
         let interval = interval.0.cast_mut_ptr::<pg_sys::Interval>() as *const pg_sys::Interval;
-        // TODO: store the postgres interval object and use postgres timestamp/interval functions
-        let interval =
-            ((*interval).month as i64 * 30 + (*interval).day as i64) * 24 * 60 * 60 * 1000000
-                + (*interval).time;
-
-        let fill_method = match fill_method.to_lowercase().as_str() {
-            "locf" => FillToMethod::Locf,
-            "interpolate" => FillToMethod::Interpolate,
-            "linear" => FillToMethod::Interpolate,
-            "nearest" => FillToMethod::Nearest,
-            _ => panic!("Invalid fill method"),
-        };
+        ((*interval).month as i64 * 30 + (*interval).day as i64) * 24 * 60 * 60 * 1000000
+            + (*interval).time
+    }
+}
 
+pub fn fill_to<'s>(
+    stream: PointStream<'s>,
+    element: &toolkit_experimental::Element,
+) -> PointStream<'s> {
+    let (interval, method) = match element {
         Element::FillTo {
             interval,
             fill_method,
-        }
-        .flatten()
+        } => (*interval, fill_method.clone()),
+        _ => unreachable!(),
+    };
+
+    let stream = stream.require_sorted("Timevector must be sorted prior to passing to fill_to");
+
+    if stream.has_nulls() {
+        // TODO: This should be supportable outside of FillMode::Interpolate
+        panic!("Fill_to requires a timevector to not have NULL values")
     }
+
+    // the interval is stored in microseconds
+    let interval = stream.time_axis().usecs_to_ticks(interval);
+
+    // the last point passed on, and the one after it while the gap between
+    // them is being filled
+    let mut lhs: Option<TSPoint> = None;
+    let mut rhs: Option<TSPoint> = None;
+    let mut target = 0;
+    stream.adapt(|mut points| {
+        std::iter::from_fn(move || {
+            if let (Some(left), Some(right)) = (lhs, rhs) {
+                if target < right.ts {
+                    let filled = method.fill_point(&left, &right, target);
+                    target += interval;
+                    return Some((filled, false));
+                }
+                lhs = rhs.take();
+                return Some((right, false));
+            }
+
+            let (point, _) = points.next()?;
+            match lhs {
+                Some(left) if point.ts - left.ts > interval => {
+                    rhs = Some(point);
+                    target = left.ts + interval;
+                    let filled = method.fill_point(&left, &point, target);
+                    target += interval;
+                    Some((filled, false))
+                }
+                _ => {
+                    lhs = Some(point);
+                    Some((point, false))
+                }
+            }
+        })
+    })
 }
 
-pub fn fill_to<'s>(
-    series: Timevector_TSTZ_F64<'s>,
+pub fn fill_to_typed<T: TimevectorValue>(
+    mut series: TypedSeries<T>,
     element: &toolkit_experimental::Element,
-) -> Timevector_TSTZ_F64<'s> {
+) -> TypedSeries<T> {
     let (interval, method) = match element {
         Element::FillTo {
             interval,
@@ -99,43 +159,38 @@ pub fn fill_to<'s>(
     }
 
     if series.has_nulls() {
-        // TODO: This should be supportable outside of FillMode::Interpolate
         panic!("Fill_to requires a timevector to not have NULL values")
     }
 
     let mut result = vec![];
-    let mut it = series.iter().peekable();
-    let mut current = it.next();
-
-    while let (Some(lhs), Some(rhs)) = (current, it.peek()) {
-        if rhs.ts - lhs.ts > interval {
-            let mut target = lhs.ts + interval;
-            while target < rhs.ts {
-                result.push(method.fill_point(&lhs, rhs, target));
-                target += interval;
-            }
+    for window in series.points.windows(2) {
+        let ((lhs_ts, lhs), (rhs_ts, rhs)) = (&window[0], &window[1]);
+        let (lhs, rhs) = (lhs.as_ref().unwrap(), rhs.as_ref().unwrap());
+        let mut target = lhs_ts + interval;
+        while target < *rhs_ts {
+            let val = match method {
+                FillToMethod::Locf => lhs.clone(),
+                FillToMethod::Nearest if rhs_ts - target >= target - lhs_ts => lhs.clone(),
+                FillToMethod::Nearest => rhs.clone(),
+                FillToMethod::Interpolate => {
+                    let rhs_weight = (target - lhs_ts) as f64 / (rhs_ts - lhs_ts) as f64;
+                    T::interpolate(lhs, rhs, rhs_weight).unwrap_or_else(|| {
+                        panic!("cannot interpolate the values of a {}", T::TIMEVECTOR_TYPE)
+                    })
+                }
+            };
+            result.push((target, Some(val)));
+            target += interval;
         }
-
-        current = it.next();
     }
 
     if result.is_empty() {
         return series;
     }
 
-    let mut result: Vec<TSPoint> = series.iter().chain(result.into_iter()).collect();
-    result.sort_by_key(|p| p.ts);
-
-    let nulls_len = (result.len() + 7) / 8;
-    build! {
-        Timevector_TSTZ_F64 {
-            num_points: result.len() as _,
-            flags: series.flags,
-            internal_padding: [0; 3],
-            points: result.into(),
-            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
-        }
-    }
+    series.points.extend(result);
+    series.points.sort_by_key(|(ts, _)| *ts);
+    series
 }
 
 #[cfg(any(test, feature = "pg_test"))]
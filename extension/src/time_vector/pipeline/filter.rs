@@ -12,48 +12,103 @@ use super::*;
 pub fn filter_lambda_pipeline_element<'l, 'e>(
     lambda: toolkit_experimental::Lambda<'l>,
 ) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
-    let expression = lambda.parse();
-    if expression.ty() != &lambda::Type::Bool {
-        panic!("invalid lambda type: the lambda must return a BOOLEAN")
-    }
-
+    // the type of `$value` depends on the timevector the filter is applied
+    // to, so the lambda is type-checked when it's run
     Element::FilterLambda {
         lambda: lambda.into_data(),
     }
     .flatten()
 }
 
-pub fn apply_lambda_to<'a>(
-    mut series: Timevector_TSTZ_F64<'a>,
+pub fn apply_lambda_to<'s>(
+    stream: PointStream<'s>,
     lambda: &lambda::LambdaData<'_>,
-) -> Timevector_TSTZ_F64<'a> {
+) -> PointStream<'s> {
     let expression = lambda.parse();
+    check_filter_expression(&expression);
+
+    let time_axis = lambda_time_axis(&expression, stream.time_axis());
+    let mut executor = lambda::ProgramExecutor::owning(expression.compile());
+
+    stream.adapt(|points| {
+        points.filter(move |(point, _)| {
+            let time = time_axis.to_timestamptz(point.ts);
+            let result = executor.exec(point.val, time)[0].bool();
+            executor.advance(time, point.val, None);
+            result
+        })
+    })
+}
+
+fn check_filter_expression(expression: &lambda::Expression) {
     if expression.ty() != &lambda::Type::Bool {
         panic!("invalid lambda type: the lambda must return a BOOLEAN")
     }
+    if expression.uses_acc() {
+        panic!("$acc is only available in map lambdas")
+    }
+}
 
-    let mut executor = lambda::ExpressionExecutor::new(&expression);
+/// Values of the typed timevectors, as seen by lambdas.
+pub trait LambdaValue {
+    fn lambda_type() -> lambda::Type;
 
-    let invoke = |time: i64, value: f64| {
-        use lambda::Value::*;
-        executor.reset();
-        let result = executor.exec(value, time);
-        match result {
-            Bool(b) => b,
-            _ => unreachable!(),
-        }
-    };
+    fn to_lambda_value(&self) -> lambda::Value;
+}
 
-    filter_lambda_over_series(&mut series, invoke);
-    series
+// lambdas only do arithmetic on DOUBLE PRECISION
+impl LambdaValue for i64 {
+    fn lambda_type() -> lambda::Type {
+        lambda::Type::Double
+    }
+
+    fn to_lambda_value(&self) -> lambda::Value {
+        lambda::Value::Double(*self as f64)
+    }
 }
 
-pub fn filter_lambda_over_series(
-    series: &mut Timevector_TSTZ_F64<'_>,
-    mut func: impl FnMut(i64, f64) -> bool,
-) {
-    series.points.as_owned().retain(|p| func(p.ts, p.val));
-    series.num_points = series.points.len() as _;
+impl LambdaValue for bool {
+    fn lambda_type() -> lambda::Type {
+        lambda::Type::Bool
+    }
+
+    fn to_lambda_value(&self) -> lambda::Value {
+        lambda::Value::Bool(*self)
+    }
+}
+
+impl LambdaValue for String {
+    fn lambda_type() -> lambda::Type {
+        lambda::Type::Text
+    }
+
+    fn to_lambda_value(&self) -> lambda::Value {
+        lambda::Value::Text(self.clone())
+    }
+}
+
+// Points with NULL values are dropped without being passed to the lambda,
+// and don't count as the previous point of the next one.
+pub fn apply_lambda_to_typed<T: LambdaValue>(
+    mut series: TypedSeries<T>,
+    lambda: &lambda::LambdaData<'_>,
+) -> TypedSeries<T> {
+    let expression = lambda.parse_with_value_type(&T::lambda_type());
+    check_filter_expression(&expression);
+
+    let program = expression.compile();
+    let mut executor = lambda::ProgramExecutor::new(&program);
+
+    series.points.retain(|(time, value)| match value {
+        None => false,
+        Some(value) => {
+            let value = value.to_lambda_value();
+            let result = executor.exec_value(value.clone(), *time)[0].bool();
+            executor.advance_value(*time, value, None);
+            result
+        }
+    });
+    series
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -129,6 +184,24 @@ mod tests {
                 (ts:\"2020-01-03 00:00:00+00\",val:20)\
             ],null_val:[0])"
             );
+
+            // the previous point is the one before it in the input, whether
+            // or not that point was kept
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> filter($$ $value > $prev_value + 4 $$))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:0,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30)\
+            ],null_val:[0])"
+            );
         });
     }
 }
@@ -7,8 +7,12 @@ use pgx::{
 
 use super::*;
 
+use crate::serialization::PgProcId;
+
+pub use compiler::{Program, ProgramExecutor};
 pub use executor::ExpressionExecutor;
 
+mod compiler;
 mod executor;
 mod parser;
 
@@ -49,8 +53,9 @@ impl<'input> InOutFuncs for Lambda<'input> {
         use crate::serialization::str_from_db_encoding;
 
         let s = str_from_db_encoding(input);
-        // validate the string
-        let _ = parser::parse_expression(s);
+        // validate the string, the types can only be checked once we know
+        // what kind of timevector the lambda is applied to
+        parser::check_syntax(s);
         unsafe {
             flatten! {
                 Lambda {
@@ -63,8 +68,16 @@ impl<'input> InOutFuncs for Lambda<'input> {
 }
 
 impl<'a> LambdaData<'a> {
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(self.string.as_slice()).unwrap()
+    }
+
     pub fn parse(&self) -> Expression {
-        parser::parse_expression(std::str::from_utf8(self.string.as_slice()).unwrap())
+        parser::parse_expression(self.as_str())
+    }
+
+    pub fn parse_with_value_type(&self, value_ty: &Type) -> Expression {
+        parser::parse_expression_with_value_type(self.as_str(), value_ty)
     }
 }
 
@@ -85,8 +98,9 @@ pub fn bool_lambda<'a>(
             expression
         )
     }
-    let mut executor = ExpressionExecutor::new(&expression);
-    executor.exec(value, time.into()).bool()
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    executor.exec(value, time.into())[0].bool()
 }
 
 #[pg_extern(stable, parallel_safe, schema = "toolkit_experimental")]
@@ -99,8 +113,9 @@ pub fn f64_lambda<'a>(
     if expression.expr.ty() != &Type::Double {
         panic!("invalid return type, must return a DOUBLE PRECISION")
     }
-    let mut executor = ExpressionExecutor::new(&expression);
-    executor.exec(value, time.into()).float()
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    executor.exec(value, time.into())[0].float()
 }
 
 #[pg_extern(stable, parallel_safe, schema = "toolkit_experimental")]
@@ -113,8 +128,9 @@ pub fn ttz_lambda<'a>(
     if expression.expr.ty() != &Type::Time {
         panic!("invalid return type, must return a TimestampTZ")
     }
-    let mut executor = ExpressionExecutor::new(&expression);
-    executor.exec(value, time.into()).time().into()
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    executor.exec(value, time.into())[0].time().into()
 }
 
 use crate::raw::Interval;
@@ -128,8 +144,9 @@ pub fn interval_lambda<'a>(
     if expression.expr.ty() != &Type::Interval {
         panic!("invalid return type, must return a INTERVAL")
     }
-    let mut executor = ExpressionExecutor::new(&expression);
-    pg_sys::Datum::from(executor.exec(value, time.into()).interval()).into()
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    pg_sys::Datum::from(executor.exec(value, time.into())[0].interval()).into()
 }
 
 #[pg_extern(stable, parallel_safe, schema = "toolkit_experimental")]
@@ -143,11 +160,9 @@ pub fn point_lambda<'a>(
         panic!("invalid return type, must return a (TimestampTZ, DOUBLE PRECISION)")
     }
 
-    let mut executor = ExpressionExecutor::new(&expression);
-    let columns = match executor.exec(value, time.into()) {
-        Value::Tuple(columns) => columns,
-        _ => unreachable!(),
-    };
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    let columns = executor.exec(value, time.into());
     TableIterator::new(Some((columns[0].time().into(), columns[1].float())).into_iter())
 }
 
@@ -174,6 +189,65 @@ pub fn trace_lambda<'a>(
     )
 }
 
+// Times the tree-walking interpreter and the compiled program evaluating
+// `lambda` over every point of `series`. The results are thrown away, the
+// tests check that the two executors agree.
+#[pg_extern(stable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bench_lambda<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    lambda: toolkit_experimental::Lambda<'a>,
+) -> TableIterator<'static, (name!(executor, String), name!(seconds, f64))> {
+    use std::{hint::black_box, time::Instant};
+
+    let time_axis = series.time_axis();
+    let points: Vec<(i64, f64)> = series
+        .iter()
+        .map(|point| (time_axis.to_timestamptz(point.ts), point.val))
+        .collect();
+    let expression = lambda.parse();
+
+    let start = Instant::now();
+    let mut executor = ExpressionExecutor::new(&expression);
+    for &(time, value) in &points {
+        executor.reset();
+        let result = executor.exec(value, time);
+        let acc = match &result {
+            Value::Tuple(columns) => accumulated(columns),
+            result => accumulated(std::slice::from_ref(result)),
+        };
+        black_box(result);
+        executor.advance(time, value, acc);
+    }
+    let interpreter_time = start.elapsed();
+
+    let start = Instant::now();
+    let program = expression.compile();
+    let mut executor = ProgramExecutor::new(&program);
+    for &(time, value) in &points {
+        let result = executor.exec(value, time);
+        let acc = accumulated(result);
+        black_box(result);
+        executor.advance(time, value, acc);
+    }
+    let compiled_time = start.elapsed();
+
+    TableIterator::new(
+        vec![
+            ("interpreter".to_string(), interpreter_time.as_secs_f64()),
+            ("compiled".to_string(), compiled_time.as_secs_f64()),
+        ]
+        .into_iter(),
+    )
+}
+
+// The `$acc` of the next point when a lambda returns `result`
+fn accumulated(result: &[Value]) -> Option<f64> {
+    match result {
+        [Value::Double(f)] | [_, Value::Double(f)] => Some(*f),
+        _ => None,
+    }
+}
+
 //
 // Common types across the parser and executor
 //
@@ -187,16 +261,24 @@ pub struct Expression {
 
 #[derive(Clone, Debug)]
 pub enum ExpressionSegment {
-    ValueVar,
+    ValueVar(Type),
     TimeVar,
+    PrevTimeVar,
+    PrevValueVar(Type),
+    IndexVar,
+    AccVar,
     DoubleConstant(f64),
     TimeConstant(i64),
     IntervalConstant(*mut pg_sys::Interval),
+    TextConstant(String),
     UserVar(usize, Type),
     Unary(UnaryOp, Box<Self>, Type),
     Binary(BinOp, Box<Self>, Box<Self>, Type),
-    FunctionCall(Function, Vec<Self>),
+    FunctionCall(Function, Vec<Self>, Type),
+    UserFunctionCall(PgProcId, Vec<Self>, Type),
+    If(Box<Self>, Box<Self>, Box<Self>, Type),
     BuildTuple(Vec<Self>, Type),
+    BuildList(Vec<Self>, Type),
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -249,6 +331,12 @@ pub enum Function {
     Asinh,
     Acosh,
     Atanh,
+    // variadic
+    Greatest,
+    Least,
+    Coalesce,
+    // returns a list
+    GenerateSeries,
 }
 
 // types
@@ -258,7 +346,9 @@ pub enum Type {
     Double,
     Bool,
     Interval,
+    Text,
     Tuple(Vec<Self>),
+    List(Box<Self>),
 }
 
 // values
@@ -268,7 +358,9 @@ pub enum Value {
     Double(f64),
     Time(i64),
     Interval(*mut pg_sys::Interval),
+    Text(String),
     Tuple(Vec<Self>),
+    List(Vec<Self>),
 }
 
 impl Expression {
@@ -279,6 +371,17 @@ impl Expression {
     pub fn ty_is_ts_point(&self) -> bool {
         self.expr.ty_is_ts_point()
     }
+
+    pub fn uses_acc(&self) -> bool {
+        let is_acc = |e: &ExpressionSegment| matches!(e, ExpressionSegment::AccVar);
+        self.expr.any(&is_acc) || self.variables.iter().any(|v| v.any(&is_acc))
+    }
+
+    pub fn uses_time(&self) -> bool {
+        use ExpressionSegment::{PrevTimeVar, TimeVar};
+        let is_time = |e: &ExpressionSegment| matches!(e, TimeVar | PrevTimeVar);
+        self.expr.any(&is_time) || self.variables.iter().any(|v| v.any(&is_time))
+    }
 }
 
 impl ExpressionSegment {
@@ -286,16 +389,24 @@ impl ExpressionSegment {
         use ExpressionSegment::*;
         use Type::*;
         match self {
-            ValueVar => &Double,
+            ValueVar(ty) => ty,
             TimeVar => &Time,
+            PrevTimeVar => &Time,
+            PrevValueVar(ty) => ty,
+            IndexVar => &Double,
+            AccVar => &Double,
             DoubleConstant(_) => &Double,
             TimeConstant(_) => &Time,
             IntervalConstant(_) => &Interval,
+            TextConstant(_) => &Text,
             UserVar(_, ty) => ty,
-            FunctionCall(_, _) => &Double,
+            FunctionCall(_, _, ty) => ty,
+            UserFunctionCall(_, _, ty) => ty,
+            If(_, _, _, ty) => ty,
             Unary(_, _, ty) => ty,
             Binary(_, _, _, ty) => ty,
             BuildTuple(_, ty) => ty,
+            BuildList(_, ty) => ty,
         }
     }
 
@@ -308,19 +419,48 @@ impl ExpressionSegment {
         matches!(&**columns, [Type::Time, Type::Double])
     }
 
+    // does `pred` hold for this segment or any of its sub-expressions?
+    // variables are not followed, check `Expression::variables` for those
+    pub fn any(&self, pred: &impl Fn(&Self) -> bool) -> bool {
+        use ExpressionSegment::*;
+        if pred(self) {
+            return true;
+        }
+        match self {
+            Unary(_, e, _) => e.any(pred),
+            Binary(_, l, r, _) => l.any(pred) || r.any(pred),
+            If(c, t, o, _) => c.any(pred) || t.any(pred) || o.any(pred),
+            FunctionCall(_, args, _)
+            | UserFunctionCall(_, args, _)
+            | BuildTuple(args, _)
+            | BuildList(args, _) => args.iter().any(|a| a.any(pred)),
+            ValueVar(_) | TimeVar | PrevTimeVar | PrevValueVar(_) | IndexVar | AccVar
+            | DoubleConstant(_) | TimeConstant(_) | IntervalConstant(_) | TextConstant(_)
+            | UserVar(..) => false,
+        }
+    }
+
     pub fn name(&self) -> Cow<'static, str> {
         use ExpressionSegment::*;
         match self {
-            ValueVar => "$value".into(),
+            ValueVar(_) => "$value".into(),
             TimeVar => "$time".into(),
+            PrevTimeVar => "$prev_time".into(),
+            PrevValueVar(_) => "$prev_value".into(),
+            IndexVar => "$index".into(),
+            AccVar => "$acc".into(),
             DoubleConstant(_) => "f64 const".into(),
             TimeConstant(_) => "time const".into(),
             IntervalConstant(_) => "interval const".into(),
+            TextConstant(_) => "text const".into(),
             UserVar(i, t) => format!("user var {}: {:?}", i, t).into(),
             Unary(op, _, t) => format!("uop {:?} {:?}", op, t).into(),
             Binary(op, _, _, t) => format!("binop {:?} {:?}", op, t).into(),
-            FunctionCall(f, _) => format!("function {:?}", f).into(),
+            FunctionCall(f, _, _) => format!("function {:?}", f).into(),
+            UserFunctionCall(f, _, _) => format!("function {}", f.qualified_name()).into(),
+            If(_, _, _, t) => format!("if {:?}", t).into(),
             BuildTuple(_, t) => format!("tuple {:?}", t).into(),
+            BuildList(_, t) => format!("list {:?}", t).into(),
         }
     }
 }
@@ -353,6 +493,13 @@ impl Value {
             _ => unreachable!(),
         }
     }
+
+    pub(crate) fn text(&self) -> &str {
+        match self {
+            Value::Text(s) => s,
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl PartialOrd for Value {
@@ -371,7 +518,9 @@ impl PartialOrd for Value {
             (Bool(l0), Bool(r0)) => l0.partial_cmp(r0),
             (Double(l0), Double(r0)) => l0.partial_cmp(r0),
             (Time(l0), Time(r0)) => l0.partial_cmp(r0),
+            (Text(l0), Text(r0)) => l0.partial_cmp(r0),
             (Tuple(l0), Tuple(r0)) => l0.partial_cmp(r0),
+            (List(l0), List(r0)) => l0.partial_cmp(r0),
             (Interval(l0), Interval(r0)) => unsafe {
                 let res = pg_sys::DirectFunctionCall2Coll(
                     Some(interval_cmp),
@@ -402,7 +551,9 @@ impl PartialEq for Value {
             (Bool(l0), Bool(r0)) => l0 == r0,
             (Double(l0), Double(r0)) => l0 == r0,
             (Time(l0), Time(r0)) => l0 == r0,
+            (Text(l0), Text(r0)) => l0 == r0,
             (Tuple(l0), Tuple(r0)) => l0 == r0,
+            (List(l0), List(r0)) => l0 == r0,
             (Interval(l0), Interval(r0)) => unsafe {
                 let res = pg_sys::DirectFunctionCall2Coll(
                     Some(interval_eq),
@@ -777,6 +928,271 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_lambda_user_function() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            f64_lambda_eq!(client, "greatest(1.0, $value, 0.5)", 2.0);
+            f64_lambda_eq!(client, "least(1.0, $value, 0.5)", 0.5);
+            f64_lambda_eq!(client, "greatest(sqrt(-1), 1.0)", 1.0);
+            f64_lambda_eq!(client, "coalesce(sqrt(-1), $value)", 2.0);
+            bool_lambda_eq!(
+                client,
+                "least('2021-01-01't, '2020-01-01't) = '2020-01-01't",
+                "true"
+            );
+
+            f64_lambda_eq!(client, "extract('epoch', '1 day 2 hours'i)", 93600.0);
+            let res = client
+                .select(
+                    "SELECT ttz_lambda($$ to_timestamp($value * 86400) $$, '2020-11-22 13:00:01', 2.0)::text",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(&*res.unwrap(), "1970-01-03 00:00:00+00");
+            interval_lambda_eq!(client, "justify_hours('36 hours'i)", "1 day 12:00:00");
+            bool_lambda_eq!(client, "upper('abc') = 'ABC'", "true");
+
+            client.select("CREATE SCHEMA lambda_fns", None, None);
+            client.select(
+                "CREATE FUNCTION lambda_fns.double_it(x double precision) \
+                RETURNS double precision \
+                AS 'SELECT $1 * 2' LANGUAGE SQL IMMUTABLE",
+                None,
+                None,
+            );
+            f64_lambda_eq!(client, "lambda_fns.double_it($value) + 1", 5.0);
+            f64_lambda_eq!(
+                client,
+                "lambda_fns.double_it(lambda_fns.double_it(1.5))",
+                6.0
+            );
+
+            let rows: Vec<_> = trace_lambda!(client, "lambda_fns.double_it(1.5)");
+            assert_eq!(
+                &*rows,
+                [
+                    r#"                                      f64 const: "Double(1.5)""#,
+                    r#"function lambda_fns.double_it(double precision): "Double(3.0)""#,
+                ],
+            );
+        });
+    }
+
+    // the result of `date_trunc(text, timestamptz)` depends on the TimeZone
+    #[pg_test(error = "cannot call function `date_trunc` from a lambda, it is not immutable")]
+    fn test_lambda_stable_function() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.ttz_lambda($$ date_trunc('day', $time) $$, now(), 2.0)",
+                None,
+                None,
+            );
+        });
+    }
+
+    // Runs `lambda` over `points` generated points with both the interpreter
+    // and the compiled program, checking that they agree.
+    fn assert_executors_agree(lambda: &str, points: i64) {
+        use super::{
+            accumulated, parser::parse_expression, ExpressionExecutor, ProgramExecutor, Value,
+        };
+
+        // 2020-01-01 00:00:00+00 onwards, one point a minute
+        let point = |i: i64| {
+            (
+                631152000000000 + i * 60_000_000,
+                (i as f64 / 10.0).sin() * 100.0,
+            )
+        };
+
+        let expression = parse_expression(lambda);
+        let mut interpreted = Vec::with_capacity(points as usize);
+        let mut executor = ExpressionExecutor::new(&expression);
+        for i in 0..points {
+            let (time, value) = point(i);
+            executor.reset();
+            let result = match executor.exec(value, time) {
+                Value::Tuple(columns) => columns,
+                result => vec![result],
+            };
+            executor.advance(time, value, accumulated(&result));
+            interpreted.push(result);
+        }
+
+        let program = expression.compile();
+        let mut executor = ProgramExecutor::new(&program);
+        let mut compiled = Vec::with_capacity(points as usize);
+        for i in 0..points {
+            let (time, value) = point(i);
+            let result = executor.exec(value, time);
+            let next_acc = accumulated(result);
+            compiled.push(result.to_vec());
+            executor.advance(time, value, next_acc);
+        }
+
+        let same = |a: &Value, b: &Value| {
+            a == b
+                || matches!((a, b), (Value::Double(a), Value::Double(b)) if a.is_nan() && b.is_nan())
+        };
+        for (i, (interpreted, compiled)) in interpreted.iter().zip(&compiled).enumerate() {
+            let matches = interpreted.len() == compiled.len()
+                && interpreted.iter().zip(compiled).all(|(a, b)| same(a, b));
+            assert!(
+                matches,
+                "compiled lambda `{}` disagrees with the interpreter at point {}: {:?} vs {:?}",
+                lambda, i, compiled, interpreted
+            );
+        }
+    }
+
+    #[pg_test]
+    fn test_lambda_compiled_matches_interpreter() {
+        for lambda in [
+            "$value * 2 + 1",
+            "let $x = $value * 2; if $x > 50 then ($time, $acc) else ($time + '1 hour'i, coalesce($value - $prev_value, 0))",
+            "case when $index < 10 then $value when $value > 0 and $value < 90 then sqrt($value) else greatest($value, $prev_value, -50) end",
+            "($time, $value) = ($prev_time, $prev_value) or not ($value >= 0)",
+            "$time - date_trunc('hour', $time - $prev_time)",
+            "extract('epoch', $time - $prev_time) + abs($acc) / 2",
+            "upper('abc') = 'ABC'",
+        ] {
+            assert_executors_agree(lambda, 1000);
+        }
+    }
+
+    #[pg_test]
+    fn test_bench_lambda() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            let executors: Vec<String> = client
+                .select(
+                    "SELECT executor FROM bench_lambda(\
+                        (SELECT timevector(time, value) \
+                        FROM generate_series(1, 1000) value, \
+                        LATERAL (SELECT '2020-01-01'::timestamptz + value * '1 minute'::interval) t(time)), \
+                        $$ $value * 2 + 1 $$)",
+                    None,
+                    None,
+                )
+                .map(|r| r.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect();
+            assert_eq!(executors, ["interpreter", "compiled"]);
+        });
+    }
+
+    #[pg_test]
+    fn test_lambda_conditional() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            f64_lambda_eq!(client, "if $value > 1 then 1 else $value", 1.0);
+            f64_lambda_eq!(client, "IF $value > 3 THEN 3 ELSE $value * 1.5", 3.0);
+            f64_lambda_eq!(
+                client,
+                "if $value > 1 then if $value > 3 then 3 else 2 else 1",
+                2.0
+            );
+            f64_lambda_eq!(client, "1 + if $value < 1 then 0 else $value + 1", 4.0);
+            f64_lambda_eq!(
+                client,
+                "case when $value < 1 then 0 when $value < 3 then 1 else 2 end",
+                1.0
+            );
+            f64_lambda_eq!(
+                client,
+                "CASE WHEN $value < 1 THEN 0 WHEN $value < 2 THEN 1 ELSE 2 END",
+                2.0
+            );
+            assert!(f64_lambda!(client, "case when $value > 5 then 1 end").is_nan());
+            bool_lambda_eq!(client, "if $value = 2 then 1 = 1 else 1 = 2", "true");
+            interval_lambda_eq!(
+                client,
+                "case when $value > 1 then '1 day'i else '1 week'i end",
+                "1 day"
+            );
+            point_lambda_eq!(
+                client,
+                "let $v = $value * 10; ($time, if $v > 15 then 15 else $v)",
+                r#"("2021-01-01 00:00:00+00",15)"#
+            );
+
+            let rows: Vec<_> = trace_lambda!(client, "if $value > 1 then 1 else 2");
+            assert_eq!(
+                &*rows,
+                [
+                    r#"       $value: "Double(2.0)""#,
+                    r#"    f64 const: "Double(1.0)""#,
+                    r#"binop Gt Bool: "Bool(true)""#,
+                    r#"    f64 const: "Double(1.0)""#,
+                    r#"    if Double: "Double(1.0)""#,
+                ],
+            );
+
+            let res = client
+                .select(
+                    "SELECT (timevector(time, value) -> map($$ \
+                        case when $value < 0 then 0 when $value > 10 then 10 else $value end \
+                    $$))::TEXT \
+                    FROM (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, -5.0), \
+                                 ('2020-01-02 UTC'::TIMESTAMPTZ, 5.0), \
+                                 ('2020-01-03 UTC'::TIMESTAMPTZ, 15.0)) v(time, value)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                res.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:0),\
+                (ts:\"2020-01-02 00:00:00+00\",val:5),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10)\
+            ],null_val:[0])"
+            );
+        });
+    }
+
     #[pg_test]
     fn test_lambda_unary() {
         Spi::execute(|client| {
@@ -830,6 +1246,7 @@ mod tests {
             interval_lambda_eq!(client, "'1 day'i * 3", "3 days");
             interval_lambda_eq!(client, "4 * '1 day'i", "4 days");
             interval_lambda_eq!(client, "'4 day'i / 4", "1 day");
+            interval_lambda_eq!(client, "'2021-01-02't - '2021-01-01 12:00:00't", "12:00:00");
         });
     }
 
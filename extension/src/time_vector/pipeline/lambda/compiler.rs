@@ -0,0 +1,541 @@
+use std::{
+    ops::{Deref, Range},
+    rc::Rc,
+};
+
+use pgx::*;
+
+use super::{executor::*, *};
+
+use crate::serialization::PgProcId;
+
+// Lambdas are compiled once into a flat, register-based program so that
+// running them over a series doesn't need to walk the expression tree or
+// allocate for every point. Every expression writes its result to its own
+// registers, tuples are spread over one register per column, and `let`
+// variables are compiled into separate blocks that are run the first time a
+// point uses them, as in the interpreter.
+
+type Reg = usize;
+
+// the registers holding the point currently being processed
+const VALUE: Reg = 0;
+const TIME: Reg = 1;
+const PREV_TIME: Reg = 2;
+const PREV_VALUE: Reg = 3;
+const INDEX: Reg = 4;
+const ACC: Reg = 5;
+const NUM_POINT_REGISTERS: usize = 6;
+
+#[derive(Debug)]
+enum Op {
+    // dst, src
+    Move(Reg, Reg),
+    // run the code for the variable unless it already has been for this point
+    Force(usize),
+    Jump(usize),
+    JumpIfFalse(Reg, usize),
+    JumpIfTrue(Reg, usize),
+    JumpIfNotNull(Reg, usize),
+    // op, dst, left, right, for DOUBLE PRECISION arithmetic and comparisons
+    FloatArithmetic(BinOp, Reg, Reg, Reg),
+    FloatCompare(BinOp, Reg, Reg, Reg),
+    // op, dst, left columns, right columns
+    Compare(BinOp, Reg, Vec<Reg>, Vec<Reg>),
+    // op, result type, dst, left, right
+    Binary(BinOp, Type, Reg, Reg, Reg),
+    // op, result type, dst, src
+    Unary(UnaryOp, Type, Reg, Reg),
+    // function, dst, args
+    Function(Function, Reg, Vec<Reg>),
+    // index into `Program::functions`, result type, dst, args
+    UserFunction(usize, Type, Reg, Vec<Reg>),
+    // dst, elements
+    BuildList(Reg, Vec<Reg>),
+}
+
+#[derive(Debug)]
+pub struct Program {
+    main: Vec<Op>,
+    // the code for each variable and the registers holding its value
+    variables: Vec<(Vec<Op>, Vec<Reg>)>,
+    constants: Vec<(Reg, Value)>,
+    functions: Vec<PgProcId>,
+    // the registers holding the columns of the result
+    result: Range<Reg>,
+    num_registers: usize,
+    // which of the point registers the program reads
+    inputs: [bool; NUM_POINT_REGISTERS],
+}
+
+impl Expression {
+    pub fn compile(&self) -> Program {
+        let mut program = Program {
+            main: vec![],
+            variables: vec![],
+            constants: vec![],
+            functions: vec![],
+            result: 0..0,
+            num_registers: NUM_POINT_REGISTERS,
+            inputs: [false; NUM_POINT_REGISTERS],
+        };
+        for var in &self.variables {
+            let mut ops = vec![];
+            let result = program.compile(var, &mut ops);
+            program.variables.push((ops, result));
+        }
+        let mut main = vec![];
+        let mut result = program.compile(&self.expr, &mut main);
+        // the result is returned straight from the registers, so its columns
+        // must be next to each other
+        if result.windows(2).any(|w| w[1] != w[0] + 1) {
+            let dst = program.registers(result.len());
+            main.extend(dst.iter().zip(&result).map(|(d, s)| Op::Move(*d, *s)));
+            result = dst;
+        }
+        let start = result.first().copied().unwrap_or(NUM_POINT_REGISTERS);
+        program.result = start..start + result.len();
+        program.main = main;
+        program
+    }
+}
+
+impl Program {
+    fn registers(&mut self, n: usize) -> Vec<Reg> {
+        let start = self.num_registers;
+        self.num_registers += n;
+        (start..self.num_registers).collect()
+    }
+
+    fn register(&mut self) -> Reg {
+        self.registers(1)[0]
+    }
+
+    fn input(&mut self, reg: Reg) -> Vec<Reg> {
+        self.inputs[reg] = true;
+        vec![reg]
+    }
+
+    fn constant(&mut self, value: Value) -> Vec<Reg> {
+        let reg = self.register();
+        self.constants.push((reg, value));
+        vec![reg]
+    }
+
+    fn compile_scalar(&mut self, expr: &ExpressionSegment, ops: &mut Vec<Op>) -> Reg {
+        let regs = self.compile(expr, ops);
+        assert_eq!(regs.len(), 1);
+        regs[0]
+    }
+
+    // emits the code for `expr` into `ops` and returns the registers holding
+    // the result, one per column.
+    fn compile(&mut self, expr: &ExpressionSegment, ops: &mut Vec<Op>) -> Vec<Reg> {
+        use ExpressionSegment::*;
+        match expr {
+            ValueVar(_) => self.input(VALUE),
+            TimeVar => self.input(TIME),
+            PrevTimeVar => self.input(PREV_TIME),
+            PrevValueVar(_) => self.input(PREV_VALUE),
+            IndexVar => self.input(INDEX),
+            AccVar => self.input(ACC),
+            DoubleConstant(f) => self.constant(Value::Double(*f)),
+            TimeConstant(t) => self.constant(Value::Time(*t)),
+            IntervalConstant(i) => self.constant(Value::Interval(*i)),
+            TextConstant(s) => self.constant(Value::Text(s.clone())),
+
+            UserVar(i, _) => {
+                ops.push(Op::Force(*i));
+                self.variables[*i].1.clone()
+            }
+
+            Unary(op, expr, ty) => {
+                let src = self.compile_scalar(expr, ops);
+                let dst = self.register();
+                ops.push(Op::Unary(*op, ty.clone(), dst, src));
+                vec![dst]
+            }
+
+            Binary(op @ (BinOp::And | BinOp::Or), left, right, _) => {
+                let dst = self.register();
+                let left = self.compile_scalar(left, ops);
+                ops.push(Op::Move(dst, left));
+                let jump = ops.len();
+                ops.push(Op::Jump(0));
+                let right = self.compile_scalar(right, ops);
+                ops.push(Op::Move(dst, right));
+                ops[jump] = match op {
+                    BinOp::And => Op::JumpIfFalse(dst, ops.len()),
+                    _ => Op::JumpIfTrue(dst, ops.len()),
+                };
+                vec![dst]
+            }
+
+            Binary(op, left_expr, right_expr, ty) => {
+                use BinOp::*;
+                let left = self.compile(left_expr, ops);
+                let right = self.compile(right_expr, ops);
+                let dst = self.register();
+                let is_float = left_expr.ty() == &Type::Double;
+                let op = match op {
+                    Eq | Neq | Lt | Le | Gt | Ge if is_float => {
+                        Op::FloatCompare(*op, dst, left[0], right[0])
+                    }
+                    Eq | Neq | Lt | Le | Gt | Ge => Op::Compare(*op, dst, left, right),
+                    _ if ty == &Type::Double => Op::FloatArithmetic(*op, dst, left[0], right[0]),
+                    _ => Op::Binary(*op, ty.clone(), dst, left[0], right[0]),
+                };
+                ops.push(op);
+                vec![dst]
+            }
+
+            FunctionCall(Function::Coalesce, args, _) => {
+                // only evaluate arguments until we find one that isn't NULL
+                let dst = self.register();
+                let mut jumps = vec![];
+                for arg in args {
+                    let arg = self.compile_scalar(arg, ops);
+                    ops.push(Op::Move(dst, arg));
+                    jumps.push(ops.len());
+                    ops.push(Op::JumpIfNotNull(dst, 0));
+                }
+                let end = ops.len();
+                for jump in jumps {
+                    ops[jump] = Op::JumpIfNotNull(dst, end);
+                }
+                vec![dst]
+            }
+
+            FunctionCall(function, args, _) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile_scalar(arg, ops))
+                    .collect();
+                let dst = self.register();
+                ops.push(Op::Function(*function, dst, args));
+                vec![dst]
+            }
+
+            UserFunctionCall(function, args, ty) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile_scalar(arg, ops))
+                    .collect();
+                let dst = self.register();
+                self.functions.push(*function);
+                ops.push(Op::UserFunction(
+                    self.functions.len() - 1,
+                    ty.clone(),
+                    dst,
+                    args,
+                ));
+                vec![dst]
+            }
+
+            If(condition, then, otherwise, _) => {
+                let condition = self.compile_scalar(condition, ops);
+                let branch = ops.len();
+                ops.push(Op::Jump(0));
+
+                let then = self.compile(then, ops);
+                let dst = self.registers(then.len());
+                ops.extend(dst.iter().zip(&then).map(|(d, s)| Op::Move(*d, *s)));
+                let skip_otherwise = ops.len();
+                ops.push(Op::Jump(0));
+
+                ops[branch] = Op::JumpIfFalse(condition, ops.len());
+                let otherwise = self.compile(otherwise, ops);
+                ops.extend(dst.iter().zip(&otherwise).map(|(d, s)| Op::Move(*d, *s)));
+                ops[skip_otherwise] = Op::Jump(ops.len());
+                dst
+            }
+
+            BuildTuple(exprs, _) => exprs
+                .iter()
+                .flat_map(|expr| self.compile(expr, ops))
+                .collect(),
+
+            BuildList(exprs, _) => {
+                let elements = exprs
+                    .iter()
+                    .map(|expr| self.compile_scalar(expr, ops))
+                    .collect();
+                let dst = self.register();
+                ops.push(Op::BuildList(dst, elements));
+                vec![dst]
+            }
+        }
+    }
+}
+
+// Executors running inside a streaming pipeline outlive the function that
+// compiled their lambda, so they may own the program instead of borrowing it.
+#[derive(Clone)]
+enum ProgramRef<'p> {
+    Borrowed(&'p Program),
+    Owned(Rc<Program>),
+}
+
+impl<'p> Deref for ProgramRef<'p> {
+    type Target = Program;
+
+    fn deref(&self) -> &Program {
+        match self {
+            ProgramRef::Borrowed(program) => program,
+            ProgramRef::Owned(program) => program,
+        }
+    }
+}
+
+pub struct ProgramExecutor<'p> {
+    program: ProgramRef<'p>,
+    registers: Vec<Value>,
+    forced: Vec<bool>,
+    functions: Vec<pg_sys::FmgrInfo>,
+    // state carried from one point to the next, see `advance()`
+    index: usize,
+    prev: Option<(i64, Value)>,
+    acc: f64,
+}
+
+impl ProgramExecutor<'static> {
+    // an executor that owns its program, so it can be kept in a pipeline's
+    // iterator chain
+    pub fn owning(program: Program) -> Self {
+        Self::with_program(ProgramRef::Owned(Rc::new(program)))
+    }
+}
+
+impl<'p> ProgramExecutor<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self::with_program(ProgramRef::Borrowed(program))
+    }
+
+    fn with_program(program: ProgramRef<'p>) -> Self {
+        let mut registers = vec![Value::Double(0.0); program.num_registers];
+        for (reg, value) in &program.constants {
+            registers[*reg] = value.clone();
+        }
+        let forced = vec![false; program.variables.len()];
+        let functions = program
+            .functions
+            .iter()
+            .map(|f| *function_info(*f))
+            .collect();
+        Self {
+            program,
+            registers,
+            forced,
+            functions,
+            index: 0,
+            prev: None,
+            acc: 0.0,
+        }
+    }
+
+    // same as `ExpressionExecutor::advance()`
+    pub fn advance(&mut self, time: i64, value: f64, acc: Option<f64>) {
+        self.advance_value(time, Value::Double(value), acc)
+    }
+
+    pub fn advance_value(&mut self, time: i64, value: Value, acc: Option<f64>) {
+        self.index += 1;
+        self.prev = Some((time, value));
+        if let Some(acc) = acc {
+            self.acc = acc;
+        }
+    }
+
+    // returns the columns of the result, a single one unless the lambda
+    // returns a tuple
+    pub fn exec(&mut self, value: f64, time: i64) -> &[Value] {
+        self.exec_value(Value::Double(value), time)
+    }
+
+    // for timevectors whose values aren't DOUBLE PRECISION. There is no NULL
+    // for those types, so before the first point `$prev_value` is the
+    // current value instead.
+    pub fn exec_value(&mut self, value: Value, time: i64) -> &[Value] {
+        // only the registers the program reads need to be filled in
+        let inputs = self.program.inputs;
+        if inputs[PREV_TIME] || inputs[PREV_VALUE] {
+            let (prev_time, prev_value) = match &self.prev {
+                Some((prev_time, prev_value)) => (*prev_time, prev_value.clone()),
+                None => match value {
+                    Value::Double(_) => (time, Value::Double(f64::NAN)),
+                    _ => (time, value.clone()),
+                },
+            };
+            self.registers[PREV_TIME] = Value::Time(prev_time);
+            self.registers[PREV_VALUE] = prev_value;
+        }
+        if inputs[TIME] {
+            self.registers[TIME] = Value::Time(time);
+        }
+        if inputs[INDEX] {
+            self.set_double(INDEX, self.index as f64);
+        }
+        if inputs[ACC] {
+            self.set_double(ACC, self.acc);
+        }
+        self.registers[VALUE] = value;
+        for forced in &mut self.forced {
+            *forced = false;
+        }
+
+        let program = self.program.clone();
+        self.run(&program.main);
+
+        &self.registers[program.result.clone()]
+    }
+
+    // Registers holding scalars keep the same kind of value from one point to
+    // the next, so they are overwritten in place rather than dropping the old
+    // value first.
+    fn set_double(&mut self, dst: Reg, value: f64) {
+        match &mut self.registers[dst] {
+            Value::Double(old) => *old = value,
+            old => *old = Value::Double(value),
+        }
+    }
+
+    fn set_bool(&mut self, dst: Reg, value: bool) {
+        match &mut self.registers[dst] {
+            Value::Bool(old) => *old = value,
+            old => *old = Value::Bool(value),
+        }
+    }
+
+    fn run(&mut self, ops: &[Op]) {
+        let mut pc = 0;
+        while let Some(op) = ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Move(dst, src) => self.registers[*dst] = self.registers[*src].clone(),
+
+                Op::Force(i) => {
+                    if !self.forced[*i] {
+                        let program = self.program.clone();
+                        self.run(&program.variables[*i].0);
+                        self.forced[*i] = true;
+                    }
+                }
+
+                Op::Jump(target) => pc = *target,
+                Op::JumpIfFalse(cond, target) => {
+                    if !self.registers[*cond].bool() {
+                        pc = *target
+                    }
+                }
+                Op::JumpIfTrue(cond, target) => {
+                    if self.registers[*cond].bool() {
+                        pc = *target
+                    }
+                }
+                Op::JumpIfNotNull(val, target) => {
+                    if !self.registers[*val].float().is_nan() {
+                        pc = *target
+                    }
+                }
+
+                Op::FloatArithmetic(op, dst, left, right) => {
+                    use BinOp::*;
+                    let left = self.registers[*left].float();
+                    let right = self.registers[*right].float();
+                    let res = match op {
+                        Plus => left + right,
+                        Minus => left - right,
+                        Mul => left * right,
+                        Div => left / right,
+                        Pow => left.powf(right),
+                        _ => unreachable!(),
+                    };
+                    self.set_double(*dst, res);
+                }
+
+                Op::FloatCompare(op, dst, left, right) => {
+                    use BinOp::*;
+                    let left = self.registers[*left].float();
+                    let right = self.registers[*right].float();
+                    let res = match op {
+                        Eq => left == right,
+                        Neq => left != right,
+                        Lt => left < right,
+                        Le => left <= right,
+                        Gt => left > right,
+                        Ge => left >= right,
+                        _ => unreachable!(),
+                    };
+                    self.set_bool(*dst, res);
+                }
+
+                Op::Compare(op, dst, left, right) => {
+                    use BinOp::*;
+                    let registers = &self.registers;
+                    let left = left.iter().map(|r| &registers[*r]);
+                    let right = right.iter().map(|r| &registers[*r]);
+                    let res = match op {
+                        Eq => left.eq(right),
+                        Neq => left.ne(right),
+                        Lt => left.lt(right),
+                        Le => left.le(right),
+                        Gt => left.gt(right),
+                        Ge => left.ge(right),
+                        _ => unreachable!(),
+                    };
+                    self.set_bool(*dst, res);
+                }
+
+                Op::Binary(op, ty, dst, left, right) => {
+                    let res = binary_op(*op, ty, &self.registers[*left], &self.registers[*right]);
+                    self.registers[*dst] = res;
+                }
+
+                Op::Unary(op, ty, dst, src) => {
+                    self.registers[*dst] = unary_op(*op, ty, &self.registers[*src]);
+                }
+
+                Op::Function(function, dst, args) => match function {
+                    Function::Greatest | Function::Least => {
+                        self.registers[*dst] = extremum(
+                            matches!(function, Function::Greatest),
+                            args.iter().map(|r| &self.registers[*r]),
+                        )
+                    }
+                    Function::GenerateSeries => {
+                        self.registers[*dst] = generate_series(
+                            &self.registers[args[0]],
+                            &self.registers[args[1]],
+                            &self.registers[args[2]],
+                        )
+                    }
+                    _ => {
+                        let mut vals = [0.0; 2];
+                        for (val, arg) in vals.iter_mut().zip(args) {
+                            *val = self.registers[*arg].float();
+                        }
+                        self.set_double(*dst, float_function(*function, &vals[..args.len()]))
+                    }
+                },
+
+                Op::UserFunction(function, ty, dst, args) => {
+                    let mut datums = [pg_sys::Datum::from(0usize); 5];
+                    for (datum, arg) in datums.iter_mut().zip(args) {
+                        *datum = self.registers[*arg].to_datum();
+                    }
+                    let flinfo: *mut pg_sys::FmgrInfo = &mut self.functions[*function];
+                    let res = unsafe { call_function(flinfo, &datums[..args.len()], ty) };
+                    self.registers[*dst] = res;
+                }
+
+                Op::BuildList(dst, elements) => {
+                    let list = elements
+                        .iter()
+                        .map(|r| self.registers[*r].clone())
+                        .collect();
+                    self.registers[*dst] = Value::List(list);
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,20 @@
+use std::{collections::HashMap, mem::MaybeUninit};
+
 use pgx::*;
 
 use super::*;
 
+use crate::serialization::PgProcId;
+
 pub struct ExpressionExecutor<'e, T> {
     exprs: &'e Expression,
     var_vals: Vec<Option<Value>>,
+    // lookup info for the user functions called, filled in on first call
+    user_functions: HashMap<pg_sys::Oid, Box<pg_sys::FmgrInfo>>,
+    // state carried from one point to the next, see `advance()`
+    index: usize,
+    prev: Option<(i64, f64)>,
+    acc: f64,
     tracer: T,
 }
 
@@ -28,6 +38,10 @@ where
     pub fn with_tracer(exprs: &'e Expression, tracer: T) -> Self {
         Self {
             var_vals: vec![None; exprs.variables.len()],
+            user_functions: HashMap::new(),
+            index: 0,
+            prev: None,
+            acc: 0.0,
             exprs,
             tracer,
         }
@@ -39,6 +53,18 @@ where
         }
     }
 
+    // Move on to the next point in the series: the point at `time`/`value`
+    // becomes `$prev_time`/`$prev_value`, and `$acc` becomes `acc` if one is
+    // given. Before the first call `$index` and `$acc` are 0, `$prev_value`
+    // is NULL (NaN) and `$prev_time` is the current point's time.
+    pub fn advance(&mut self, time: i64, value: f64, acc: Option<f64>) {
+        self.index += 1;
+        self.prev = Some((time, value));
+        if let Some(acc) = acc {
+            self.acc = acc;
+        }
+    }
+
     pub fn exec(&mut self, value: f64, time: i64) -> Value {
         self.exec_expression(&self.exprs.expr, value, time)
     }
@@ -52,15 +78,33 @@ where
     ) -> Value {
         use ExpressionSegment::*;
         let res = match expr {
-            ValueVar => Value::Double(value),
+            ValueVar(_) => Value::Double(value),
             TimeVar => Value::Time(time),
+            PrevTimeVar => Value::Time(self.prev.map_or(time, |(t, _)| t)),
+            PrevValueVar(_) => Value::Double(self.prev.map_or(f64::NAN, |(_, v)| v)),
+            IndexVar => Value::Double(self.index as f64),
+            AccVar => Value::Double(self.acc),
             DoubleConstant(f) => Value::Double(*f),
             TimeConstant(t) => Value::Time(*t),
             IntervalConstant(i) => Value::Interval(*i),
+            TextConstant(s) => Value::Text(s.clone()),
 
             UserVar(i, _) => self.force_var(*i, value, time),
 
-            FunctionCall(function, args) => self.exec_function(function, args, value, time),
+            FunctionCall(function, args, _) => self.exec_function(function, args, value, time),
+
+            UserFunctionCall(function, args, ty) => {
+                self.exec_user_function(*function, args, ty, value, time)
+            }
+
+            If(condition, then, otherwise, _) => {
+                // only evaluate the branch that is taken
+                if self.exec_expression(condition, value, time).bool() {
+                    self.exec_expression(then, value, time)
+                } else {
+                    self.exec_expression(otherwise, value, time)
+                }
+            }
 
             Unary(op, expr, ty) => self.exec_unary_op(*op, ty, expr, value, time),
 
@@ -72,6 +116,13 @@ where
                     .map(|e| self.exec_expression(e, value, time))
                     .collect(),
             ),
+
+            BuildList(exprs, _) => Value::List(
+                exprs
+                    .iter()
+                    .map(|e| self.exec_expression(e, value, time))
+                    .collect(),
+            ),
         };
         self.tracer.trace(expr, &res);
         res
@@ -95,53 +146,62 @@ where
         time: i64,
     ) -> Value {
         use Function::*;
-        macro_rules! unary_function {
-            ($func:ident ( )) => {{
-                let then = self.exec_expression(&args[0], value, time).float();
-                then.$func().into()
-            }};
-        }
-        macro_rules! binary_function {
-            ($func:ident ( )) => {{
-                let args = &args[0..2];
-                let a = self.exec_expression(&args[0], value, time).float();
-                let b = self.exec_expression(&args[1], value, time).float();
-                a.$func(b).into()
-            }};
-        }
         match function {
-            Abs => unary_function!(abs()),
-            Cbrt => unary_function!(cbrt()),
-            Ceil => unary_function!(ceil()),
-            Floor => unary_function!(floor()),
-            Ln => unary_function!(ln()),
-            Log10 => unary_function!(log10()),
-            Log => {
-                let base = self.exec_expression(&args[1], value, time).float();
-                let a = self.exec_expression(&args[0], value, time).float();
-                a.log(base).into()
+            Greatest | Least => {
+                let args: Vec<Value> = args
+                    .iter()
+                    .map(|arg| self.exec_expression(arg, value, time))
+                    .collect();
+                extremum(matches!(function, Greatest), args.iter())
+            }
+            Coalesce => {
+                for arg in args {
+                    let val = self.exec_expression(arg, value, time).float();
+                    if !val.is_nan() {
+                        return val.into();
+                    }
+                }
+                f64::NAN.into()
+            }
+            GenerateSeries => {
+                let args: Vec<Value> = args
+                    .iter()
+                    .map(|arg| self.exec_expression(arg, value, time))
+                    .collect();
+                generate_series(&args[0], &args[1], &args[2])
+            }
+            _ => {
+                let mut vals = [0.0; 2];
+                for (val, arg) in vals.iter_mut().zip(args) {
+                    *val = self.exec_expression(arg, value, time).float();
+                }
+                float_function(*function, &vals[..args.len()]).into()
             }
-            Pi => std::f64::consts::PI.into(),
-            Round => unary_function!(round()),
-            Sign => unary_function!(signum()),
-            Sqrt => unary_function!(sqrt()),
-            Trunc => unary_function!(trunc()),
-            Acos => unary_function!(acos()),
-            Asin => unary_function!(asin()),
-            Atan => unary_function!(atan()),
-            Atan2 => binary_function!(atan2()),
-            Cos => unary_function!(cos()),
-            Sin => unary_function!(sin()),
-            Tan => unary_function!(tan()),
-            Sinh => unary_function!(sinh()),
-            Cosh => unary_function!(cosh()),
-            Tanh => unary_function!(tanh()),
-            Asinh => unary_function!(asinh()),
-            Acosh => unary_function!(acosh()),
-            Atanh => unary_function!(atanh()),
         }
     }
 
+    fn exec_user_function(
+        &mut self,
+        function: PgProcId,
+        args: &[ExpressionSegment],
+        ty: &Type,
+        value: f64,
+        time: i64,
+    ) -> Value {
+        let args: Vec<pg_sys::Datum> = args
+            .iter()
+            .map(|arg| self.exec_expression(arg, value, time).to_datum())
+            .collect();
+
+        let flinfo = self
+            .user_functions
+            .entry(function.0)
+            .or_insert_with(|| function_info(function));
+        let flinfo: *mut pg_sys::FmgrInfo = &mut **flinfo;
+
+        unsafe { call_function(flinfo, &args, ty) }
+    }
+
     fn exec_unary_op(
         &mut self,
         op: UnaryOp,
@@ -150,24 +210,8 @@ where
         value: f64,
         time: i64,
     ) -> Value {
-        use Type::*;
-        use UnaryOp::*;
-        match op {
-            Not => {
-                let val = self.exec_expression(expr, value, time).bool();
-                (!val).into()
-            }
-            Negative => {
-                match ty {
-                    Double => {
-                        let val = self.exec_expression(expr, value, time).float();
-                        (-val).into()
-                    }
-                    // TODO interval?
-                    _ => unreachable!(),
-                }
-            }
-        }
+        let val = self.exec_expression(expr, value, time);
+        unary_op(op, ty, &val)
     }
 
     fn exec_binary_op(
@@ -180,170 +224,353 @@ where
         time: i64,
     ) -> Value {
         use BinOp::*;
-        use Type::*;
-
-        // FIXME pgx wraps all functions in rust wrappers, which makes them
-        //       uncallable with DirectFunctionCall(). Is there a way to
-        //       export both?
-        // TODO This is fixed in a newer pgx version, should remove after upgrade
-        extern "C" {
-            fn interval_pl(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-            fn interval_mi(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-            fn interval_mul(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-            fn interval_div(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-
-            fn timestamptz_pl_interval(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-            fn timestamptz_mi_interval(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
-        }
-
-        macro_rules! float_op {
-            (($left: ident, $right: ident) $calc: expr) => {{
-                let $left = self.exec_expression(left, value, time).float();
-                let $right = self.exec_expression(right, value, time).float();
-                ($calc).into()
-            }};
-        }
+        match op {
+            // boolean operators short-circuit
+            And => {
+                let left = self.exec_expression(left, value, time).bool();
+                if !left {
+                    return false.into();
+                }
+                self.exec_expression(right, value, time)
+            }
 
-        macro_rules! interval_op {
-            (($left: ident, $right: ident) $calc: ident) => {{
-                let left = self.exec_expression(left, value, time).interval();
-                let right = self.exec_expression(right, value, time).interval();
+            Or => {
+                let left = self.exec_expression(left, value, time).bool();
+                if left {
+                    return true.into();
+                }
+                self.exec_expression(right, value, time)
+            }
 
-                let res: *mut pg_sys::Interval = unsafe {
-                    pg_sys::DirectFunctionCall2Coll(
-                        Some($calc),
-                        pg_sys::InvalidOid,
-                        pg_sys::Datum::from(left),
-                        pg_sys::Datum::from(right),
-                    )
-                    .cast_mut_ptr()
-                };
-                assert!(!res.is_null());
-                Value::Interval(res)
-            }};
+            _ => {
+                let left = self.exec_expression(left, value, time);
+                let right = self.exec_expression(right, value, time);
+                binary_op(op, ty, &left, &right)
+            }
         }
+    }
+}
 
-        macro_rules! interval_float_op {
-            (($left: ident, $right: ident) $calc: ident) => {{
-                let left = self.exec_expression(left, value, time).interval();
-                let right = self.exec_expression(right, value, time).float();
+//
+// Operator implementations, shared with the compiled lambdas in `compiler.rs`
+//
+
+// evaluates the builtin functions that take and return DOUBLE PRECISION
+pub(super) fn float_function(function: Function, args: &[f64]) -> f64 {
+    use Function::*;
+    match function {
+        Abs => args[0].abs(),
+        Cbrt => args[0].cbrt(),
+        Ceil => args[0].ceil(),
+        Floor => args[0].floor(),
+        Ln => args[0].ln(),
+        Log10 => args[0].log10(),
+        Log => args[0].log(args[1]),
+        Pi => std::f64::consts::PI,
+        Round => args[0].round(),
+        Sign => args[0].signum(),
+        Sqrt => args[0].sqrt(),
+        Trunc => args[0].trunc(),
+        Acos => args[0].acos(),
+        Asin => args[0].asin(),
+        Atan => args[0].atan(),
+        Atan2 => args[0].atan2(args[1]),
+        Cos => args[0].cos(),
+        Sin => args[0].sin(),
+        Tan => args[0].tan(),
+        Sinh => args[0].sinh(),
+        Cosh => args[0].cosh(),
+        Tanh => args[0].tanh(),
+        Asinh => args[0].asinh(),
+        Acosh => args[0].acosh(),
+        Atanh => args[0].atanh(),
+        Greatest | Least | Coalesce => unreachable!("{:?} is variadic", function),
+        GenerateSeries => unreachable!("{:?} returns a list", function),
+    }
+}
 
-                let res: *mut pg_sys::Interval = unsafe {
-                    pg_sys::DirectFunctionCall2Coll(
-                        Some($calc),
-                        pg_sys::InvalidOid,
-                        pg_sys::Datum::from(left),
-                        right.into_datum().unwrap(),
-                    )
-                    .value() as _
-                };
-                assert!(!res.is_null());
-                Value::Interval(res)
-            }};
+// GREATEST()/LEAST(), NULLs (NaN) are skipped unless all the arguments are NULL
+pub(super) fn extremum<'v>(greatest: bool, args: impl Iterator<Item = &'v Value>) -> Value {
+    let mut result: Option<&Value> = None;
+    for val in args {
+        if matches!(val, Value::Double(f) if f.is_nan()) {
+            continue;
         }
-
-        macro_rules! time_op {
-            (($left: ident, $right: ident) $calc: ident) => {{
-                let left = self.exec_expression(left, value, time).time();
-                let right = self.exec_expression(right, value, time).interval();
-
-                let res: i64 = unsafe {
-                    pg_sys::DirectFunctionCall2Coll(
-                        Some($calc),
-                        pg_sys::InvalidOid,
-                        pg_sys::Datum::from(left),
-                        pg_sys::Datum::from(right),
-                    )
-                    .value() as _
-                };
-
-                Value::Time(res)
-            }};
+        let replace = match result {
+            None => true,
+            Some(current) if greatest => val > current,
+            Some(current) => val < current,
+        };
+        if replace {
+            result = Some(val)
         }
+    }
+    result.cloned().unwrap_or(Value::Double(f64::NAN))
+}
 
-        match op {
-            // arithmetic operators
-            Plus => match ty {
-                Double => float_op!((left, right) left + right),
-                Time => time_op!((left, right) timestamptz_pl_interval),
-                Interval => interval_op!((left, right) interval_pl),
-                _ => unreachable!(),
-            },
-
-            Minus => match ty {
-                Double => float_op!((left, right) left - right),
-                Time => time_op!((left, right) timestamptz_mi_interval),
-                Interval => interval_op!((left, right) interval_mi),
-                _ => unreachable!(),
-            },
+pub(super) fn function_info(function: PgProcId) -> Box<pg_sys::FmgrInfo> {
+    let mut flinfo = Box::new(unsafe { MaybeUninit::zeroed().assume_init() });
+    unsafe { pg_sys::fmgr_info(function.0, &mut *flinfo) };
+    flinfo
+}
 
-            Mul => match ty {
-                Double => float_op!((left, right) left * right),
-                Interval => interval_float_op!((left, right) interval_mul),
-                _ => unreachable!(),
-            },
+pub(super) unsafe fn call_function(
+    flinfo: *mut pg_sys::FmgrInfo,
+    args: &[pg_sys::Datum],
+    ty: &Type,
+) -> Value {
+    // the FunctionCallNColl() functions error if the result is NULL
+    let collation = pg_sys::DEFAULT_COLLATION_OID;
+    let result = match *args {
+        [] => pg_sys::FunctionCall0Coll(flinfo, collation),
+        [a] => pg_sys::FunctionCall1Coll(flinfo, collation, a),
+        [a, b] => pg_sys::FunctionCall2Coll(flinfo, collation, a, b),
+        [a, b, c] => pg_sys::FunctionCall3Coll(flinfo, collation, a, b, c),
+        [a, b, c, d] => pg_sys::FunctionCall4Coll(flinfo, collation, a, b, c, d),
+        [a, b, c, d, e] => pg_sys::FunctionCall5Coll(flinfo, collation, a, b, c, d, e),
+        _ => unreachable!("the number of arguments is checked when parsing"),
+    };
+    Value::from_datum(result, ty)
+}
 
-            Div => match ty {
-                Double => float_op!((left, right) left / right),
-                Interval => interval_float_op!((left, right) interval_div),
+pub(super) fn unary_op(op: UnaryOp, ty: &Type, val: &Value) -> Value {
+    use Type::*;
+    use UnaryOp::*;
+    match op {
+        Not => (!val.bool()).into(),
+        Negative => {
+            match ty {
+                Double => (-val.float()).into(),
+                // TODO interval?
                 _ => unreachable!(),
-            },
-
-            Pow => float_op!((left, right) left.powf(right)),
-
-            // comparison operators
-            Eq => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left == right).into()
             }
+        }
+    }
+}
 
-            Neq => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left != right).into()
+// The most values a single `generate_series()` call in a lambda may return,
+// since the whole list is kept in memory.
+const MAX_SERIES_LEN: usize = 1_000_000;
+
+// `stop` is included if the series reaches it, as in SQL. A negative step
+// counts down.
+pub(super) fn generate_series(start: &Value, stop: &Value, step: &Value) -> Value {
+    let mut series = vec![];
+    let mut push = |val: Value| {
+        if series.len() == MAX_SERIES_LEN {
+            panic!(
+                "generate_series cannot return more than {} values",
+                MAX_SERIES_LEN
+            )
+        }
+        series.push(val);
+    };
+    match (start, stop, step) {
+        (Value::Double(start), Value::Double(stop), Value::Double(step)) => {
+            // same checks as for NUMERIC in SQL
+            for (val, name) in [
+                (start, "start value"),
+                (stop, "stop value"),
+                (step, "step size"),
+            ] {
+                if val.is_nan() {
+                    panic!("{} cannot be NaN", name)
+                }
+                if val.is_infinite() {
+                    panic!("{} cannot be infinity", name)
+                }
             }
-
-            Lt => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left < right).into()
+            if *step == 0.0 {
+                panic!("step size cannot equal zero")
             }
-
-            Gt => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left > right).into()
+            // multiply rather than add so errors don't accumulate
+            for i in 0_u64.. {
+                let val = start + i as f64 * step;
+                if (*step > 0.0 && val > *stop) || (*step < 0.0 && val < *stop) {
+                    break;
+                }
+                push(Value::Double(val));
             }
-
-            Le => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left <= right).into()
+        }
+        (Value::Time(_), Value::Time(stop), Value::Interval(_)) => {
+            let next = |val: &Value| binary_op(BinOp::Plus, &Type::Time, val, step);
+            let ascending = match next(start).time().cmp(&start.time()) {
+                std::cmp::Ordering::Equal => panic!("step size cannot equal zero"),
+                ordering => ordering == std::cmp::Ordering::Greater,
+            };
+            let mut val = start.clone();
+            while (ascending && val.time() <= *stop) || (!ascending && val.time() >= *stop) {
+                let following = next(&val);
+                push(val);
+                val = following;
             }
+        }
+        _ => unreachable!(),
+    }
+    Value::List(series)
+}
 
-            Ge => {
-                let left = self.exec_expression(left, value, time);
-                let right = self.exec_expression(right, value, time);
-                (left >= right).into()
-            }
+// every binary operator except the short-circuiting AND and OR
+pub(super) fn binary_op(op: BinOp, ty: &Type, left: &Value, right: &Value) -> Value {
+    use BinOp::*;
+    use Type::*;
+
+    // FIXME pgx wraps all functions in rust wrappers, which makes them
+    //       uncallable with DirectFunctionCall(). Is there a way to
+    //       export both?
+    // TODO This is fixed in a newer pgx version, should remove after upgrade
+    extern "C" {
+        fn interval_pl(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+        fn interval_mi(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+        fn interval_mul(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+        fn interval_div(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+
+        fn timestamptz_pl_interval(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+        fn timestamptz_mi_interval(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+        fn timestamp_mi(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
 
-            // boolean operators
-            And => {
-                let left = self.exec_expression(left, value, time).bool();
-                if !left {
-                    return false.into();
-                }
-                self.exec_expression(right, value, time)
-            }
+    macro_rules! float_op {
+        (($left: ident, $right: ident) $calc: expr) => {{
+            let $left = left.float();
+            let $right = right.float();
+            ($calc).into()
+        }};
+    }
 
-            Or => {
-                let left = self.exec_expression(left, value, time).bool();
-                if left {
-                    return true.into();
-                }
-                self.exec_expression(right, value, time)
+    macro_rules! interval_op {
+        (($left: ident, $right: ident) $calc: ident) => {{
+            let left = left.interval();
+            let right = right.interval();
+
+            let res: *mut pg_sys::Interval = unsafe {
+                pg_sys::DirectFunctionCall2Coll(
+                    Some($calc),
+                    pg_sys::InvalidOid,
+                    pg_sys::Datum::from(left),
+                    pg_sys::Datum::from(right),
+                )
+                .cast_mut_ptr()
+            };
+            assert!(!res.is_null());
+            Value::Interval(res)
+        }};
+    }
+
+    macro_rules! interval_float_op {
+        (($left: ident, $right: ident) $calc: ident) => {{
+            let left = left.interval();
+            let right = right.float();
+
+            let res: *mut pg_sys::Interval = unsafe {
+                pg_sys::DirectFunctionCall2Coll(
+                    Some($calc),
+                    pg_sys::InvalidOid,
+                    pg_sys::Datum::from(left),
+                    right.into_datum().unwrap(),
+                )
+                .value() as _
+            };
+            assert!(!res.is_null());
+            Value::Interval(res)
+        }};
+    }
+
+    macro_rules! time_op {
+        (($left: ident, $right: ident) $calc: ident) => {{
+            let left = left.time();
+            let right = right.interval();
+
+            let res: i64 = unsafe {
+                pg_sys::DirectFunctionCall2Coll(
+                    Some($calc),
+                    pg_sys::InvalidOid,
+                    pg_sys::Datum::from(left),
+                    pg_sys::Datum::from(right),
+                )
+                .value() as _
+            };
+
+            Value::Time(res)
+        }};
+    }
+
+    match op {
+        // arithmetic operators
+        Plus => match ty {
+            Double => float_op!((left, right) left + right),
+            Time => time_op!((left, right) timestamptz_pl_interval),
+            Interval => interval_op!((left, right) interval_pl),
+            _ => unreachable!(),
+        },
+
+        Minus => match ty {
+            Double => float_op!((left, right) left - right),
+            Time => time_op!((left, right) timestamptz_mi_interval),
+            // TIMESTAMPTZ - TIMESTAMPTZ
+            Interval if matches!(left, Value::Time(_)) => {
+                let res: *mut pg_sys::Interval = unsafe {
+                    pg_sys::DirectFunctionCall2Coll(
+                        Some(timestamp_mi),
+                        pg_sys::InvalidOid,
+                        pg_sys::Datum::from(left.time()),
+                        pg_sys::Datum::from(right.time()),
+                    )
+                    .cast_mut_ptr()
+                };
+                assert!(!res.is_null());
+                Value::Interval(res)
             }
+            Interval => interval_op!((left, right) interval_mi),
+            _ => unreachable!(),
+        },
+
+        Mul => match ty {
+            Double => float_op!((left, right) left * right),
+            Interval => interval_float_op!((left, right) interval_mul),
+            _ => unreachable!(),
+        },
+
+        Div => match ty {
+            Double => float_op!((left, right) left / right),
+            Interval => interval_float_op!((left, right) interval_div),
+            _ => unreachable!(),
+        },
+
+        Pow => float_op!((left, right) left.powf(right)),
+
+        // comparison operators
+        Eq => (left == right).into(),
+        Neq => (left != right).into(),
+        Lt => (left < right).into(),
+        Gt => (left > right).into(),
+        Le => (left <= right).into(),
+        Ge => (left >= right).into(),
+
+        And | Or => unreachable!("{:?} is short-circuiting", op),
+    }
+}
+
+impl Value {
+    pub(super) fn to_datum(&self) -> pg_sys::Datum {
+        match self {
+            Value::Bool(b) => b.into_datum().unwrap(),
+            Value::Double(f) => f.into_datum().unwrap(),
+            Value::Time(t) => pg_sys::Datum::from(*t),
+            Value::Interval(i) => pg_sys::Datum::from(*i),
+            Value::Text(s) => s.as_str().into_datum().unwrap(),
+            Value::Tuple(_) | Value::List(_) => unreachable!(),
+        }
+    }
+
+    unsafe fn from_datum(datum: pg_sys::Datum, ty: &Type) -> Self {
+        match ty {
+            Type::Bool => Value::Bool(bool::from_datum(datum, false).unwrap()),
+            Type::Double => Value::Double(f64::from_datum(datum, false).unwrap()),
+            Type::Time => Value::Time(datum.value() as _),
+            Type::Interval => Value::Interval(datum.cast_mut_ptr()),
+            Type::Text => Value::Text(String::from_datum(datum, false).unwrap()),
+            Type::Tuple(_) | Type::List(_) => unreachable!(),
         }
     }
 }
@@ -4,6 +4,8 @@ use pgx::*;
 
 use super::*;
 
+use crate::serialization::PgProcId;
+
 use pest::{
     iterators::{Pair, Pairs},
     prec_climber::{Assoc, Operator, PrecClimber},
@@ -20,7 +22,10 @@ use UnaryOp::*;
 // Expression       :=  'let' Variable '=' Expression ';' Expression | BinaryExpression
 // BinaryExpression := PrefixExpression ({',', '+', '-', '*', ...}  BinaryExpression)
 // PrefixExpression := {'-', 'NOT'} ParenExpression
-// ParenExpression  := '(' Expression ')' | Variable | Literal
+// ParenExpression  := '(' Expression ')' | Conditional | Variable | Literal | List
+// Conditional      := 'IF' BinaryExpression 'THEN' BinaryExpression 'ELSE' BinaryExpression
+//                   | 'CASE' ('WHEN' BinaryExpression 'THEN' BinaryExpression)+ ('ELSE' BinaryExpression)? 'END'
+// List             := '[' BinaryExpression (',' BinaryExpression)* ']'
 // Variable         := $[a-bA-B_][a-bA-B0-9_]*
 // Literal          := <number> | '<string>'
 // ```
@@ -33,18 +38,31 @@ use UnaryOp::*;
 pub struct ExpressionParser;
 
 pub fn parse_expression(input: &str) -> Expression {
+    parse_expression_with_value_type(input, &Double)
+}
+
+// `$value` and `$prev_value` have the type of the values of the timevector
+// the lambda is applied to
+pub fn parse_expression_with_value_type(input: &str, value_ty: &Type) -> Expression {
     let parsed = ExpressionParser::parse(calculation, input).unwrap_or_else(|e| panic!("{}", e));
 
     let mut variables = Vec::new();
-    let expr = build_expression(parsed, &mut variables, &mut HashMap::new());
+    let expr = build_expression(parsed, &mut variables, &mut HashMap::new(), value_ty);
     Expression { variables, expr }
 }
 
+// only checks that the lambda is syntactically valid, types are checked once
+// the type of `$value` is known
+pub fn check_syntax(input: &str) {
+    ExpressionParser::parse(calculation, input).unwrap_or_else(|e| panic!("{}", e));
+}
+
 // main parsing function.
 fn build_expression<'a>(
     parsed: Pairs<'a, Rule>,
     var_expressions: &mut Vec<ExpressionSegment>,
     known_vars: &mut HashMap<&'a str, (Type, usize)>,
+    value_ty: &Type,
 ) -> ExpressionSegment {
     // Everything except binary operations are handled by `parse_primary()`
     // when we encounter a sequence of binary operations eg `<> + <> * <>`
@@ -52,7 +70,7 @@ fn build_expression<'a>(
     // in descending precedence order.
     PREC_CLIMBER.climb(
         parsed,
-        |pair| parse_primary(pair, var_expressions, known_vars),
+        |pair| parse_primary(pair, var_expressions, known_vars, value_ty),
         |left: ExpressionSegment, op: Pair<Rule>, right: ExpressionSegment| {
             build_binary_op(op, left, right)
         },
@@ -65,6 +83,7 @@ fn parse_primary<'a>(
     pair: Pair<'a, Rule>,
     var_expressions: &mut Vec<ExpressionSegment>,
     known_vars: &mut HashMap<&'a str, (Type, usize)>,
+    value_ty: &Type,
 ) -> ExpressionSegment {
     // HOW TO READ:
     //   every rule (the left hand side of the `=` in the `.pest` file) has a
@@ -84,8 +103,12 @@ fn parse_primary<'a>(
             DoubleConstant(val)
         }
 
-        val_var => ValueVar,
+        val_var => ValueVar(value_ty.clone()),
         time_var => TimeVar,
+        prev_time_var => PrevTimeVar,
+        prev_val_var => PrevValueVar(value_ty.clone()),
+        index_var => IndexVar,
+        acc_var => AccVar,
 
         time => {
             let s = pair.as_str();
@@ -107,31 +130,117 @@ fn parse_primary<'a>(
             UserVar(v, ty)
         }
 
+        text => {
+            let s = pair.as_str();
+            TextConstant(s[1..s.len() - 1].to_string())
+        }
+
         function => {
             let mut pairs = pair.into_inner();
             let func_name = pairs.next().unwrap();
-            let (num_args, func_id) = *BUILTIN_FUNCTION
-                .get(func_name.as_str())
-                .unwrap_or_else(|| panic!("unknown function: {}", func_name.as_str()));
-
             let args: Vec<_> = pairs
-                .map(|p| parse_primary(p, var_expressions, known_vars))
+                .map(|p| parse_primary(p, var_expressions, known_vars, value_ty))
                 .collect();
-            if args.len() != num_args {
+
+            if func_name.as_str() == "generate_series" {
+                return build_generate_series(args);
+            }
+
+            if let Some(&(num_args, func_id)) = BUILTIN_FUNCTION.get(func_name.as_str()) {
+                if args.len() != num_args {
+                    panic!(
+                        "function `{}` expects {} arguments and received {}",
+                        func_name.as_str(),
+                        num_args,
+                        args.len(),
+                    )
+                }
+                return FunctionCall(func_id, args, Double);
+            }
+
+            if let Some(&func_id) = VARIADIC_FUNCTION.get(func_name.as_str()) {
+                return build_variadic_call(func_name.as_str(), func_id, args);
+            }
+
+            build_user_function_call(func_name.as_str(), args)
+        }
+
+        list => {
+            let elements: Vec<_> = pair
+                .into_inner()
+                .map(|p| parse_primary(p, var_expressions, known_vars, value_ty))
+                .collect();
+            // the grammar doesn't allow empty lists, so there's always a
+            // first element to take the type from
+            let ty = elements[0].ty().clone();
+            if let Some(element) = elements.iter().find(|e| e.ty() != &ty) {
                 panic!(
-                    "function `{}` expects {} arguments and received {}",
-                    func_name.as_str(),
-                    num_args,
-                    args.len(),
+                    "mismatched types for list elements: {:?}, {:?}",
+                    ty,
+                    element.ty()
                 )
             }
+            if matches!(ty, Tuple(_) | List(_)) {
+                panic!("lists can only contain single values, not {:?}", ty)
+            }
+            BuildList(elements, List(ty.into()))
+        }
 
-            FunctionCall(func_id, args)
+        if_expr => {
+            let mut pairs = pair.into_inner();
+            let condition =
+                parse_primary(pairs.next().unwrap(), var_expressions, known_vars, value_ty);
+            let then = parse_primary(pairs.next().unwrap(), var_expressions, known_vars, value_ty);
+            let otherwise =
+                parse_primary(pairs.next().unwrap(), var_expressions, known_vars, value_ty);
+            build_conditional(condition, then, otherwise)
+        }
+
+        case_expr => {
+            // `CASE WHEN a THEN b WHEN c THEN d ELSE e END` is built as
+            // `IF a THEN b ELSE (IF c THEN d ELSE e)`
+            let mut branches = vec![];
+            let mut otherwise = None;
+            for pair in pair.into_inner() {
+                match pair.as_rule() {
+                    when_clause => {
+                        let mut pairs = pair.into_inner();
+                        let condition = parse_primary(
+                            pairs.next().unwrap(),
+                            var_expressions,
+                            known_vars,
+                            value_ty,
+                        );
+                        let then = parse_primary(
+                            pairs.next().unwrap(),
+                            var_expressions,
+                            known_vars,
+                            value_ty,
+                        );
+                        branches.push((condition, then));
+                    }
+                    _ => {
+                        otherwise = Some(parse_primary(pair, var_expressions, known_vars, value_ty))
+                    }
+                }
+            }
+            // as elsewhere NULL is represented as NaN, so only DOUBLE
+            // PRECISION cases may leave out the ELSE
+            let otherwise = otherwise.unwrap_or_else(|| match branches[0].1.ty() {
+                Double => DoubleConstant(f64::NAN),
+                ty => panic!("CASE returning {:?} must have an ELSE", ty),
+            });
+            branches
+                .into_iter()
+                .rev()
+                .fold(otherwise, |otherwise, (condition, then)| {
+                    build_conditional(condition, then, otherwise)
+                })
         }
 
         neg => {
             let value = pair.into_inner().next().unwrap();
-            let value = parse_primary(value, var_expressions, known_vars);
+            let value = parse_primary(value, var_expressions, known_vars, value_ty);
             if value.ty() != &Double {
                 panic!("can only apply `-` to a DOUBLE PRECISION")
             }
@@ -140,7 +249,7 @@ fn parse_primary<'a>(
 
         not => {
             let value = pair.into_inner().next().unwrap();
-            let value = parse_primary(value, var_expressions, known_vars);
+            let value = parse_primary(value, var_expressions, known_vars, value_ty);
             if value.ty() != &Bool {
                 panic!("can only apply NOT to a BOOLEAN")
             }
@@ -148,7 +257,7 @@ fn parse_primary<'a>(
         }
 
         // pass the sequence of binary operation to the precedence_climber to handle
-        binops => build_expression(pair.into_inner(), var_expressions, known_vars),
+        binops => build_expression(pair.into_inner(), var_expressions, known_vars, value_ty),
 
         let_expr => {
             let mut pairs = pair.into_inner();
@@ -159,11 +268,18 @@ fn parse_primary<'a>(
                 // in the first state, otherwise we must be in the second.
                 let var_name_or_expr = pairs.next().unwrap();
                 let var_value = match pairs.next() {
-                    None => return parse_primary(var_name_or_expr, var_expressions, known_vars),
+                    None => {
+                        return parse_primary(
+                            var_name_or_expr,
+                            var_expressions,
+                            known_vars,
+                            value_ty,
+                        )
+                    }
                     Some(val) => val,
                 };
 
-                let var_value = parse_primary(var_value, var_expressions, known_vars);
+                let var_value = parse_primary(var_value, var_expressions, known_vars, value_ty);
 
                 let var_name = var_name_or_expr.as_str();
                 known_vars
@@ -182,15 +298,15 @@ fn parse_primary<'a>(
             // expression.
             let mut pairs = pair.into_inner();
             let first = pairs.next().unwrap();
-            let first_val = parse_primary(first, var_expressions, known_vars);
+            let first_val = parse_primary(first, var_expressions, known_vars, value_ty);
             match pairs.next() {
                 None => first_val,
                 Some(pair) => {
                     let mut vals = vec![first_val];
-                    let val = parse_primary(pair, var_expressions, known_vars);
+                    let val = parse_primary(pair, var_expressions, known_vars, value_ty);
                     vals.push(val);
                     for p in pairs {
-                        let val = parse_primary(p, var_expressions, known_vars);
+                        let val = parse_primary(p, var_expressions, known_vars, value_ty);
                         vals.push(val);
                     }
                     let ty = Tuple(vals.iter().map(|v| v.ty().clone()).collect());
@@ -201,8 +317,8 @@ fn parse_primary<'a>(
 
         // operations marked with a `_` or that are below a `@` are never passed
         // to us, so we can ignore them.
-        EOI | int | operation | string | unary | term | function_name | WHITESPACE
-        | calculation => unreachable!("{} should be transparent", pair),
+        EOI | int | operation | string | unary | term | function_name | identifier | WHITESPACE
+        | calculation | when_clause => unreachable!("{} should be transparent", pair),
 
         // infix operations should be passed to `build_binary_op()` by the
         // precedence climber, so we should never see them here.
@@ -212,6 +328,25 @@ fn parse_primary<'a>(
     }
 }
 
+fn build_conditional(
+    condition: ExpressionSegment,
+    then: ExpressionSegment,
+    otherwise: ExpressionSegment,
+) -> ExpressionSegment {
+    if condition.ty() != &Bool {
+        panic!("condition must be a BOOLEAN, not {:?}", condition.ty())
+    }
+    if then.ty() != otherwise.ty() {
+        panic!(
+            "mismatched types for branches: {:?}, {:?}",
+            then.ty(),
+            otherwise.ty()
+        )
+    }
+    let ty = then.ty().clone();
+    If(condition.into(), then.into(), otherwise.into(), ty)
+}
+
 fn build_binary_op(
     op: Pair<Rule>,
     left: ExpressionSegment,
@@ -246,6 +381,7 @@ fn build_binary_op(
             let result_type = return_ty!("-"
                 (Double, Double) => Double,
                 (Type::Time, Interval) => Type::Time,
+                (Type::Time, Type::Time) => Interval,
                 (Interval, Interval) => Interval,
             );
             Binary(Minus, left.into(), right.into(), result_type)
@@ -364,6 +500,118 @@ fn build_binary_op(
     }
 }
 
+// `greatest()`, `least()` and `coalesce()` are syntax rather than functions
+// in SQL, so they're builtins here. As in timevectors, NULL is represented by
+// NaN, which these skip over.
+fn build_variadic_call(
+    name: &str,
+    func_id: Function,
+    args: Vec<ExpressionSegment>,
+) -> ExpressionSegment {
+    let ty = match args.first() {
+        None => panic!("function `{}` expects at least one argument", name),
+        Some(arg) => arg.ty().clone(),
+    };
+    if let Some(arg) = args.iter().find(|arg| arg.ty() != &ty) {
+        panic!("mismatched types for `{}`: {:?}, {:?}", name, ty, arg.ty())
+    }
+    match (func_id, &ty) {
+        (Function::Coalesce, Double) => (),
+        (Function::Coalesce, _) => panic!("`coalesce` can only be applied to DOUBLE PRECISION"),
+        (_, Double | Type::Time | Type::Interval | Type::Text) => (),
+        (_, ty) => panic!("`{}` cannot be applied to {:?}", name, ty),
+    }
+    FunctionCall(func_id, args, ty)
+}
+
+// `generate_series(start, stop, step)` for DOUBLE PRECISION, or TIMESTAMPTZ
+// with an INTERVAL step, returns a list rather than a set as in SQL.
+fn build_generate_series(args: Vec<ExpressionSegment>) -> ExpressionSegment {
+    let types: Vec<_> = args.iter().map(|arg| arg.ty()).collect();
+    let ty = match &types[..] {
+        [Double, Double, Double] => Double,
+        [Type::Time, Type::Time, Type::Interval] => Type::Time,
+        _ => panic!(
+            "no function `generate_series` taking {:?}, only `generate_series(DOUBLE, DOUBLE, DOUBLE)` and `generate_series(TIMESTAMPTZ, TIMESTAMPTZ, INTERVAL)`",
+            types
+        ),
+    };
+    FunctionCall(Function::GenerateSeries, args, List(ty.into()))
+}
+
+// Any other function is looked up in the catalog by its name and the types of
+// its arguments, which must match exactly. Only functions taking and returning
+// the types lambdas understand can be called, and since the pipeline elements
+// running lambdas are immutable, so must the function be.
+fn build_user_function_call(name: &str, args: Vec<ExpressionSegment>) -> ExpressionSegment {
+    const MAX_ARGS: usize = 5;
+
+    // `extract(field FROM source)` is syntax too, `date_part()` is the
+    // function that implements it
+    let name = match name {
+        "extract" => "date_part",
+        name => name,
+    };
+    if args.len() > MAX_ARGS {
+        panic!(
+            "function `{}` has {} arguments, at most {} are supported",
+            name,
+            args.len(),
+            MAX_ARGS
+        )
+    }
+
+    let arg_types: Vec<_> = args.iter().map(|arg| pg_type_name(arg.ty())).collect();
+    let procedure = PgProcId::from_signature(&format!("{}({})", name, arg_types.join(",")));
+
+    let (volatility, returns_set, rettype) = unsafe {
+        (
+            pg_sys::func_volatile(procedure.0) as u8,
+            pg_sys::get_func_retset(procedure.0),
+            pg_sys::get_func_rettype(procedure.0),
+        )
+    };
+    if volatility != pg_sys::PROVOLATILE_IMMUTABLE as u8 {
+        panic!(
+            "cannot call function `{}` from a lambda, it is not immutable",
+            name
+        )
+    }
+    if returns_set {
+        panic!(
+            "cannot call set-returning function `{}` from a lambda",
+            name
+        )
+    }
+    let ty = lambda_type(rettype)
+        .unwrap_or_else(|| panic!("function `{}` has an unsupported return type", name));
+
+    UserFunctionCall(procedure, args, ty)
+}
+
+fn pg_type_name(ty: &Type) -> &'static str {
+    match ty {
+        Double => "double precision",
+        Type::Time => "timestamp with time zone",
+        Type::Interval => "interval",
+        Bool => "boolean",
+        Type::Text => "text",
+        Tuple(_) => panic!("cannot pass a tuple to a function"),
+        List(_) => panic!("cannot pass a list to a function"),
+    }
+}
+
+fn lambda_type(oid: pg_sys::Oid) -> Option<Type> {
+    match oid {
+        pg_sys::FLOAT8OID => Some(Double),
+        pg_sys::TIMESTAMPTZOID => Some(Type::Time),
+        pg_sys::INTERVALOID => Some(Type::Interval),
+        pg_sys::BOOLOID => Some(Bool),
+        pg_sys::TEXTOID => Some(Type::Text),
+        _ => None,
+    }
+}
+
 fn parse_timestamptz(val: &str) -> i64 {
     // FIXME pgx wraps all functions in rust wrappers, which makes them
     //       uncallable with DirectFunctionCall(). Is there a way to
@@ -428,7 +676,7 @@ static PREC_CLIMBER: once_cell::sync::Lazy<PrecClimber<Rule>> = once_cell::sync:
     ])
 });
 
-// Table of builtin functions (all of them for now).
+// Table of builtin math functions.
 // Maps function name to a tuple (num arguments, function identifier)
 static BUILTIN_FUNCTION: once_cell::sync::Lazy<HashMap<&str, (usize, Function)>> =
     once_cell::sync::Lazy::new(|| {
@@ -463,3 +711,16 @@ static BUILTIN_FUNCTION: once_cell::sync::Lazy<HashMap<&str, (usize, Function)>>
         .into_iter()
         .collect()
     });
+
+// Builtin functions that take any number of arguments of the same type.
+static VARIADIC_FUNCTION: once_cell::sync::Lazy<HashMap<&str, Function>> =
+    once_cell::sync::Lazy::new(|| {
+        use Function::*;
+        [
+            ("greatest", Greatest),
+            ("least", Least),
+            ("coalesce", Coalesce),
+        ]
+        .into_iter()
+        .collect()
+    });
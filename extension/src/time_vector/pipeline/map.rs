@@ -30,53 +30,157 @@ pub fn map_lambda_pipeline_element<'l, 'e>(
     .flatten()
 }
 
-pub fn apply_lambda_to<'a>(
-    mut series: Timevector_TSTZ_F64<'a>,
+pub fn apply_lambda_to<'s>(
+    stream: PointStream<'s>,
     lambda: &lambda::LambdaData<'_>,
-) -> Timevector_TSTZ_F64<'a> {
+) -> PointStream<'s> {
     let expression = lambda.parse();
     let only_val = expression.ty() == &lambda::Type::Double;
     if !only_val && !expression.ty_is_ts_point() {
         panic!("invalid lambda type: the lambda must return a DOUBLE PRECISION or (TimestampTZ, DOUBLE PRECISION)")
     }
 
-    let mut executor = lambda::ExpressionExecutor::new(&expression);
+    let input_axis = lambda_time_axis(&expression, stream.time_axis());
+    let time_axis = stream.time_axis();
+    let mut executor = lambda::ProgramExecutor::owning(expression.compile());
 
-    let invoke = |time: i64, value: f64| {
+    let mut invoke = move |time: i64, value: f64| {
         use lambda::Value::*;
-        executor.reset();
-        let result = executor.exec(value, time);
-        match result {
-            Double(f) => (None, Some(f)),
-            Time(t) => (Some(t), None),
-            Tuple(cols) => match &*cols {
-                [Time(t), Double(f)] => (Some(*t), Some(*f)),
-                _ => unreachable!(),
-            },
-
+        let lambda_time = input_axis.to_timestamptz(time);
+        let (new_time, new_val) = match executor.exec(value, lambda_time) {
+            [Double(f)] => (time, *f),
+            [Time(t), Double(f)] => (*t, *f),
             _ => unreachable!(),
+        };
+        // `$acc` is the value the lambda returned for the previous point
+        executor.advance(lambda_time, value, Some(new_val));
+        // unchanged times keep whatever precision the conversion would lose
+        if only_val || (new_time == lambda_time && input_axis == time_axis) {
+            (time, new_val)
+        } else {
+            (time_axis.from_timestamptz(new_time), new_val)
         }
     };
 
-    map_lambda_over_series(&mut series, only_val, invoke);
-    series
+    stream.adapt(|points| {
+        points.map(move |(point, null)| {
+            let (ts, val) = invoke(point.ts, point.val);
+            (TSPoint { ts, val }, null)
+        })
+    })
 }
 
-pub fn map_lambda_over_series(
-    series: &mut Timevector_TSTZ_F64<'_>,
-    only_val: bool,
-    mut func: impl FnMut(i64, f64) -> (Option<i64>, Option<f64>),
-) {
-    for point in series.points.as_owned() {
-        let (new_time, new_val) = func(point.ts, point.val);
-        *point = TSPoint {
-            ts: if only_val {
-                point.ts
-            } else {
-                new_time.unwrap_or(point.ts)
-            },
-            val: new_val.unwrap_or(point.val),
+// TODO is (stable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "flat_map",
+    schema = "toolkit_experimental"
+)]
+pub fn flat_map_lambda_pipeline_element<'l, 'e>(
+    lambda: toolkit_experimental::Lambda<'l>,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    let expression = lambda.parse();
+    check_flat_map_expression(&expression);
+
+    Element::FlatMapLambda {
+        lambda: lambda.into_data(),
+    }
+    .flatten()
+}
+
+// A flat_map lambda returns a `(time, value)` tuple where either column may
+// be a list, e.g. one built with `generate_series()`. Each point is replaced
+// by one point per element, with the other column repeated if it's a single
+// value, or paired up element by element if it's a list too. An empty list
+// drops the point.
+fn check_flat_map_expression(expression: &lambda::Expression) {
+    use lambda::Type::*;
+    let is = |ty: &lambda::Type, scalar: lambda::Type| {
+        *ty == scalar || *ty == List(scalar.clone().into())
+    };
+    let valid = matches!(
+        expression.ty(),
+        Tuple(columns) if columns.len() == 2 && is(&columns[0], Time) && is(&columns[1], Double)
+    );
+    if !valid {
+        panic!("invalid lambda type: the lambda must return a (TimestampTZ, DOUBLE PRECISION) where either may be a list")
+    }
+    if expression.uses_acc() {
+        panic!("$acc is only available in map lambdas")
+    }
+}
+
+// NULL points are dropped without being passed to the lambda
+pub fn apply_flat_map_lambda_to<'s>(
+    stream: PointStream<'s>,
+    lambda: &lambda::LambdaData<'_>,
+) -> PointStream<'s> {
+    let expression = lambda.parse();
+    check_flat_map_expression(&expression);
+
+    let input_axis = lambda_time_axis(&expression, stream.time_axis());
+    let time_axis = stream.time_axis();
+    let mut executor = lambda::ProgramExecutor::owning(expression.compile());
+
+    // the points the last input point was expanded into, and how many of
+    // them have been passed on
+    let mut expanded = vec![];
+    let mut next = 0;
+    stream
+        .adapt(|mut points| {
+            std::iter::from_fn(move || loop {
+                if let Some(point) = expanded.get(next) {
+                    next += 1;
+                    return Some((*point, false));
+                }
+                let (point, null) = points.next()?;
+                if null {
+                    continue;
+                }
+                let time = input_axis.to_timestamptz(point.ts);
+                expanded.clear();
+                next = 0;
+                expand_flat_map_result(executor.exec(point.val, time), &mut expanded);
+                for new_point in &mut expanded {
+                    new_point.ts = if new_point.ts == time && input_axis == time_axis {
+                        point.ts
+                    } else {
+                        time_axis.from_timestamptz(new_point.ts)
+                    };
+                }
+                executor.advance(time, point.val, None);
+            })
+        })
+        .reorder(time_axis.flag_bits())
+}
+
+fn expand_flat_map_result(result: &[lambda::Value], points: &mut Vec<TSPoint>) {
+    use lambda::Value::*;
+    match result {
+        [Time(ts), Double(val)] => points.push(TSPoint { ts: *ts, val: *val }),
+        [List(times), Double(val)] => points.extend(times.iter().map(|ts| TSPoint {
+            ts: ts.time(),
+            val: *val,
+        })),
+        [Time(ts), List(vals)] => points.extend(vals.iter().map(|val| TSPoint {
+            ts: *ts,
+            val: val.float(),
+        })),
+        [List(times), List(vals)] => {
+            if times.len() != vals.len() {
+                panic!(
+                    "flat_map lambda returned {} times and {} values, the lists must be the same length",
+                    times.len(),
+                    vals.len()
+                )
+            }
+            points.extend(times.iter().zip(vals).map(|(ts, val)| TSPoint {
+                ts: ts.time(),
+                val: val.float(),
+            }))
         }
+        _ => unreachable!(),
     }
 }
 
@@ -180,15 +284,17 @@ pub fn map_data_pipeline_element<'e>(
     .flatten()
 }
 
-pub fn apply_to(
-    mut series: Timevector_TSTZ_F64<'_>,
-    func: pg_sys::RegProcedure,
-) -> Timevector_TSTZ_F64<'_> {
-    let mut flinfo: pg_sys::FmgrInfo = unsafe { MaybeUninit::zeroed().assume_init() };
+pub fn apply_to(stream: PointStream<'_>, func: pg_sys::RegProcedure) -> PointStream<'_> {
+    use std::panic::AssertUnwindSafe;
+
+    // boxed so the pointer to it in `fc_info` stays valid as the points are
+    // streamed
+    let mut flinfo: Box<pg_sys::FmgrInfo> =
+        Box::new(unsafe { MaybeUninit::zeroed().assume_init() });
 
     let fn_addr: unsafe extern "C" fn(*mut pg_sys::FunctionCallInfoBaseData) -> pg_sys::Datum;
     let mut fc_info = unsafe {
-        pg_sys::fmgr_info(func, &mut flinfo);
+        pg_sys::fmgr_info(func, &mut *flinfo);
         fn_addr = flinfo.fn_addr.expect("null function in timevector map");
         union FcInfo1 {
             data: ManuallyDrop<pg_sys::FunctionCallInfoBaseData>,
@@ -198,7 +304,7 @@ pub fn apply_to(
         }
         FcInfo1 {
             data: ManuallyDrop::new(pg_sys::FunctionCallInfoBaseData {
-                flinfo: &mut flinfo,
+                flinfo: &mut *flinfo,
                 context: std::ptr::null_mut(),
                 resultinfo: std::ptr::null_mut(),
                 fncollation: pg_sys::InvalidOid,
@@ -209,7 +315,9 @@ pub fn apply_to(
         }
     };
 
-    let invoke = |val: f64| unsafe {
+    let mut invoke = move |val: f64| unsafe {
+        // `flinfo` has to live as long as the stream
+        let _flinfo = &flinfo;
         let fc_info = &mut *fc_info.data;
         let args = fc_info.args.as_mut_slice(1);
         args[0].value = val.into_datum().unwrap();
@@ -219,26 +327,11 @@ pub fn apply_to(
             .expect("unexpected NULL in timevector mapping function")
     };
 
-    map_series(&mut series, invoke);
-    series
-}
-
-pub fn map_series(series: &mut Timevector_TSTZ_F64<'_>, mut func: impl FnMut(f64) -> f64) {
-    use std::panic::AssertUnwindSafe;
-
-    let points = series.points.as_owned().iter_mut();
-    // setjump guard around the loop to reduce the amount we have to
-    // call it
+    // the points are produced one at a time, so each call gets its own
+    // setjump guard
     // NOTE need to be careful that there's not allocation within the
-    //      loop body so it cannot leak
-    pg_sys::guard(AssertUnwindSafe(|| {
-        for point in points {
-            *point = TSPoint {
-                ts: point.ts,
-                val: func(point.val),
-            }
-        }
-    }))
+    //      guarded call so it cannot leak
+    stream.map_values(move |val| pg_sys::guard(AssertUnwindSafe(|| invoke(val))))
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -406,6 +499,210 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_pipeline_map_lambda_prev() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)",
+                None,
+                None,
+            );
+
+            let map = |lambda: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT (timevector(time, value) -> sort() -> map($$ {} $$))::TEXT FROM series",
+                            lambda
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+            let expected = |vals: [&str; 5]| {
+                format!(
+                    "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                    (ts:\"2020-01-01 00:00:00+00\",val:{}),\
+                    (ts:\"2020-01-02 00:00:00+00\",val:{}),\
+                    (ts:\"2020-01-03 00:00:00+00\",val:{}),\
+                    (ts:\"2020-01-04 00:00:00+00\",val:{}),\
+                    (ts:\"2020-01-05 00:00:00+00\",val:{})\
+                ],null_val:[0])",
+                    vals[0], vals[1], vals[2], vals[3], vals[4]
+                )
+            };
+
+            assert_eq!(map("$index"), expected(["0", "1", "2", "3", "4"]));
+            // delta
+            assert_eq!(
+                map("coalesce($value - $prev_value, 0)"),
+                expected(["0", "5", "5", "5", "5"])
+            );
+            // per-day rate
+            assert_eq!(
+                map("coalesce(($value - $prev_value) / extract('epoch', $time - $prev_time) * 86400, 0)"),
+                expected(["0", "5", "5", "5", "5"])
+            );
+            // cumulative sum
+            assert_eq!(
+                map("$acc + $value"),
+                expected(["10", "25", "45", "70", "100"])
+            );
+            // EWMA
+            assert_eq!(
+                map("if $index = 0 then $value else 0.5 * $value + 0.5 * $acc"),
+                expected(["10", "12.5", "16.25", "20.625", "25.3125"])
+            );
+            // $acc holds the value part of a (time, value) result
+            assert_eq!(
+                map("($time, $acc + 1)"),
+                expected(["1", "2", "3", "4", "5"])
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_pipeline_flat_map_lambda() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            // seconds of downtime starting at each time
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 00:00:00 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-01-01 00:01:00 UTC'::TIMESTAMPTZ, 0.0), \
+                    ('2020-01-01 00:02:00 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-01 00:03:00 UTC'::TIMESTAMPTZ, 1.0)",
+                None,
+                None,
+            );
+
+            let flat_map = |lambda: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT (timevector(time, value) -> flat_map($$ {} $$))::TEXT FROM series",
+                            lambda
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            // one point per second of downtime, an empty list drops the point
+            assert_eq!(
+                flat_map(
+                    "(generate_series($time, $time + ($value - 1) * '1 second'i, '1 second'i), 1)"
+                ),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-01 00:00:01+00\",val:1),\
+                (ts:\"2020-01-01 00:00:02+00\",val:1),\
+                (ts:\"2020-01-01 00:03:00+00\",val:1)\
+            ],null_val:[0])"
+            );
+
+            // lists in both columns are paired up
+            assert_eq!(
+                flat_map("([$time, $time + '10 seconds'i], [$value, $value * 2])"),
+                "(version:1,num_points:6,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:3),\
+                (ts:\"2020-01-01 00:00:10+00\",val:6),\
+                (ts:\"2020-01-01 00:01:00+00\",val:0),\
+                (ts:\"2020-01-01 00:01:10+00\",val:0),\
+                (ts:\"2020-01-01 00:03:00+00\",val:1),\
+                (ts:\"2020-01-01 00:03:10+00\",val:2)\
+            ],null_val:[0])"
+            );
+
+            // a single value for the time repeats it
+            assert_eq!(
+                flat_map("($time, generate_series(1, $value, 1))"),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-01 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:3),\
+                (ts:\"2020-01-01 00:03:00+00\",val:1)\
+            ],null_val:[0])"
+            );
+        });
+    }
+
+    #[pg_test(error = "stop value cannot be infinity")]
+    fn test_pipeline_flat_map_infinite_series() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.timevector(now(), 1.0) \
+                    OPERATOR(toolkit_experimental.->) \
+                    toolkit_experimental.flat_map($$ ($time, generate_series($value, 1 / 0, 1)) $$)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "generate_series cannot return more than 1000000 values")]
+    fn test_pipeline_flat_map_long_series() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.timevector(now(), 1.0) \
+                    OPERATOR(toolkit_experimental.->) \
+                    toolkit_experimental.flat_map($$ ($time, generate_series($value, 1e7, 1)) $$)",
+                None,
+                None,
+            );
+        });
+    }
+
     #[pg_test]
     fn test_pipeline_map_data() {
         Spi::execute(|client| {
@@ -0,0 +1,586 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    os::raw::{c_char, c_int, c_long},
+    ptr,
+};
+
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+const USECS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000;
+// time_bucket() buckets weeks from a Monday, and months from the start of a year
+const DEFAULT_ORIGIN: i64 = 2 * USECS_PER_DAY; // 2000-01-03
+const DEFAULT_MONTH_ORIGIN: i64 = 0; // 2000-01-01
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum ResampleAggregate {
+    Avg,
+    Min,
+    Max,
+    First,
+    Last,
+    Sum,
+    Count,
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "resample",
+    schema = "toolkit_experimental"
+)]
+pub fn resample_pipeline_element<'e>(
+    bucket_width: crate::raw::Interval,
+    agg: String,
+    origin: default!(Option<crate::raw::TimestampTz>, "NULL"),
+    timezone: default!(Option<String>, "NULL"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    let (months, usecs) = unsafe {
+        let interval = bucket_width.0.cast_mut_ptr::<pg_sys::Interval>() as *const pg_sys::Interval;
+        (
+            (*interval).month as i64,
+            (*interval).day as i64 * USECS_PER_DAY + (*interval).time,
+        )
+    };
+    if months != 0 && usecs != 0 {
+        panic!("resample() cannot use month intervals with day or time components")
+    }
+    if months < 0 || usecs < 0 || (months == 0 && usecs == 0) {
+        panic!("resample() bucket width must be positive")
+    }
+
+    let aggregate = match agg.to_lowercase().as_str() {
+        "avg" | "average" => ResampleAggregate::Avg,
+        "min" => ResampleAggregate::Min,
+        "max" => ResampleAggregate::Max,
+        "first" => ResampleAggregate::First,
+        "last" => ResampleAggregate::Last,
+        "sum" => ResampleAggregate::Sum,
+        "count" => ResampleAggregate::Count,
+        _ => panic!("Invalid resample aggregate"),
+    };
+
+    // the origin is stored in the same frame the buckets are computed in, so
+    // with a timezone it is a local time
+    let origin = match (origin, &timezone) {
+        (None, _) if months != 0 => DEFAULT_MONTH_ORIGIN,
+        (None, _) => DEFAULT_ORIGIN,
+        (Some(origin), None) => origin.into(),
+        (Some(origin), Some(timezone)) => Timezone::new(timezone).to_local_time(origin.into()),
+    };
+
+    let timezone = timezone.unwrap_or_default();
+    Element::Resample {
+        months,
+        usecs,
+        origin,
+        aggregate,
+        timezone_len: timezone.len() as _,
+        timezone: timezone.as_bytes().into(),
+    }
+    .flatten()
+}
+
+pub fn resample<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'static> {
+    let (months, usecs, origin, aggregate, timezone) = match element {
+        Element::Resample {
+            months,
+            usecs,
+            origin,
+            aggregate,
+            timezone,
+            ..
+        } => (*months, *usecs, *origin, *aggregate, timezone.as_slice()),
+        _ => unreachable!(),
+    };
+    // TIMESTAMP times are local times already, as is the origin
+    let time_axis = series.time_axis();
+    if time_axis != TimeAxis::TimestampTz && !timezone.is_empty() {
+        panic!("resample() can only use a timezone with timestamptz times")
+    }
+    let timezone = match timezone {
+        [] => None,
+        tz => Some(Timezone::new(std::str::from_utf8(tz).unwrap())),
+    };
+    let (width, origin) = match time_axis {
+        TimeAxis::TimestampTz | TimeAxis::Timestamp => (usecs, origin),
+        TimeAxis::Integer(_) if months != 0 => {
+            panic!("resample() cannot use month intervals with bigint times")
+        }
+        TimeAxis::Integer(_) => (
+            time_axis.usecs_to_ticks(usecs),
+            time_axis.from_timestamptz(origin),
+        ),
+    };
+
+    let bucket_start = |ts: i64| {
+        let ts = match &timezone {
+            None => ts,
+            Some(tz) => tz.to_local_time(ts),
+        };
+        let start = if months != 0 {
+            month_bucket(months, origin, ts)
+        } else {
+            origin + (ts - origin).div_euclid(width) * width
+        };
+        match &timezone {
+            None => start,
+            Some(tz) => tz.from_local_time(start),
+        }
+    };
+
+    let mut buckets: BTreeMap<i64, Bucket> = BTreeMap::new();
+    for (i, point) in series.iter().enumerate() {
+        let bucket = buckets.entry(bucket_start(point.ts)).or_default();
+        if !series.is_null_val(i) {
+            bucket.add(point);
+        }
+    }
+
+    let mut points = Vec::with_capacity(buckets.len());
+    let mut null_val = vec![0; (buckets.len() + 7) / 8];
+    let mut flags = FLAG_IS_SORTED;
+    for (ts, bucket) in buckets {
+        let val = bucket.result(aggregate);
+        if val.is_none() {
+            flags |= FLAG_HAS_NULLS;
+            null_val[points.len() / 8] |= 1 << (points.len() % 8);
+        }
+        points.push(TSPoint {
+            ts,
+            val: val.unwrap_or(f64::NAN),
+        });
+    }
+
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: null_val.into(),
+        }
+    }
+}
+
+// The non-NULL values in a bucket. first and last are by time, ties go to the
+// earlier point in the timevector.
+#[derive(Default)]
+struct Bucket {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: Option<TSPoint>,
+    last: Option<TSPoint>,
+}
+
+impl Bucket {
+    fn add(&mut self, point: TSPoint) {
+        if self.count == 0 {
+            self.min = point.val;
+            self.max = point.val;
+        }
+        self.count += 1;
+        self.sum += point.val;
+        self.min = self.min.min(point.val);
+        self.max = self.max.max(point.val);
+        if !matches!(self.first, Some(first) if first.ts <= point.ts) {
+            self.first = Some(point);
+        }
+        if !matches!(self.last, Some(last) if last.ts >= point.ts) {
+            self.last = Some(point);
+        }
+    }
+
+    fn result(&self, aggregate: ResampleAggregate) -> Option<f64> {
+        use ResampleAggregate::*;
+        if self.count == 0 {
+            return match aggregate {
+                Count => Some(0.0),
+                _ => None,
+            };
+        }
+        let val = match aggregate {
+            Avg => self.sum / self.count as f64,
+            Min => self.min,
+            Max => self.max,
+            First => self.first.unwrap().val,
+            Last => self.last.unwrap().val,
+            Sum => self.sum,
+            Count => self.count as f64,
+        };
+        Some(val)
+    }
+}
+
+// Month buckets start on the day-of-month and time-of-day of the origin, if a
+// month is too short for that day the bucket starts on its last day instead.
+fn month_bucket(months: i64, origin: i64, ts: i64) -> i64 {
+    let month_index = |ts: i64| {
+        let (year, month, _) = civil_from_days(ts.div_euclid(USECS_PER_DAY));
+        year * 12 + month - 1
+    };
+    let mut bucket = (month_index(ts) - month_index(origin)).div_euclid(months) * months;
+    let mut start = add_months(origin, bucket);
+    // the bucket for this month may not have started yet
+    while start > ts {
+        bucket -= months;
+        start = add_months(origin, bucket);
+    }
+    start
+}
+
+fn add_months(ts: i64, months: i64) -> i64 {
+    let days = ts.div_euclid(USECS_PER_DAY);
+    let time_of_day = ts.rem_euclid(USECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let index = year * 12 + month - 1 + months;
+    let (year, month) = (index.div_euclid(12), index.rem_euclid(12) + 1);
+    let day = day.min(days_in_month(year, month));
+    days_from_civil(year, month, day) * USECS_PER_DAY + time_of_day
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)
+}
+
+// Conversions between days since the postgres epoch (2000-01-01) and
+// proleptic Gregorian dates, from http://howardhinnant.github.io/date_algorithms.html
+const UNIX_EPOCH_DAYS: i64 = 10957;
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + UNIX_EPOCH_DAYS + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468 - UNIX_EPOCH_DAYS
+}
+
+// A timezone looked up once for a whole resample() rather than for every
+// point. Like the AT TIME ZONE operators, abbreviations are tried before zone
+// names; they are rare enough to keep going through those operators.
+enum Timezone {
+    Zone(*mut PgTz),
+    Abbreviation(pg_sys::Datum),
+}
+
+// pg_tz is opaque, pg_tm is the struct from pgtime.h
+#[repr(C)]
+struct PgTz {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PgTm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: c_long,
+    tm_zone: *const c_char,
+}
+
+// from datetime.h
+const UNKNOWN_FIELD: c_int = 31;
+
+extern "C" {
+    fn pg_tzset(name: *const c_char) -> *mut PgTz;
+    fn downcase_truncate_identifier(ident: *const c_char, len: c_int, warn: bool) -> *mut c_char;
+    fn DecodeTimezoneAbbrev(
+        field: c_int,
+        lowtoken: *mut c_char,
+        offset: *mut c_int,
+        tz: *mut *mut PgTz,
+    ) -> c_int;
+    fn DetermineTimeZoneOffset(tm: *mut PgTm, tzp: *mut PgTz) -> c_int;
+    fn timestamp2tm(
+        dt: i64,
+        tzp: *mut c_int,
+        tm: *mut PgTm,
+        fsec: *mut i32,
+        tzn: *mut *const c_char,
+        attimezone: *mut PgTz,
+    ) -> c_int;
+    fn tm2timestamp(tm: *mut PgTm, fsec: i32, tzp: *mut c_int, dt: *mut i64) -> c_int;
+    fn timestamptz_zone(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    fn timestamp_zone(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+}
+
+impl Timezone {
+    fn new(timezone: &str) -> Self {
+        let name = CString::new(timezone).unwrap();
+        unsafe {
+            let lower =
+                downcase_truncate_identifier(name.as_ptr(), name.as_bytes().len() as _, false);
+            let (mut offset, mut zone) = (0, ptr::null_mut());
+            if DecodeTimezoneAbbrev(0, lower, &mut offset, &mut zone) != UNKNOWN_FIELD {
+                return Timezone::Abbreviation(timezone.into_datum().unwrap());
+            }
+            let zone = pg_tzset(name.as_ptr());
+            if zone.is_null() {
+                panic!("time zone \"{}\" not recognized", timezone)
+            }
+            Timezone::Zone(zone)
+        }
+    }
+
+    // the wall-clock time in this timezone at `ts`
+    fn to_local_time(&self, ts: i64) -> i64 {
+        let zone = match self {
+            Timezone::Zone(zone) => *zone,
+            Timezone::Abbreviation(name) => return call_zone_function(timestamptz_zone, *name, ts),
+        };
+        if ts == i64::MIN || ts == i64::MAX {
+            return ts;
+        }
+        unsafe {
+            let mut tm: PgTm = std::mem::zeroed();
+            let (mut offset, mut fsec, mut local) = (0, 0, 0);
+            if timestamp2tm(ts, &mut offset, &mut tm, &mut fsec, ptr::null_mut(), zone) != 0
+                || tm2timestamp(&mut tm, fsec, ptr::null_mut(), &mut local) != 0
+            {
+                panic!("timestamp out of range")
+            }
+            local
+        }
+    }
+
+    // the time at which the wall-clock in this timezone shows `local`
+    fn from_local_time(&self, local: i64) -> i64 {
+        let zone = match self {
+            Timezone::Zone(zone) => *zone,
+            Timezone::Abbreviation(name) => {
+                return call_zone_function(timestamp_zone, *name, local)
+            }
+        };
+        if local == i64::MIN || local == i64::MAX {
+            return local;
+        }
+        unsafe {
+            let mut tm: PgTm = std::mem::zeroed();
+            let (mut fsec, mut ts) = (0, 0);
+            if timestamp2tm(
+                local,
+                ptr::null_mut(),
+                &mut tm,
+                &mut fsec,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            ) != 0
+            {
+                panic!("timestamp out of range")
+            }
+            let mut offset = DetermineTimeZoneOffset(&mut tm, zone);
+            if tm2timestamp(&mut tm, fsec, &mut offset, &mut ts) != 0 {
+                panic!("timestamp out of range")
+            }
+            ts
+        }
+    }
+}
+
+fn call_zone_function(
+    function: unsafe extern "C" fn(pg_sys::FunctionCallInfo) -> pg_sys::Datum,
+    timezone: pg_sys::Datum,
+    ts: i64,
+) -> i64 {
+    unsafe {
+        pg_sys::DirectFunctionCall2Coll(
+            Some(function),
+            pg_sys::InvalidOid,
+            timezone,
+            pg_sys::Datum::from(ts),
+        )
+        .value() as _
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_resample() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 01:00 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-01 05:00 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-01 13:00 UTC'::TIMESTAMPTZ, 90.0), \
+                    ('2020-01-01 12:00 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-02 23:00 UTC'::TIMESTAMPTZ, NULL), \
+                    ('2020-01-02 22:00 UTC'::TIMESTAMPTZ, 40.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('12 hours', 'avg'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:15),\
+                (ts:\"2020-01-01 12:00:00+00\",val:60),\
+                (ts:\"2020-01-02 12:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('12 hours', 'first'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-01 12:00:00+00\",val:30),\
+                (ts:\"2020-01-02 12:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('1 day', 'count', '2020-01-01 12:00 UTC'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2019-12-31 12:00:00+00\",val:2),\
+                (ts:\"2020-01-01 12:00:00+00\",val:2),\
+                (ts:\"2020-01-02 12:00:00+00\",val:1)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('1 day', 'max', timezone => 'America/New_York'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2019-12-31 05:00:00+00\",val:10),\
+                (ts:\"2020-01-01 05:00:00+00\",val:90),\
+                (ts:\"2020-01-02 05:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            // abbreviations are looked up before zone names, as with AT TIME ZONE
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('1 day', 'max', timezone => 'EST'))::TEXT \
+                        = (timevector(time, value) -> resample('1 day', 'max', timezone => 'America/New_York'))::TEXT \
+                    FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample('1 month', 'sum'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:190)\
+            ],null_val:[0])"
+            );
+        });
+    }
+
+    #[pg_test(error = "time zone \"Mars/Olympus_Mons\" not recognized")]
+    fn test_pipeline_resample_unknown_timezone() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.timevector(now(), 1.0) \
+                    OPERATOR(toolkit_experimental.->) \
+                    toolkit_experimental.resample('1 day', 'max', timezone => 'Mars/Olympus_Mons')",
+                None,
+                None,
+            );
+        });
+    }
+}
@@ -0,0 +1,524 @@
+use std::collections::VecDeque;
+
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+use super::fill_to::interval_to_usecs;
+use crate::stats_agg::InternalStatsSummary1D;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum RollingFunction {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Stddev,
+}
+
+// Trailing windows end at the point they're computed for, centred windows
+// extend equally to both sides of it. With an even number of points the extra
+// point of a centred window is the later one.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum RollingAlignment {
+    Trailing,
+    Centered,
+}
+
+impl RollingAlignment {
+    fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "trailing" => RollingAlignment::Trailing,
+            "centered" | "centred" | "center" | "centre" => RollingAlignment::Centered,
+            _ => panic!("Invalid rolling window alignment"),
+        }
+    }
+}
+
+fn rolling_points_element<'e>(
+    function: RollingFunction,
+    num_points: i64,
+    alignment: &str,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    if num_points <= 0 {
+        panic!("rolling window must contain at least one point")
+    }
+    Element::Rolling {
+        function,
+        alignment: RollingAlignment::from_name(alignment),
+        num_points: num_points as _,
+        usecs: 0,
+    }
+    .flatten()
+}
+
+fn rolling_time_element<'e>(
+    function: RollingFunction,
+    time_window: crate::raw::Interval,
+    alignment: &str,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    let usecs = interval_to_usecs(&time_window);
+    if usecs <= 0 {
+        panic!("rolling window must have a positive duration")
+    }
+    Element::Rolling {
+        function,
+        alignment: RollingAlignment::from_name(alignment),
+        num_points: 0,
+        usecs,
+    }
+    .flatten()
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_avg",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_avg_points<'e>(
+    num_points: i64,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_points_element(RollingFunction::Avg, num_points, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_avg",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_avg_time<'e>(
+    time_window: crate::raw::Interval,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_time_element(RollingFunction::Avg, time_window, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_sum",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_sum_points<'e>(
+    num_points: i64,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_points_element(RollingFunction::Sum, num_points, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_sum",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_sum_time<'e>(
+    time_window: crate::raw::Interval,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_time_element(RollingFunction::Sum, time_window, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_min",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_min_points<'e>(
+    num_points: i64,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_points_element(RollingFunction::Min, num_points, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_min",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_min_time<'e>(
+    time_window: crate::raw::Interval,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_time_element(RollingFunction::Min, time_window, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_max",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_max_points<'e>(
+    num_points: i64,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_points_element(RollingFunction::Max, num_points, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_max",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_max_time<'e>(
+    time_window: crate::raw::Interval,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_time_element(RollingFunction::Max, time_window, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_stddev",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_stddev_points<'e>(
+    num_points: i64,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_points_element(RollingFunction::Stddev, num_points, alignment)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rolling_stddev",
+    schema = "toolkit_experimental"
+)]
+pub fn rolling_stddev_time<'e>(
+    time_window: crate::raw::Interval,
+    alignment: default!(&str, "'trailing'"),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    rolling_time_element(RollingFunction::Stddev, time_window, alignment)
+}
+
+// Computes `function` over the window around every point in a single pass,
+// the window bounds only ever move forward so each point enters and leaves
+// the window once. NULLs take up space in count-based windows but are
+// otherwise ignored, a window with no values results in a NULL.
+pub fn rolling<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'static> {
+    let (function, alignment, num_points, usecs) = match element {
+        Element::Rolling {
+            function,
+            alignment,
+            num_points,
+            usecs,
+        } => (*function, *alignment, *num_points as usize, *usecs),
+        _ => unreachable!(),
+    };
+
+    if !series.is_sorted() {
+        panic!("Timevector must be sorted prior to passing to a rolling window")
+    }
+
+    let points = series.points.as_slice();
+    let is_null = |i: usize| series.has_nulls() && series.is_null_val(i);
+
+    // how far the window extends before and after the current point
+    let window_width = match num_points {
+        0 => series.time_axis().usecs_to_ticks(usecs),
+        _ => 0,
+    };
+    let (before, after) = match (alignment, num_points) {
+        (RollingAlignment::Trailing, 0) => (window_width, 0),
+        (RollingAlignment::Centered, 0) => (window_width / 2, window_width - window_width / 2),
+        (RollingAlignment::Trailing, n) => (n as i64 - 1, 0),
+        (RollingAlignment::Centered, n) => ((n as i64 - 1) / 2, n as i64 / 2),
+    };
+
+    let mut window = RollingWindow::new(function);
+    let (mut lo, mut hi) = (0, 0);
+    let mut result = Vec::with_capacity(points.len());
+    let mut null_val = std::vec::from_elem(0_u8, (points.len() + 7) / 8);
+    let mut flags = series.flags & !FLAG_HAS_NULLS;
+    for (i, point) in points.iter().enumerate() {
+        // count-based windows cover [i - before, i + after], time-based ones
+        // (ts - before, ts + after]
+        let (new_lo, new_hi) = if num_points != 0 {
+            (
+                i.saturating_sub(before as usize),
+                (i + after as usize + 1).min(points.len()),
+            )
+        } else {
+            let mut new_lo = lo;
+            while new_lo < i && points[new_lo].ts <= point.ts - before {
+                new_lo += 1;
+            }
+            let mut new_hi = hi.max(i + 1);
+            while new_hi < points.len() && points[new_hi].ts <= point.ts + after {
+                new_hi += 1;
+            }
+            (new_lo, new_hi)
+        };
+
+        while hi < new_hi {
+            if !is_null(hi) {
+                window.push(hi, points[hi].val);
+            }
+            hi += 1;
+        }
+        while lo < new_lo {
+            if !is_null(lo) && !window.pop(lo, points[lo].val) {
+                window.recompute((lo + 1..hi).filter(|&j| !is_null(j)).map(|j| points[j].val));
+            }
+            lo += 1;
+        }
+
+        match window.result() {
+            Some(val) => result.push(TSPoint { ts: point.ts, val }),
+            None => {
+                flags |= FLAG_HAS_NULLS;
+                null_val[i / 8] |= 1 << (i % 8);
+                result.push(TSPoint {
+                    ts: point.ts,
+                    val: f64::NAN,
+                });
+            }
+        }
+    }
+
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: result.len() as _,
+            flags,
+            internal_padding: [0; 3],
+            points: result.into(),
+            null_val: null_val.into(),
+        }
+    }
+}
+
+// The values currently in a window. Sums are kept incrementally, the min and
+// max with a monotonic queue of (index, value) pairs.
+struct RollingWindow {
+    function: RollingFunction,
+    stats: InternalStatsSummary1D<f64>,
+    extrema: VecDeque<(usize, f64)>,
+}
+
+impl RollingWindow {
+    fn new(function: RollingFunction) -> Self {
+        Self {
+            function,
+            stats: InternalStatsSummary1D::new(),
+            extrema: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, index: usize, val: f64) {
+        use RollingFunction::*;
+        match self.function {
+            Avg | Sum | Stddev => self
+                .stats
+                .accum(val)
+                .expect("error while computing rolling window"),
+            Min => {
+                while matches!(self.extrema.back(), Some(&(_, back)) if back >= val) {
+                    self.extrema.pop_back();
+                }
+                self.extrema.push_back((index, val));
+            }
+            Max => {
+                while matches!(self.extrema.back(), Some(&(_, back)) if back <= val) {
+                    self.extrema.pop_back();
+                }
+                self.extrema.push_back((index, val));
+            }
+        }
+    }
+
+    // returns false if the value could not be removed without losing too
+    // much precision, in which case the window must be recomputed
+    fn pop(&mut self, index: usize, val: f64) -> bool {
+        use RollingFunction::*;
+        match self.function {
+            Avg | Sum | Stddev => match self.stats.remove(val) {
+                Some(stats) => self.stats = stats,
+                None => return false,
+            },
+            Min | Max => {
+                if matches!(self.extrema.front(), Some(&(i, _)) if i == index) {
+                    self.extrema.pop_front();
+                }
+            }
+        }
+        true
+    }
+
+    fn recompute(&mut self, vals: impl Iterator<Item = f64>) {
+        self.stats = InternalStatsSummary1D::new();
+        for val in vals {
+            self.stats
+                .accum(val)
+                .expect("error while computing rolling window");
+        }
+    }
+
+    fn result(&self) -> Option<f64> {
+        use RollingFunction::*;
+        match self.function {
+            Avg => self.stats.avg(),
+            Sum => self.stats.sum(),
+            // a single value has no sample standard deviation
+            Stddev if self.stats.n < 2 => None,
+            Stddev => self.stats.stddev_samp(),
+            Min | Max => self.extrema.front().map(|&(_, val)| val),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_rolling() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 90.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-07 UTC'::TIMESTAMPTZ, 40.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rolling_avg(2))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-03 00:00:00+00\",val:55),\
+                (ts:\"2020-01-04 00:00:00+00\",val:60),\
+                (ts:\"2020-01-07 00:00:00+00\",val:35)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rolling_max(3, 'centered'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:20),\
+                (ts:\"2020-01-02 00:00:00+00\",val:90),\
+                (ts:\"2020-01-03 00:00:00+00\",val:90),\
+                (ts:\"2020-01-04 00:00:00+00\",val:90),\
+                (ts:\"2020-01-07 00:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rolling_sum('2 days'::interval))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:30),\
+                (ts:\"2020-01-03 00:00:00+00\",val:110),\
+                (ts:\"2020-01-04 00:00:00+00\",val:120),\
+                (ts:\"2020-01-07 00:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rolling_min('2 days'::interval, 'centered'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:20),\
+                (ts:\"2020-01-03 00:00:00+00\",val:30),\
+                (ts:\"2020-01-04 00:00:00+00\",val:30),\
+                (ts:\"2020-01-07 00:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rolling_stddev(2))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:3,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:NaN),\
+                (ts:\"2020-01-02 00:00:00+00\",val:7.0710678118654755),\
+                (ts:\"2020-01-03 00:00:00+00\",val:49.49747468305833),\
+                (ts:\"2020-01-04 00:00:00+00\",val:42.42640687119285),\
+                (ts:\"2020-01-07 00:00:00+00\",val:7.0710678118654755)\
+            ],null_val:[1])"
+            );
+        });
+    }
+}
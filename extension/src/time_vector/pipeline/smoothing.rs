@@ -0,0 +1,459 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use exponential_smoothing::{Fit, Model, Params, Seasonality};
+
+use super::*;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum SmoothingModel {
+    Simple,
+    Linear,
+    HoltWintersAdditive,
+    HoltWintersMultiplicative,
+}
+
+impl SmoothingModel {
+    fn model(self, season_length: u64) -> Model {
+        let seasonal = |seasonality| Model::HoltWinters {
+            season_length: season_length as _,
+            seasonality,
+        };
+        match self {
+            SmoothingModel::Simple => Model::Simple,
+            SmoothingModel::Linear => Model::Linear,
+            SmoothingModel::HoltWintersAdditive => seasonal(Seasonality::Additive),
+            SmoothingModel::HoltWintersMultiplicative => seasonal(Seasonality::Multiplicative),
+        }
+    }
+}
+
+fn holt_winters_model(seasonality: &str) -> SmoothingModel {
+    match seasonality.to_lowercase().as_str() {
+        "additive" => SmoothingModel::HoltWintersAdditive,
+        "multiplicative" => SmoothingModel::HoltWintersMultiplicative,
+        _ => panic!("Invalid seasonality, expected 'additive' or 'multiplicative'"),
+    }
+}
+
+fn check_season_length(season_length: i32) -> u64 {
+    if season_length <= 0 {
+        panic!("season length must be positive")
+    }
+    season_length as _
+}
+
+fn check_forecast_points(forecast_points: i32) -> u64 {
+    if forecast_points < 0 {
+        panic!("number of forecast points cannot be negative")
+    }
+    forecast_points as _
+}
+
+//
+// pipeline elements
+//
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "exponential_smoothing",
+    schema = "toolkit_experimental"
+)]
+pub fn exponential_smoothing_pipeline_element<'e>(
+    alpha: default!(Option<f64>, "NULL"),
+    forecast_points: default!(i32, 0),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    // parameters that aren't given are stored as NaN and estimated when run
+    Element::Smoothing {
+        model: SmoothingModel::Simple,
+        season_length: 0,
+        alpha: alpha.unwrap_or(f64::NAN),
+        beta: f64::NAN,
+        gamma: f64::NAN,
+        forecast_points: check_forecast_points(forecast_points),
+    }
+    .flatten()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "holt_linear",
+    schema = "toolkit_experimental"
+)]
+pub fn holt_linear_pipeline_element<'e>(
+    alpha: default!(Option<f64>, "NULL"),
+    beta: default!(Option<f64>, "NULL"),
+    forecast_points: default!(i32, 0),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    Element::Smoothing {
+        model: SmoothingModel::Linear,
+        season_length: 0,
+        alpha: alpha.unwrap_or(f64::NAN),
+        beta: beta.unwrap_or(f64::NAN),
+        gamma: f64::NAN,
+        forecast_points: check_forecast_points(forecast_points),
+    }
+    .flatten()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "holt_winters",
+    schema = "toolkit_experimental"
+)]
+pub fn holt_winters_pipeline_element<'e>(
+    season_length: i32,
+    seasonality: default!(&str, "'additive'"),
+    alpha: default!(Option<f64>, "NULL"),
+    beta: default!(Option<f64>, "NULL"),
+    gamma: default!(Option<f64>, "NULL"),
+    forecast_points: default!(i32, 0),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    Element::Smoothing {
+        model: holt_winters_model(seasonality),
+        season_length: check_season_length(season_length),
+        alpha: alpha.unwrap_or(f64::NAN),
+        beta: beta.unwrap_or(f64::NAN),
+        gamma: gamma.unwrap_or(f64::NAN),
+        forecast_points: check_forecast_points(forecast_points),
+    }
+    .flatten()
+}
+
+fn fit_timevector(series: &Timevector_TSTZ_F64<'_>, model: Model, params: Params) -> Fit {
+    if !series.is_sorted() {
+        panic!("Timevector must be sorted prior to exponential smoothing")
+    }
+    if series.has_nulls() {
+        panic!("Unable to apply exponential smoothing to a timevector containing nulls")
+    }
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    exponential_smoothing::fit(model, &values, params)
+}
+
+// Replaces the values with the one-step-ahead predictions of the model, and
+// appends the forecast. Forecast points are spaced by the average distance
+// between the input points.
+pub fn smooth<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'static> {
+    let (model, season_length, alpha, beta, gamma, forecast_points) = match element {
+        Element::Smoothing {
+            model,
+            season_length,
+            alpha,
+            beta,
+            gamma,
+            forecast_points,
+        } => (
+            *model,
+            *season_length,
+            *alpha,
+            *beta,
+            *gamma,
+            *forecast_points as usize,
+        ),
+        _ => unreachable!(),
+    };
+    let given = |param: f64| if param.is_nan() { None } else { Some(param) };
+    let params = Params {
+        alpha: given(alpha),
+        beta: given(beta),
+        gamma: given(gamma),
+    };
+    let fit = fit_timevector(series, model.model(season_length), params);
+
+    let mut points: Vec<TSPoint> = series
+        .iter()
+        .zip(&fit.fitted)
+        .map(|(p, &val)| TSPoint { ts: p.ts, val })
+        .collect();
+    if forecast_points > 0 {
+        if points.len() < 2 {
+            panic!("forecasting requires at least two points")
+        }
+        let first = points[0].ts;
+        let last = points[points.len() - 1].ts;
+        let step = (last - first) / (points.len() as i64 - 1);
+        for (h, val) in fit.forecast(forecast_points).into_iter().enumerate() {
+            points.push(TSPoint {
+                ts: last + (h as i64 + 1) * step,
+                val,
+            });
+        }
+    }
+
+    let nulls_len = (points.len() + 7) / 8;
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags: FLAG_IS_SORTED,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        }
+    }
+}
+
+//
+// fitted models
+//
+
+// The parameters and final state of a model.
+extension_sql!(
+    "\n\
+    CREATE TYPE toolkit_experimental.smoothing_fit AS (\n\
+        alpha DOUBLE PRECISION,\n\
+        beta DOUBLE PRECISION,\n\
+        gamma DOUBLE PRECISION,\n\
+        level DOUBLE PRECISION,\n\
+        trend DOUBLE PRECISION,\n\
+        seasonal DOUBLE PRECISION[],\n\
+        sse DOUBLE PRECISION\n\
+    );\n\
+    ",
+    name = "smoothing_fit_type",
+);
+
+fn fitted_model(fit: Fit) -> pgx::composite_type!("toolkit_experimental.smoothing_fit") {
+    let mut model = PgHeapTuple::new_composite_type("toolkit_experimental.smoothing_fit").unwrap();
+    model.set_by_name("alpha", fit.alpha).unwrap();
+    model.set_by_name("beta", fit.beta).unwrap();
+    model.set_by_name("gamma", fit.gamma).unwrap();
+    model.set_by_name("level", fit.level).unwrap();
+    model.set_by_name("trend", fit.trend).unwrap();
+    model.set_by_name("seasonal", fit.seasonal).unwrap();
+    model.set_by_name("sse", fit.sse).unwrap();
+    model
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    requires = ["smoothing_fit_type"]
+)]
+pub fn exponential_smoothing_fit<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    alpha: default!(Option<f64>, "NULL"),
+) -> pgx::composite_type!("toolkit_experimental.smoothing_fit") {
+    let params = Params {
+        alpha,
+        ..Default::default()
+    };
+    fitted_model(fit_timevector(&series, Model::Simple, params))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    requires = ["smoothing_fit_type"]
+)]
+pub fn holt_linear_fit<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    alpha: default!(Option<f64>, "NULL"),
+    beta: default!(Option<f64>, "NULL"),
+) -> pgx::composite_type!("toolkit_experimental.smoothing_fit") {
+    let params = Params {
+        alpha,
+        beta,
+        ..Default::default()
+    };
+    fitted_model(fit_timevector(&series, Model::Linear, params))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    requires = ["smoothing_fit_type"]
+)]
+pub fn holt_winters_fit<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    season_length: i32,
+    seasonality: default!(&str, "'additive'"),
+    alpha: default!(Option<f64>, "NULL"),
+    beta: default!(Option<f64>, "NULL"),
+    gamma: default!(Option<f64>, "NULL"),
+) -> pgx::composite_type!("toolkit_experimental.smoothing_fit") {
+    let model = holt_winters_model(seasonality).model(check_season_length(season_length));
+    let params = Params { alpha, beta, gamma };
+    fitted_model(fit_timevector(&series, model, params))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_exponential_smoothing() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 3.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> exponential_smoothing(0.5, 1))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:1),\
+                (ts:\"2020-01-03 00:00:00+00\",val:1.5),\
+                (ts:\"2020-01-04 00:00:00+00\",val:2.25)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> holt_linear(0.5, 0.5, 2))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:3),\
+                (ts:\"2020-01-04 00:00:00+00\",val:4),\
+                (ts:\"2020-01-05 00:00:00+00\",val:5)\
+            ],null_val:[0])"
+            );
+
+            // a steadily rising series is best followed by ignoring history
+            let val = client
+                .select(
+                    "SELECT alpha > 0.99 AND beta IS NULL \
+                    FROM exponential_smoothing_fit((SELECT timevector(time, value) FROM series))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_holt_winters() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series AS \
+                SELECT '2020-01-01 UTC'::TIMESTAMPTZ + n * '1 hour'::INTERVAL AS time, \
+                    (ARRAY[1.0, 3.0, 2.0, 4.0])[n % 4 + 1]::DOUBLE PRECISION AS value \
+                FROM generate_series(0, 7) n",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT format('%s %s %s %s %s %s %s', alpha, beta, gamma, level, trend, seasonal, sse) \
+                    FROM holt_winters_fit( \
+                        (SELECT timevector(time, value) FROM series), 4, 'additive', 0.5, 0.5, 0.5)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "0.5 0.5 0.5 2.5 0 {-1.5,0.5,-0.5,1.5} 0");
+
+            // the fit is a composite, so its fields can be picked out directly
+            let val = client
+                .select(
+                    "SELECT (holt_winters_fit( \
+                        (SELECT timevector(time, value) FROM series), 4, 'additive', 0.5, 0.5, 0.5)).seasonal::TEXT",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{-1.5,0.5,-0.5,1.5}");
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> holt_winters(4, 'additive', 0.5, 0.5, 0.5, 3))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:11,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-01 01:00:00+00\",val:3),\
+                (ts:\"2020-01-01 02:00:00+00\",val:2),\
+                (ts:\"2020-01-01 03:00:00+00\",val:4),\
+                (ts:\"2020-01-01 04:00:00+00\",val:1),\
+                (ts:\"2020-01-01 05:00:00+00\",val:3),\
+                (ts:\"2020-01-01 06:00:00+00\",val:2),\
+                (ts:\"2020-01-01 07:00:00+00\",val:4),\
+                (ts:\"2020-01-01 08:00:00+00\",val:1),\
+                (ts:\"2020-01-01 09:00:00+00\",val:3),\
+                (ts:\"2020-01-01 10:00:00+00\",val:2)\
+            ],null_val:[0,0])"
+            );
+        });
+    }
+}
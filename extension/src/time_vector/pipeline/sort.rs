@@ -57,6 +57,12 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
     .into()
 }
 
+// the sort is stable, so points with the same time keep their order
+pub fn sort_typed<T>(mut series: TypedSeries<T>) -> TypedSeries<T> {
+    series.points.sort_by_key(|(ts, _)| *ts);
+    series
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -0,0 +1,332 @@
+use std::{cell::Cell, rc::Rc};
+
+use super::*;
+
+// Pipelines are run as a chain of iterators, so that the elements that work
+// on one point at a time don't each build a timevector for the next one. Only
+// the elements that need the whole timevector, see
+// `explain::forces_materialization()`, collect the points into one.
+
+/// A point and whether its value is NULL.
+pub type StreamPoint = (TSPoint, bool);
+
+pub type Points<'s> = Box<dyn Iterator<Item = StreamPoint> + 's>;
+
+/// A timevector whose points are produced as they are needed.
+pub struct PointStream<'s> {
+    // the flags the timevector would have if it were materialized here
+    flags: u8,
+    sorted: Sortedness,
+    source: Source<'s>,
+}
+
+enum Source<'s> {
+    // no element has been applied since the timevector was materialized
+    Timevector(Timevector_TSTZ_F64<'s>),
+    Points(Points<'s>),
+}
+
+// Whether the points are sorted is usually known up front, from the flags.
+// After an element that can reorder them it's only known once they have all
+// been produced.
+#[derive(Clone)]
+enum Sortedness {
+    Flags,
+    Pending(Rc<Cell<bool>>),
+}
+
+impl<'s> PointStream<'s> {
+    pub fn new(series: Timevector_TSTZ_F64<'s>) -> Self {
+        Self {
+            flags: series.flags,
+            sorted: Sortedness::Flags,
+            source: Source::Timevector(series),
+        }
+    }
+
+    pub fn time_axis(&self) -> TimeAxis {
+        TimeAxis::from_flags(self.flags)
+    }
+
+    pub fn has_nulls(&self) -> bool {
+        self.flags & FLAG_HAS_NULLS != 0
+    }
+
+    /// Replaces the points by those produced by `adapter`, the flags are kept.
+    pub fn adapt<I>(self, adapter: impl FnOnce(Points<'s>) -> I) -> Self
+    where
+        I: Iterator<Item = StreamPoint> + 's,
+    {
+        let Self {
+            flags,
+            sorted,
+            source,
+        } = self;
+        Self {
+            flags,
+            sorted,
+            source: Source::Points(Box::new(adapter(source.into_points()))),
+        }
+    }
+
+    pub fn map_values(self, mut func: impl FnMut(f64) -> f64 + 's) -> Self {
+        self.adapt(|points| {
+            points.map(move |(point, null)| {
+                let val = func(point.val);
+                (TSPoint { ts: point.ts, val }, null)
+            })
+        })
+    }
+
+    /// For elements that may reorder the points: the flags are replaced by
+    /// `flags`, and whether the points are sorted is worked out as they are
+    /// produced.
+    pub fn reorder(self, flags: u8) -> Self {
+        let sorted = Rc::new(Cell::new(true));
+        let mut prev = None;
+        let tracker = sorted.clone();
+        let points = self.into_points().inspect(move |(point, _)| {
+            if prev.map_or(false, |prev| prev > point.ts) {
+                tracker.set(false);
+            }
+            prev = Some(point.ts);
+        });
+        Self {
+            flags: flags & !FLAG_IS_SORTED,
+            sorted: Sortedness::Pending(sorted),
+            source: Source::Points(Box::new(points)),
+        }
+    }
+
+    /// Panics with `message` unless the points are sorted. If that isn't
+    /// known yet, it's checked once all the points have been produced.
+    pub fn require_sorted(self, message: &'static str) -> Self {
+        let sorted = match &self.sorted {
+            Sortedness::Flags if self.flags & FLAG_IS_SORTED == 0 => panic!("{}", message),
+            Sortedness::Flags => return self,
+            Sortedness::Pending(sorted) => sorted.clone(),
+        };
+        self.adapt(|points| {
+            points.chain(std::iter::from_fn(move || {
+                if !sorted.get() {
+                    panic!("{}", message)
+                }
+                None
+            }))
+        })
+    }
+
+    pub fn into_points(self) -> Points<'s> {
+        self.source.into_points()
+    }
+
+    pub fn materialize(self) -> Timevector_TSTZ_F64<'s> {
+        let points = match self.source {
+            Source::Timevector(series) => return series,
+            Source::Points(points) => points,
+        };
+
+        let mut result = vec![];
+        let mut nulls = vec![];
+        for (i, (point, null)) in points.enumerate() {
+            if i % 8 == 0 {
+                nulls.push(0_u8);
+            }
+            if null {
+                nulls[i / 8] |= 1 << (i % 8);
+            }
+            result.push(point);
+        }
+
+        let mut flags = self.flags;
+        if let Sortedness::Pending(sorted) = self.sorted {
+            if sorted.get() {
+                flags |= FLAG_IS_SORTED;
+            }
+        }
+        build! {
+            Timevector_TSTZ_F64 {
+                num_points: result.len() as _,
+                flags,
+                internal_padding: [0; 3],
+                points: result.into(),
+                null_val: nulls.into(),
+            }
+        }
+    }
+}
+
+impl<'s> Source<'s> {
+    fn into_points(self) -> Points<'s> {
+        match self {
+            Source::Points(points) => points,
+            Source::Timevector(series) => {
+                let has_nulls = series.has_nulls();
+                Box::new((0..series.num_points()).map(move |i| {
+                    let null = has_nulls && series.is_null_val(i);
+                    (series.points.as_slice()[i], null)
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_streaming() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0)",
+                None,
+                None,
+            );
+
+            let pipeline = "filter($$ $value != 15 $$) \
+                -> map($$ $value * 2 $$) \
+                -> fill_to('1 day', 'linear') \
+                -> delta()";
+
+            // the same points come out whether the pipeline is materialized
+            // or consumed by unnest() or an aggregate
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (timevector(time, value) -> {})::TEXT FROM series",
+                        pipeline
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-02 00:00:00+00\",val:10),\
+                (ts:\"2020-01-03 00:00:00+00\",val:10),\
+                (ts:\"2020-01-04 00:00:00+00\",val:10),\
+                (ts:\"2020-01-05 00:00:00+00\",val:10)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT array_agg(val)::TEXT \
+                        FROM (SELECT timevector(time, value) -> {} -> unnest() as val FROM series) t",
+                        pipeline
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{\"(\\\"2020-01-02 00:00:00+00\\\",10)\",\"(\\\"2020-01-03 00:00:00+00\\\",10)\",\"(\\\"2020-01-04 00:00:00+00\\\",10)\",\"(\\\"2020-01-05 00:00:00+00\\\",10)\"}");
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT timevector(time, value) -> {} -> num_vals() FROM series",
+                        pipeline
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val.unwrap(), 4);
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (timevector(time, value) -> {} -> stats_agg())::TEXT FROM series",
+                        pipeline
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(version:1,n:4,sx:40,sx2:0,sx3:0,sx4:0)");
+
+            // whether the output of flat_map() is sorted is only known once
+            // all of it has been produced
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) \
+                        -> flat_map($$ ([$time, $time + '12 hours'i], [$value, $value + 1]) $$) \
+                        -> delta())::TEXT \
+                    FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:7,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 12:00:00+00\",val:1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:4),\
+                (ts:\"2020-01-02 12:00:00+00\",val:1),\
+                (ts:\"2020-01-04 00:00:00+00\",val:9),\
+                (ts:\"2020-01-04 12:00:00+00\",val:1),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4),\
+                (ts:\"2020-01-05 12:00:00+00\",val:1)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) \
+                        -> flat_map($$ ([$time, $time - '2 days'i], [$value, $value]) $$))::TEXT \
+                    FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:8,flags:0,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2019-12-30 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2019-12-31 00:00:00+00\",val:15),\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-02 00:00:00+00\",val:25),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30),\
+                (ts:\"2020-01-03 00:00:00+00\",val:30)\
+            ],null_val:[0])"
+            );
+        });
+    }
+}
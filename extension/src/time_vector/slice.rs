@@ -0,0 +1,434 @@
+//! Lookups into a timevector without unnesting it: where its holes are, the
+//! points within a time range, the first or last `n` points, and the value
+//! at a given time. Sorted timevectors are searched with a binary search,
+//! unsorted ones are scanned.
+
+use pgx::{iter::TableIterator, *};
+
+use tspoint::TSPoint;
+
+use crate::{
+    build,
+    range::get_range,
+    raw::{tstzrange, Interval, TimestampTz},
+};
+
+use super::{Timevector_TSTZ_F64, FLAG_HAS_NULLS, FLAG_IS_SORTED, TIME_AXIS_MASK};
+
+/// Returns the ranges longer than `min_gap` in which the timevector has no
+/// points, as the times of the points on either side of the gap. Times on
+/// the other time axes are returned as TIMESTAMPTZ.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "gaps",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_gaps<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    min_gap: Interval,
+) -> TableIterator<'static, (name!(gap_start, TimestampTz), name!(gap_end, TimestampTz))> {
+    let mut times: Vec<i64> = series.iter().map(|p| p.ts).collect();
+    if !series.is_sorted() {
+        times.sort_unstable();
+    }
+
+    let time_axis = series.time_axis();
+    let min_gap = match times.first() {
+        None => 0,
+        Some(&first) => {
+            let first = time_axis.to_timestamptz(first).into();
+            time_axis.usecs_to_ticks(crate::datum_utils::interval_to_ms(&first, &min_gap))
+        }
+    };
+
+    let gaps: Vec<_> = times
+        .windows(2)
+        .filter(|w| w[1] - w[0] > min_gap)
+        .map(|w| {
+            (
+                time_axis.to_timestamptz(w[0]).into(),
+                time_axis.to_timestamptz(w[1]).into(),
+            )
+        })
+        .collect();
+    TableIterator::new(gaps.into_iter())
+}
+
+/// Returns the points whose time is within `range`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "slice",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_slice<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    range: tstzrange,
+) -> Timevector_TSTZ_F64<'static> {
+    // `get_range()` returns half-open ranges, and `None` for empty ones
+    let time_axis = series.time_axis();
+    let (start, end) = match unsafe { get_range(range.0.cast_mut_ptr()) } {
+        None => return select_points(&series, std::iter::empty()),
+        Some(range) => (
+            range
+                .left
+                .map_or(i64::MIN, |t| time_axis.from_timestamptz(t)),
+            range
+                .right
+                .map_or(i64::MAX, |t| time_axis.from_timestamptz(t)),
+        ),
+    };
+
+    let points = series.points.as_slice();
+    if series.is_sorted() {
+        let first = points.partition_point(|p| p.ts < start);
+        let last = points.partition_point(|p| p.ts < end);
+        select_points(&series, first..last.max(first))
+    } else {
+        let in_range = (0..points.len()).filter(|&i| start <= points[i].ts && points[i].ts < end);
+        select_points(&series, in_range)
+    }
+}
+
+/// Returns the first `n` points of the timevector, the earliest ones if it
+/// is sorted.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "head",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_head<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    n: i64,
+) -> Timevector_TSTZ_F64<'static> {
+    let n = (n.max(0) as usize).min(series.num_points());
+    select_points(&series, 0..n)
+}
+
+/// Returns the last `n` points of the timevector, the latest ones if it is
+/// sorted.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "tail",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_tail<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    n: i64,
+) -> Timevector_TSTZ_F64<'static> {
+    let len = series.num_points();
+    let n = (n.max(0) as usize).min(len);
+    select_points(&series, len - n..len)
+}
+
+// builds a timevector out of the points at `indices`, which must be in
+// increasing order
+fn select_points(
+    series: &Timevector_TSTZ_F64<'_>,
+    indices: impl Iterator<Item = usize>,
+) -> Timevector_TSTZ_F64<'static> {
+    let mut points = vec![];
+    let mut null_val = vec![];
+    let mut flags = series.flags & (FLAG_IS_SORTED | TIME_AXIS_MASK);
+    for index in indices {
+        let new_index = points.len();
+        if new_index % 8 == 0 {
+            null_val.push(0);
+        }
+        if series.has_nulls() && series.is_null_val(index) {
+            null_val[new_index / 8] |= 1 << (new_index % 8);
+            flags |= FLAG_HAS_NULLS;
+        }
+        points.push(series.points.as_slice()[index]);
+    }
+
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: null_val.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AtTimeMethod {
+    Exact,
+    Locf,
+    Nearest,
+    Interpolate,
+}
+
+/// Returns the value of the timevector at `time`. With `'exact'` only a
+/// point at that time is used, `'locf'` uses the last point at or before it,
+/// `'nearest'` the closest point, and `'interpolate'` interpolates linearly
+/// between the points on either side. Returns NULL if there is no such point
+/// or its value is NULL.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "at_time",
+    schema = "toolkit_experimental"
+)]
+pub fn timevector_at_time<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    time: TimestampTz,
+    method: default!(&str, "'exact'"),
+) -> Option<f64> {
+    let method = match method.to_lowercase().as_str() {
+        "exact" => AtTimeMethod::Exact,
+        "locf" => AtTimeMethod::Locf,
+        "nearest" => AtTimeMethod::Nearest,
+        "interpolate" => AtTimeMethod::Interpolate,
+        "linear" => AtTimeMethod::Interpolate,
+        _ => panic!("Invalid at_time method"),
+    };
+    let time = series.time_axis().from_timestamptz(time.into());
+    value_at(&series, time, method)
+}
+
+fn value_at(series: &Timevector_TSTZ_F64<'_>, target: i64, method: AtTimeMethod) -> Option<f64> {
+    let (before, after) = neighbors(series, target);
+    let value = |index: usize| {
+        if series.has_nulls() && series.is_null_val(index) {
+            None
+        } else {
+            Some(series.points.as_slice()[index].val)
+        }
+    };
+    let point = |index: usize| series.points.as_slice()[index];
+
+    match method {
+        AtTimeMethod::Exact => before.filter(|&i| point(i).ts == target).and_then(value),
+        AtTimeMethod::Locf => before.and_then(value),
+        AtTimeMethod::Nearest => match (before, after) {
+            (Some(before), Some(after)) => {
+                if point(after).ts - target >= target - point(before).ts {
+                    value(before)
+                } else {
+                    value(after)
+                }
+            }
+            (Some(only), None) | (None, Some(only)) => value(only),
+            (None, None) => None,
+        },
+        AtTimeMethod::Interpolate => match (before, after) {
+            (Some(before), _) if point(before).ts == target => value(before),
+            (Some(before), Some(after)) => {
+                let (lhs, rhs) = (point(before), point(after));
+                let (lhs_val, rhs_val) = (value(before)?, value(after)?);
+                Some(interpolate(lhs, lhs_val, rhs, rhs_val, target))
+            }
+            _ => None,
+        },
+    }
+}
+
+// the indices of the last point at or before `target` and the first point at
+// or after it
+fn neighbors(series: &Timevector_TSTZ_F64<'_>, target: i64) -> (Option<usize>, Option<usize>) {
+    let points = series.points.as_slice();
+    if series.is_sorted() {
+        let before = points.partition_point(|p| p.ts <= target).checked_sub(1);
+        let after = points.partition_point(|p| p.ts < target);
+        return (before, (after < points.len()).then(|| after));
+    }
+
+    let mut before: Option<usize> = None;
+    let mut after: Option<usize> = None;
+    for (i, p) in points.iter().enumerate() {
+        if p.ts <= target && before.map_or(true, |b| points[b].ts <= p.ts) {
+            before = Some(i);
+        }
+        if p.ts >= target && after.map_or(true, |a| points[a].ts > p.ts) {
+            after = Some(i);
+        }
+    }
+    (before, after)
+}
+
+fn interpolate(lhs: TSPoint, lhs_val: f64, rhs: TSPoint, rhs_val: f64, target: i64) -> f64 {
+    let rhs_weight = (target - lhs.ts) as f64 / (rhs.ts - lhs.ts) as f64;
+    lhs_val * (1.0 - rhs_weight) + rhs_val * rhs_weight
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_timevector_lookups() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE data(time TIMESTAMPTZ, value DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO data VALUES
+                    ('2020-1-10', 40.0),
+                    ('2020-1-1', 30.0),
+                    ('2020-1-2', 45.0),
+                    ('2020-1-3', NULL),
+                    ('2020-1-6', 55.5),
+                    ('2020-1-7', 10.0)"#,
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE tvs AS \
+                SELECT timevector(time, value) AS unsorted, \
+                    timevector(time, value) -> sort() AS sorted \
+                FROM data",
+                None,
+                None,
+            );
+
+            for column in ["sorted", "unsorted"] {
+                let mut gaps = client.select(
+                    &format!("SELECT gaps({}, '1 day')::TEXT FROM tvs", column),
+                    None,
+                    None,
+                );
+                assert_eq!(
+                    gaps.next().unwrap()[1].value(),
+                    Some("(\"2020-01-03 00:00:00+00\",\"2020-01-06 00:00:00+00\")")
+                );
+                assert_eq!(
+                    gaps.next().unwrap()[1].value(),
+                    Some("(\"2020-01-07 00:00:00+00\",\"2020-01-10 00:00:00+00\")")
+                );
+                assert!(gaps.next().is_none());
+
+                let val = client
+                    .select(
+                        &format!(
+                            "SELECT slice({}, '[2020-01-02, 2020-01-07)')::TEXT FROM tvs",
+                            column
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>();
+                assert_eq!(
+                    val.unwrap(),
+                    format!(
+                        "(version:1,num_points:3,flags:{},internal_padding:(0,0,0),points:[\
+                        (ts:\"2020-01-02 00:00:00+00\",val:45),\
+                        (ts:\"2020-01-03 00:00:00+00\",val:NaN),\
+                        (ts:\"2020-01-06 00:00:00+00\",val:55.5)\
+                    ],null_val:[2])",
+                        if column == "sorted" { 3 } else { 2 }
+                    )
+                );
+
+                let (exact, locf) = client
+                    .select(
+                        &format!(
+                            "SELECT \
+                                at_time({0}, '2020-01-08'), \
+                                at_time({0}, '2020-01-08', 'locf') \
+                            FROM tvs",
+                            column
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<f64, f64>();
+                assert_eq!(exact, None);
+                assert_eq!(locf, Some(10.0));
+
+                let (nearest, interpolate) = client
+                    .select(
+                        &format!(
+                            "SELECT \
+                                at_time({0}, '2020-01-09', 'nearest'), \
+                                at_time({0}, '2020-01-01 12:00', 'interpolate') \
+                            FROM tvs",
+                            column
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<f64, f64>();
+                assert_eq!(nearest, Some(40.0));
+                assert_eq!(interpolate, Some(37.5));
+
+                // NULL values stay NULL
+                let (exact, interpolate) = client
+                    .select(
+                        &format!(
+                            "SELECT \
+                                at_time({0}, '2020-01-03'), \
+                                at_time({0}, '2020-01-04', 'interpolate') \
+                            FROM tvs",
+                            column
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<f64, f64>();
+                assert_eq!(exact, None);
+                assert_eq!(interpolate, None);
+            }
+
+            let val = client
+                .select("SELECT head(sorted, 2)::TEXT FROM tvs", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:30),\
+                (ts:\"2020-01-02 00:00:00+00\",val:45)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT tail(sorted, 10)::TEXT = sorted::TEXT FROM tvs",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(val, Some(true));
+
+            let val = client
+                .select("SELECT tail(sorted, 1)::TEXT FROM tvs", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-10 00:00:00+00\",val:40)\
+            ],null_val:[0])"
+            );
+        });
+    }
+}
@@ -0,0 +1,241 @@
+use std::ffi::{CStr, CString};
+
+use pgx::*;
+use serde::{Deserialize, Serialize};
+
+// The time axis of a timevector is stored in bits 2-4 of its flags, so that
+// timevectors written before it existed are read as TIMESTAMPTZ.
+pub const TIME_AXIS_SHIFT: u8 = 2;
+pub const TIME_AXIS_MASK: u8 = 0x07 << TIME_AXIS_SHIFT;
+
+pub(super) const USECS_PER_SEC: i64 = 1_000_000;
+// seconds from the unix epoch to the postgres one, 2000-01-01
+pub(super) const POSTGRES_EPOCH_SECS: i64 = 946_684_800;
+
+/// What the times of a timevector are. TIMESTAMPTZ and TIMESTAMP times are
+/// microseconds from 2000-01-01, integer times are whatever the column held,
+/// counted from the unix epoch in `unit` if one was given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeAxis {
+    TimestampTz,
+    Timestamp,
+    Integer(Option<TimeUnit>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "ns" | "nanosecond" | "nanoseconds" => TimeUnit::Nanoseconds,
+            "us" | "microsecond" | "microseconds" => TimeUnit::Microseconds,
+            "ms" | "millisecond" | "milliseconds" => TimeUnit::Milliseconds,
+            "s" | "second" | "seconds" => TimeUnit::Seconds,
+            _ => panic!("Invalid time unit, expected one of 'ns', 'us', 'ms' or 's'"),
+        }
+    }
+}
+
+impl TimeAxis {
+    pub fn from_flags(flags: u8) -> Self {
+        use TimeUnit::*;
+        match (flags & TIME_AXIS_MASK) >> TIME_AXIS_SHIFT {
+            0 => TimeAxis::TimestampTz,
+            1 => TimeAxis::Timestamp,
+            2 => TimeAxis::Integer(None),
+            3 => TimeAxis::Integer(Some(Nanoseconds)),
+            4 => TimeAxis::Integer(Some(Microseconds)),
+            5 => TimeAxis::Integer(Some(Milliseconds)),
+            6 => TimeAxis::Integer(Some(Seconds)),
+            _ => panic!("invalid timevector time axis"),
+        }
+    }
+
+    pub fn flag_bits(self) -> u8 {
+        use TimeUnit::*;
+        let axis = match self {
+            TimeAxis::TimestampTz => 0,
+            TimeAxis::Timestamp => 1,
+            TimeAxis::Integer(None) => 2,
+            TimeAxis::Integer(Some(Nanoseconds)) => 3,
+            TimeAxis::Integer(Some(Microseconds)) => 4,
+            TimeAxis::Integer(Some(Milliseconds)) => 5,
+            TimeAxis::Integer(Some(Seconds)) => 6,
+        };
+        axis << TIME_AXIS_SHIFT
+    }
+
+    // replaces the time axis recorded in `flags` with this one
+    pub fn set_in(self, flags: u8) -> u8 {
+        (flags & !TIME_AXIS_MASK) | self.flag_bits()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeAxis::TimestampTz => "timestamptz",
+            TimeAxis::Timestamp => "timestamp",
+            TimeAxis::Integer(_) => "bigint",
+        }
+    }
+
+    pub fn assert_is(self, expected: TimeAxis, function: &str) {
+        if self != expected {
+            panic!(
+                "{} requires a timevector with {} times, this one has {} times",
+                function,
+                expected.name(),
+                self.name()
+            )
+        }
+    }
+
+    /// Converts a duration in microseconds, which is how intervals are
+    /// stored, into the units of this axis.
+    pub fn usecs_to_ticks(self, usecs: i64) -> i64 {
+        use TimeUnit::*;
+        let ticks = match self {
+            TimeAxis::TimestampTz | TimeAxis::Timestamp => usecs,
+            TimeAxis::Integer(None) => panic!(
+                "intervals cannot be used with integer times that have no unit, \
+                build the timevector with timevector(time, value, unit)"
+            ),
+            TimeAxis::Integer(Some(Nanoseconds)) => usecs * 1000,
+            TimeAxis::Integer(Some(Microseconds)) => usecs,
+            TimeAxis::Integer(Some(Milliseconds)) => usecs / 1000,
+            TimeAxis::Integer(Some(Seconds)) => usecs / USECS_PER_SEC,
+        };
+        if ticks == 0 && usecs != 0 {
+            panic!("interval is shorter than the unit of the times")
+        }
+        ticks
+    }
+
+    /// Converts a time on this axis to a TIMESTAMPTZ, which is what lambdas
+    /// and the functions taking TIMESTAMPTZ arguments work with.
+    /// TIMESTAMPs are taken to be in UTC, so that the result doesn't depend
+    /// on the session timezone.
+    pub fn to_timestamptz(self, ts: i64) -> i64 {
+        use TimeUnit::*;
+        let unix_usecs = match self {
+            TimeAxis::TimestampTz | TimeAxis::Timestamp => return ts,
+            TimeAxis::Integer(None) => no_unit(),
+            TimeAxis::Integer(Some(Nanoseconds)) => ts.div_euclid(1000),
+            TimeAxis::Integer(Some(Microseconds)) => ts,
+            TimeAxis::Integer(Some(Milliseconds)) => ts * 1000,
+            TimeAxis::Integer(Some(Seconds)) => ts * USECS_PER_SEC,
+        };
+        unix_usecs - POSTGRES_EPOCH_SECS * USECS_PER_SEC
+    }
+
+    /// The inverse of `to_timestamptz()`, nanosecond times lose everything
+    /// below a microsecond.
+    pub fn from_timestamptz(self, ts: i64) -> i64 {
+        use TimeUnit::*;
+        let unix_usecs = ts + POSTGRES_EPOCH_SECS * USECS_PER_SEC;
+        match self {
+            TimeAxis::TimestampTz | TimeAxis::Timestamp => ts,
+            TimeAxis::Integer(None) => no_unit(),
+            TimeAxis::Integer(Some(Nanoseconds)) => unix_usecs * 1000,
+            TimeAxis::Integer(Some(Microseconds)) => unix_usecs,
+            TimeAxis::Integer(Some(Milliseconds)) => unix_usecs.div_euclid(1000),
+            TimeAxis::Integer(Some(Seconds)) => unix_usecs.div_euclid(USECS_PER_SEC),
+        }
+    }
+}
+
+/// A time as written in a timevector's text format: a timestamp for the
+/// TIMESTAMPTZ and TIMESTAMP axes, the integer itself for integer times.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TextTime {
+    Integer(i64),
+    Timestamp(String),
+}
+
+impl TimeAxis {
+    pub fn time_to_text(self, ts: i64) -> TextTime {
+        match self {
+            TimeAxis::TimestampTz => TextTime::Timestamp(timestamptz_to_string(ts)),
+            TimeAxis::Timestamp => TextTime::Timestamp(timestamp_to_string(ts)),
+            TimeAxis::Integer(_) => TextTime::Integer(ts),
+        }
+    }
+
+    pub fn time_from_text(self, text: &TextTime) -> i64 {
+        match (self, text) {
+            (TimeAxis::TimestampTz, TextTime::Timestamp(ts)) => {
+                crate::serialization::_ts_toolkit_decode_timestamptz(ts)
+            }
+            (TimeAxis::Timestamp, TextTime::Timestamp(ts)) => timestamp_from_string(ts),
+            (TimeAxis::Integer(_), TextTime::Integer(ts)) => *ts,
+            (axis, TextTime::Integer(ts)) => {
+                panic!(
+                    "invalid time {} for a timevector with {} times",
+                    ts,
+                    axis.name()
+                )
+            }
+            (axis, TextTime::Timestamp(ts)) => panic!(
+                "invalid time \"{}\" for a timevector with {} times",
+                ts,
+                axis.name()
+            ),
+        }
+    }
+}
+
+pub(super) fn timestamptz_to_string(ts: i64) -> String {
+    let mut buf = [0; pg_sys::MAXDATELEN as _];
+    crate::serialization::_ts_toolkit_encode_timestamptz(ts, &mut buf);
+    let ts = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    ts.to_str().unwrap().to_string()
+}
+
+fn timestamp_to_string(ts: i64) -> String {
+    extern "C" {
+        fn timestamp_out(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe {
+        let text = pg_sys::DirectFunctionCall1Coll(
+            Some(timestamp_out),
+            pg_sys::InvalidOid,
+            pg_sys::Datum::from(ts),
+        );
+        let string = CStr::from_ptr(text.cast_mut_ptr())
+            .to_str()
+            .unwrap()
+            .to_string();
+        pg_sys::pfree(text.cast_mut_ptr());
+        string
+    }
+}
+
+fn timestamp_from_string(text: &str) -> i64 {
+    extern "C" {
+        fn timestamp_in(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    let text = CString::new(text).unwrap();
+    unsafe {
+        pg_sys::DirectFunctionCall3Coll(
+            Some(timestamp_in),
+            pg_sys::InvalidOid,
+            pg_sys::Datum::from(text.as_ptr()),
+            pg_sys::Datum::from(pg_sys::InvalidOid),
+            pg_sys::Datum::from(-1i32),
+        )
+        .value() as _
+    }
+}
+
+fn no_unit() -> ! {
+    panic!(
+        "integer times that have no unit cannot be used as timestamps, \
+        build the timevector with timevector(time, value, unit)"
+    )
+}
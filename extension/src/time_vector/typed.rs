@@ -0,0 +1,1107 @@
+//! Timevectors of BIGINT, BOOLEAN and TEXT values, for exact counters,
+//! up/down series and state series that shouldn't be forced through DOUBLE
+//! PRECISION. For example
+//! ```SQL
+//! SELECT toolkit_experimental.timevector(time, state)
+//!     -> toolkit_experimental.sort()
+//!     -> toolkit_experimental.filter($$ $value != $prev_value $$)
+//! FROM machine_states;
+//! ```
+//! returns the points at which the state changed. Only the pipeline elements
+//! that don't depend on the values being floats, `sort`, `filter` and
+//! `fill_to`, can be applied to them. BIGINT values are seen as DOUBLE
+//! PRECISION by lambdas.
+
+use pgx::{iter::TableIterator, *};
+use serde::{Deserialize, Serialize};
+
+use flat_serialize::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    build,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::{bytea, TimestampTz},
+};
+
+use super::{
+    pipeline::{run_typed_pipeline_elements, UnstableTimevectorPipeline},
+    time_axis::timestamptz_to_string,
+    FLAG_HAS_NULLS, FLAG_IS_SORTED,
+};
+
+use toolkit_experimental::*;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        #[allow(non_camel_case_types)]
+        struct Timevector_TSTZ_I64<'input> {
+            num_points: u32,
+            flags: u8,         // same as the flags of a Timevector_TSTZ_F64
+            internal_padding: [u8; 3],
+            times: [i64; self.num_points],
+            values: [i64; self.num_points],
+            null_val: [u8; (self.num_points + 7) / 8], // bit vector, same layout as Timevector_TSTZ_F64
+        }
+    }
+
+    pg_type! {
+        #[derive(Debug)]
+        #[allow(non_camel_case_types)]
+        struct Timevector_TSTZ_Bool<'input> {
+            num_points: u32,
+            flags: u8,
+            internal_padding: [u8; 3],
+            times: [i64; self.num_points],
+            values: [bool; self.num_points],
+            null_val: [u8; (self.num_points + 7) / 8],
+        }
+    }
+
+    pg_type! {
+        #[derive(Debug)]
+        #[allow(non_camel_case_types)]
+        struct Timevector_TSTZ_Text<'input> {
+            num_points: u32,
+            flags: u8,
+            internal_padding: [u8; 3],
+            times: [i64; self.num_points],
+            // the value of point `i` is `value_bytes[value_ends[i-1]..value_ends[i]]`
+            value_ends: [u32; self.num_points],
+            value_bytes_len: u32,
+            value_bytes: [u8; self.value_bytes_len],
+            null_val: [u8; (self.num_points + 7) / 8],
+        }
+    }
+}
+
+/// The points of a typed timevector in a form that's easy to work on, used
+/// for the aggregate state and by the pipeline elements.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TypedSeries<T> {
+    pub points: Vec<(i64, Option<T>)>,
+}
+
+impl<T> TypedSeries<T> {
+    pub fn is_sorted(&self) -> bool {
+        self.points.windows(2).all(|w| w[0].0 <= w[1].0)
+    }
+
+    pub fn has_nulls(&self) -> bool {
+        self.points.iter().any(|(_, val)| val.is_none())
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.is_sorted() {
+            flags |= FLAG_IS_SORTED;
+        }
+        if self.has_nulls() {
+            flags |= FLAG_HAS_NULLS;
+        }
+        flags
+    }
+
+    fn null_val(&self) -> Vec<u8> {
+        let mut null_val = vec![0; (self.points.len() + 7) / 8];
+        for (i, (_, val)) in self.points.iter().enumerate() {
+            if val.is_none() {
+                null_val[i / 8] |= 1 << (i % 8);
+            }
+        }
+        null_val
+    }
+
+    fn times(&self) -> Vec<i64> {
+        self.points.iter().map(|(ts, _)| *ts).collect()
+    }
+}
+
+fn is_null(null_val: &[u8], index: usize) -> bool {
+    null_val[index / 8] & (1 << (index % 8)) != 0
+}
+
+/// The values a typed timevector can hold.
+pub trait TimevectorValue: Clone + Sized {
+    // for error messages
+    const TIMEVECTOR_TYPE: &'static str;
+
+    fn series(state: &TypedTransState) -> &TypedSeries<Self>;
+
+    fn series_mut(state: &mut TypedTransState) -> &mut TypedSeries<Self>;
+
+    fn new_state() -> TypedTransState;
+
+    // linear interpolation, `None` if it doesn't make sense for the type
+    fn interpolate(_lhs: &Self, _rhs: &Self, _rhs_weight: f64) -> Option<Self> {
+        None
+    }
+}
+
+impl TimevectorValue for i64 {
+    const TIMEVECTOR_TYPE: &'static str = "Timevector_TSTZ_I64";
+
+    fn series(state: &TypedTransState) -> &TypedSeries<Self> {
+        match state {
+            TypedTransState::I64(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn series_mut(state: &mut TypedTransState) -> &mut TypedSeries<Self> {
+        match state {
+            TypedTransState::I64(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn new_state() -> TypedTransState {
+        TypedTransState::I64(TypedSeries::default())
+    }
+
+    fn interpolate(lhs: &Self, rhs: &Self, rhs_weight: f64) -> Option<Self> {
+        let val = *lhs as f64 * (1.0 - rhs_weight) + *rhs as f64 * rhs_weight;
+        Some(val.round() as i64)
+    }
+}
+
+impl TimevectorValue for bool {
+    const TIMEVECTOR_TYPE: &'static str = "Timevector_TSTZ_Bool";
+
+    fn series(state: &TypedTransState) -> &TypedSeries<Self> {
+        match state {
+            TypedTransState::Bool(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn series_mut(state: &mut TypedTransState) -> &mut TypedSeries<Self> {
+        match state {
+            TypedTransState::Bool(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn new_state() -> TypedTransState {
+        TypedTransState::Bool(TypedSeries::default())
+    }
+}
+
+impl TimevectorValue for String {
+    const TIMEVECTOR_TYPE: &'static str = "Timevector_TSTZ_Text";
+
+    fn series(state: &TypedTransState) -> &TypedSeries<Self> {
+        match state {
+            TypedTransState::Text(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn series_mut(state: &mut TypedTransState) -> &mut TypedSeries<Self> {
+        match state {
+            TypedTransState::Text(series) => series,
+            _ => unreachable!(),
+        }
+    }
+
+    fn new_state() -> TypedTransState {
+        TypedTransState::Text(TypedSeries::default())
+    }
+}
+
+//
+// conversions to and from the flat types
+//
+
+impl<'input> Timevector_TSTZ_I64<'input> {
+    pub fn to_series(&self) -> TypedSeries<i64> {
+        let null_val = self.null_val.as_slice();
+        let points = self
+            .times
+            .iter()
+            .zip(self.values.iter())
+            .enumerate()
+            .map(|(i, (ts, val))| (ts, (!is_null(null_val, i)).then(|| val)))
+            .collect();
+        TypedSeries { points }
+    }
+}
+
+impl TypedSeries<i64> {
+    pub fn into_timevector(self) -> Timevector_TSTZ_I64<'static> {
+        let values: Vec<i64> = self
+            .points
+            .iter()
+            .map(|(_, val)| val.unwrap_or(0))
+            .collect();
+        build! {
+            Timevector_TSTZ_I64 {
+                num_points: self.points.len() as _,
+                flags: self.flags(),
+                internal_padding: [0; 3],
+                times: self.times().into(),
+                values: values.into(),
+                null_val: self.null_val().into(),
+            }
+        }
+    }
+}
+
+impl<'input> Timevector_TSTZ_Bool<'input> {
+    pub fn to_series(&self) -> TypedSeries<bool> {
+        let null_val = self.null_val.as_slice();
+        let points = self
+            .times
+            .iter()
+            .zip(self.values.iter())
+            .enumerate()
+            .map(|(i, (ts, val))| (ts, (!is_null(null_val, i)).then(|| val)))
+            .collect();
+        TypedSeries { points }
+    }
+}
+
+impl TypedSeries<bool> {
+    pub fn into_timevector(self) -> Timevector_TSTZ_Bool<'static> {
+        let values: Vec<bool> = self
+            .points
+            .iter()
+            .map(|(_, val)| val.unwrap_or(false))
+            .collect();
+        build! {
+            Timevector_TSTZ_Bool {
+                num_points: self.points.len() as _,
+                flags: self.flags(),
+                internal_padding: [0; 3],
+                times: self.times().into(),
+                values: values.into(),
+                null_val: self.null_val().into(),
+            }
+        }
+    }
+}
+
+impl<'input> Timevector_TSTZ_Text<'input> {
+    pub fn to_series(&self) -> TypedSeries<String> {
+        let null_val = self.null_val.as_slice();
+        let bytes = self.value_bytes.as_slice();
+        let mut start = 0;
+        let points = self
+            .times
+            .iter()
+            .zip(self.value_ends.iter())
+            .enumerate()
+            .map(|(i, (ts, end))| {
+                let value = std::str::from_utf8(&bytes[start..end as usize]).unwrap();
+                start = end as usize;
+                (ts, (!is_null(null_val, i)).then(|| value.to_string()))
+            })
+            .collect();
+        TypedSeries { points }
+    }
+}
+
+impl TypedSeries<String> {
+    pub fn into_timevector(self) -> Timevector_TSTZ_Text<'static> {
+        let mut value_bytes = vec![];
+        let mut value_ends = vec![];
+        for (_, val) in &self.points {
+            if let Some(val) = val {
+                value_bytes.extend_from_slice(val.as_bytes());
+            }
+            value_ends.push(value_bytes.len() as u32);
+        }
+        build! {
+            Timevector_TSTZ_Text {
+                num_points: self.points.len() as _,
+                flags: self.flags(),
+                internal_padding: [0; 3],
+                times: self.times().into(),
+                value_ends: value_ends.into(),
+                value_bytes_len: value_bytes.len() as _,
+                value_bytes: value_bytes.into(),
+                null_val: self.null_val().into(),
+            }
+        }
+    }
+}
+
+//
+// text format
+//
+
+// Unlike Timevector_TSTZ_F64 the values are stored in a form that isn't
+// readable, so the text format lists the points instead, with NULL values as
+// `None`.
+#[derive(Serialize, Deserialize)]
+struct ReadableTimevector<T> {
+    version: u8,
+    num_points: u32,
+    flags: u8,
+    points: Vec<ReadablePoint<T>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReadablePoint<T> {
+    ts: String,
+    val: Option<T>,
+}
+
+impl<T> From<TypedSeries<T>> for ReadableTimevector<T> {
+    fn from(series: TypedSeries<T>) -> Self {
+        let flags = series.flags();
+        let points: Vec<_> = series
+            .points
+            .into_iter()
+            .map(|(ts, val)| ReadablePoint {
+                ts: timestamptz_to_string(ts),
+                val,
+            })
+            .collect();
+        Self {
+            version: 1,
+            num_points: points.len() as _,
+            flags,
+            points,
+        }
+    }
+}
+
+impl<T> From<ReadableTimevector<T>> for TypedSeries<T> {
+    fn from(readable: ReadableTimevector<T>) -> Self {
+        let points = readable
+            .points
+            .into_iter()
+            .map(|p| {
+                let ts = crate::serialization::_ts_toolkit_decode_timestamptz(&p.ts);
+                (ts, p.val)
+            })
+            .collect();
+        Self { points }
+    }
+}
+
+macro_rules! typed_inout_funcs {
+    ($name: ident, $value: ty) => {
+        impl<'input> InOutFuncs for $name<'input> {
+            fn output(&self, buffer: &mut StringInfo) {
+                use crate::serialization::{str_to_db_encoding, EncodedStr::*};
+
+                let readable = ReadableTimevector::from(self.to_series());
+                let stringified = ron::to_string(&readable).unwrap();
+                match str_to_db_encoding(&stringified) {
+                    Utf8(s) => buffer.push_str(s),
+                    Other(s) => buffer.push_bytes(s.to_bytes()),
+                }
+            }
+
+            fn input(input: &pgx::cstr_core::CStr) -> Self
+            where
+                Self: Sized,
+            {
+                use crate::serialization::str_from_db_encoding;
+
+                let input = str_from_db_encoding(input);
+                let readable: ReadableTimevector<$value> = ron::from_str(input).unwrap();
+                let series = TypedSeries::from(readable).into_timevector();
+                unsafe { series.0.flatten() }
+            }
+        }
+    };
+}
+
+typed_inout_funcs!(Timevector_TSTZ_I64, i64);
+typed_inout_funcs!(Timevector_TSTZ_Bool, bool);
+typed_inout_funcs!(Timevector_TSTZ_Text, String);
+
+//
+// aggregates
+//
+
+// Intermediate state kept in postgres, shared by the aggregates for all the
+// value types.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TypedTransState {
+    I64(TypedSeries<i64>),
+    Bool(TypedSeries<bool>),
+    Text(TypedSeries<String>),
+}
+
+impl TypedTransState {
+    fn append(&mut self, other: &Self) {
+        use TypedTransState::*;
+        match (self, other) {
+            (I64(a), I64(b)) => a.points.extend_from_slice(&b.points),
+            (Bool(a), Bool(b)) => a.points.extend_from_slice(&b.points),
+            (Text(a), Text(b)) => a.points.extend_from_slice(&b.points),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn typed_trans_inner<T: TimevectorValue>(
+    state: Option<Inner<TypedTransState>>,
+    points: impl Iterator<Item = (i64, Option<T>)>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<TypedTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state.unwrap_or_else(|| T::new_state().into());
+            T::series_mut(&mut state).points.extend(points);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_i64_trans(
+    state: Internal,
+    time: Option<TimestampTz>,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    let time = match time {
+        None => return state.internal(),
+        Some(time) => time.into(),
+    };
+    typed_trans_inner(state, std::iter::once((time, value)), fcinfo).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_bool_trans(
+    state: Internal,
+    time: Option<TimestampTz>,
+    value: Option<bool>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    let time = match time {
+        None => return state.internal(),
+        Some(time) => time.into(),
+    };
+    typed_trans_inner(state, std::iter::once((time, value)), fcinfo).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_text_trans(
+    state: Internal,
+    time: Option<TimestampTz>,
+    value: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    let time = match time {
+        None => return state.internal(),
+        Some(time) => time.into(),
+    };
+    typed_trans_inner(state, std::iter::once((time, value)), fcinfo).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_i64_compound_trans<'a>(
+    state: Internal,
+    series: Option<Timevector_TSTZ_I64<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    match series {
+        None => state.internal(),
+        Some(series) => {
+            typed_trans_inner(state, series.to_series().points.into_iter(), fcinfo).internal()
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_bool_compound_trans<'a>(
+    state: Internal,
+    series: Option<Timevector_TSTZ_Bool<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    match series {
+        None => state.internal(),
+        Some(series) => {
+            typed_trans_inner(state, series.to_series().points.into_iter(), fcinfo).internal()
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_text_compound_trans<'a>(
+    state: Internal,
+    series: Option<Timevector_TSTZ_Text<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    match series {
+        None => state.internal(),
+        Some(series) => {
+            typed_trans_inner(state, series.to_series().points.into_iter(), fcinfo).internal()
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn typed_timevector_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { typed_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+
+fn typed_combine_inner(
+    state1: Option<Inner<TypedTransState>>,
+    state2: Option<Inner<TypedTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<TypedTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone().into()),
+            (Some(state1), Some(state2)) => {
+                let mut combined = state1.clone();
+                combined.append(&state2);
+                Some(combined.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn typed_timevector_serialize(state: Internal) -> bytea {
+    let state: &TypedTransState = unsafe { state.get().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn typed_timevector_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: TypedTransState = crate::do_deserialize!(bytes, TypedTransState);
+    Inner::from(state).internal()
+}
+
+fn typed_final_inner<T: TimevectorValue>(state: Internal) -> Option<TypedSeries<T>> {
+    let state: Option<&TypedTransState> = unsafe { state.get() };
+    state.map(|state| T::series(state).clone())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_i64_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Timevector_TSTZ_I64<'static>> {
+    typed_final_inner::<i64>(state).map(TypedSeries::into_timevector)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_bool_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Timevector_TSTZ_Bool<'static>> {
+    typed_final_inner::<bool>(state).map(TypedSeries::into_timevector)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_text_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Timevector_TSTZ_Text<'static>> {
+    typed_final_inner::<String>(state).map(TypedSeries::into_timevector)
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts TIMESTAMPTZ, value BIGINT) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_i64_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_i64_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(toolkit_experimental.timevector_tstz_i64) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_i64_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_i64_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_tstz_i64_agg",
+    requires = [
+        timevector_tstz_i64_trans,
+        timevector_tstz_i64_compound_trans,
+        timevector_tstz_i64_final,
+        typed_timevector_combine,
+        typed_timevector_serialize,
+        typed_timevector_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts TIMESTAMPTZ, value BOOLEAN) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_bool_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_bool_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(toolkit_experimental.timevector_tstz_bool) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_bool_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_bool_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_tstz_bool_agg",
+    requires = [
+        timevector_tstz_bool_trans,
+        timevector_tstz_bool_compound_trans,
+        timevector_tstz_bool_final,
+        typed_timevector_combine,
+        typed_timevector_serialize,
+        typed_timevector_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts TIMESTAMPTZ, value TEXT) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_text_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(toolkit_experimental.timevector_tstz_text) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_text_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.timevector_tstz_text_final,\n\
+        combinefunc = toolkit_experimental.typed_timevector_combine,\n\
+        serialfunc = toolkit_experimental.typed_timevector_serialize,\n\
+        deserialfunc = toolkit_experimental.typed_timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_tstz_text_agg",
+    requires = [
+        timevector_tstz_text_trans,
+        timevector_tstz_text_compound_trans,
+        timevector_tstz_text_final,
+        typed_timevector_combine,
+        typed_timevector_serialize,
+        typed_timevector_deserialize
+    ],
+);
+
+//
+// accessors
+//
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_i64<'a>(
+    series: Timevector_TSTZ_I64<'a>,
+) -> TableIterator<'a, (name!(time, TimestampTz), name!(value, Option<i64>))> {
+    TableIterator::new(
+        series
+            .to_series()
+            .points
+            .into_iter()
+            .map(|(ts, val)| (ts.into(), val)),
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_bool<'a>(
+    series: Timevector_TSTZ_Bool<'a>,
+) -> TableIterator<'a, (name!(time, TimestampTz), name!(value, Option<bool>))> {
+    TableIterator::new(
+        series
+            .to_series()
+            .points
+            .into_iter()
+            .map(|(ts, val)| (ts.into(), val)),
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "unnest",
+    schema = "toolkit_experimental"
+)]
+pub fn unnest_text<'a>(
+    series: Timevector_TSTZ_Text<'a>,
+) -> TableIterator<'a, (name!(time, TimestampTz), name!(value, Option<String>))> {
+    TableIterator::new(
+        series
+            .to_series()
+            .points
+            .into_iter()
+            .map(|(ts, val)| (ts.into(), val)),
+    )
+}
+
+//
+// pipelines
+//
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_tstz_i64<'a>(
+    timevector: Timevector_TSTZ_I64<'a>,
+    pipeline: UnstableTimevectorPipeline<'a>,
+) -> Timevector_TSTZ_I64<'static> {
+    run_typed_pipeline_elements(timevector.to_series(), pipeline.elements.iter()).into_timevector()
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_tstz_bool<'a>(
+    timevector: Timevector_TSTZ_Bool<'a>,
+    pipeline: UnstableTimevectorPipeline<'a>,
+) -> Timevector_TSTZ_Bool<'static> {
+    run_typed_pipeline_elements(timevector.to_series(), pipeline.elements.iter()).into_timevector()
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_tstz_text<'a>(
+    timevector: Timevector_TSTZ_Text<'a>,
+    pipeline: UnstableTimevectorPipeline<'a>,
+) -> Timevector_TSTZ_Text<'static> {
+    run_typed_pipeline_elements(timevector.to_series(), pipeline.elements.iter()).into_timevector()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    fn setup(client: &SpiClient) {
+        client.select("SET timezone TO 'UTC'", None, None);
+        // using the search path trick for this test b/c the operator is
+        // difficult to spot otherwise.
+        let sp = client
+            .select(
+                "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                None,
+                None,
+            )
+            .first()
+            .get_one::<String>()
+            .unwrap();
+        client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+        client.select(
+            "CREATE TABLE data(time TIMESTAMPTZ, count BIGINT, up BOOLEAN, state TEXT)",
+            None,
+            None,
+        );
+        client.select(
+            r#"INSERT INTO data VALUES
+                ('2020-1-1', 10, true, 'starting'),
+                ('2020-1-2', 25, true, 'running'),
+                ('2020-1-4', NULL, false, 'running'),
+                ('2020-1-3', 9007199254740993, NULL, 'error'),
+                ('2020-1-5', 40, true, NULL),
+                ('2020-1-6', 50, true, 'running')"#,
+            None,
+            None,
+        );
+    }
+
+    #[pg_test]
+    fn test_typed_timevector_i64() {
+        Spi::execute(|client| {
+            setup(&client);
+
+            let expected = "(version:1,num_points:6,flags:2,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(10)),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(25)),\
+                (ts:\"2020-01-04 00:00:00+00\",val:None),\
+                (ts:\"2020-01-03 00:00:00+00\",val:Some(9007199254740993)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(40)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(50))\
+            ])";
+            let val = client
+                .select("SELECT timevector(time, count)::TEXT FROM data", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            // text input round-trips
+            let val = client
+                .select(
+                    &format!("SELECT '{}'::timevector_tstz_i64::TEXT", expected),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), expected);
+
+            // values are exact
+            let values: Vec<Option<i64>> = client
+                .select(
+                    "SELECT value FROM unnest((SELECT timevector(time, count) FROM data))",
+                    None,
+                    None,
+                )
+                .map(|row| row[1].value::<i64>())
+                .collect();
+            assert_eq!(
+                values,
+                vec![
+                    Some(10),
+                    Some(25),
+                    None,
+                    Some(9007199254740993),
+                    Some(40),
+                    Some(50)
+                ]
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, count) \
+                        -> sort() \
+                        -> filter($$ $value > 20 $$))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,points:[\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(25)),\
+                (ts:\"2020-01-03 00:00:00+00\",val:Some(9007199254740993)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(40)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(50))\
+            ])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, count) \
+                        -> sort() \
+                        -> filter($$ $value < 100 $$) \
+                        -> fill_to('1 day', 'interpolate'))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:6,flags:1,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(10)),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(25)),\
+                (ts:\"2020-01-03 00:00:00+00\",val:Some(30)),\
+                (ts:\"2020-01-04 00:00:00+00\",val:Some(35)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(40)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(50))\
+            ])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT rollup(tv)::TEXT FROM ( \
+                        SELECT timevector(time, count) AS tv FROM data WHERE time < '2020-01-03' \
+                        UNION ALL \
+                        SELECT timevector(time, count) FROM data WHERE time >= '2020-01-05' \
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(10)),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(25)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(40)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(50))\
+            ])"
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_typed_timevector_bool() {
+        Spi::execute(|client| {
+            setup(&client);
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, up) -> sort())::TEXT FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:6,flags:3,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-03 00:00:00+00\",val:None),\
+                (ts:\"2020-01-04 00:00:00+00\",val:Some(false)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(true))\
+            ])"
+            );
+
+            // NULL values are dropped by filters
+            let val = client
+                .select(
+                    "SELECT (timevector(time, up) \
+                        -> sort() \
+                        -> filter($$ $value or $time > '2020-01-05't $$) \
+                        -> fill_to('1 day', 'locf'))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:6,flags:1,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-03 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-04 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-05 00:00:00+00\",val:Some(true)),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(true))\
+            ])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT count(*) FROM unnest((SELECT timevector(time, up) FROM data)) \
+                    WHERE value",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(val, Some(4));
+        });
+    }
+
+    #[pg_test]
+    fn test_typed_timevector_text() {
+        Spi::execute(|client| {
+            setup(&client);
+
+            // the points at which the state changed
+            let val = client
+                .select(
+                    "SELECT (timevector(time, state) \
+                        -> sort() \
+                        -> filter($$ $value != $prev_value or $index = 0 $$))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(\"starting\")),\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(\"running\")),\
+                (ts:\"2020-01-03 00:00:00+00\",val:Some(\"error\")),\
+                (ts:\"2020-01-04 00:00:00+00\",val:Some(\"running\"))\
+            ])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, state) -> filter($$ $value = 'running' $$))::TEXT \
+                    FROM data",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,points:[\
+                (ts:\"2020-01-02 00:00:00+00\",val:Some(\"running\")),\
+                (ts:\"2020-01-04 00:00:00+00\",val:Some(\"running\")),\
+                (ts:\"2020-01-06 00:00:00+00\",val:Some(\"running\"))\
+            ])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT '(version:1,num_points:2,flags:1,points:[\
+                        (ts:\"2020-01-01 00:00:00+00\",val:Some(\"a\")),\
+                        (ts:\"2020-01-02 00:00:00+00\",val:None)\
+                    ])'::timevector_tstz_text::TEXT",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:3,points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:Some(\"a\")),\
+                (ts:\"2020-01-02 00:00:00+00\",val:None)\
+            ])"
+            );
+
+            // state_agg can be built directly from a text timevector
+            let (running, error) = client
+                .select(
+                    "SELECT duration_in('running', agg)::TEXT, duration_in('error', agg)::TEXT \
+                    FROM (SELECT state_agg(timevector(time, state)) AS agg FROM data) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(running.unwrap(), "3 days");
+            assert_eq!(error.unwrap(), "1 day");
+        });
+    }
+}
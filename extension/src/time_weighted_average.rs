@@ -35,6 +35,29 @@ pg_type! {
 ron_inout_funcs!(TimeWeightSummary);
 
 impl<'input> TimeWeightSummary<'input> {
+    // Builds the summary of points already sorted by time, returns `None` if
+    // there are no points.
+    pub(crate) fn from_sorted_points(
+        method: TimeWeightMethod,
+        points: impl IntoIterator<Item = TSPoint>,
+    ) -> Option<TimeWeightSummary<'static>> {
+        let mut points = points.into_iter();
+        let mut summary = TimeWeightSummaryInternal::new(points.next()?, method);
+        for point in points {
+            if summary.accum(point).is_err() {
+                panic!("time_weight requires the points to be sorted by time")
+            }
+        }
+        unsafe {
+            Some(flatten!(TimeWeightSummary {
+                first: summary.first,
+                last: summary.last,
+                weighted_sum: summary.w_sum,
+                method: summary.method,
+            }))
+        }
+    }
+
     fn internal(&self) -> TimeWeightSummaryInternal {
         TimeWeightSummaryInternal {
             method: self.method,
@@ -170,6 +193,15 @@ pub fn time_weight_trans(
     unsafe { time_weight_trans_inner(state.to_inner(), method, ts, val, fcinfo).internal() }
 }
 
+pub(crate) fn parse_method(method: &str) -> TimeWeightMethod {
+    // TODO technically not portable to ASCII-compatible charsets
+    match method.trim().to_lowercase().as_str() {
+        "linear" | "trapezoidal" => TimeWeightMethod::Linear,
+        "locf" => TimeWeightMethod::LOCF,
+        _ => panic!("unknown method"),
+    }
+}
+
 pub fn time_weight_trans_inner(
     state: Option<Inner<TimeWeightTransState>>,
     method: String,
@@ -189,12 +221,7 @@ pub fn time_weight_trans_inner(
                 None => {
                     let mut s = TimeWeightTransState {
                         point_buffer: vec![],
-                        // TODO technically not portable to ASCII-compatible charsets
-                        method: match method.trim().to_lowercase().as_str() {
-                            "linear" | "trapezoidal" => TimeWeightMethod::Linear,
-                            "locf" => TimeWeightMethod::LOCF,
-                            _ => panic!("unknown method"),
-                        },
+                        method: parse_method(&method),
                         summary_buffer: vec![],
                     };
                     s.push_point(p);
@@ -1,3 +1,6 @@
+#[cfg(any(test, feature = "pg_test"))]
+use pgx::*;
+
 #[derive(Copy, Clone, Debug)]
 pub enum CachedDatum<'r> {
     None,
@@ -202,6 +205,36 @@ macro_rules! pg_type_impl {
                 }
             }
 
+            // binary I/O, see `send_flattened()`
+            #[pgx::pg_extern(immutable, parallel_safe)]
+            pub fn [<$name:lower _send>]<$lifetemplate>(value: $name<$lifetemplate>) -> Vec<u8> {
+                use $crate::type_builder::CachedDatum::*;
+                let bytes = match value.1 {
+                    FromInput(bytes) | Flattened(bytes) => bytes,
+                    None => value.0.to_pg_bytes(),
+                };
+                $crate::type_builder::send_flattened(bytes)
+            }
+
+            #[pgx::pg_extern(immutable, parallel_safe)]
+            pub fn [<$name:lower _recv>](input: pgx::Internal) -> $name<'static> {
+                use flat_serialize::FlatSerializable as _;
+                let bytes = unsafe { $crate::type_builder::recv_flattened(input) };
+                let data = match [<$name Data>]::try_ref(bytes) {
+                    Ok((data, rem)) if rem.is_empty() => data,
+                    Ok((_, rem)) => error!(concat!("invalid binary ", stringify!($name), ", {} trailing bytes"), rem.len()),
+                    Err(e) => error!(concat!("invalid binary ", stringify!($name), " {:?}, got len {}"), e, bytes.len()),
+                };
+                let value = $name(data, $crate::type_builder::CachedDatum::Flattened(bytes));
+                {
+                    // unused for types with their own `check_invariants()`
+                    #[allow(unused_imports)]
+                    use $crate::type_builder::CheckInvariants as _;
+                    value.check_invariants();
+                }
+                value
+            }
+
             impl<$lifetemplate> pgx::IntoDatum for $name<$lifetemplate> {
                 fn into_datum(self) -> Option<pgx::pg_sys::Datum> {
                     use $crate::type_builder::CachedDatum::*;
@@ -246,6 +279,65 @@ macro_rules! pg_type_impl {
     }
 }
 
+/// Checks a value read from its text or binary format for invariants that its
+/// layout doesn't guarantee, such as offsets into its other fields. This does
+/// nothing by default; types with such invariants define an inherent
+/// `check_invariants(&self)`, which method resolution prefers to this one.
+pub trait CheckInvariants {
+    fn check_invariants(&self) {}
+}
+
+impl<T: ?Sized> CheckInvariants for T {}
+
+/// The version of the binary format written by the `_send` functions every
+/// `pg_type!` gets.
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+/// The binary format of a flattened type: a format version followed by the
+/// type's flat_serialize layout, including its own version, without the
+/// varlena header.
+pub fn send_flattened(flattened: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(flattened.len() - 3);
+    bytes.push(BINARY_FORMAT_VERSION);
+    bytes.extend_from_slice(&flattened[4..]);
+    bytes
+}
+
+/// Reads the rest of the `StringInfo` a `_recv` function got into a newly
+/// palloc'd varlena holding the flat_serialize layout.
+///
+/// # Safety
+///
+/// `input` must point to a valid `StringInfo`.
+pub unsafe fn recv_flattened(input: pgx::Internal) -> &'static [u8] {
+    use std::{ptr, slice};
+
+    let buf = match input.unwrap() {
+        Some(buf) => &mut *buf.cast_mut_ptr::<pgx::pg_sys::StringInfoData>(),
+        None => pgx::error!("binary input is missing"),
+    };
+    let received = slice::from_raw_parts(
+        buf.data.add(buf.cursor as usize) as *const u8,
+        (buf.len - buf.cursor) as usize,
+    );
+    buf.cursor = buf.len;
+
+    let flattened = match received.split_first() {
+        Some((&BINARY_FORMAT_VERSION, flattened)) => flattened,
+        Some((version, _)) => pgx::error!("unsupported binary format version {}", version),
+        None => pgx::error!("binary input is empty"),
+    };
+    let len = flattened.len() + 4;
+    // valena tyes have a maximum size
+    if len > 0x3FFFFFFF {
+        pgx::error!("size {} bytes is to large", len)
+    }
+    let memory: *mut u8 = pgx::pg_sys::palloc0(len).cast();
+    ptr::copy_nonoverlapping(flattened.as_ptr(), memory.add(4), flattened.len());
+    pgx::set_varsize(memory.cast(), len as i32);
+    slice::from_raw_parts(memory, len)
+}
+
 #[macro_export]
 macro_rules! ron_inout_funcs {
     ($name:ident) => {
@@ -268,7 +360,14 @@ macro_rules! ron_inout_funcs {
 
                 let input = str_from_db_encoding(input);
                 let val = ron::from_str(input).unwrap();
-                unsafe { Self(val, $crate::type_builder::CachedDatum::None).flatten() }
+                let value = unsafe { Self(val, $crate::type_builder::CachedDatum::None).flatten() };
+                {
+                    // unused for types with their own `check_invariants()`
+                    #[allow(unused_imports)]
+                    use $crate::type_builder::CheckInvariants as _;
+                    value.check_invariants();
+                }
+                value
             }
         }
     };
@@ -399,3 +498,85 @@ macro_rules! do_deserialize {
         state.into()
     }};
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    use super::BINARY_FORMAT_VERSION;
+    use crate::{
+        stats_agg::{statssummary1d_recv, statssummary1d_send},
+        time_vector::{timevector_tstz_f64_recv, timevector_tstz_f64_send},
+    };
+
+    // call a `_recv` function the way postgres does
+    fn recv<T>(recv: fn(Internal) -> T, mut bytes: Vec<u8>) -> T {
+        let mut buf = pg_sys::StringInfoData {
+            data: bytes.as_mut_ptr().cast(),
+            len: bytes.len() as _,
+            maxlen: bytes.len() as _,
+            cursor: 0,
+        };
+        let buf: *mut pg_sys::StringInfoData = &mut buf;
+        recv(Internal::from(Some(pg_sys::Datum::from(buf))))
+    }
+
+    #[pg_test]
+    fn test_binary_round_trip() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+
+            let (text, sent) = client
+                .select(
+                    "SELECT stats_agg(v)::TEXT, statssummary1d_send(stats_agg(v)) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, Vec<u8>>();
+            let sent = sent.unwrap();
+            assert_eq!(sent[0], BINARY_FORMAT_VERSION);
+            let received = recv(statssummary1d_recv, sent.clone());
+            assert_eq!(ron::to_string(&*received).unwrap(), text.unwrap());
+            assert_eq!(statssummary1d_send(received), sent);
+
+            let (text, sent) = client
+                .select(
+                    "SELECT tv::TEXT, timevector_tstz_f64_send(tv) \
+                    FROM (SELECT toolkit_experimental.timevector(time, value) AS tv \
+                        FROM (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                            ('2020-01-02 UTC', NULL), \
+                            ('2020-01-03 UTC', 3.0)) v(time, value)) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, Vec<u8>>();
+            let sent = sent.unwrap();
+            let received = recv(timevector_tstz_f64_recv, sent.clone());
+            assert_eq!(received.num_points, 3);
+            assert!(received.is_null_val(1));
+            assert_eq!(ron::to_string(&*received).unwrap(), text.unwrap());
+            assert_eq!(timevector_tstz_f64_send(received), sent);
+        });
+    }
+
+    #[pg_test(error = "unsupported binary format version 2")]
+    fn test_binary_format_version() {
+        let mut sent =
+            Spi::get_one::<Vec<u8>>("SELECT statssummary1d_send(stats_agg(1.0))").unwrap();
+        sent[0] = 2;
+        recv(statssummary1d_recv, sent);
+    }
+
+    #[pg_test(error = "invalid binary StatsSummary1D, 1 trailing bytes")]
+    fn test_binary_trailing_bytes() {
+        let mut sent =
+            Spi::get_one::<Vec<u8>>("SELECT statssummary1d_send(stats_agg(1.0))").unwrap();
+        sent.push(0);
+        recv(statssummary1d_recv, sent);
+    }
+}
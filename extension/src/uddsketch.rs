@@ -1,8 +1,11 @@
-use pgx::*;
+use pgx::{iter::TableIterator, *};
 
 use encodings::{delta, prefix_varint};
 
-use uddsketch::{SketchHashKey, UDDSketch as UddSketchInternal};
+use uddsketch::{
+    ExponentialHistogram, SketchHashKey, UDDSketch as UddSketchInternal, MAX_EXPONENTIAL_SCALE,
+    MIN_EXPONENTIAL_SCALE,
+};
 
 use crate::{
     accessors::{
@@ -34,18 +37,58 @@ pub fn uddsketch_trans_inner(
     max_error: f64,
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<UddSketchInternal>> {
+    uddsketch_weighted_trans_inner(state, size, max_error, value, Some(1), fcinfo)
+}
+
+// PG function for adding values that each stand for `weight` rows, such as
+// the buckets of a pre-aggregated histogram.
+// Null values and weights are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_weighted_trans(
+    state: Internal,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    uddsketch_weighted_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        max_error,
+        value,
+        weight,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn uddsketch_weighted_trans_inner(
+    state: Option<Inner<UddSketchInternal>>,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<UddSketchInternal>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let value = match value {
-                None => return state,
-                Some(value) => value,
+            let (value, weight) = match (value, weight) {
+                (Some(value), Some(weight)) => (value, weight),
+                _ => return state,
             };
+            if weight < 0 {
+                panic!("uddsketch weights cannot be negative, got {}", weight)
+            }
+            if weight == 0 {
+                return state;
+            }
             let mut state = match state {
                 None => UddSketchInternal::new(size as u64, max_error).into(),
                 Some(state) => state,
             };
-            state.add_value(value);
+            state.add_weighted_value(value, weight as u64);
             Some(state)
         })
     }
@@ -75,6 +118,26 @@ pub fn percentile_agg_trans_inner(
     uddsketch_trans_inner(state, default_size as _, default_max_error, value, fcinfo)
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn percentile_agg_weighted_trans(
+    state: Internal,
+    value: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let default_size = PERCENTILE_AGG_DEFAULT_SIZE;
+    let default_max_error = PERCENTILE_AGG_DEFAULT_ERROR;
+    uddsketch_weighted_trans_inner(
+        unsafe { state.to_inner() },
+        default_size as _,
+        default_max_error,
+        value,
+        weight,
+        fcinfo,
+    )
+    .internal()
+}
+
 // PG function for merging sketches.
 #[pg_extern(immutable, parallel_safe)]
 pub fn uddsketch_combine(
@@ -350,16 +413,27 @@ impl<'input> UddSketch<'input> {
     }
 }
 
+impl<'input> UddSketch<'input> {
+    pub(crate) fn from_values(
+        size: u64,
+        max_error: f64,
+        values: impl IntoIterator<Item = f64>,
+    ) -> Self {
+        let mut sketch = UddSketchInternal::new(size, max_error);
+        for value in values {
+            sketch.add_value(value);
+        }
+        Self::from_internal(&sketch)
+    }
+}
+
 impl<'input> FromIterator<f64> for UddSketch<'input> {
     fn from_iter<T: IntoIterator<Item = f64>>(iter: T) -> Self {
-        let mut sketch = UddSketchInternal::new(
+        Self::from_values(
             PERCENTILE_AGG_DEFAULT_SIZE.into(),
             PERCENTILE_AGG_DEFAULT_ERROR,
-        );
-        for value in iter {
-            sketch.add_value(value);
-        }
-        Self::from_internal(&sketch)
+            iter,
+        )
     }
 }
 
@@ -506,6 +580,53 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.uddsketch(\n\
+        size integer, max_error DOUBLE PRECISION, value DOUBLE PRECISION, weight BIGINT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.uddsketch_weighted_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "udd_weighted_agg",
+    requires = [
+        uddsketch_weighted_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.percentile_agg(value DOUBLE PRECISION, weight BIGINT)\n\
+    (\n\
+        sfunc = toolkit_experimental.percentile_agg_weighted_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "percentile_weighted_agg",
+    requires = [
+        percentile_agg_weighted_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn uddsketch_compound_trans<'a>(
     state: Internal,
@@ -643,6 +764,242 @@ pub fn uddsketch_error<'a>(sketch: UddSketch<'a>) -> f64 {
     sketch.alpha
 }
 
+// Builds a sketch from a Prometheus histogram: the `le` bounds of its buckets
+// and the cumulative counts of the values at most those bounds.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_prometheus(
+    size: i32,
+    max_error: f64,
+    upper_bounds: Vec<f64>,
+    cumulative_counts: Vec<i64>,
+    sum: default!(Option<f64>, "NULL"),
+) -> UddSketch<'static> {
+    if upper_bounds.len() != cumulative_counts.len() {
+        panic!(
+            "there must be as many bucket bounds as counts, got {} bounds and {} counts",
+            upper_bounds.len(),
+            cumulative_counts.len()
+        )
+    }
+    for (i, bound) in upper_bounds.iter().enumerate() {
+        let next = upper_bounds.get(i + 1);
+        if bound.is_nan() || *bound == f64::NEG_INFINITY || next.map_or(false, |next| next <= bound)
+        {
+            panic!(
+                "bucket bounds must be increasing numbers, got {:?}",
+                upper_bounds
+            )
+        }
+    }
+    if cumulative_counts.first().map_or(false, |&count| count < 0)
+        || cumulative_counts
+            .windows(2)
+            .any(|counts| counts[1] < counts[0])
+    {
+        panic!(
+            "cumulative counts cannot be negative or decrease, got {:?}",
+            cumulative_counts
+        )
+    }
+    if upper_bounds.first() == Some(&f64::INFINITY) && cumulative_counts[0] > 0 {
+        panic!("a histogram with values needs a finite bucket bound")
+    }
+
+    let counts: Vec<u64> = cumulative_counts
+        .iter()
+        .map(|&count| count as u64)
+        .collect();
+    let sketch = UddSketchInternal::from_cumulative_buckets(
+        size as u64,
+        max_error,
+        &upper_bounds,
+        &counts,
+        sum,
+    );
+    UddSketch::from_internal(&sketch)
+}
+
+// Builds a sketch from the buckets of an OpenTelemetry exponential histogram.
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_exponential_histogram(
+    size: i32,
+    scale: i32,
+    zero_count: i64,
+    positive_offset: i32,
+    positive_counts: Vec<i64>,
+    negative_offset: default!(i32, 0),
+    negative_counts: default!(Vec<i64>, "'{}'"),
+    sum: default!(Option<f64>, "NULL"),
+) -> UddSketch<'static> {
+    if !(MIN_EXPONENTIAL_SCALE..=MAX_EXPONENTIAL_SCALE).contains(&scale) {
+        panic!(
+            "scale must be between {} and {}, got {}",
+            MIN_EXPONENTIAL_SCALE, MAX_EXPONENTIAL_SCALE, scale
+        )
+    }
+    let to_counts = |counts: &[i64]| -> Vec<u64> {
+        counts
+            .iter()
+            .map(|&count| {
+                if count < 0 {
+                    panic!("bucket counts cannot be negative, got {}", count)
+                }
+                count as u64
+            })
+            .collect()
+    };
+    let histogram = ExponentialHistogram {
+        scale,
+        zero_count: to_counts(&[zero_count])[0],
+        positive_offset,
+        positive_counts: to_counts(&positive_counts),
+        negative_offset,
+        negative_counts: to_counts(&negative_counts),
+    };
+    let sketch = UddSketchInternal::from_exponential_histogram(size as u64, &histogram, sum);
+    UddSketch::from_internal(&sketch)
+}
+
+extension_sql!(
+    "\n\
+    CREATE TYPE toolkit_experimental.exponential_histogram AS (\n\
+        scale INTEGER,\n\
+        zero_count BIGINT,\n\
+        positive_offset INTEGER,\n\
+        positive_counts BIGINT[],\n\
+        negative_offset INTEGER,\n\
+        negative_counts BIGINT[]\n\
+    );\n\
+    ",
+    name = "exponential_histogram_type",
+);
+
+// The buckets of a sketch as an OpenTelemetry exponential histogram, at the
+// largest scale that holds whole buckets of the sketch and doesn't need more
+// buckets than the sketch can have.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    requires = ["exponential_histogram_type"]
+)]
+pub fn to_exponential_histogram<'a>(
+    sketch: UddSketch<'a>,
+) -> pgx::composite_type!("toolkit_experimental.exponential_histogram") {
+    let histogram = sketch.to_uddsketch().to_exponential_histogram();
+    let to_counts =
+        |counts: Vec<u64>| -> Vec<i64> { counts.into_iter().map(|count| count as i64).collect() };
+    let mut result =
+        PgHeapTuple::new_composite_type("toolkit_experimental.exponential_histogram").unwrap();
+    result.set_by_name("scale", histogram.scale).unwrap();
+    result
+        .set_by_name("zero_count", histogram.zero_count as i64)
+        .unwrap();
+    result
+        .set_by_name("positive_offset", histogram.positive_offset)
+        .unwrap();
+    result
+        .set_by_name("positive_counts", to_counts(histogram.positive_counts))
+        .unwrap();
+    result
+        .set_by_name("negative_offset", histogram.negative_offset)
+        .unwrap();
+    result
+        .set_by_name("negative_counts", to_counts(histogram.negative_counts))
+        .unwrap();
+    result
+}
+
+// The number of values in the sketch falling into each of the buckets
+// delimited by `boundaries`. Values are counted at the representative value of
+// the sketch bucket holding them, so bucket boundaries within the error of the
+// sketch may count values on the wrong side.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "histogram"
+)]
+pub fn uddsketch_histogram<'a>(
+    sketch: UddSketch<'a>,
+    boundaries: Vec<f64>,
+) -> TableIterator<'a, (name!(lower, f64), name!(upper, f64), name!(count, i64))> {
+    let internal = sketch.to_uddsketch();
+    let values = sketch
+        .keys()
+        .zip(sketch.counts())
+        .map(|(key, count)| (internal.bucket_value(key), count));
+    TableIterator::new(crate::sketch_utils::histogram(&boundaries, values).into_iter())
+}
+
+// The approximate cumulative distribution function of the values in the
+// sketch, sampled at `n_points` evenly spaced fractions from 0 to 1.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "cdf"
+)]
+pub fn uddsketch_cdf<'a>(
+    sketch: UddSketch<'a>,
+    n_points: i32,
+) -> TableIterator<'a, (name!(value, f64), name!(fraction, f64))> {
+    let internal = sketch.to_uddsketch();
+    TableIterator::new(
+        crate::sketch_utils::cdf_fractions(n_points)
+            .map(move |fraction| (internal.estimate_quantile(fraction), fraction)),
+    )
+}
+
+// The raw buckets of the sketch, in increasing order of value.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "buckets"
+)]
+pub fn uddsketch_buckets<'a>(
+    sketch: UddSketch<'a>,
+) -> TableIterator<'a, (name!(lower, f64), name!(upper, f64), name!(count, i64))> {
+    let internal = sketch.to_uddsketch();
+    let buckets: Vec<_> = sketch
+        .keys()
+        .zip(sketch.counts())
+        .map(|(key, count)| {
+            let (lower, upper) = internal.bucket_bounds(key);
+            (lower, upper, count as i64)
+        })
+        .collect();
+    TableIterator::new(buckets.into_iter())
+}
+
+// Approximate mean of the values between the `low_q` and `high_q` quantiles,
+// e.g. the mean excluding the top 1% with `low_q = 0` and `high_q = 0.99`.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn approx_trimmed_mean<'a>(sketch: UddSketch<'a>, low_q: f64, high_q: f64) -> Option<f64> {
+    let internal = sketch.to_uddsketch();
+    let values = sketch
+        .keys()
+        .zip(sketch.counts())
+        .map(|(key, count)| (internal.bucket_value(key), count));
+    crate::sketch_utils::trimmed_mean(values, sketch.count, low_q, high_q)
+}
+
+// Approximate mean of the values after clamping them to the values at the
+// `low_q` and `high_q` quantiles.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn approx_winsorized_mean<'a>(sketch: UddSketch<'a>, low_q: f64, high_q: f64) -> Option<f64> {
+    let internal = sketch.to_uddsketch();
+    let values = sketch
+        .keys()
+        .zip(sketch.counts())
+        .map(|(key, count)| (internal.bucket_value(key), count));
+    crate::sketch_utils::winsorized_mean(values, sketch.count, low_q, high_q, |q| {
+        internal.estimate_quantile(q)
+    })
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -921,6 +1278,296 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_weighted_aggregates() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE weighted_test (value DOUBLE PRECISION, weight BIGINT)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO weighted_test VALUES \
+                    (-2.5, 3), (0, 10), (0.125, 1), (4, 250), (1000, 5000000), \
+                    (7, 0), (NULL, 12), (8, NULL)",
+                None,
+                None,
+            );
+
+            // a weighted value is the same as the value repeated that many
+            // times, both before and after the sketch has been compacted
+            for size in [100, 3] {
+                let (weighted, repeated) = client
+                    .select(
+                        &format!(
+                            "SELECT \
+                                (SELECT toolkit_experimental.uddsketch({size}, 0.01, value, weight)::TEXT \
+                                    FROM weighted_test WHERE weight < 1000), \
+                                (SELECT uddsketch({size}, 0.01, value)::TEXT \
+                                    FROM weighted_test, generate_series(1, weight) \
+                                    WHERE weight < 1000)",
+                            size = size
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<String, String>();
+                assert_eq!(weighted.unwrap(), repeated.unwrap());
+            }
+
+            let (count, mean, median) = client
+                .select(
+                    "SELECT num_vals(sketch), mean(sketch), approx_percentile(0.5, sketch) \
+                    FROM (SELECT toolkit_experimental.percentile_agg(value, weight) AS sketch \
+                        FROM weighted_test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(count.unwrap(), 5_000_264.0);
+            apx_eql(
+                mean.unwrap(),
+                (-7.5 + 0.125 + 1000.0 + 5_000_000_000.0) / 5_000_264.0,
+                0.000001,
+            );
+            pct_eql(median.unwrap(), 1000.0, 0.001);
+
+            // the weights survive a rollup and the text format
+            let (rolled_up, text) = client
+                .select(
+                    "SELECT num_vals(rollup(sketch)), \
+                        num_vals((toolkit_experimental.percentile_agg(value, weight)::TEXT)::UddSketch) \
+                    FROM (SELECT value, weight, \
+                        toolkit_experimental.percentile_agg(value, weight) AS sketch \
+                        FROM weighted_test GROUP BY value, weight) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(rolled_up.unwrap(), 5_000_264.0);
+            assert_eq!(text.unwrap(), 5_000_264.0);
+        });
+    }
+
+    #[pg_test(error = "uddsketch weights cannot be negative, got -1")]
+    fn test_negative_weight() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.percentile_agg(1.0, -1)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_exponential_histograms() {
+        Spi::execute(|client| {
+            let histogram = "toolkit_experimental.uddsketch_from_exponential_histogram(\
+                100, 3, 4, -2, '{1, 0, 5, 2}', 7, '{3}')";
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT h::TEXT FROM toolkit_experimental.to_exponential_histogram({}) h",
+                        histogram
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(3,4,-2,\"{1,0,5,2}\",7,{3})");
+
+            let val = client
+                .select(
+                    &format!(
+                        "SELECT (toolkit_experimental.to_exponential_histogram({})).positive_counts::TEXT",
+                        histogram
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "{1,0,5,2}");
+
+            // the median is in the bucket with index -2, (2^(-2/8), 2^(-1/8)]
+            let (count, median) = client
+                .select(
+                    &format!(
+                        "SELECT num_vals({0}), approx_percentile(0.5, {0})",
+                        histogram
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count.unwrap(), 15.0);
+            let median = median.unwrap();
+            assert!(median > 2f64.powf(-0.25) && median <= 2f64.powf(-0.125));
+
+            // histograms at different scales can be rolled up
+            let val = client
+                .select(
+                    "SELECT h::TEXT FROM toolkit_experimental.to_exponential_histogram(( \
+                        SELECT rollup(toolkit_experimental.uddsketch_from_exponential_histogram( \
+                            100, scale, 0, 0, '{1, 1}')) \
+                        FROM (VALUES (2), (1)) v(scale))) h",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(val.unwrap(), "(1,0,0,\"{3,1}\",0,{})");
+        });
+    }
+
+    #[pg_test]
+    fn test_prometheus_histograms() {
+        Spi::execute(|client| {
+            let (count, median, max, mean) = client
+                .select(
+                    "SELECT num_vals(sketch), approx_percentile(0.5, sketch), \
+                        approx_percentile(1.0, sketch), mean(sketch) \
+                    FROM (SELECT toolkit_experimental.uddsketch_from_prometheus( \
+                        100, 0.01, '{0.1, 0.5, 1, Infinity}', '{10, 30, 40, 45}', 22.5) AS sketch) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_four::<f64, f64, f64, f64>();
+            assert_eq!(count.unwrap(), 45.0);
+            // values are spread evenly within their bucket
+            pct_eql(median.unwrap(), 0.35, 0.01);
+            // and those above the largest bound are at it
+            pct_eql(max.unwrap(), 1.0, 0.01);
+            assert_eq!(mean.unwrap(), 0.5);
+        });
+    }
+
+    #[pg_test(error = "cumulative counts cannot be negative or decrease, got [10, 5]")]
+    fn test_prometheus_decreasing_counts() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.uddsketch_from_prometheus(100, 0.01, '{1, 2}', '{10, 5}')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "scale must be between -5 and 20, got 21")]
+    fn test_exponential_histogram_scale() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.uddsketch_from_exponential_histogram(100, 21, 0, 0, '{1}')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_histogram() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE VIEW sketch AS \
+                SELECT uddsketch(1000, 0.001, data) FROM generate_series(1, 100) data",
+                None,
+                None,
+            );
+
+            let counts = client
+                .select(
+                    "SELECT array_agg(count ORDER BY lower)::TEXT \
+                    FROM sketch, toolkit_experimental.histogram(uddsketch, '{25.5, 50.5, 75.5}')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(counts.unwrap(), "{25,25,25,25}");
+
+            let (buckets, count, ordered) = client
+                .select(
+                    "SELECT count(*), sum(count)::BIGINT, bool_and(lower < upper) \
+                    FROM sketch, toolkit_experimental.buckets(uddsketch)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<i64, i64, bool>();
+            assert_eq!(buckets.unwrap(), 100);
+            assert_eq!(count.unwrap(), 100);
+            assert!(ordered.unwrap());
+
+            let (fractions, median) = client
+                .select(
+                    "SELECT array_agg(fraction)::TEXT, max(value) FILTER (WHERE fraction = 0.5) \
+                    FROM sketch, toolkit_experimental.cdf(uddsketch, 5)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, f64>();
+            assert_eq!(fractions.unwrap(), "{0,0.25,0.5,0.75,1}");
+            pct_eql(median.unwrap(), 50.0, 0.001);
+        });
+    }
+
+    #[pg_test(error = "histogram boundaries must be strictly increasing, got [2.0, 1.0]")]
+    fn test_uddsketch_histogram_unordered_boundaries() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.histogram(\
+                    (SELECT uddsketch(100, 0.01, data) FROM generate_series(1, 10) data), '{2, 1}')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_trimmed_means() {
+        Spi::execute(|client| {
+            let (trimmed, untrimmed, winsorized) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_trimmed_mean(sketch, 0, 0.99), \
+                        toolkit_experimental.approx_trimmed_mean(sketch, 0, 1), \
+                        toolkit_experimental.approx_winsorized_mean(sketch, 0.1, 0.9) \
+                    FROM (SELECT uddsketch(1000, 0.001, data) AS sketch \
+                        FROM generate_series(1, 100) data) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<f64, f64, f64>();
+            // the mean of 1 to 99
+            pct_eql(trimmed.unwrap(), 50.0, 0.001);
+            pct_eql(untrimmed.unwrap(), 50.5, 0.001);
+            // 1 to 10 become 11, 91 to 100 become 90
+            pct_eql(winsorized.unwrap(), 50.5, 0.01);
+        });
+    }
+
+    #[pg_test(error = "quantiles must satisfy 0 <= low_q < high_q <= 1, got 0.9 and 0.1")]
+    fn test_uddsketch_trimmed_mean_quantiles() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.approx_trimmed_mean(\
+                    (SELECT uddsketch(100, 0.01, data) FROM generate_series(1, 10) data), 0.9, 0.1)",
+                None,
+                None,
+            );
+        });
+    }
+
     #[pg_test]
     fn uddsketch_io_test() {
         Spi::execute(|client| {
@@ -0,0 +1,104 @@
+// pgx creates our types with only the text I/O functions. Every `pg_type!`
+// also has a `<type>_send` and `<type>_recv` function, which pgx creates after
+// the type since they take or return it. Setting them with `ALTER TYPE`
+// needs a superuser, so instead we move the functions before the
+// `CREATE TYPE <type> (...)` that defines the type, after the shell type like
+// the text I/O functions, and name them there.
+pub(crate) fn add_send_recv_to_types(lines: Vec<String>) -> Vec<String> {
+    let mut lines: Vec<Option<String>> = lines.into_iter().map(Some).collect();
+    let mut insertions = vec![];
+
+    for type_start in 0..lines.len() {
+        let type_name = match lines[type_start].as_deref().and_then(full_type_definition) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let send = find_function(&lines, type_start, &format!("{}_send", type_name));
+        let recv = find_function(&lines, type_start, &format!("{}_recv", type_name));
+        let (send, recv) = match (send, recv) {
+            (Some(send), Some(recv)) => (send, recv),
+            _ => continue,
+        };
+
+        let mut moved = vec![];
+        for (start, end) in [send, recv] {
+            moved.extend(
+                lines[start..end]
+                    .iter_mut()
+                    .map(|line| line.take().unwrap()),
+            );
+        }
+
+        // name the functions after `OUTPUT = ...,`
+        let output = (type_start..lines.len())
+            .find(|&i| {
+                lines[i]
+                    .as_deref()
+                    .map_or(false, |l| l.trim_start().starts_with("OUTPUT"))
+            })
+            .unwrap_or_else(|| panic!("no OUTPUT for type {}", type_name));
+        let output_line = lines[output].as_mut().unwrap();
+        output_line.push_str(&format!(
+            "\n\tSEND = {name}_send,\n\tRECEIVE = {name}_recv,",
+            name = type_name
+        ));
+
+        insertions.push((statement_start(&lines, type_start), moved));
+    }
+
+    let mut output = Vec::with_capacity(lines.len());
+    let mut insertions = insertions.into_iter().peekable();
+    for (i, line) in lines.into_iter().enumerate() {
+        while let Some((_, moved)) = insertions.next_if(|(at, _)| *at == i) {
+            output.extend(moved);
+        }
+        output.extend(line);
+    }
+    output
+}
+
+// returns the lowercased, possibly schema-qualified, name of the type for
+// `CREATE TYPE <name> (`
+fn full_type_definition(line: &str) -> Option<String> {
+    let name = line
+        .trim_start()
+        .strip_prefix("CREATE TYPE ")?
+        .trim_end()
+        .strip_suffix('(')?;
+    Some(name.trim().to_ascii_lowercase())
+}
+
+// find the `CREATE FUNCTION <name>(...) ... ;` after `from`, and return the
+// lines it spans along with the comments before it
+fn find_function(lines: &[Option<String>], from: usize, name: &str) -> Option<(usize, usize)> {
+    let create = (from..lines.len()).find(|&i| {
+        lines[i]
+            .as_deref()
+            .and_then(|l| l.trim_start().strip_prefix("CREATE FUNCTION "))
+            .map_or(false, |l| {
+                let function = l.split('(').next().unwrap().replace('"', "");
+                function.trim().eq_ignore_ascii_case(name)
+            })
+    })?;
+    let end = (create..lines.len())
+        .find(|&i| {
+            lines[i]
+                .as_deref()
+                .map_or(false, |l| l.trim_end().ends_with(';'))
+        })
+        .unwrap_or_else(|| panic!("unterminated CREATE FUNCTION {}", name));
+    Some((statement_start(lines, create), end + 1))
+}
+
+// the first of the comment lines directly before the statement at `line`
+fn statement_start(lines: &[Option<String>], mut line: usize) -> usize {
+    while line > 0
+        && lines[line - 1]
+            .as_deref()
+            .map_or(false, |l| l.trim_start().starts_with("--"))
+    {
+        line -= 1;
+    }
+    line
+}
@@ -8,6 +8,7 @@ use std::{
 
 use xshell::cmd;
 
+mod binary_io;
 mod update_script;
 
 macro_rules! path {
@@ -53,6 +54,9 @@ fn try_main() -> xshell::Result<()> {
     // replace `MODULE_PATH` with `$libdir/timescaledb_toolkit-<current version>`
     add_version_to_install_script(&extension_info);
 
+    // create our types with their binary I/O functions
+    add_binary_io_to_install_script(&extension_info);
+
     generate_update_scripts(&extension_info);
 
     Ok(())
@@ -222,6 +226,28 @@ fn add_version_to_install_script(
     rename_file(&versioned_script, &install_script);
 }
 
+fn add_binary_io_to_install_script(
+    ExtensionInfo {
+        current_version,
+        extension_dir,
+        ..
+    }: &ExtensionInfo,
+) {
+    let install_script =
+        path!(extension_dir / format!("timescaledb_toolkit--{}.sql", current_version));
+
+    let lines = open_file(&install_script)
+        .lines()
+        .map(|line| line.expect("cannot read install script"))
+        .collect();
+    let lines = binary_io::add_send_recv_to_types(lines);
+
+    let mut script = create_file(&install_script);
+    for line in lines {
+        writeln!(script, "{}", line).expect("cannot write install script");
+    }
+}
+
 //
 // upgrade scripts
 //